@@ -0,0 +1,4 @@
+// This file was generated by [ts-rs](https://github.com/Aleph-Alpha/ts-rs). Do not edit this file manually.
+import type { TokenScope } from "./TokenScope";
+
+export interface AccessToken { id: string, name: string, scopes: Array<TokenScope>, createdAt: any, expiresAt?: any, lastUsedAt?: any, }
\ No newline at end of file
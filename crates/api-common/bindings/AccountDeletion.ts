@@ -0,0 +1,5 @@
+// This file was generated by [ts-rs](https://github.com/Aleph-Alpha/ts-rs). Do not edit this file manually.
+import type { AccountDeletionProgress } from "./AccountDeletionProgress";
+import type { AccountDeletionState } from "./AccountDeletionState";
+
+export interface AccountDeletion { username: string, state: AccountDeletionState, progress: AccountDeletionProgress, error?: string, startedAt: any, updatedAt: any, }
\ No newline at end of file
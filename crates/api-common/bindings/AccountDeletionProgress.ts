@@ -0,0 +1,3 @@
+// This file was generated by [ts-rs](https://github.com/Aleph-Alpha/ts-rs). Do not edit this file manually.
+
+export interface AccountDeletionProgress { projectsTotal: number, projectsDeleted: number, collaborationsRemoved: number, librariesDeleted: number, friendsRemoved: number, invitesDeleted: number, tokensRevoked: number, clientsDeleted: number, }
\ No newline at end of file
@@ -0,0 +1,3 @@
+// This file was generated by [ts-rs](https://github.com/Aleph-Alpha/ts-rs). Do not edit this file manually.
+
+export type AuditAction = "banUser" | "unbanUser" | "setUserRole" | "resetTwoFactor" | "setProjectState" | "setLibraryState" | "authorizeHost" | "unauthorizeHost" | "rotateHostSecret" | "deleteGroup" | "banAddress" | "unbanAddress" | "addIpReputationEntry" | "removeIpReputationEntry";
\ No newline at end of file
@@ -0,0 +1,4 @@
+// This file was generated by [ts-rs](https://github.com/Aleph-Alpha/ts-rs). Do not edit this file manually.
+import type { AuditAction } from "./AuditAction";
+
+export interface AuditLogQuery { actor?: string, target?: string, action?: AuditAction, page?: bigint, limit?: bigint, }
\ No newline at end of file
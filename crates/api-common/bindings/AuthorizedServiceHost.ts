@@ -1,4 +1,5 @@
 // This file was generated by [ts-rs](https://github.com/Aleph-Alpha/ts-rs). Do not edit this file manually.
+import type { HostCapability } from "./HostCapability";
 import type { ServiceHostScope } from "./ServiceHostScope";
 
-export interface AuthorizedServiceHost { url: string, id: string, visibility: ServiceHostScope, }
\ No newline at end of file
+export interface AuthorizedServiceHost { url: string, id: string, visibility: ServiceHostScope, capabilities: Array<HostCapability>, }
\ No newline at end of file
@@ -1,3 +1,3 @@
 // This file was generated by [ts-rs](https://github.com/Aleph-Alpha/ts-rs). Do not edit this file manually.
 
-export interface BannedAccount { username: string, email: string, bannedAt: any, }
\ No newline at end of file
+export interface BannedAccount { username: string, email: string, bannedAt: any, moderator?: string, reason?: string, expiresAt?: any, }
\ No newline at end of file
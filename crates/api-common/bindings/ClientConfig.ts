@@ -1,4 +1,4 @@
 // This file was generated by [ts-rs](https://github.com/Aleph-Alpha/ts-rs). Do not edit this file manually.
 import type { ServiceHost } from "./ServiceHost";
 
-export interface ClientConfig { clientId: string, username?: string, servicesHosts: Array<ServiceHost>, cloudUrl: string, }
\ No newline at end of file
+export interface ClientConfig { clientId: string, clientSecret: string, username?: string, servicesHosts: Array<ServiceHost>, cloudUrl: string, csrfToken: string, }
\ No newline at end of file
@@ -0,0 +1,4 @@
+// This file was generated by [ts-rs](https://github.com/Aleph-Alpha/ts-rs). Do not edit this file manually.
+import type { AccessToken } from "./AccessToken";
+
+export interface CreatedAccessToken { secret: string, token: AccessToken, }
\ No newline at end of file
@@ -0,0 +1,3 @@
+// This file was generated by [ts-rs](https://github.com/Aleph-Alpha/ts-rs). Do not edit this file manually.
+
+export interface IpReputationEntry { id: string, source: string, addr: string, reason?: string, createdBy: string, createdAt: any, }
\ No newline at end of file
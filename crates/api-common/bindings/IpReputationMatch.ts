@@ -0,0 +1,4 @@
+// This file was generated by [ts-rs](https://github.com/Aleph-Alpha/ts-rs). Do not edit this file manually.
+import type { IpReputationAction } from "./IpReputationAction";
+
+export interface IpReputationMatch { source: string, range: string, actions: Array<IpReputationAction>, }
\ No newline at end of file
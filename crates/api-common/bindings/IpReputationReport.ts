@@ -0,0 +1,4 @@
+// This file was generated by [ts-rs](https://github.com/Aleph-Alpha/ts-rs). Do not edit this file manually.
+import type { IpReputationMatch } from "./IpReputationMatch";
+
+export interface IpReputationReport { addr: string, matches: Array<IpReputationMatch>, }
\ No newline at end of file
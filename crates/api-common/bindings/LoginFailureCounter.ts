@@ -0,0 +1,4 @@
+// This file was generated by [ts-rs](https://github.com/Aleph-Alpha/ts-rs). Do not edit this file manually.
+import type { LoginCounterKind } from "./LoginCounterKind";
+
+export interface LoginFailureCounter { kind: LoginCounterKind, key: string, failures: any, lockouts: number, lockedUntil?: any, }
\ No newline at end of file
@@ -2,4 +2,4 @@
 import type { ClientId } from "./ClientId";
 import type { Credentials } from "./Credentials";
 
-export interface LoginRequest { credentials: Credentials, clientId?: ClientId, }
\ No newline at end of file
+export interface LoginRequest { credentials: Credentials, clientId?: ClientId, clientSecret?: string, twoFactorCode?: string, }
\ No newline at end of file
@@ -0,0 +1,4 @@
+// This file was generated by [ts-rs](https://github.com/Aleph-Alpha/ts-rs). Do not edit this file manually.
+import type { ClientId } from "./ClientId";
+
+export interface MagicLinkCodeLoginData { email: string, username: string, code: string, clientId?: ClientId, clientSecret?: string, }
\ No newline at end of file
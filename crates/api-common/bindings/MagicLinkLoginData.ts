@@ -2,4 +2,4 @@
 import type { ClientId } from "./ClientId";
 import type { MagicLinkId } from "./MagicLinkId";
 
-export interface MagicLinkLoginData { linkId: MagicLinkId, username: string, clientId?: ClientId, redirectUri?: string, }
\ No newline at end of file
+export interface MagicLinkLoginData { linkId: MagicLinkId, username: string, clientId?: ClientId, clientSecret?: string, redirectUri?: string, }
\ No newline at end of file
@@ -0,0 +1,3 @@
+// This file was generated by [ts-rs](https://github.com/Aleph-Alpha/ts-rs). Do not edit this file manually.
+
+export interface SignupChallenge { id: string, difficulty: number, expiresAt: any, }
\ No newline at end of file
@@ -4,4 +4,4 @@ import type { LinkedAccount } from "./LinkedAccount";
 import type { ServiceHost } from "./ServiceHost";
 import type { UserRole } from "./UserRole";
 
-export interface User { username: string, email: string, groupId?: GroupId, role: UserRole, linkedAccounts: Array<LinkedAccount>, servicesHosts?: Array<ServiceHost>, }
\ No newline at end of file
+export interface User { username: string, email: string, groupId?: GroupId, role: UserRole, linkedAccounts: Array<LinkedAccount>, servicesHosts?: Array<ServiceHost>, verified: boolean, }
\ No newline at end of file
@@ -1,7 +1,8 @@
 use crate::{
-    oauth, AppId, ClientId, FriendInvite, FriendLinkState, GroupId, InvitationState, LinkedAccount,
-    MagicLinkId, ProjectId, PublishState, RoleId, RoleMetadata, SaveState, ServiceHost,
-    ServiceHostScope, UserRole,
+    oauth, AccountDeletionState, AppId, AuditAction, ClientId, FriendInvite, FriendLinkState,
+    GroupId, HostCapability, InvitationState, LinkedAccount, LoginCounterKind, MagicLinkId,
+    ProjectId, PublishState, RoleId, RoleMetadata, SaveState, ServiceHost, ServiceHostScope,
+    UserRole,
 };
 use bson::{doc, Bson, DateTime};
 
@@ -34,6 +35,16 @@ impl From<FriendLinkState> for Bson {
     }
 }
 
+impl From<AccountDeletionState> for Bson {
+    fn from(state: AccountDeletionState) -> Bson {
+        match state {
+            AccountDeletionState::InProgress => Bson::String("inProgress".into()),
+            AccountDeletionState::Complete => Bson::String("complete".into()),
+            AccountDeletionState::Failed => Bson::String("failed".into()),
+        }
+    }
+}
+
 impl From<UserRole> for Bson {
     fn from(role: UserRole) -> Bson {
         match role {
@@ -45,6 +56,36 @@ impl From<UserRole> for Bson {
     }
 }
 
+impl From<LoginCounterKind> for Bson {
+    fn from(kind: LoginCounterKind) -> Bson {
+        match kind {
+            LoginCounterKind::Username => Bson::String("username".into()),
+            LoginCounterKind::Address => Bson::String("address".into()),
+        }
+    }
+}
+
+impl From<AuditAction> for Bson {
+    fn from(action: AuditAction) -> Bson {
+        match action {
+            AuditAction::BanUser => Bson::String("banUser".into()),
+            AuditAction::UnbanUser => Bson::String("unbanUser".into()),
+            AuditAction::SetUserRole => Bson::String("setUserRole".into()),
+            AuditAction::ResetTwoFactor => Bson::String("resetTwoFactor".into()),
+            AuditAction::SetProjectState => Bson::String("setProjectState".into()),
+            AuditAction::SetLibraryState => Bson::String("setLibraryState".into()),
+            AuditAction::AuthorizeHost => Bson::String("authorizeHost".into()),
+            AuditAction::UnauthorizeHost => Bson::String("unauthorizeHost".into()),
+            AuditAction::RotateHostSecret => Bson::String("rotateHostSecret".into()),
+            AuditAction::DeleteGroup => Bson::String("deleteGroup".into()),
+            AuditAction::BanAddress => Bson::String("banAddress".into()),
+            AuditAction::UnbanAddress => Bson::String("unbanAddress".into()),
+            AuditAction::AddIpReputationEntry => Bson::String("addIpReputationEntry".into()),
+            AuditAction::RemoveIpReputationEntry => Bson::String("removeIpReputationEntry".into()),
+        }
+    }
+}
+
 impl From<FriendInvite> for Bson {
     fn from(invite: FriendInvite) -> Bson {
         Bson::Document(doc! {
@@ -139,25 +180,21 @@ impl From<oauth::Client> for Bson {
         Bson::Document(doc! {
             "name": client.name,
             "id": client.id,
+            "allowedScopes": client.allowed_scopes,
+            "public": client.public,
         })
     }
 }
 
-impl From<oauth::CodeId> for Bson {
-    fn from(id: oauth::CodeId) -> Bson {
-        Bson::String(id.as_str().to_owned())
+impl From<oauth::Scope> for Bson {
+    fn from(scope: oauth::Scope) -> Bson {
+        Bson::String(scope.as_str().to_owned())
     }
 }
 
-impl From<oauth::Code> for Bson {
-    fn from(code: oauth::Code) -> Bson {
-        Bson::Document(doc! {
-            "id": code.id,
-            "username": code.username,
-            "clientId": code.client_id,
-            "redirectUri": code.redirect_uri,
-            "createdAt": DateTime::from_system_time(code.created_at),
-        })
+impl From<oauth::CodeId> for Bson {
+    fn from(id: oauth::CodeId) -> Bson {
+        Bson::String(id.as_str().to_owned())
     }
 }
 
@@ -167,6 +204,12 @@ impl From<oauth::TokenId> for Bson {
     }
 }
 
+impl From<HostCapability> for Bson {
+    fn from(capability: HostCapability) -> Bson {
+        Bson::String(capability.as_str().to_owned())
+    }
+}
+
 impl From<ServiceHostScope> for Bson {
     fn from(scope: ServiceHostScope) -> Bson {
         match scope {
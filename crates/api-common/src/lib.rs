@@ -20,10 +20,15 @@ const APP_NAME: &str = "NetsBlox";
 #[ts(export)]
 pub struct ClientConfig {
     pub client_id: String,
+    /// Secret for the client ID. Required to connect, set the client state, or
+    /// login/logout as the client (using the `X-Client-Secret` header).
+    pub client_secret: String,
     #[ts(optional)]
     pub username: Option<String>,
     pub services_hosts: Vec<ServiceHost>,
     pub cloud_url: String,
+    /// Token to include in the `X-CSRF-Token` header of state-changing requests
+    pub csrf_token: String,
 }
 
 #[derive(Deserialize, Serialize, TS)]
@@ -46,6 +51,7 @@ pub struct User {
     pub linked_accounts: Vec<LinkedAccount>,
     #[ts(optional)]
     pub services_hosts: Option<Vec<ServiceHost>>,
+    pub verified: bool,
 }
 
 #[derive(Serialize, Deserialize, TS, Clone)]
@@ -71,6 +77,27 @@ pub struct NewUser {
     pub role: Option<UserRole>,
 }
 
+/// Proof-of-work challenge which may be required to create an account. The
+/// solution is a nonce such that SHA-256(id + nonce) starts with `difficulty`
+/// zero bits.
+#[derive(Serialize, Deserialize, Clone, Debug, TS)]
+#[serde(rename_all = "camelCase")]
+#[ts(export)]
+pub struct SignupChallenge {
+    pub id: String,
+    pub difficulty: u8,
+    #[ts(type = "any")]
+    pub expires_at: SystemTime,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, TS)]
+#[serde(rename_all = "camelCase")]
+#[ts(export)]
+pub struct SignupChallengeSolution {
+    pub challenge_id: String,
+    pub nonce: String,
+}
+
 #[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, TS)]
 #[serde(rename_all = "camelCase")]
 #[ts(export)]
@@ -151,6 +178,30 @@ pub struct LinkedAccount {
     pub strategy: String,
 }
 
+/// Whether failed logins are counted for a username or an IP address
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq, TS)]
+#[serde(rename_all = "camelCase")]
+#[ts(export)]
+pub enum LoginCounterKind {
+    Username,
+    Address,
+}
+
+/// Recent failed logins for a username or IP address
+#[derive(TS, Serialize, Deserialize, Clone, Debug)]
+#[serde(rename_all = "camelCase")]
+#[ts(export)]
+pub struct LoginFailureCounter {
+    pub kind: LoginCounterKind,
+    pub key: String,
+    #[ts(type = "any")] // FIXME
+    pub failures: Vec<SystemTime>,
+    /// Number of times the username/address has been locked out recently
+    pub lockouts: u32,
+    #[ts(optional, type = "any")] // FIXME
+    pub locked_until: Option<SystemTime>,
+}
+
 #[derive(TS, Serialize, Deserialize, Clone)]
 #[serde(rename_all = "camelCase")]
 #[ts(export)]
@@ -159,6 +210,329 @@ pub struct BannedAccount {
     pub email: String,
     #[ts(type = "any")] // FIXME
     pub banned_at: SystemTime,
+    /// Moderator who banned the account (unknown for older bans)
+    #[ts(optional)]
+    pub moderator: Option<String>,
+    #[ts(optional)]
+    pub reason: Option<String>,
+    /// Time at which the ban is lifted. Bans without an expiry are permanent.
+    #[ts(optional, type = "any")] // FIXME
+    pub expires_at: Option<SystemTime>,
+}
+
+#[derive(TS, Serialize, Deserialize, Clone, Debug, Default)]
+#[serde(rename_all = "camelCase")]
+#[ts(export)]
+pub struct BanData {
+    #[ts(optional)]
+    pub reason: Option<String>,
+    /// Duration of the ban. The ban is permanent if omitted.
+    #[ts(optional)]
+    pub expires_in_hours: Option<u32>,
+}
+
+#[derive(TS, Serialize, Deserialize, Clone, Debug)]
+#[serde(rename_all = "camelCase")]
+#[ts(export)]
+pub struct BanAddressData {
+    /// IP address or CIDR range (eg, 192.168.0.0/16)
+    pub addr: String,
+    #[ts(optional)]
+    pub reason: Option<String>,
+    /// Duration of the ban. The ban is permanent if omitted.
+    #[ts(optional)]
+    pub expires_in_hours: Option<u32>,
+}
+
+/// A ban on an IP address (or range of addresses)
+#[derive(TS, Serialize, Deserialize, Clone, Debug)]
+#[serde(rename_all = "camelCase")]
+#[ts(export)]
+pub struct BannedAddress {
+    pub id: String,
+    /// Banned address in CIDR notation
+    pub addr: String,
+    pub moderator: String,
+    #[ts(optional)]
+    pub reason: Option<String>,
+    #[ts(type = "any")] // FIXME
+    pub banned_at: SystemTime,
+    #[ts(optional, type = "any")] // FIXME
+    pub expires_at: Option<SystemTime>,
+}
+
+/// What to do when a client's address is on an IP reputation list
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq, TS)]
+#[serde(rename_all = "camelCase")]
+#[ts(export)]
+pub enum IpReputationAction {
+    BlockSignup,
+    BlockLogin,
+    /// Allow the request but log it for review
+    Flag,
+}
+
+/// A list (of an IP reputation source) containing the tested address
+#[derive(TS, Serialize, Deserialize, Clone, Debug)]
+#[serde(rename_all = "camelCase")]
+#[ts(export)]
+pub struct IpReputationMatch {
+    pub source: String,
+    /// Matching entry in CIDR notation
+    pub range: String,
+    pub actions: Vec<IpReputationAction>,
+}
+
+#[derive(TS, Serialize, Deserialize, Clone, Debug)]
+#[serde(rename_all = "camelCase")]
+#[ts(export)]
+pub struct IpReputationReport {
+    pub addr: String,
+    pub matches: Vec<IpReputationMatch>,
+}
+
+#[derive(TS, Serialize, Deserialize, Clone, Debug)]
+#[serde(rename_all = "camelCase")]
+#[ts(export)]
+pub struct IpReputationEntryData {
+    /// IP address or CIDR range (eg, 192.168.0.0/16)
+    pub addr: String,
+    #[ts(optional)]
+    pub reason: Option<String>,
+}
+
+/// An entry added by an admin to an (admin-managed) IP reputation source
+#[derive(TS, Serialize, Deserialize, Clone, Debug)]
+#[serde(rename_all = "camelCase")]
+#[ts(export)]
+pub struct IpReputationEntry {
+    pub id: String,
+    pub source: String,
+    /// Address in CIDR notation
+    pub addr: String,
+    #[ts(optional)]
+    pub reason: Option<String>,
+    pub created_by: String,
+    #[ts(type = "any")] // FIXME
+    pub created_at: SystemTime,
+}
+
+/// Status of the cleanup of the data (projects, libraries, friends, etc)
+/// owned by a deleted account
+#[derive(TS, Serialize, Deserialize, Clone, Debug)]
+#[serde(rename_all = "camelCase")]
+#[ts(export)]
+pub struct AccountDeletion {
+    pub username: String,
+    pub state: AccountDeletionState,
+    pub progress: AccountDeletionProgress,
+    #[ts(optional)]
+    pub error: Option<String>,
+    #[ts(type = "any")] // FIXME
+    pub started_at: SystemTime,
+    #[ts(type = "any")] // FIXME
+    pub updated_at: SystemTime,
+}
+
+#[derive(TS, Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+#[serde(rename_all = "camelCase")]
+#[ts(export)]
+pub enum AccountDeletionState {
+    InProgress,
+    Complete,
+    Failed,
+}
+
+#[derive(TS, Serialize, Deserialize, Clone, Debug, Default, PartialEq, Eq)]
+#[serde(rename_all = "camelCase")]
+#[ts(export)]
+pub struct AccountDeletionProgress {
+    /// Number of projects owned by the user
+    pub projects_total: u32,
+    pub projects_deleted: u32,
+    /// Number of other projects the user was removed from as a collaborator
+    pub collaborations_removed: u32,
+    pub libraries_deleted: u32,
+    pub friends_removed: u32,
+    /// Number of collaboration and occupant invites sent to/from the user
+    pub invites_deleted: u32,
+    /// Number of OAuth tokens, access tokens, etc, revoked
+    pub tokens_revoked: u32,
+    /// Number of OAuth clients registered by the user
+    #[serde(default)]
+    pub clients_deleted: u32,
+}
+
+/// An active login session for a user (eg, on a given device)
+#[derive(Serialize, Deserialize, Clone, Debug, TS)]
+#[serde(rename_all = "camelCase")]
+#[ts(export)]
+pub struct UserSession {
+    pub id: String,
+    /// User agent of the client which logged in
+    #[ts(optional)]
+    pub device: Option<String>,
+    #[ts(optional)]
+    pub ip: Option<String>,
+    #[ts(type = "any")] // FIXME
+    pub created_at: SystemTime,
+    #[ts(type = "any")] // FIXME
+    pub last_seen: SystemTime,
+}
+
+/// Permissions granted to a personal access token
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, TS)]
+#[serde(rename_all = "camelCase")]
+#[ts(export)]
+pub enum TokenScope {
+    /// Read-only access (ie, GET requests)
+    Read,
+    /// Permission to make changes on behalf of the user
+    Write,
+}
+
+impl FromStr for TokenScope {
+    type Err = TokenScopeError;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "read" => Ok(TokenScope::Read),
+            "write" => Ok(TokenScope::Write),
+            _ => Err(TokenScopeError),
+        }
+    }
+}
+
+#[derive(Debug, Display, Error, TS)]
+#[display(fmt = "Unable to parse token scope. Expected read or write.")]
+#[ts(export)]
+pub struct TokenScopeError;
+
+/// A personal access token (without the token itself)
+#[derive(Serialize, Deserialize, Clone, Debug, TS)]
+#[serde(rename_all = "camelCase")]
+#[ts(export)]
+pub struct AccessToken {
+    pub id: String,
+    pub name: String,
+    pub scopes: Vec<TokenScope>,
+    #[ts(type = "any")] // FIXME
+    pub created_at: SystemTime,
+    #[ts(type = "any")] // FIXME
+    #[ts(optional)]
+    pub expires_at: Option<SystemTime>,
+    #[ts(type = "any")] // FIXME
+    #[ts(optional)]
+    pub last_used_at: Option<SystemTime>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, TS)]
+#[serde(rename_all = "camelCase")]
+#[ts(export)]
+pub struct CreateAccessTokenData {
+    pub name: String,
+    pub scopes: Vec<TokenScope>,
+    /// Number of days until the token expires. Tokens without an expiration
+    /// are valid until revoked.
+    #[ts(optional)]
+    pub expires_in_days: Option<u32>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, TS)]
+#[serde(rename_all = "camelCase")]
+#[ts(export)]
+pub struct CreatedAccessToken {
+    /// The token to use in the Authorization header. This is only available
+    /// when the token is created.
+    pub secret: String,
+    pub token: AccessToken,
+}
+
+/// Privileged or administrative action recorded in the audit log
+#[derive(Serialize, Deserialize, Copy, Clone, Debug, PartialEq, Eq, TS)]
+#[serde(rename_all = "camelCase")]
+#[ts(export)]
+pub enum AuditAction {
+    BanUser,
+    UnbanUser,
+    SetUserRole,
+    ResetTwoFactor,
+    SetProjectState,
+    SetLibraryState,
+    AuthorizeHost,
+    UnauthorizeHost,
+    RotateHostSecret,
+    DeleteGroup,
+    BanAddress,
+    UnbanAddress,
+    AddIpReputationEntry,
+    RemoveIpReputationEntry,
+}
+
+impl FromStr for AuditAction {
+    type Err = AuditActionError;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "banUser" => Ok(AuditAction::BanUser),
+            "unbanUser" => Ok(AuditAction::UnbanUser),
+            "setUserRole" => Ok(AuditAction::SetUserRole),
+            "resetTwoFactor" => Ok(AuditAction::ResetTwoFactor),
+            "setProjectState" => Ok(AuditAction::SetProjectState),
+            "setLibraryState" => Ok(AuditAction::SetLibraryState),
+            "authorizeHost" => Ok(AuditAction::AuthorizeHost),
+            "unauthorizeHost" => Ok(AuditAction::UnauthorizeHost),
+            "rotateHostSecret" => Ok(AuditAction::RotateHostSecret),
+            "deleteGroup" => Ok(AuditAction::DeleteGroup),
+            "banAddress" => Ok(AuditAction::BanAddress),
+            "unbanAddress" => Ok(AuditAction::UnbanAddress),
+            "addIpReputationEntry" => Ok(AuditAction::AddIpReputationEntry),
+            "removeIpReputationEntry" => Ok(AuditAction::RemoveIpReputationEntry),
+            _ => Err(AuditActionError),
+        }
+    }
+}
+
+#[derive(Debug, Display, Error, TS)]
+#[display(
+    fmt = "Unable to parse audit action. Expected banUser, unbanUser, setUserRole, resetTwoFactor, setProjectState, setLibraryState, authorizeHost, unauthorizeHost, rotateHostSecret, deleteGroup, banAddress, unbanAddress, addIpReputationEntry, or removeIpReputationEntry."
+)]
+#[ts(export)]
+pub struct AuditActionError;
+
+#[derive(Serialize, Deserialize, Clone, Debug, TS)]
+#[serde(rename_all = "camelCase")]
+#[ts(export)]
+pub struct AuditLogEntry {
+    pub id: String,
+    /// Username of the user who performed the action
+    pub actor: String,
+    pub action: AuditAction,
+    /// The affected user, project, library, etc
+    pub target: String,
+    #[ts(type = "any")]
+    #[ts(optional)]
+    pub before: Option<Value>,
+    #[ts(type = "any")]
+    #[ts(optional)]
+    pub after: Option<Value>,
+    #[ts(type = "any")] // FIXME
+    pub created_at: SystemTime,
+}
+
+/// Filters for the audit log. Entries are returned newest first.
+#[derive(Serialize, Deserialize, Clone, Debug, Default, TS)]
+#[serde(rename_all = "camelCase")]
+#[ts(export)]
+pub struct AuditLogQuery {
+    #[ts(optional)]
+    pub actor: Option<String>,
+    #[ts(optional)]
+    pub target: Option<String>,
+    #[ts(optional)]
+    pub action: Option<AuditAction>,
+    #[ts(optional)]
+    pub page: Option<u64>,
+    #[ts(optional)]
+    pub limit: Option<i64>,
 }
 
 #[derive(Serialize, Deserialize, Debug, TS)]
@@ -167,7 +541,25 @@ pub struct BannedAccount {
 pub struct LoginRequest {
     pub credentials: Credentials,
     #[ts(optional)]
-    pub client_id: Option<ClientId>, // TODO: add a secret token for the client?
+    pub client_id: Option<ClientId>,
+    /// Secret issued with the client ID (required if the client ID is set)
+    #[ts(optional)]
+    pub client_secret: Option<String>,
+    /// TOTP (or recovery) code. Required if the user has enabled two-factor authentication.
+    #[ts(optional)]
+    pub two_factor_code: Option<String>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, TS)]
+#[serde(rename_all = "camelCase")]
+#[ts(export)]
+pub struct TwoFactorEnrollment {
+    /// Base32-encoded secret for manual entry into an authenticator app
+    pub secret: String,
+    /// otpauth:// URI to be encoded as a QR code
+    pub provisioning_uri: String,
+    /// One-time recovery codes. These cannot be retrieved again.
+    pub recovery_codes: Vec<String>,
 }
 
 #[derive(Deserialize, Serialize, Debug, Clone, TS)]
@@ -677,8 +1069,67 @@ pub struct AuthorizedServiceHost {
     pub url: String,
     pub id: String,
     pub visibility: ServiceHostScope,
+    /// Capabilities granted to the host
+    #[serde(default)]
+    pub capabilities: Vec<HostCapability>,
+}
+
+/// Permissions which can be granted to an authorized service host
+#[derive(Deserialize, Serialize, Debug, Clone, Copy, PartialEq, Eq, Hash, TS)]
+#[serde(rename_all = "camelCase")]
+#[ts(export)]
+pub enum HostCapability {
+    /// View user accounts (and groups) and the users of connected clients
+    ViewUsers,
+    /// View any project
+    ViewProjects,
+    /// Send messages to any client
+    SendMessages,
+    /// Read the service settings of users and groups
+    ReadServiceSettings,
+    /// Create and manage any OAuth client
+    ManageOAuthClients,
+}
+
+impl HostCapability {
+    pub fn all() -> Vec<HostCapability> {
+        vec![
+            HostCapability::ViewUsers,
+            HostCapability::ViewProjects,
+            HostCapability::SendMessages,
+            HostCapability::ReadServiceSettings,
+            HostCapability::ManageOAuthClients,
+        ]
+    }
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            HostCapability::ViewUsers => "viewUsers",
+            HostCapability::ViewProjects => "viewProjects",
+            HostCapability::SendMessages => "sendMessages",
+            HostCapability::ReadServiceSettings => "readServiceSettings",
+            HostCapability::ManageOAuthClients => "manageOAuthClients",
+        }
+    }
 }
 
+impl FromStr for HostCapability {
+    type Err = HostCapabilityError;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        HostCapability::all()
+            .into_iter()
+            .find(|capability| capability.as_str() == s)
+            .ok_or(HostCapabilityError)
+    }
+}
+
+#[derive(Debug, Display, Error, TS)]
+#[display(
+    fmt = "Unable to parse host capability. Expected viewUsers, viewProjects, sendMessages, readServiceSettings, or manageOAuthClients."
+)]
+#[ts(export)]
+pub struct HostCapabilityError;
+
 #[derive(Deserialize, Serialize, Debug, Clone, TS)]
 #[serde(rename_all = "camelCase")]
 #[ts(export)]
@@ -789,10 +1240,28 @@ pub struct MagicLinkLoginData {
     pub username: String,
     #[ts(optional)]
     pub client_id: Option<ClientId>,
+    /// Secret issued with the client ID (required if the client ID is set)
+    #[ts(optional)]
+    pub client_secret: Option<String>,
     #[ts(optional)]
     pub redirect_uri: Option<String>,
 }
 
+/// Login using the numeric code sent with a magic link (instead of following the link)
+#[derive(Serialize, Deserialize, Clone, Debug, TS)]
+#[serde(rename_all = "camelCase")]
+#[ts(export)]
+pub struct MagicLinkCodeLoginData {
+    pub email: String,
+    pub username: String,
+    pub code: String,
+    #[ts(optional)]
+    pub client_id: Option<ClientId>,
+    /// Secret issued with the client ID (required if the client ID is set)
+    #[ts(optional)]
+    pub client_secret: Option<String>,
+}
+
 #[derive(Serialize, Deserialize, Clone, Debug, TS)]
 #[serde(rename_all = "camelCase")]
 #[ts(export)]
@@ -844,6 +1313,18 @@ mod tests {
         assert_eq!(&role_str, "\"user\"");
     }
 
+    #[test]
+    fn serialize_host_capabilities_as_strings() {
+        for capability in HostCapability::all() {
+            let capability_str = serde_json::to_string(&capability).unwrap();
+            assert_eq!(capability_str, format!("\"{}\"", capability.as_str()));
+            assert_eq!(
+                capability.as_str().parse::<HostCapability>().unwrap(),
+                capability
+            );
+        }
+    }
+
     #[test]
     fn deserialize_app_id_lowercase() {
         let app_id_str = String::from("\"NetsBlox\"");
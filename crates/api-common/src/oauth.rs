@@ -1,6 +1,6 @@
-use std::time::SystemTime;
+use std::{str::FromStr, time::SystemTime};
 
-use derive_more::{Display, FromStr};
+use derive_more::{Display, Error, FromStr};
 use serde::{Deserialize, Serialize};
 
 #[derive(Deserialize, Serialize, Clone, Debug, PartialEq, Eq, Display, Hash, FromStr)]
@@ -16,21 +16,167 @@ impl ClientId {
     }
 }
 
+/// Permissions which can be requested by OAuth clients (using the space-delimited
+/// `scope` parameter) and granted by the user.
+#[derive(Deserialize, Serialize, Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum Scope {
+    #[serde(rename = "user:read")]
+    ReadUser,
+    #[serde(rename = "projects:read")]
+    ReadProjects,
+    #[serde(rename = "projects:write")]
+    WriteProjects,
+    #[serde(rename = "libraries:read")]
+    ReadLibraries,
+    #[serde(rename = "libraries:write")]
+    WriteLibraries,
+    #[serde(rename = "alexa:read")]
+    ViewAlexaSkills,
+    #[serde(rename = "blocks:execute")]
+    ExecuteBlocks,
+    /// Sign in using OpenID Connect (an ID token is issued along with the access token)
+    #[serde(rename = "openid")]
+    OpenId,
+    #[serde(rename = "email")]
+    Email,
+}
+
+impl Scope {
+    /// Scopes granted to clients and tokens created before scopes could be requested
+    pub fn legacy() -> Vec<Scope> {
+        vec![Scope::ViewAlexaSkills, Scope::ExecuteBlocks]
+    }
+
+    pub fn all() -> Vec<Scope> {
+        vec![
+            Scope::ReadUser,
+            Scope::ReadProjects,
+            Scope::WriteProjects,
+            Scope::ReadLibraries,
+            Scope::WriteLibraries,
+            Scope::ViewAlexaSkills,
+            Scope::ExecuteBlocks,
+            Scope::OpenId,
+            Scope::Email,
+        ]
+    }
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Scope::ReadUser => "user:read",
+            Scope::ReadProjects => "projects:read",
+            Scope::WriteProjects => "projects:write",
+            Scope::ReadLibraries => "libraries:read",
+            Scope::WriteLibraries => "libraries:write",
+            Scope::ViewAlexaSkills => "alexa:read",
+            Scope::ExecuteBlocks => "blocks:execute",
+            Scope::OpenId => "openid",
+            Scope::Email => "email",
+        }
+    }
+
+    /// Description of the scope shown to the user when authorizing a client
+    pub fn description(&self) -> &'static str {
+        match self {
+            Scope::ReadUser => "View your username and email address",
+            Scope::ReadProjects => "View your projects",
+            Scope::WriteProjects => "Create and edit your projects",
+            Scope::ReadLibraries => "View your libraries",
+            Scope::WriteLibraries => "Create and edit your libraries",
+            Scope::ViewAlexaSkills => "View created Alexa skills",
+            Scope::ExecuteBlocks => "Execute blocks on your behalf",
+            Scope::OpenId => "Sign you in with your NetsBlox account",
+            Scope::Email => "View your email address",
+        }
+    }
+
+    /// Parse a space-delimited list of scopes (as used in the `scope` parameter).
+    /// Duplicate scopes are ignored.
+    pub fn parse_list(scopes: &str) -> Result<Vec<Scope>, ScopeError> {
+        let mut parsed: Vec<Scope> = Vec::new();
+        for scope in scopes.split_whitespace() {
+            let scope = scope.parse()?;
+            if !parsed.contains(&scope) {
+                parsed.push(scope);
+            }
+        }
+
+        Ok(parsed)
+    }
+
+    /// Format scopes as a space-delimited list (as used in the `scope` parameter)
+    pub fn format_list(scopes: &[Scope]) -> String {
+        scopes
+            .iter()
+            .map(|scope| scope.as_str())
+            .collect::<Vec<_>>()
+            .join(" ")
+    }
+}
+
+impl FromStr for Scope {
+    type Err = ScopeError;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "user:read" => Ok(Scope::ReadUser),
+            "projects:read" => Ok(Scope::ReadProjects),
+            "projects:write" => Ok(Scope::WriteProjects),
+            "libraries:read" => Ok(Scope::ReadLibraries),
+            "libraries:write" => Ok(Scope::WriteLibraries),
+            "alexa:read" => Ok(Scope::ViewAlexaSkills),
+            "blocks:execute" => Ok(Scope::ExecuteBlocks),
+            "openid" => Ok(Scope::OpenId),
+            "email" => Ok(Scope::Email),
+            _ => Err(ScopeError),
+        }
+    }
+}
+
+#[derive(Debug, Display, Error)]
+#[display(fmt = "Unable to parse OAuth scope.")]
+pub struct ScopeError;
+
 #[derive(Deserialize, Serialize, Clone, Debug, Display)]
+#[display(fmt = "{}", name)]
+#[serde(rename_all = "camelCase")]
 pub struct CreateClientData {
     pub name: String,
+    /// Scopes the client is allowed to request
+    #[serde(default)]
+    pub allowed_scopes: Vec<Scope>,
+    /// Public clients (eg, mobile apps) cannot keep a secret and must use PKCE instead
+    #[serde(default)]
+    pub public: bool,
+    /// Redirect URIs allowed in authorization requests (must match exactly)
+    #[serde(default)]
+    pub redirect_uris: Vec<String>,
+}
+
+#[derive(Deserialize, Serialize, Clone, Debug, Default)]
+#[serde(rename_all = "camelCase")]
+pub struct UpdateClientData {
+    pub name: Option<String>,
+    pub allowed_scopes: Option<Vec<Scope>>,
+    pub redirect_uris: Option<Vec<String>>,
 }
 
 #[derive(Deserialize, Serialize, Clone, Debug)]
 pub struct CreatedClientData {
     pub id: ClientId,
-    pub secret: String,
+    /// Client secret (not set for public clients)
+    pub secret: Option<String>,
 }
 
 #[derive(Deserialize, Serialize, Clone, Debug)]
+#[serde(rename_all = "camelCase")]
 pub struct Client {
     pub id: ClientId,
     pub name: String,
+    /// User who registered the client (not set for clients registered by an admin)
+    pub owner: Option<String>,
+    pub allowed_scopes: Vec<Scope>,
+    pub redirect_uris: Vec<String>,
+    pub public: bool,
 }
 
 #[derive(Deserialize, Serialize, Clone, Debug, FromStr)]
@@ -46,16 +192,6 @@ impl CodeId {
     }
 }
 
-#[derive(Deserialize, Serialize, Clone, Debug)]
-#[serde(rename_all = "camelCase")]
-pub struct Code {
-    pub id: CodeId,
-    pub username: String,
-    pub client_id: ClientId,
-    pub redirect_uri: String,
-    pub created_at: SystemTime,
-}
-
 #[derive(Deserialize, Serialize, Clone, Debug, Display, FromStr)]
 pub struct TokenId(String);
 
@@ -75,12 +211,96 @@ pub struct Token {
     pub id: TokenId,
     pub client_id: ClientId,
     pub username: String,
+    pub scopes: Vec<Scope>,
     pub created_at: SystemTime,
+    /// Tokens created before token lifetimes were introduced do not expire
+    pub expires_at: Option<SystemTime>,
 }
 
+/// OAuth client which has been authorized to access a user's account
 #[derive(Deserialize, Serialize, Clone, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct AuthorizedClient {
+    pub id: ClientId,
+    pub name: String,
+    /// Scopes granted to the client (across all its tokens)
+    pub scopes: Vec<Scope>,
+    /// Number of active access tokens held by the client
+    pub token_count: u32,
+    /// Time the most recent token was issued
+    pub last_authorized_at: SystemTime,
+}
+
+/// Parameters for the token revocation endpoint (RFC 7009)
+#[derive(Deserialize, Serialize, Clone, Debug, Default)]
+pub struct RevokeTokenParams {
+    pub token: String,
+    /// Either "access_token" or "refresh_token". Both types are checked regardless.
+    pub token_type_hint: Option<String>,
+    pub client_id: Option<ClientId>,
+    pub client_secret: Option<String>,
+}
+
+/// Parameters for the token endpoint (RFC 6749). Access tokens are created by
+/// exchanging an authorization code (`grant_type=authorization_code`) or a
+/// refresh token (`grant_type=refresh_token`).
+#[derive(Deserialize, Serialize, Clone, Debug, Default)]
 pub struct CreateTokenParams {
+    pub grant_type: Option<String>,
     pub code: Option<String>,
     pub redirect_uri: Option<String>,
-    pub grant_type: Option<String>,
+    /// PKCE code verifier (RFC 7636)
+    pub code_verifier: Option<String>,
+    pub refresh_token: Option<String>,
+    /// Space-delimited scopes to request when refreshing a token. These must
+    /// have been granted originally.
+    pub scope: Option<String>,
+    pub client_id: Option<ClientId>,
+    pub client_secret: Option<String>,
+}
+
+/// Successful response from the token endpoint (RFC 6749 section 5.1)
+#[derive(Deserialize, Serialize, Clone, Debug)]
+pub struct TokenResponse {
+    pub access_token: TokenId,
+    pub token_type: String,
+    /// Lifetime of the access token in seconds
+    pub expires_in: u64,
+    pub refresh_token: String,
+    /// Space-delimited scopes granted to the token
+    pub scope: String,
+    /// Signed OpenID Connect ID token (if the `openid` scope was granted)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub id_token: Option<String>,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_scope_list() {
+        let scopes = Scope::parse_list("user:read  projects:read user:read").unwrap();
+        assert_eq!(scopes, vec![Scope::ReadUser, Scope::ReadProjects]);
+    }
+
+    #[test]
+    fn test_format_scope_list() {
+        let scopes = [Scope::ReadUser, Scope::ExecuteBlocks];
+        assert_eq!(Scope::format_list(&scopes), "user:read blocks:execute");
+    }
+
+    #[test]
+    fn test_parse_scope_list_invalid() {
+        assert!(Scope::parse_list("user:read admin").is_err());
+    }
+
+    #[test]
+    fn test_scope_str_roundtrip() {
+        for scope in Scope::all() {
+            let json = serde_json::to_string(&scope).unwrap();
+            assert_eq!(json, format!("\"{}\"", scope.as_str()));
+            assert_eq!(scope.as_str().parse::<Scope>().unwrap(), scope);
+        }
+    }
 }
@@ -6,6 +6,8 @@ pub enum Error {
     BadRequestError(String),
     #[display(fmt = "Login required.")]
     LoginRequiredError,
+    #[display(fmt = "Two-factor authentication code required.")]
+    TwoFactorRequiredError,
     #[display(fmt = "Unauthorized: {}", _0)]
     PermissionsError(String),
     #[display(fmt = "{}", _0)]
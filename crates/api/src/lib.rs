@@ -4,14 +4,15 @@ pub mod error;
 use crate::common::*;
 use futures_util::SinkExt;
 use netsblox_api_common::{
-    CreateGroupData, CreateMagicLinkData, ServiceHostScope, UpdateGroupData, UpdateUserData,
+    CreateGroupData, CreateMagicLinkData, MagicLinkCodeLoginData, ServiceHostScope,
+    UpdateGroupData, UpdateUserData,
 };
 use reqwest::{self, Method, RequestBuilder, Response};
 use serde::{Deserialize, Serialize};
 pub use serde_json;
 use serde_json::{json, Value};
 use tokio::net::TcpStream;
-use tokio_tungstenite::tungstenite::Message;
+use tokio_tungstenite::tungstenite::{client::IntoClientRequest, Message};
 use tokio_tungstenite::{connect_async, MaybeTlsStream, WebSocketStream};
 
 #[derive(Serialize, Deserialize, Debug, Clone)]
@@ -20,6 +21,12 @@ pub struct Config {
     pub url: String,
     pub token: Option<String>,
     pub username: Option<String>,
+    /// Personal access token to use instead of the session token (if set)
+    #[serde(default)]
+    pub access_token: Option<String>,
+    /// CSRF token bound to the session (required for requests which make changes)
+    #[serde(default)]
+    pub csrf_token: Option<String>,
 }
 
 impl Default for Config {
@@ -28,6 +35,8 @@ impl Default for Config {
             app_id: None,
             username: None,
             token: None,
+            access_token: None,
+            csrf_token: None,
             url: "https://cloud.netsblox.org".to_owned(),
         }
     }
@@ -37,10 +46,16 @@ async fn check_response(response: Response) -> Result<Response, error::Error> {
     let status_code = response.status().as_u16();
     let is_error = status_code > 399;
     if is_error {
+        let needs_two_factor = response
+            .headers()
+            .get(reqwest::header::WWW_AUTHENTICATE)
+            .map(|value| value == "TOTP")
+            .unwrap_or(false);
         let msg = response.text().await.map_err(error::Error::RequestError)?;
 
         match status_code {
             400 => Err(error::Error::BadRequestError(msg)),
+            401 if needs_two_factor => Err(error::Error::TwoFactorRequiredError),
             401 => Err(error::Error::LoginRequiredError),
             403 => Err(error::Error::PermissionsError(msg)),
             404 => Err(error::Error::NotFoundError(msg)),
@@ -53,7 +68,9 @@ async fn check_response(response: Response) -> Result<Response, error::Error> {
 }
 
 pub type Token = String;
-pub async fn login(mut cfg: Config, credentials: &LoginRequest) -> Result<Config, error::Error> {
+/// Login to NetsBlox. If the user has enabled two-factor authentication, this will
+/// return `Error::TwoFactorRequiredError` unless `two_factor_code` is set on the request.
+pub async fn login(cfg: Config, credentials: &LoginRequest) -> Result<Config, error::Error> {
     let client = reqwest::Client::new();
     let response = client
         .post(format!("{}/users/login", cfg.url))
@@ -62,6 +79,30 @@ pub async fn login(mut cfg: Config, credentials: &LoginRequest) -> Result<Config
         .await
         .map_err(error::Error::RequestError)?;
 
+    finish_login(cfg, client, response).await
+}
+
+/// Login using the code sent with a magic link
+pub async fn login_with_magic_link_code(
+    cfg: Config,
+    data: &MagicLinkCodeLoginData,
+) -> Result<Config, error::Error> {
+    let client = reqwest::Client::new();
+    let response = client
+        .post(format!("{}/magic-links/login/code", cfg.url))
+        .json(&data)
+        .send()
+        .await
+        .map_err(error::Error::RequestError)?;
+
+    finish_login(cfg, client, response).await
+}
+
+async fn finish_login(
+    mut cfg: Config,
+    client: reqwest::Client,
+    response: Response,
+) -> Result<Config, error::Error> {
     let response = check_response(response).await?;
     let cookie = response
         .cookies()
@@ -72,8 +113,20 @@ pub async fn login(mut cfg: Config, credentials: &LoginRequest) -> Result<Config
     let token = cookie.value().to_owned();
 
     let user = response.json::<User>().await.unwrap();
+
+    // Requests which make changes must include the CSRF token for the session
+    let response = client
+        .get(format!("{}/configuration", cfg.url))
+        .header("Cookie", format!("netsblox={}", token))
+        .send()
+        .await
+        .map_err(error::Error::RequestError)?;
+    let response = check_response(response).await?;
+    let config = response.json::<ClientConfig>().await.unwrap();
+
     cfg.username = Some(user.username);
     cfg.token = Some(token);
+    cfg.csrf_token = Some(config.csrf_token);
     Ok(cfg)
 }
 
@@ -100,9 +153,17 @@ impl Client {
         let client = reqwest::Client::new();
         let empty = "".to_owned();
         let token = self.cfg.token.as_ref().unwrap_or(&empty);
-        client
-            .request(method, format!("{}{}", self.cfg.url, path))
-            .header("Cookie", format!("netsblox={}", token))
+        let builder = client.request(method, format!("{}{}", self.cfg.url, path));
+
+        if let Some(access_token) = &self.cfg.access_token {
+            builder.bearer_auth(access_token)
+        } else if let Some(csrf_token) = &self.cfg.csrf_token {
+            builder
+                .header("Cookie", format!("netsblox={}", token))
+                .header("X-CSRF-Token", csrf_token)
+        } else {
+            builder.header("Cookie", format!("netsblox={}", token))
+        }
     }
 
     // User management
@@ -173,6 +234,34 @@ impl Client {
         Ok(())
     }
 
+    /// Download an archive (zip) of all the data stored about the given user
+    pub async fn export_user_data(&self, username: &str) -> Result<Vec<u8>, error::Error> {
+        let response = self
+            .request(Method::GET, &format!("/users/{}/export", username))
+            .send()
+            .await
+            .map_err(error::Error::RequestError)?;
+
+        let response = check_response(response).await?;
+        let archive = response.bytes().await.map_err(error::Error::RequestError)?;
+        Ok(archive.to_vec())
+    }
+
+    /// View the progress of the removal of the data owned by a deleted user
+    pub async fn view_account_deletion(
+        &self,
+        username: &str,
+    ) -> Result<AccountDeletion, error::Error> {
+        let response = self
+            .request(Method::GET, &format!("/users/{}/deletion", username))
+            .send()
+            .await
+            .map_err(error::Error::RequestError)?;
+
+        let response = check_response(response).await?;
+        Ok(response.json::<AccountDeletion>().await.unwrap())
+    }
+
     pub async fn view_user(&self, username: &str) -> Result<User, error::Error> {
         let response = self
             .request(Method::GET, &format!("/users/{}", username))
@@ -246,9 +335,166 @@ impl Client {
         Ok(())
     }
 
-    pub async fn ban_user(&self, username: &str) -> Result<BannedAccount, error::Error> {
+    pub async fn list_sessions(&self, username: &str) -> Result<Vec<UserSession>, error::Error> {
+        let response = self
+            .request(Method::GET, &format!("/users/{}/sessions", username))
+            .send()
+            .await
+            .map_err(error::Error::RequestError)?;
+
+        let response = check_response(response).await?;
+        Ok(response.json::<Vec<UserSession>>().await.unwrap())
+    }
+
+    pub async fn revoke_session(
+        &self,
+        username: &str,
+        id: &str,
+    ) -> Result<UserSession, error::Error> {
+        let response = self
+            .request(
+                Method::DELETE,
+                &format!("/users/{}/sessions/{}", username, id),
+            )
+            .send()
+            .await
+            .map_err(error::Error::RequestError)?;
+
+        let response = check_response(response).await?;
+        Ok(response.json::<UserSession>().await.unwrap())
+    }
+
+    /// Revoke all sessions for the given user (ie, log out everywhere)
+    pub async fn revoke_all_sessions(&self, username: &str) -> Result<(), error::Error> {
+        let response = self
+            .request(Method::DELETE, &format!("/users/{}/sessions", username))
+            .send()
+            .await
+            .map_err(error::Error::RequestError)?;
+
+        check_response(response).await?;
+        Ok(())
+    }
+
+    pub async fn list_access_tokens(
+        &self,
+        username: &str,
+    ) -> Result<Vec<AccessToken>, error::Error> {
+        let response = self
+            .request(Method::GET, &format!("/users/{}/tokens", username))
+            .send()
+            .await
+            .map_err(error::Error::RequestError)?;
+
+        let response = check_response(response).await?;
+        Ok(response.json::<Vec<AccessToken>>().await.unwrap())
+    }
+
+    /// Create a personal access token. The secret is only available in the
+    /// response.
+    pub async fn create_access_token(
+        &self,
+        username: &str,
+        data: &CreateAccessTokenData,
+    ) -> Result<CreatedAccessToken, error::Error> {
+        let response = self
+            .request(Method::POST, &format!("/users/{}/tokens", username))
+            .json(data)
+            .send()
+            .await
+            .map_err(error::Error::RequestError)?;
+
+        let response = check_response(response).await?;
+        Ok(response.json::<CreatedAccessToken>().await.unwrap())
+    }
+
+    pub async fn revoke_access_token(
+        &self,
+        username: &str,
+        id: &str,
+    ) -> Result<AccessToken, error::Error> {
+        let response = self
+            .request(
+                Method::DELETE,
+                &format!("/users/{}/tokens/{}", username, id),
+            )
+            .send()
+            .await
+            .map_err(error::Error::RequestError)?;
+
+        let response = check_response(response).await?;
+        Ok(response.json::<AccessToken>().await.unwrap())
+    }
+
+    /// Start enrolling the given user in two-factor authentication. Enrollment
+    /// must be confirmed with a code from the authenticator app.
+    pub async fn enroll_two_factor(
+        &self,
+        username: &str,
+    ) -> Result<TwoFactorEnrollment, error::Error> {
+        let response = self
+            .request(Method::POST, &format!("/users/{}/two-factor", username))
+            .send()
+            .await
+            .map_err(error::Error::RequestError)?;
+
+        let response = check_response(response).await?;
+        Ok(response.json::<TwoFactorEnrollment>().await.unwrap())
+    }
+
+    pub async fn confirm_two_factor(&self, username: &str, code: &str) -> Result<(), error::Error> {
+        let response = self
+            .request(
+                Method::POST,
+                &format!("/users/{}/two-factor/confirm", username),
+            )
+            .json(&code)
+            .send()
+            .await
+            .map_err(error::Error::RequestError)?;
+
+        check_response(response).await?;
+        Ok(())
+    }
+
+    pub async fn disable_two_factor(&self, username: &str, code: &str) -> Result<(), error::Error> {
+        let response = self
+            .request(
+                Method::POST,
+                &format!("/users/{}/two-factor/disable", username),
+            )
+            .json(&code)
+            .send()
+            .await
+            .map_err(error::Error::RequestError)?;
+
+        check_response(response).await?;
+        Ok(())
+    }
+
+    /// Remove two-factor authentication from another user's account (admin only)
+    pub async fn reset_two_factor(&self, username: &str) -> Result<User, error::Error> {
+        let response = self
+            .request(
+                Method::POST,
+                &format!("/users/{}/two-factor/reset", username),
+            )
+            .send()
+            .await
+            .map_err(error::Error::RequestError)?;
+
+        let response = check_response(response).await?;
+        Ok(response.json::<User>().await.unwrap())
+    }
+
+    pub async fn ban_user(
+        &self,
+        username: &str,
+        data: &BanData,
+    ) -> Result<BannedAccount, error::Error> {
         let response = self
             .request(Method::POST, &format!("/users/{}/ban", username))
+            .json(data)
             .send()
             .await
             .map_err(error::Error::RequestError)?;
@@ -268,6 +514,148 @@ impl Client {
         Ok(response.json::<BannedAccount>().await.unwrap())
     }
 
+    pub async fn list_banned_accounts(&self) -> Result<Vec<BannedAccount>, error::Error> {
+        let response = self
+            .request(Method::GET, "/users/bans")
+            .send()
+            .await
+            .map_err(error::Error::RequestError)?;
+
+        let response = check_response(response).await?;
+        Ok(response.json::<Vec<BannedAccount>>().await.unwrap())
+    }
+
+    /// Ban an IP address (or CIDR range) from logging in or creating accounts
+    pub async fn ban_address(&self, data: &BanAddressData) -> Result<BannedAddress, error::Error> {
+        let response = self
+            .request(Method::POST, "/users/bans/addresses")
+            .json(data)
+            .send()
+            .await
+            .map_err(error::Error::RequestError)?;
+
+        let response = check_response(response).await?;
+        Ok(response.json::<BannedAddress>().await.unwrap())
+    }
+
+    pub async fn list_banned_addresses(&self) -> Result<Vec<BannedAddress>, error::Error> {
+        let response = self
+            .request(Method::GET, "/users/bans/addresses")
+            .send()
+            .await
+            .map_err(error::Error::RequestError)?;
+
+        let response = check_response(response).await?;
+        Ok(response.json::<Vec<BannedAddress>>().await.unwrap())
+    }
+
+    pub async fn unban_address(&self, id: &str) -> Result<BannedAddress, error::Error> {
+        let response = self
+            .request(Method::DELETE, &format!("/users/bans/addresses/{}", id))
+            .send()
+            .await
+            .map_err(error::Error::RequestError)?;
+
+        let response = check_response(response).await?;
+        Ok(response.json::<BannedAddress>().await.unwrap())
+    }
+
+    /// Check which IP reputation sources list the given address. Only available to admins.
+    pub async fn test_ip_reputation(&self, addr: &str) -> Result<IpReputationReport, error::Error> {
+        let response = self
+            .request(Method::GET, &format!("/admin/ip-reputation/{}", addr))
+            .send()
+            .await
+            .map_err(error::Error::RequestError)?;
+
+        let response = check_response(response).await?;
+        Ok(response.json::<IpReputationReport>().await.unwrap())
+    }
+
+    /// List the entries of an admin-managed IP reputation source
+    pub async fn list_ip_reputation_entries(
+        &self,
+        source: &str,
+    ) -> Result<Vec<IpReputationEntry>, error::Error> {
+        let response = self
+            .request(
+                Method::GET,
+                &format!("/admin/ip-reputation/sources/{}/entries", source),
+            )
+            .send()
+            .await
+            .map_err(error::Error::RequestError)?;
+
+        let response = check_response(response).await?;
+        Ok(response.json::<Vec<IpReputationEntry>>().await.unwrap())
+    }
+
+    pub async fn add_ip_reputation_entry(
+        &self,
+        source: &str,
+        data: &IpReputationEntryData,
+    ) -> Result<IpReputationEntry, error::Error> {
+        let response = self
+            .request(
+                Method::POST,
+                &format!("/admin/ip-reputation/sources/{}/entries", source),
+            )
+            .json(data)
+            .send()
+            .await
+            .map_err(error::Error::RequestError)?;
+
+        let response = check_response(response).await?;
+        Ok(response.json::<IpReputationEntry>().await.unwrap())
+    }
+
+    pub async fn remove_ip_reputation_entry(
+        &self,
+        source: &str,
+        id: &str,
+    ) -> Result<IpReputationEntry, error::Error> {
+        let response = self
+            .request(
+                Method::DELETE,
+                &format!("/admin/ip-reputation/sources/{}/entries/{}", source, id),
+            )
+            .send()
+            .await
+            .map_err(error::Error::RequestError)?;
+
+        let response = check_response(response).await?;
+        Ok(response.json::<IpReputationEntry>().await.unwrap())
+    }
+
+    /// List entries in the audit log (most recent first). Only available to admins.
+    pub async fn list_audit_log(
+        &self,
+        query: &AuditLogQuery,
+    ) -> Result<Vec<AuditLogEntry>, error::Error> {
+        let response = self
+            .request(Method::GET, "/admin/audit")
+            .query(query)
+            .send()
+            .await
+            .map_err(error::Error::RequestError)?;
+
+        let response = check_response(response).await?;
+        Ok(response.json::<Vec<AuditLogEntry>>().await.unwrap())
+    }
+
+    /// List the failed login counters (and lockouts) for usernames and IP
+    /// addresses. Only available to admins.
+    pub async fn list_login_failures(&self) -> Result<Vec<LoginFailureCounter>, error::Error> {
+        let response = self
+            .request(Method::GET, "/admin/login-failures")
+            .send()
+            .await
+            .map_err(error::Error::RequestError)?;
+
+        let response = check_response(response).await?;
+        Ok(response.json::<Vec<LoginFailureCounter>>().await.unwrap())
+    }
+
     /// Send a magic link to the given email address. Usable for any user associated with the
     /// address.
     pub async fn send_magic_link(&self, data: &CreateMagicLinkData) -> Result<(), error::Error> {
@@ -962,11 +1350,13 @@ impl Client {
         url: &str,
         id: &str,
         visibility: ServiceHostScope,
+        capabilities: Vec<HostCapability>,
     ) -> Result<String, error::Error> {
         let host = AuthorizedServiceHost {
             url: url.to_owned(),
             id: id.to_owned(),
             visibility,
+            capabilities,
         };
         let response = self
             .request(Method::POST, "/services/hosts/authorized/")
@@ -993,6 +1383,22 @@ impl Client {
         Ok(())
     }
 
+    /// Create a new secret for an authorized host. The previous secrets remain
+    /// valid for a while (configured on the server).
+    pub async fn rotate_host_secret(&self, id: &str) -> Result<String, error::Error> {
+        let response = self
+            .request(
+                Method::POST,
+                &format!("/services/hosts/authorized/{}/rotate", id),
+            )
+            .send()
+            .await
+            .map_err(error::Error::RequestError)?;
+
+        let response = check_response(response).await?;
+        Ok(response.json::<String>().await.unwrap())
+    }
+
     pub async fn list_authorized_hosts(&self) -> Result<Vec<AuthorizedServiceHost>, error::Error> {
         let response = self
             .request(Method::GET, "/services/hosts/authorized/")
@@ -1245,9 +1651,15 @@ impl Client {
         let url = format!(
             "{}/network/{}/connect",
             self.cfg.url.replace("http", "ws"),
-            config.client_id
+            config.client_id,
         );
-        let (ws_stream, _) = connect_async(&url).await.unwrap();
+        // Send the secret as a subprotocol so it isn't included in the URL
+        let mut request = url.into_client_request().unwrap();
+        let protocols = format!("netsblox, netsblox.secret.{}", config.client_secret);
+        request
+            .headers_mut()
+            .insert("Sec-WebSocket-Protocol", protocols.parse().unwrap());
+        let (ws_stream, _) = connect_async(request).await.unwrap();
 
         let state = ClientStateData {
             state: ClientState::External(ExternalClientState {
@@ -1261,6 +1673,7 @@ impl Client {
                 Method::POST,
                 &format!("/network/{}/state", config.client_id),
             )
+            .header("X-Client-Secret", &config.client_secret)
             .json(&state)
             .send()
             .await
@@ -1302,6 +1715,38 @@ impl Client {
         Ok(())
     }
 
+    pub async fn update_oauth_client(
+        &self,
+        id: &oauth::ClientId,
+        data: &oauth::UpdateClientData,
+    ) -> Result<oauth::Client, error::Error> {
+        let response = self
+            .request(Method::PATCH, &format!("/oauth/clients/{}", id))
+            .json(&data)
+            .send()
+            .await
+            .map_err(error::Error::RequestError)?;
+
+        let response = check_response(response).await?;
+
+        Ok(response.json::<oauth::Client>().await.unwrap())
+    }
+
+    pub async fn rotate_oauth_client_secret(
+        &self,
+        id: &oauth::ClientId,
+    ) -> Result<oauth::CreatedClientData, error::Error> {
+        let response = self
+            .request(Method::POST, &format!("/oauth/clients/{}/secret", id))
+            .send()
+            .await
+            .map_err(error::Error::RequestError)?;
+
+        let response = check_response(response).await?;
+
+        Ok(response.json::<oauth::CreatedClientData>().await.unwrap())
+    }
+
     pub async fn list_oauth_clients(&self) -> Result<Vec<oauth::Client>, error::Error> {
         let response = self
             .request(Method::GET, "/oauth/clients/")
@@ -1313,6 +1758,57 @@ impl Client {
 
         Ok(response.json::<Vec<oauth::Client>>().await.unwrap())
     }
+
+    pub async fn list_authorized_clients(
+        &self,
+        username: &str,
+    ) -> Result<Vec<oauth::AuthorizedClient>, error::Error> {
+        let response = self
+            .request(Method::GET, &format!("/oauth/user/{}/tokens", username))
+            .send()
+            .await
+            .map_err(error::Error::RequestError)?;
+
+        let response = check_response(response).await?;
+
+        Ok(response
+            .json::<Vec<oauth::AuthorizedClient>>()
+            .await
+            .unwrap())
+    }
+
+    pub async fn revoke_authorized_client(
+        &self,
+        username: &str,
+        id: &oauth::ClientId,
+    ) -> Result<(), error::Error> {
+        let response = self
+            .request(
+                Method::DELETE,
+                &format!("/oauth/user/{}/tokens/{}", username, id),
+            )
+            .send()
+            .await
+            .map_err(error::Error::RequestError)?;
+
+        check_response(response).await?;
+        Ok(())
+    }
+
+    pub async fn revoke_oauth_token(
+        &self,
+        params: &oauth::RevokeTokenParams,
+    ) -> Result<(), error::Error> {
+        let response = self
+            .request(Method::POST, "/oauth/revoke")
+            .json(&params)
+            .send()
+            .await
+            .map_err(error::Error::RequestError)?;
+
+        check_response(response).await?;
+        Ok(())
+    }
 }
 
 pub struct MessageChannel {
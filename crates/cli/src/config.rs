@@ -4,6 +4,10 @@ use lazy_static::lazy_static;
 use netsblox_api::{self, common::AppId};
 use serde::{Deserialize, Serialize};
 
+/// Environment variable used to provide a personal access token (instead of
+/// storing it in the configuration file)
+const ACCESS_TOKEN_VAR: &str = "NETSBLOX_ACCESS_TOKEN";
+
 lazy_static! {
     static ref DEFAULT_HOST: HostConfig = HostConfig::default();
 }
@@ -13,6 +17,21 @@ pub(crate) struct HostConfig {
     pub(crate) url: String,
     pub(crate) username: Option<String>,
     pub(crate) token: Option<String>,
+    #[serde(default)]
+    pub(crate) csrf_token: Option<String>,
+    /// Personal access token to use instead of a session
+    #[serde(default)]
+    pub(crate) access_token: Option<String>,
+}
+
+impl HostConfig {
+    /// Get the personal access token from the environment or the configuration
+    pub(crate) fn access_token(&self) -> Option<String> {
+        std::env::var(ACCESS_TOKEN_VAR)
+            .ok()
+            .filter(|token| !token.is_empty())
+            .or_else(|| self.access_token.clone())
+    }
 }
 
 impl Default for HostConfig {
@@ -21,6 +40,8 @@ impl Default for HostConfig {
             url: "https://cloud.netsblox.org".to_owned(),
             username: None,
             token: None,
+            csrf_token: None,
+            access_token: None,
         }
     }
 }
@@ -38,6 +59,8 @@ impl Default for Config {
             url: String::from("http://localhost:7777"),
             username: None,
             token: None,
+            csrf_token: None,
+            access_token: None,
         };
         let hosts = HashMap::from([
             (current_host.clone(), HostConfig::default()),
@@ -60,6 +83,15 @@ impl Config {
         if let Some(cfg) = self.hosts.get_mut(&self.current_host) {
             cfg.username = api_cfg.username.to_owned();
             cfg.token = api_cfg.token.to_owned();
+            cfg.csrf_token = api_cfg.csrf_token.to_owned();
+            cfg.access_token = None;
+        }
+    }
+
+    pub(crate) fn set_access_token(&mut self, username: &str, token: &str) {
+        if let Some(cfg) = self.hosts.get_mut(&self.current_host) {
+            cfg.username = Some(username.to_owned());
+            cfg.access_token = Some(token.to_owned());
         }
     }
 
@@ -67,17 +99,22 @@ impl Config {
         if let Some(cfg) = self.hosts.get_mut(&self.current_host) {
             cfg.username = None;
             cfg.token = None;
+            cfg.csrf_token = None;
+            cfg.access_token = None;
         }
     }
 }
 
 impl From<HostConfig> for netsblox_api::Config {
     fn from(config: HostConfig) -> netsblox_api::Config {
+        let access_token = config.access_token();
         netsblox_api::Config {
             app_id: Some(AppId::new("NetsBloxCLI")),
             url: config.url,
             username: config.username,
             token: config.token,
+            access_token,
+            csrf_token: config.csrf_token,
         }
     }
 }
@@ -9,9 +9,11 @@ use clap::{Parser, Subcommand};
 use futures_util::StreamExt;
 use inquire::{Confirm, Password, PasswordDisplayMode};
 use netsblox_api::common::{
-    oauth, ClientId, CreateMagicLinkData, CreateProjectData, Credentials, FriendLinkState, GroupId,
-    InvitationState, LinkedAccount, ProjectId, PublishState, RoleData, SaveState, ServiceHost,
-    ServiceHostScope, UpdateUserData, UserRole,
+    oauth, AuditAction, AuditLogQuery, BanAddressData, BanData, ClientId, CreateAccessTokenData,
+    CreateMagicLinkData, CreateProjectData, Credentials, FriendLinkState, GroupId, HostCapability,
+    InvitationState, IpReputationEntryData, LinkedAccount, MagicLinkCodeLoginData, ProjectId,
+    PublishState, RoleData, SaveState, ServiceHost, ServiceHostScope, TokenScope, UpdateUserData,
+    UserRole,
 };
 use netsblox_api::{self, serde_json, Client};
 use std::path::Path;
@@ -68,6 +70,8 @@ enum Users {
         #[clap(short, long)]
         no_confirm: bool,
     },
+    /// View the progress of the removal of a deleted user's data (projects, etc)
+    DeletionStatus { username: String },
     /// View the current user
     View {
         /// Perform this action on behalf of this user
@@ -100,11 +104,38 @@ enum Users {
     Ban {
         /// NetsBlox user to ban
         username: String,
+        /// Reason for the ban (visible to moderators)
+        #[clap(long)]
+        reason: Option<String>,
+        /// Duration of the ban in hours. Bans are permanent by default.
+        #[clap(long)]
+        hours: Option<u32>,
     },
     Unban {
         /// NetsBlox user to unban
         username: String,
     },
+    /// List active bans
+    Bans {
+        /// List banned IP addresses rather than banned accounts
+        #[clap(long)]
+        addresses: bool,
+    },
+    /// Ban an IP address or CIDR range (eg, 192.168.0.0/16)
+    BanAddress {
+        addr: String,
+        /// Reason for the ban (visible to moderators)
+        #[clap(long)]
+        reason: Option<String>,
+        /// Duration of the ban in hours. Bans are permanent by default.
+        #[clap(long)]
+        hours: Option<u32>,
+    },
+    /// Remove a ban on an IP address (or range)
+    UnbanAddress {
+        /// ID of the ban to remove
+        id: String,
+    },
     /// Link an account to a Snap! account (for login)
     Link {
         /// Snap! username to link to NetsBlox account
@@ -127,6 +158,70 @@ enum Users {
         #[clap(short, long)]
         user: Option<String>,
     },
+    /// Download an archive (zip) of all the data stored about a user
+    Export {
+        /// Path to save the archive to (defaults to <username>.zip)
+        #[clap(short, long)]
+        output: Option<String>,
+        /// Perform this action on behalf of this user
+        #[clap(short, long)]
+        user: Option<String>,
+    },
+    /// List the active login sessions for a user
+    Sessions {
+        /// Perform this action on behalf of this user
+        #[clap(short, long)]
+        user: Option<String>,
+    },
+    /// Revoke a login session (or all sessions)
+    RevokeSession {
+        /// ID of the session to revoke
+        #[clap(required_unless_present = "all")]
+        id: Option<String>,
+        /// Revoke all sessions (log out everywhere)
+        #[clap(long, conflicts_with = "id")]
+        all: bool,
+        /// Perform this action on behalf of this user
+        #[clap(short, long)]
+        user: Option<String>,
+    },
+    /// Enable two-factor authentication (TOTP) for the current user
+    EnableTwoFactor,
+    /// Disable two-factor authentication for the current user
+    DisableTwoFactor,
+    /// Remove two-factor authentication from a user's account (admin only)
+    ResetTwoFactor {
+        /// NetsBlox user to reset
+        username: String,
+    },
+    /// Manage personal access tokens (for authenticating from scripts, etc)
+    #[clap(alias = "token")]
+    Tokens {
+        #[clap(subcommand)]
+        subcmd: AccessTokens,
+    },
+}
+
+#[derive(Subcommand, Debug)]
+enum AccessTokens {
+    /// Create a new personal access token
+    Create {
+        /// Name of the token (eg, where it will be used)
+        name: String,
+        /// Permissions granted to the token (read or write)
+        #[clap(short, long, default_value = "read")]
+        scope: Vec<TokenScope>,
+        /// Number of days until the token expires. If unset, the token is valid until revoked
+        #[clap(short, long)]
+        expires_in: Option<u32>,
+    },
+    /// List the personal access tokens for the current user
+    List,
+    /// Revoke a personal access token
+    Revoke {
+        /// ID of the token to revoke
+        id: String,
+    },
 }
 
 /// Send "magic links" for password-less sign in
@@ -136,9 +231,15 @@ enum MagicLinks {
     Send {
         /// Email to send the magic link to.
         email: String,
-        /// Redirect the user to this URL after login
-        #[clap(short, long, default_value = "https://editor.netsblox.org")]
-        url: String,
+        /// Redirect the user to this URL after login (must be allowed by the server)
+        #[clap(short, long)]
+        url: Option<String>,
+    },
+    /// Login using the code sent with a magic link
+    Login {
+        /// Email the magic link was sent to
+        email: String,
+        username: String,
     },
 }
 
@@ -306,9 +407,16 @@ enum ServiceHosts {
         /// Set the public categories for the host. Omit to keep service private
         #[clap(short, long)]
         categories: Option<String>,
+        /// Grant the host a capability (eg, sendMessages). Can be repeated.
+        /// Omit to authorize the host without any capabilities
+        #[clap(short, long)]
+        grant: Vec<HostCapability>,
     },
     /// Revoke the service host's authorization
     Unauthorize { url: String },
+    /// Create a new secret for an authorized service host. The previous secret
+    /// remains valid for a while so the host can be updated.
+    RotateSecret { url: String },
 }
 
 /// Manage settings for services (eg, API keys) for different service hosts
@@ -440,16 +548,46 @@ enum Oauth {
     //     #[clap(short, long)]
     //     user: Option<String>,
     // },
-    // /// Revoke authorization for an OAuth client
-    // Revoke {
-    //     client: String, // TODO: should we use an ID or name?
-    //     #[clap(short, long)]
-    //     user: Option<String>,
-    // },
-    /// List all OAuth clients
+    /// List the OAuth clients authorized by a user
+    Authorized {
+        #[clap(short, long)]
+        user: Option<String>,
+    },
+    /// Revoke authorization for an OAuth client (deleting all its tokens for the user)
+    Revoke {
+        client_id: oauth::ClientId,
+        #[clap(short, long)]
+        user: Option<String>,
+    },
+    /// List OAuth clients (only your own clients unless you are an admin)
     List,
     /// Register new OAuth client with NetsBlox
-    AddClient { name: String },
+    AddClient {
+        name: String,
+        /// Scope the client is allowed to request (eg, "user:read"). Can be repeated.
+        #[clap(short, long)]
+        scope: Vec<oauth::Scope>,
+        /// Redirect URI allowed for the client (must match exactly). Can be repeated.
+        #[clap(short, long)]
+        redirect_uri: Vec<String>,
+        /// Register a public client (eg, a mobile app) which uses PKCE instead of a secret
+        #[clap(long)]
+        public: bool,
+    },
+    /// Update a registered OAuth client. Scopes and redirect URIs replace the existing ones.
+    UpdateClient {
+        id: oauth::ClientId,
+        #[clap(short, long)]
+        name: Option<String>,
+        /// Scope the client is allowed to request (eg, "user:read"). Can be repeated.
+        #[clap(short, long)]
+        scope: Vec<oauth::Scope>,
+        /// Redirect URI allowed for the client (must match exactly). Can be repeated.
+        #[clap(short, long)]
+        redirect_uri: Vec<String>,
+    },
+    /// Generate a new secret for an OAuth client. The previous secret will no longer work.
+    RotateSecret { id: oauth::ClientId },
     /// Remove registered OAuth client from NetsBlox
     RemoveClient { id: oauth::ClientId },
 }
@@ -610,6 +748,40 @@ enum Host {
     Add { name: String, url: String },
     /// Remove an existing NetsBlox cloud instance
     Remove { name: String },
+    /// Authenticate to the active host with a personal access token. The token
+    /// can also be provided using the NETSBLOX_ACCESS_TOKEN environment variable.
+    UseToken {
+        /// Owner of the access token
+        username: String,
+        /// Personal access token (prompted if not provided)
+        #[clap(long)]
+        token: Option<String>,
+    },
+}
+
+/// View the audit log of privileged actions (admin only)
+#[derive(Subcommand, Debug)]
+enum Audit {
+    /// List audit log entries, most recent first
+    List {
+        /// Only list actions performed by the given user
+        #[clap(long)]
+        actor: Option<String>,
+        /// Only list actions affecting the given target (eg, username or project ID)
+        #[clap(long)]
+        target: Option<String>,
+        /// Only list actions of the given type (eg, banUser, setUserRole)
+        #[clap(long)]
+        action: Option<AuditAction>,
+        /// Page of results to list (starting at 0)
+        #[clap(long)]
+        page: Option<u64>,
+        /// Maximum number of entries per page
+        #[clap(long)]
+        limit: Option<i64>,
+    },
+    /// List failed login counters (and lockouts) for usernames and addresses
+    LoginFailures,
 }
 
 #[derive(Parser, Debug)]
@@ -672,6 +844,45 @@ struct OauthCommand {
     subcmd: Oauth,
 }
 
+/// Manage the IP reputation lists (admin only)
+#[derive(Subcommand, Debug)]
+enum IpReputation {
+    /// Check which sources list an IP address (and what they do about it)
+    Test { addr: String },
+    /// List the entries of an admin-managed source
+    Entries {
+        #[clap(long, default_value = "admin")]
+        source: String,
+    },
+    /// Add an IP address or CIDR range (eg, 192.168.0.0/16) to an admin-managed source
+    Add {
+        addr: String,
+        #[clap(long, default_value = "admin")]
+        source: String,
+        #[clap(long)]
+        reason: Option<String>,
+    },
+    /// Remove an entry from an admin-managed source
+    Remove {
+        /// ID of the entry to remove
+        id: String,
+        #[clap(long, default_value = "admin")]
+        source: String,
+    },
+}
+
+#[derive(Parser, Debug)]
+struct IpReputationCommand {
+    #[clap(subcommand)]
+    subcmd: IpReputation,
+}
+
+#[derive(Parser, Debug)]
+struct AuditCommand {
+    #[clap(subcommand)]
+    subcmd: Audit,
+}
+
 #[derive(Parser, Debug)]
 struct HostCommand {
     #[clap(subcommand)]
@@ -701,6 +912,8 @@ enum Command {
     #[clap(alias = "library")]
     Libraries(LibraryCommand),
     Oauth(OauthCommand),
+    Audit(AuditCommand),
+    IpReputation(IpReputationCommand),
     #[clap(alias = "hosts")]
     Host(HostCommand),
 }
@@ -731,8 +944,17 @@ fn prompt_credentials() -> (String, String, bool) {
     (username, password, use_snap)
 }
 
+fn prompt_two_factor_code() -> String {
+    inquire::Text::new("Two-factor authentication code:")
+        .with_help_message("Enter the code from your authenticator app or a recovery code")
+        .prompt()
+        .expect("Unable to prompt two-factor authentication code")
+}
+
 fn get_current_user(cfg: &HostConfig) -> String {
-    cfg.username.as_ref().unwrap().clone()
+    cfg.username
+        .clone()
+        .expect("Unknown current user. Please specify the user or run \"host use-token\".")
 }
 
 fn save_config(cfg: &Config) {
@@ -757,12 +979,13 @@ async fn main() {
 }
 
 async fn do_command(mut cfg: Config, args: Cli) -> Result<(), error::Error> {
-    let is_logged_in = !(cfg.host().token.is_none() || cfg.host().username.is_none());
+    let has_session = cfg.host().token.is_some() && cfg.host().username.is_some();
+    let is_logged_in = has_session || cfg.host().access_token().is_some();
     let login_required = match &args.cmd {
         Command::Login => true,
         Command::Logout => false,
         Command::MagicLinks(cmd) => match &cmd.subcmd {
-            MagicLinks::Send { .. } => false,
+            MagicLinks::Send { .. } | MagicLinks::Login { .. } => false,
         },
         Command::Users(cmd) => match &cmd.subcmd {
             Users::Create { .. } => false,
@@ -779,14 +1002,21 @@ async fn do_command(mut cfg: Config, args: Cli) -> Result<(), error::Error> {
         } else {
             Credentials::NetsBlox { username, password }
         };
-        let request = netsblox_api::common::LoginRequest {
+        let mut request = netsblox_api::common::LoginRequest {
             credentials,
             client_id: None,
+            client_secret: None,
+            two_factor_code: None,
         };
         let api_cfg: netsblox_api::Config = cfg.host().clone().into();
-        let api_cfg = netsblox_api::login(api_cfg, &request)
-            .await
-            .expect("Login failed");
+        let api_cfg = match netsblox_api::login(api_cfg.clone(), &request).await {
+            Err(netsblox_api::error::Error::TwoFactorRequiredError) => {
+                request.two_factor_code = Some(prompt_two_factor_code());
+                netsblox_api::login(api_cfg, &request).await
+            }
+            result => result,
+        }
+        .expect("Login failed");
 
         cfg.set_credentials(&api_cfg);
         save_config(&cfg);
@@ -863,8 +1093,13 @@ async fn do_command(mut cfg: Config, args: Cli) -> Result<(), error::Error> {
                 if confirmed {
                     client.delete_user(username).await?;
                     println!("deleted {}", username);
+                    eprintln!("Remaining data will be removed in the background. Check progress with `users deletion-status`.");
                 }
             }
+            Users::DeletionStatus { username } => {
+                let deletion = client.view_account_deletion(username).await?;
+                println!("{}", serde_json::to_string_pretty(&deletion).unwrap());
+            }
             Users::View { user } => {
                 let username = user.clone().unwrap_or_else(|| get_current_user(cfg.host()));
                 let user = client.view_user(&username).await?;
@@ -890,22 +1125,148 @@ async fn do_command(mut cfg: Config, args: Cli) -> Result<(), error::Error> {
                 };
                 client.unlink_account(&as_user, &account).await?;
             }
-            Users::Ban { username } => {
-                client.ban_user(username).await?;
+            Users::Ban {
+                username,
+                reason,
+                hours,
+            } => {
+                let data = BanData {
+                    reason: reason.to_owned(),
+                    expires_in_hours: hours.to_owned(),
+                };
+                client.ban_user(username, &data).await?;
             }
             Users::Unban { username } => {
                 client.unban_user(username).await?;
             }
+            Users::Bans { addresses } => {
+                if *addresses {
+                    for ban in client.list_banned_addresses().await? {
+                        println!("{}", serde_json::to_string(&ban).unwrap());
+                    }
+                } else {
+                    for account in client.list_banned_accounts().await? {
+                        println!("{}", serde_json::to_string(&account).unwrap());
+                    }
+                }
+            }
+            Users::BanAddress {
+                addr,
+                reason,
+                hours,
+            } => {
+                let data = BanAddressData {
+                    addr: addr.to_owned(),
+                    reason: reason.to_owned(),
+                    expires_in_hours: hours.to_owned(),
+                };
+                let ban = client.ban_address(&data).await?;
+                println!("{}", ban.id);
+            }
+            Users::UnbanAddress { id } => {
+                client.unban_address(id).await?;
+            }
+            Users::Export { output, user } => {
+                let username = user.clone().unwrap_or_else(|| get_current_user(cfg.host()));
+                let archive = client.export_user_data(&username).await?;
+                let path = output
+                    .clone()
+                    .unwrap_or_else(|| format!("{}.zip", &username));
+                fs::write(&path, archive).expect("Unable to write archive");
+                println!("Saved data for {} to {}", username, path);
+            }
+            Users::Sessions { user } => {
+                let username = user.clone().unwrap_or_else(|| get_current_user(cfg.host()));
+                for session in client.list_sessions(&username).await? {
+                    println!("{}", serde_json::to_string(&session).unwrap());
+                }
+            }
+            Users::RevokeSession { id, all, user } => {
+                let username = user.clone().unwrap_or_else(|| get_current_user(cfg.host()));
+                if *all {
+                    client.revoke_all_sessions(&username).await?;
+                } else if let Some(id) = id {
+                    client.revoke_session(&username, id).await?;
+                }
+            }
+            Users::EnableTwoFactor => {
+                let username = get_current_user(cfg.host());
+                let enrollment = client.enroll_two_factor(&username).await?;
+                println!("Add the following to your authenticator app (or scan it as a QR code):");
+                println!("{}", enrollment.provisioning_uri);
+                println!("Secret: {}", enrollment.secret);
+                println!("\nRecovery codes (these will not be shown again):");
+                for code in enrollment.recovery_codes {
+                    println!("  {}", code);
+                }
+                println!();
+
+                let code = prompt_two_factor_code();
+                client.confirm_two_factor(&username, &code).await?;
+                println!("Two-factor authentication enabled.");
+            }
+            Users::DisableTwoFactor => {
+                let username = get_current_user(cfg.host());
+                let code = prompt_two_factor_code();
+                client.disable_two_factor(&username, &code).await?;
+                println!("Two-factor authentication disabled.");
+            }
+            Users::ResetTwoFactor { username } => {
+                client.reset_two_factor(username).await?;
+            }
+            Users::Tokens { subcmd } => {
+                let username = get_current_user(cfg.host());
+                match subcmd {
+                    AccessTokens::Create {
+                        name,
+                        scope,
+                        expires_in,
+                    } => {
+                        let data = CreateAccessTokenData {
+                            name: name.to_owned(),
+                            scopes: scope.to_owned(),
+                            expires_in_days: *expires_in,
+                        };
+                        let created = client.create_access_token(&username, &data).await?;
+                        println!("{}", created.secret);
+                        eprintln!("Token created. It will not be shown again.");
+                    }
+                    AccessTokens::List => {
+                        for token in client.list_access_tokens(&username).await? {
+                            println!("{}", serde_json::to_string(&token).unwrap());
+                        }
+                    }
+                    AccessTokens::Revoke { id } => {
+                        client.revoke_access_token(&username, id).await?;
+                    }
+                }
+            }
         },
         Command::MagicLinks(cmd) => match &cmd.subcmd {
             MagicLinks::Send { email, url } => {
                 let data = CreateMagicLinkData {
                     email: email.clone(),
-                    redirect_uri: Some(url.to_owned()),
+                    redirect_uri: url.to_owned(),
                 };
                 client.send_magic_link(&data).await?;
                 println!("Magic link sent to {}!", email);
             }
+            MagicLinks::Login { email, username } => {
+                let code = inquire::Text::new("Sign-in code:")
+                    .with_help_message("Enter the code from the magic link email")
+                    .prompt()
+                    .expect("Unable to prompt sign-in code");
+                let data = MagicLinkCodeLoginData {
+                    email: email.to_owned(),
+                    username: username.to_owned(),
+                    code,
+                    client_id: None,
+                    client_secret: None,
+                };
+                let api_cfg = netsblox_api::login_with_magic_link_code(api_cfg, &data).await?;
+                cfg.set_credentials(&api_cfg);
+                save_config(&cfg);
+            }
         },
         Command::Projects(cmd) => match &cmd.subcmd {
             Projects::Import {
@@ -1349,6 +1710,7 @@ async fn do_command(mut cfg: Config, args: Cli) -> Result<(), error::Error> {
                 url,
                 client_id,
                 categories,
+                grant,
             } => {
                 let visibility = categories
                     .as_ref()
@@ -1359,7 +1721,9 @@ async fn do_command(mut cfg: Config, args: Cli) -> Result<(), error::Error> {
                     })
                     .unwrap_or(ServiceHostScope::Private);
 
-                let secret = client.authorize_host(url, client_id, visibility).await?;
+                let secret = client
+                    .authorize_host(url, client_id, visibility, grant.clone())
+                    .await?;
                 println!("{}", secret);
             }
             ServiceHosts::Unauthorize { url } => {
@@ -1375,6 +1739,20 @@ async fn do_command(mut cfg: Config, args: Cli) -> Result<(), error::Error> {
                     })?;
                 client.unauthorize_host(&host.id).await?;
             }
+            ServiceHosts::RotateSecret { url } => {
+                let host = client
+                    .list_authorized_hosts()
+                    .await?
+                    .into_iter()
+                    .find(|host| &host.url == url)
+                    .ok_or_else(|| {
+                        netsblox_api::error::Error::NotFoundError(
+                            "Authorized host not found.".to_string(),
+                        )
+                    })?;
+                let secret = client.rotate_host_secret(&host.id).await?;
+                println!("{}", secret);
+            }
         },
         Command::ServiceSettings(cmd) => match &cmd.subcmd {
             ServiceSettings::List { group, user } => {
@@ -1612,16 +1990,106 @@ async fn do_command(mut cfg: Config, args: Cli) -> Result<(), error::Error> {
                     .into_iter()
                     .for_each(|client| println!("{:?}", client));
             }
-            Oauth::AddClient { name } => {
+            Oauth::AddClient {
+                name,
+                scope,
+                redirect_uri,
+                public,
+            } => {
                 let client_data = oauth::CreateClientData {
                     name: name.to_owned(),
+                    allowed_scopes: scope.to_owned(),
+                    public: *public,
+                    redirect_uris: redirect_uri.to_owned(),
                 };
                 let client_id = client.add_oauth_client(&client_data).await?;
                 println!("{:?}", client_id);
             }
+            Oauth::UpdateClient {
+                id,
+                name,
+                scope,
+                redirect_uri,
+            } => {
+                let data = oauth::UpdateClientData {
+                    name: name.to_owned(),
+                    allowed_scopes: (!scope.is_empty()).then(|| scope.to_owned()),
+                    redirect_uris: (!redirect_uri.is_empty()).then(|| redirect_uri.to_owned()),
+                };
+                let updated = client.update_oauth_client(id, &data).await?;
+                println!("{:?}", updated);
+            }
+            Oauth::RotateSecret { id } => {
+                let created = client.rotate_oauth_client_secret(id).await?;
+                println!("{:?}", created);
+                eprintln!("The new secret will not be shown again.");
+            }
             Oauth::RemoveClient { id } => {
                 client.remove_oauth_client(id).await?;
             }
+            Oauth::Authorized { user } => {
+                let username = user.clone().unwrap_or_else(|| get_current_user(cfg.host()));
+                for authorized in client.list_authorized_clients(&username).await? {
+                    println!("{}", serde_json::to_string(&authorized).unwrap());
+                }
+            }
+            Oauth::Revoke { client_id, user } => {
+                let username = user.clone().unwrap_or_else(|| get_current_user(cfg.host()));
+                client
+                    .revoke_authorized_client(&username, client_id)
+                    .await?;
+            }
+        },
+        Command::Audit(cmd) => match &cmd.subcmd {
+            Audit::List {
+                actor,
+                target,
+                action,
+                page,
+                limit,
+            } => {
+                let query = AuditLogQuery {
+                    actor: actor.to_owned(),
+                    target: target.to_owned(),
+                    action: action.to_owned(),
+                    page: page.to_owned(),
+                    limit: limit.to_owned(),
+                };
+                for entry in client.list_audit_log(&query).await? {
+                    println!("{}", serde_json::to_string(&entry).unwrap());
+                }
+            }
+            Audit::LoginFailures => {
+                for counter in client.list_login_failures().await? {
+                    println!("{}", serde_json::to_string(&counter).unwrap());
+                }
+            }
+        },
+        Command::IpReputation(cmd) => match &cmd.subcmd {
+            IpReputation::Test { addr } => {
+                let report = client.test_ip_reputation(addr).await?;
+                println!("{}", serde_json::to_string(&report).unwrap());
+            }
+            IpReputation::Entries { source } => {
+                for entry in client.list_ip_reputation_entries(source).await? {
+                    println!("{}", serde_json::to_string(&entry).unwrap());
+                }
+            }
+            IpReputation::Add {
+                addr,
+                source,
+                reason,
+            } => {
+                let data = IpReputationEntryData {
+                    addr: addr.to_owned(),
+                    reason: reason.to_owned(),
+                };
+                let entry = client.add_ip_reputation_entry(source, &data).await?;
+                println!("{}", entry.id);
+            }
+            IpReputation::Remove { id, source } => {
+                client.remove_ip_reputation_entry(source, id).await?;
+            }
         },
         Command::Host(cmd) => match &cmd.subcmd {
             Host::View => {
@@ -1653,6 +2121,8 @@ async fn do_command(mut cfg: Config, args: Cli) -> Result<(), error::Error> {
                     url: url.to_owned(),
                     username: None,
                     token: None,
+                    csrf_token: None,
+                    access_token: None,
                 };
                 cfg.hosts.insert(name.to_owned(), config);
                 save_config(&cfg);
@@ -1669,6 +2139,17 @@ async fn do_command(mut cfg: Config, args: Cli) -> Result<(), error::Error> {
                 cfg.hosts.remove(name);
                 save_config(&cfg);
             }
+            Host::UseToken { username, token } => {
+                let token = token.clone().unwrap_or_else(|| {
+                    Password::new("Access token:")
+                        .with_display_mode(PasswordDisplayMode::Masked)
+                        .without_confirmation()
+                        .prompt()
+                        .expect("Unable to prompt access token")
+                });
+                cfg.set_access_token(username, &token);
+                save_config(&cfg);
+            }
         },
     }
 
@@ -1,14 +1,16 @@
-use mongodb::bson::{self, doc, document::Document, Bson, DateTime};
+pub mod password;
+
+use mongodb::bson::{self, doc, document::Document, oid::ObjectId, Bson, DateTime};
 pub use netsblox_api_common as api;
 use netsblox_api_common::{
     oauth, ClientState, LibraryMetadata, NewUser, PublishState, RoleId, UserRole,
 };
 use netsblox_api_common::{
-    FriendInvite, FriendLinkState, GroupId, InvitationState, LinkedAccount, ProjectId, RoleData,
-    SaveState, ServiceHost, ServiceHostScope,
+    FriendInvite, FriendLinkState, GroupId, HostCapability, InvitationState, LinkedAccount,
+    ProjectId, RoleData, SaveState, ServiceHost, ServiceHostScope,
 };
+use password::HashParams;
 use serde::{Deserialize, Serialize};
-use sha2::{Digest, Sha512};
 use std::{
     collections::HashMap,
     time::{Duration, SystemTime},
@@ -18,6 +20,10 @@ use uuid::Uuid;
 #[derive(Serialize, Deserialize, Clone, Debug)]
 #[serde(rename_all = "camelCase")]
 pub struct User {
+    /// Unlike the username, the ID is never reused (eg, after an account is deleted).
+    /// It is assigned by the database when the user is created.
+    #[serde(rename = "_id", default, skip_serializing_if = "Option::is_none")]
+    pub id: Option<ObjectId>,
     pub username: String,
     pub email: String,
     pub hash: String,
@@ -28,12 +34,52 @@ pub struct User {
     pub linked_accounts: Vec<LinkedAccount>,
     pub services_hosts: Option<Vec<ServiceHost>>,
     pub service_settings: HashMap<String, String>,
+    #[serde(default)]
+    pub two_factor: Option<TwoFactorAuth>,
+    /// Whether the email address has been verified. Accounts created before
+    /// email verification was introduced are considered verified.
+    #[serde(default = "User::verified_default")]
+    pub verified: bool,
+    /// IP address the account was created from (used to limit signups)
+    #[serde(default)]
+    pub signup_ip: Option<String>,
+    /// Subnet containing the signup IP address (used to limit signups)
+    #[serde(default)]
+    pub signup_subnet: Option<String>,
+    #[serde(default)]
+    pub signup_user_agent: Option<String>,
 }
 
 impl User {
     pub fn is_member(&self) -> bool {
         self.group_id.is_some()
     }
+
+    pub fn has_two_factor(&self) -> bool {
+        self.two_factor
+            .as_ref()
+            .map(|tfa| tfa.enabled)
+            .unwrap_or(false)
+    }
+
+    fn verified_default() -> bool {
+        true
+    }
+}
+
+/// TOTP-based two-factor authentication for a user
+#[derive(Serialize, Deserialize, Clone, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct TwoFactorAuth {
+    /// Base32-encoded shared secret
+    pub secret: String,
+    /// Set once enrollment has been confirmed with a valid code
+    pub enabled: bool,
+    /// Hashes of the unused recovery codes
+    pub recovery_codes: Vec<String>,
+    /// Time step of the last accepted code (so codes cannot be reused)
+    pub last_used_step: Option<i64>,
+    pub created_at: DateTime,
 }
 
 impl From<User> for Bson {
@@ -49,6 +95,11 @@ impl From<User> for Bson {
             "linkedAccounts": user.linked_accounts,
             "servicesHosts": user.services_hosts,
             "serviceSettings": bson::to_bson(&user.service_settings).unwrap(),
+            "twoFactor": bson::to_bson(&user.two_factor).unwrap(),
+            "verified": user.verified,
+            "signupIp": user.signup_ip,
+            "signupSubnet": user.signup_subnet,
+            "signupUserAgent": user.signup_user_agent,
         })
     }
 }
@@ -63,30 +114,28 @@ impl From<User> for netsblox_api_common::User {
             created_at: user.created_at.to_system_time(),
             linked_accounts: user.linked_accounts,
             services_hosts: user.services_hosts,
+            verified: user.verified,
         }
     }
 }
 
-impl From<NewUser> for User {
-    fn from(user_data: NewUser) -> Self {
-        let salt = passwords::PasswordGenerator::new()
-            .length(8)
-            .exclude_similar_characters(true)
-            .numbers(true)
-            .spaces(false)
-            .generate_one()
-            .unwrap_or_else(|_err| "salt".to_owned());
-
+impl User {
+    /// Create a new user, hashing the password (if provided) with the given parameters
+    pub fn from_new_user(
+        user_data: NewUser,
+        params: &HashParams,
+    ) -> Result<Self, password::HashError> {
         let hash: String = if let Some(pwd) = user_data.password {
-            sha512(&(pwd + &salt))
+            password::hash(&pwd, params)?
         } else {
             "None".to_owned()
         };
 
-        User {
+        Ok(User {
+            id: None,
             username: user_data.username,
             hash,
-            salt: Some(salt),
+            salt: None,
             email: user_data.email,
             group_id: user_data.group_id,
             created_at: DateTime::from_system_time(SystemTime::now()),
@@ -94,7 +143,19 @@ impl From<NewUser> for User {
             role: user_data.role.unwrap_or(UserRole::User),
             services_hosts: None,
             service_settings: HashMap::new(),
-        }
+            two_factor: None,
+            verified: true,
+            signup_ip: None,
+            signup_subnet: None,
+            signup_user_agent: None,
+        })
+    }
+}
+
+impl From<NewUser> for User {
+    fn from(user_data: NewUser) -> Self {
+        User::from_new_user(user_data, &HashParams::default())
+            .expect("Default password hashing parameters are valid")
     }
 }
 
@@ -104,15 +165,27 @@ pub struct BannedAccount {
     pub username: String,
     pub email: String,
     pub banned_at: DateTime,
+    pub moderator: Option<String>,
+    pub reason: Option<String>,
+    pub expires_at: Option<DateTime>,
 }
 
 impl BannedAccount {
-    pub fn new(username: String, email: String) -> BannedAccount {
-        let banned_at = DateTime::now();
+    pub fn new(
+        username: String,
+        email: String,
+        moderator: Option<String>,
+        reason: Option<String>,
+        ttl: Option<Duration>,
+    ) -> BannedAccount {
+        let now = SystemTime::now();
         BannedAccount {
             username,
             email,
-            banned_at,
+            banned_at: DateTime::from_system_time(now),
+            moderator,
+            reason,
+            expires_at: ttl.map(|ttl| DateTime::from_system_time(now + ttl)),
         }
     }
 }
@@ -123,6 +196,9 @@ impl From<BannedAccount> for Bson {
             "username": account.username,
             "email": account.email,
             "bannedAt": account.banned_at,
+            "moderator": account.moderator,
+            "reason": account.reason,
+            "expiresAt": account.expires_at,
         })
     }
 }
@@ -133,6 +209,133 @@ impl From<BannedAccount> for api::BannedAccount {
             username: account.username,
             email: account.email,
             banned_at: account.banned_at.into(),
+            moderator: account.moderator,
+            reason: account.reason,
+            expires_at: account.expires_at.map(|time| time.to_system_time()),
+        }
+    }
+}
+
+/// Record of the cleanup of the data owned by a deleted account
+#[derive(Serialize, Deserialize, Clone, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct AccountDeletion {
+    pub username: String,
+    /// User who deleted the account
+    pub requestor: String,
+    pub state: api::AccountDeletionState,
+    pub progress: api::AccountDeletionProgress,
+    pub error: Option<String>,
+    pub started_at: DateTime,
+    pub updated_at: DateTime,
+}
+
+impl AccountDeletion {
+    pub fn new(username: String, requestor: String) -> Self {
+        let now = DateTime::now();
+        Self {
+            username,
+            requestor,
+            state: api::AccountDeletionState::InProgress,
+            progress: api::AccountDeletionProgress::default(),
+            error: None,
+            started_at: now,
+            updated_at: now,
+        }
+    }
+}
+
+impl From<AccountDeletion> for api::AccountDeletion {
+    fn from(deletion: AccountDeletion) -> Self {
+        api::AccountDeletion {
+            username: deletion.username,
+            state: deletion.state,
+            progress: deletion.progress,
+            error: deletion.error,
+            started_at: deletion.started_at.to_system_time(),
+            updated_at: deletion.updated_at.to_system_time(),
+        }
+    }
+}
+
+/// A ban on an IP address or range of addresses (in CIDR notation)
+#[derive(Serialize, Deserialize, Clone, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct BannedAddress {
+    pub id: String,
+    pub addr: String,
+    pub moderator: String,
+    pub reason: Option<String>,
+    pub banned_at: DateTime,
+    pub expires_at: Option<DateTime>,
+}
+
+impl BannedAddress {
+    pub fn new(
+        addr: String,
+        moderator: String,
+        reason: Option<String>,
+        ttl: Option<Duration>,
+    ) -> Self {
+        let now = SystemTime::now();
+        Self {
+            id: Uuid::new_v4().to_string(),
+            addr,
+            moderator,
+            reason,
+            banned_at: DateTime::from_system_time(now),
+            expires_at: ttl.map(|ttl| DateTime::from_system_time(now + ttl)),
+        }
+    }
+}
+
+impl From<BannedAddress> for api::BannedAddress {
+    fn from(ban: BannedAddress) -> Self {
+        api::BannedAddress {
+            id: ban.id,
+            addr: ban.addr,
+            moderator: ban.moderator,
+            reason: ban.reason,
+            banned_at: ban.banned_at.to_system_time(),
+            expires_at: ban.expires_at.map(|time| time.to_system_time()),
+        }
+    }
+}
+
+/// An address (or range of addresses) added to an admin-managed IP reputation source
+#[derive(Serialize, Deserialize, Clone, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct IpReputationEntry {
+    pub id: String,
+    pub source: String,
+    pub addr: String,
+    pub reason: Option<String>,
+    pub created_by: String,
+    pub created_at: DateTime,
+}
+
+impl IpReputationEntry {
+    pub fn new(source: String, addr: String, reason: Option<String>, created_by: String) -> Self {
+        Self {
+            id: Uuid::new_v4().to_string(),
+            source,
+            addr,
+            reason,
+            created_by,
+            created_at: DateTime::from_system_time(SystemTime::now()),
+        }
+    }
+}
+
+impl From<IpReputationEntry> for api::IpReputationEntry {
+    fn from(entry: IpReputationEntry) -> Self {
+        api::IpReputationEntry {
+            id: entry.id,
+            source: entry.source,
+            addr: entry.addr,
+            reason: entry.reason,
+            created_by: entry.created_by,
+            created_at: entry.created_at.to_system_time(),
         }
     }
 }
@@ -631,6 +834,41 @@ impl SetPasswordToken {
     }
 }
 
+/// Token sent to a user to confirm the email address of their account
+#[derive(Deserialize, Serialize, Debug, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct EmailVerificationToken {
+    pub username: String,
+    pub email: String,
+    pub secret: String,
+    pub created_at: DateTime,
+}
+
+impl EmailVerificationToken {
+    pub fn new(username: String, email: String) -> Self {
+        let secret = Uuid::new_v4().to_string();
+        let created_at = DateTime::from_system_time(SystemTime::now());
+
+        EmailVerificationToken {
+            username,
+            email,
+            secret,
+            created_at,
+        }
+    }
+}
+
+impl From<EmailVerificationToken> for Bson {
+    fn from(token: EmailVerificationToken) -> Bson {
+        Bson::Document(doc! {
+            "username": token.username,
+            "email": token.email,
+            "secret": token.secret,
+            "createdAt": token.created_at,
+        })
+    }
+}
+
 impl From<SetPasswordToken> for Bson {
     fn from(token: SetPasswordToken) -> Bson {
         Bson::Document(doc! {
@@ -647,23 +885,38 @@ pub struct AuthorizedServiceHost {
     pub url: String,
     pub id: String,
     pub visibility: ServiceHostScope,
-    pub secret: String,
+    #[serde(default)]
+    pub capabilities: Vec<HostCapability>,
+    /// Hashed secrets of the host. There can be multiple active secrets while
+    /// a secret is being rotated.
+    #[serde(default)]
+    pub secrets: Vec<HostSecret>,
 }
 
 impl AuthorizedServiceHost {
-    pub fn new(url: String, id: String, visibility: ServiceHostScope) -> Self {
-        let secret = Uuid::new_v4().to_string();
+    pub fn new(
+        url: String,
+        id: String,
+        visibility: ServiceHostScope,
+        capabilities: Vec<HostCapability>,
+        secret: HostSecret,
+    ) -> Self {
         AuthorizedServiceHost {
             url,
             id,
-            secret,
             visibility,
+            capabilities,
+            secrets: vec![secret],
         }
     }
 
-    pub fn auth_header(&self) -> (&'static str, String) {
-        let token = self.id.clone() + ":" + &self.secret;
-        ("X-Authorization", token)
+    pub fn has_capability(&self, capability: HostCapability) -> bool {
+        self.capabilities.contains(&capability)
+    }
+
+    /// Secrets which have not expired yet
+    pub fn active_secrets(&self) -> impl Iterator<Item = &HostSecret> {
+        self.secrets.iter().filter(|secret| !secret.is_expired())
     }
 }
 
@@ -673,14 +926,135 @@ impl From<AuthorizedServiceHost> for Bson {
             "url": host.url,
             "id": host.id,
             "visibility": host.visibility,
-            "secret": host.secret,
+            "capabilities": host.capabilities,
+            "secrets": host.secrets,
         })
     }
 }
 
-impl From<netsblox_api_common::AuthorizedServiceHost> for AuthorizedServiceHost {
-    fn from(data: netsblox_api_common::AuthorizedServiceHost) -> AuthorizedServiceHost {
-        AuthorizedServiceHost::new(data.url, data.id, data.visibility)
+/// A secret of an authorized service host. Only the (SHA-512) hash of the
+/// secret is stored along with the (encrypted) key derived from the secret
+/// which is used to sign requests so the host does not need to send the
+/// secret itself.
+#[derive(Deserialize, Serialize, Debug, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct HostSecret {
+    pub hash: String,
+    /// Encrypted signing key. This is missing for secrets hashed before signing
+    /// keys were stored until the host sends the secret again.
+    #[serde(default)]
+    pub signing_key: Option<String>,
+    pub created_at: DateTime,
+    /// Previous secrets remain valid for a while after rotation
+    pub expires_at: Option<DateTime>,
+}
+
+impl HostSecret {
+    pub fn new(hash: String, signing_key: Option<String>) -> Self {
+        HostSecret {
+            hash,
+            signing_key,
+            created_at: DateTime::from_system_time(SystemTime::now()),
+            expires_at: None,
+        }
+    }
+
+    pub fn is_expired(&self) -> bool {
+        self.expires_at
+            .map(|time| time.to_system_time() < SystemTime::now())
+            .unwrap_or(false)
+    }
+}
+
+impl From<HostSecret> for Bson {
+    fn from(secret: HostSecret) -> Bson {
+        Bson::Document(doc! {
+            "hash": secret.hash,
+            "signingKey": secret.signing_key,
+            "createdAt": secret.created_at,
+            "expiresAt": secret.expires_at,
+        })
+    }
+}
+
+/// Nonce of a signed request from an authorized host. These are stored (until
+/// the request timestamp would be rejected anyway) to prevent replay attacks.
+#[derive(Deserialize, Serialize, Debug, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct HostRequestNonce {
+    pub host_id: String,
+    pub nonce: String,
+    pub created_at: DateTime,
+}
+
+impl HostRequestNonce {
+    pub fn new(host_id: String, nonce: String) -> Self {
+        HostRequestNonce {
+            host_id,
+            nonce,
+            created_at: DateTime::from_system_time(SystemTime::now()),
+        }
+    }
+}
+
+impl From<HostRequestNonce> for Bson {
+    fn from(nonce: HostRequestNonce) -> Bson {
+        Bson::Document(doc! {
+            "hostId": nonce.host_id,
+            "nonce": nonce.nonce,
+            "createdAt": nonce.created_at,
+        })
+    }
+}
+
+/// Recent failed logins for a username or IP address. Used to lock out
+/// clients guessing passwords (with exponential backoff).
+#[derive(Deserialize, Serialize, Debug, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct LoginFailureCounter {
+    pub kind: api::LoginCounterKind,
+    pub key: String,
+    /// Times of the most recent failures
+    pub failures: Vec<DateTime>,
+    /// Number of lockouts since the counter was last reset
+    pub lockouts: u32,
+    pub locked_until: Option<DateTime>,
+    pub updated_at: DateTime,
+}
+
+impl From<LoginFailureCounter> for api::LoginFailureCounter {
+    fn from(counter: LoginFailureCounter) -> api::LoginFailureCounter {
+        api::LoginFailureCounter {
+            kind: counter.kind,
+            key: counter.key,
+            failures: counter
+                .failures
+                .into_iter()
+                .map(|time| time.to_system_time())
+                .collect(),
+            lockouts: counter.lockouts,
+            locked_until: counter.locked_until.map(|time| time.to_system_time()),
+        }
+    }
+}
+
+/// Proof-of-work challenge issued for creating an account. Challenges are
+/// removed when used so each can only be used for a single account.
+#[derive(Deserialize, Serialize, Debug, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct SignupChallenge {
+    pub id: String,
+    pub difficulty: u8,
+    pub created_at: DateTime,
+}
+
+impl SignupChallenge {
+    pub fn new(id: String, difficulty: u8) -> Self {
+        SignupChallenge {
+            id,
+            difficulty,
+            created_at: DateTime::from_system_time(SystemTime::now()),
+        }
     }
 }
 
@@ -690,6 +1064,7 @@ impl From<AuthorizedServiceHost> for netsblox_api_common::AuthorizedServiceHost
             id: host.id,
             url: host.url,
             visibility: host.visibility,
+            capabilities: host.capabilities,
         }
     }
 }
@@ -746,28 +1121,59 @@ impl From<Library> for Bson {
 pub struct OAuthClient {
     pub id: oauth::ClientId,
     pub name: String,
+    /// User who registered the client (not set for clients registered by an
+    /// admin or authorized service host)
+    #[serde(default)]
+    pub owner: Option<String>,
     created_at: DateTime,
-    hash: String,
-    salt: String,
+    /// Hash of the client secret (not set for public clients)
+    hash: Option<String>,
+    /// Only set for clients with legacy (sha512) secrets
+    salt: Option<String>,
+    /// Scopes the client is allowed to request
+    #[serde(default = "oauth::Scope::legacy")]
+    pub allowed_scopes: Vec<oauth::Scope>,
+    /// Public clients do not have a secret and must use PKCE
+    #[serde(default)]
+    pub public: bool,
+    /// Redirect URIs allowed in authorization requests (exact match)
+    #[serde(default)]
+    pub redirect_uris: Vec<String>,
 }
 
 impl OAuthClient {
-    pub fn new(name: String, password: String) -> Self {
-        let salt = passwords::PasswordGenerator::new()
-            .length(8)
-            .exclude_similar_characters(true)
-            .numbers(true)
-            .spaces(false)
-            .generate_one()
-            .unwrap_or_else(|_err| "salt".to_owned());
-
-        let hash = sha512(&(password + &salt));
-        Self {
+    /// Create a new client. Clients without a secret are public clients.
+    pub fn new(
+        name: String,
+        owner: Option<String>,
+        secret: Option<String>,
+        allowed_scopes: Vec<oauth::Scope>,
+        redirect_uris: Vec<String>,
+        params: &HashParams,
+    ) -> Result<Self, password::HashError> {
+        let hash = secret
+            .map(|secret| password::hash(&secret, params))
+            .transpose()?;
+        let public = hash.is_none();
+
+        Ok(Self {
             id: oauth::ClientId::new(Uuid::new_v4().to_string()),
             name,
+            owner,
             created_at: DateTime::from_system_time(SystemTime::now()),
             hash,
-            salt,
+            salt: None,
+            allowed_scopes,
+            public,
+            redirect_uris,
+        })
+    }
+
+    /// Check the given client secret against the stored hash
+    pub fn verify_secret(&self, secret: &str, params: &HashParams) -> password::Verification {
+        match &self.hash {
+            Some(hash) => password::verify(secret, hash, self.salt.as_deref(), params),
+            None => password::Verification::Invalid,
         }
     }
 }
@@ -777,9 +1183,13 @@ impl From<OAuthClient> for Bson {
         Bson::Document(doc! {
             "id": client.id,
             "name": client.name,
+            "owner": client.owner,
             "createdAt": client.created_at,
             "hash": client.hash,
             "salt": client.salt,
+            "allowedScopes": client.allowed_scopes,
+            "public": client.public,
+            "redirectUris": client.redirect_uris,
         })
     }
 }
@@ -789,6 +1199,10 @@ impl From<OAuthClient> for oauth::Client {
         oauth::Client {
             id: client.id,
             name: client.name,
+            owner: client.owner,
+            allowed_scopes: client.allowed_scopes,
+            redirect_uris: client.redirect_uris,
+            public: client.public,
         }
     }
 }
@@ -799,19 +1213,31 @@ pub struct OAuthToken {
     pub id: oauth::TokenId,
     pub client_id: oauth::ClientId,
     pub username: String,
+    /// Scopes granted by the user
+    #[serde(default = "oauth::Scope::legacy")]
+    pub scopes: Vec<oauth::Scope>,
     pub created_at: DateTime,
+    /// Tokens created before token lifetimes were introduced do not expire
+    pub expires_at: Option<DateTime>,
 }
 
 impl OAuthToken {
-    pub fn new(client_id: oauth::ClientId, username: String) -> Self {
+    pub fn new(
+        client_id: oauth::ClientId,
+        username: String,
+        scopes: Vec<oauth::Scope>,
+        ttl: Duration,
+    ) -> Self {
         let id = oauth::TokenId::new(Uuid::new_v4().to_string());
-        let created_at = DateTime::from_system_time(SystemTime::now());
+        let now = SystemTime::now();
 
         Self {
             id,
             client_id,
             username,
-            created_at,
+            scopes,
+            created_at: DateTime::from_system_time(now),
+            expires_at: Some(DateTime::from_system_time(now + ttl)),
         }
     }
 }
@@ -822,7 +1248,9 @@ impl From<OAuthToken> for oauth::Token {
             id: token.id,
             client_id: token.client_id,
             username: token.username,
+            scopes: token.scopes,
             created_at: token.created_at.to_system_time(),
+            expires_at: token.expires_at.map(|time| time.to_system_time()),
         }
     }
 }
@@ -831,18 +1259,96 @@ impl From<OAuthToken> for Bson {
     fn from(token: OAuthToken) -> Bson {
         Bson::Document(doc! {
             "id": token.id,
-            "client_id": token.client_id,
+            "clientId": token.client_id,
             "username": token.username,
+            "scopes": token.scopes,
             "createdAt": token.created_at,
+            "expiresAt": token.expires_at,
         })
     }
 }
 
-pub(crate) fn sha512(text: &str) -> String {
-    let mut hasher = Sha512::new();
-    hasher.update(text);
-    let hash = hasher.finalize();
-    hex::encode(hash)
+/// Authorization code issued to an OAuth client after the user consents. Codes
+/// can only be exchanged for a token once and expire shortly after being issued.
+#[derive(Serialize, Deserialize, Clone, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct OAuthCode {
+    pub id: oauth::CodeId,
+    pub username: String,
+    pub client_id: oauth::ClientId,
+    pub redirect_uri: String,
+    /// Scopes the user consented to
+    pub scopes: Vec<oauth::Scope>,
+    /// PKCE code challenge (S256)
+    pub code_challenge: Option<String>,
+    /// OpenID Connect nonce (included in the ID token)
+    #[serde(default)]
+    pub nonce: Option<String>,
+    pub created_at: DateTime,
+    pub expires_at: DateTime,
+}
+
+impl OAuthCode {
+    pub fn new(
+        username: String,
+        client_id: oauth::ClientId,
+        redirect_uri: String,
+        scopes: Vec<oauth::Scope>,
+        code_challenge: Option<String>,
+        nonce: Option<String>,
+        ttl: Duration,
+    ) -> Self {
+        let now = SystemTime::now();
+        Self {
+            id: oauth::CodeId::new(Uuid::new_v4().to_string()),
+            username,
+            client_id,
+            redirect_uri,
+            scopes,
+            code_challenge,
+            nonce,
+            created_at: DateTime::from_system_time(now),
+            expires_at: DateTime::from_system_time(now + ttl),
+        }
+    }
+
+    pub fn is_expired(&self) -> bool {
+        self.expires_at.to_system_time() < SystemTime::now()
+    }
+}
+
+/// Refresh token used to obtain a new OAuth access token. Refresh tokens are
+/// rotated: each one can only be used once and is replaced by a new one.
+#[derive(Serialize, Deserialize, Clone, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct OAuthRefreshToken {
+    pub token_hash: String,
+    /// Access token issued along with the refresh token
+    pub token_id: oauth::TokenId,
+    pub client_id: oauth::ClientId,
+    pub username: String,
+    pub scopes: Vec<oauth::Scope>,
+    pub created_at: DateTime,
+    pub expires_at: DateTime,
+}
+
+impl OAuthRefreshToken {
+    pub fn new(token_hash: String, token: &OAuthToken, ttl: Duration) -> Self {
+        let now = SystemTime::now();
+        Self {
+            token_hash,
+            token_id: token.id.clone(),
+            client_id: token.client_id.clone(),
+            username: token.username.clone(),
+            scopes: token.scopes.clone(),
+            created_at: DateTime::from_system_time(now),
+            expires_at: DateTime::from_system_time(now + ttl),
+        }
+    }
+
+    pub fn is_expired(&self) -> bool {
+        self.expires_at.to_system_time() < SystemTime::now()
+    }
 }
 
 /// A magic link is used for password-less login. It has no
@@ -853,15 +1359,27 @@ pub(crate) fn sha512(text: &str) -> String {
 pub struct MagicLink {
     pub id: api::MagicLinkId,
     pub email: String,
+    /// Short numeric code which can be entered instead of following the link
+    pub code: String,
+    /// Number of times a code has been entered for the link
+    #[serde(default)]
+    pub code_attempts: u32,
     pub created_at: DateTime,
+    pub expires_at: DateTime,
 }
 
 impl MagicLink {
-    pub fn new(email: String) -> Self {
+    pub fn new(email: String, ttl: Duration) -> Self {
+        let now = SystemTime::now();
+        // UUIDs (v4) are generated using a CSPRNG
+        let code = Uuid::new_v4().as_u128() % 1_000_000;
         Self {
             id: api::MagicLinkId::new(Uuid::new_v4().to_string()),
             email,
-            created_at: DateTime::now(),
+            code: format!("{:06}", code),
+            code_attempts: 0,
+            created_at: DateTime::from_system_time(now),
+            expires_at: DateTime::from_system_time(now + ttl),
         }
     }
 }
@@ -871,11 +1389,181 @@ impl From<MagicLink> for Bson {
         Bson::Document(doc! {
             "id": link.id,
             "email": link.email,
+            "code": link.code,
+            "codeAttempts": link.code_attempts,
             "createdAt": link.created_at,
+            "expiresAt": link.expires_at,
         })
     }
 }
 
+/// Server-side session state (used by the session middleware). Only the
+/// hash of the session key (stored in the cookie) is persisted.
+#[derive(Serialize, Deserialize, Clone, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct UserSession {
+    pub id: String,
+    pub key_hash: String,
+    pub username: Option<String>,
+    pub state: HashMap<String, String>,
+    pub device: Option<String>,
+    pub ip: Option<String>,
+    pub created_at: DateTime,
+    pub last_seen: DateTime,
+    pub expires_at: DateTime,
+}
+
+impl UserSession {
+    pub fn new(key_hash: String, state: HashMap<String, String>, ttl: Duration) -> Self {
+        let now = SystemTime::now();
+        let mut session = Self {
+            id: Uuid::new_v4().to_string(),
+            key_hash,
+            username: None,
+            state: HashMap::new(),
+            device: None,
+            ip: None,
+            created_at: DateTime::from_system_time(now),
+            last_seen: DateTime::from_system_time(now),
+            expires_at: DateTime::from_system_time(now + ttl),
+        };
+        session.set_state(state);
+        session
+    }
+
+    /// Set the session state (and the fields derived from it)
+    pub fn set_state(&mut self, state: HashMap<String, String>) {
+        // session values are JSON-encoded by the session middleware
+        let get_value = |key: &str| {
+            state
+                .get(key)
+                .and_then(|value| serde_json::from_str::<String>(value).ok())
+        };
+        self.username = get_value("username");
+        self.device = get_value("device");
+        self.ip = get_value("ip");
+        self.state = state;
+    }
+}
+
+impl From<UserSession> for api::UserSession {
+    fn from(session: UserSession) -> api::UserSession {
+        api::UserSession {
+            id: session.id,
+            device: session.device,
+            ip: session.ip,
+            created_at: session.created_at.to_system_time(),
+            last_seen: session.last_seen.to_system_time(),
+        }
+    }
+}
+
+/// Personal access token for authenticating as a user without a session (eg,
+/// from scripts). Only the hash of the token is persisted.
+#[derive(Serialize, Deserialize, Clone, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct AccessToken {
+    pub id: String,
+    pub token_hash: String,
+    pub username: String,
+    pub name: String,
+    pub scopes: Vec<api::TokenScope>,
+    pub created_at: DateTime,
+    pub expires_at: Option<DateTime>,
+    pub last_used_at: Option<DateTime>,
+}
+
+impl AccessToken {
+    pub fn new(
+        username: String,
+        token_hash: String,
+        name: String,
+        scopes: Vec<api::TokenScope>,
+        ttl: Option<Duration>,
+    ) -> Self {
+        let now = SystemTime::now();
+        Self {
+            id: Uuid::new_v4().to_string(),
+            token_hash,
+            username,
+            name,
+            scopes,
+            created_at: DateTime::from_system_time(now),
+            expires_at: ttl.map(|ttl| DateTime::from_system_time(now + ttl)),
+            last_used_at: None,
+        }
+    }
+
+    pub fn is_expired(&self) -> bool {
+        self.expires_at
+            .map(|time| time.to_system_time() < SystemTime::now())
+            .unwrap_or(false)
+    }
+}
+
+impl From<AccessToken> for api::AccessToken {
+    fn from(token: AccessToken) -> api::AccessToken {
+        api::AccessToken {
+            id: token.id,
+            name: token.name,
+            scopes: token.scopes,
+            created_at: token.created_at.to_system_time(),
+            expires_at: token.expires_at.map(|time| time.to_system_time()),
+            last_used_at: token.last_used_at.map(|time| time.to_system_time()),
+        }
+    }
+}
+
+/// Record of a privileged or administrative action (eg, banning a user)
+#[derive(Serialize, Deserialize, Clone, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct AuditLogEntry {
+    pub id: String,
+    pub actor: String,
+    pub action: api::AuditAction,
+    pub target: String,
+    pub before: Option<serde_json::Value>,
+    pub after: Option<serde_json::Value>,
+    pub created_at: DateTime,
+}
+
+impl AuditLogEntry {
+    /// Create a new entry. The before and after values are omitted if they
+    /// serialize to null (eg, None).
+    pub fn new<B: Serialize, A: Serialize>(
+        actor: String,
+        action: api::AuditAction,
+        target: String,
+        before: &B,
+        after: &A,
+    ) -> Self {
+        let to_value = |value| Some(value).filter(|value: &serde_json::Value| !value.is_null());
+        Self {
+            id: Uuid::new_v4().to_string(),
+            actor,
+            action,
+            target,
+            before: serde_json::to_value(before).ok().and_then(to_value),
+            after: serde_json::to_value(after).ok().and_then(to_value),
+            created_at: DateTime::now(),
+        }
+    }
+}
+
+impl From<AuditLogEntry> for api::AuditLogEntry {
+    fn from(entry: AuditLogEntry) -> api::AuditLogEntry {
+        api::AuditLogEntry {
+            id: entry.id,
+            actor: entry.actor,
+            action: entry.action,
+            target: entry.target,
+            before: entry.before,
+            after: entry.after,
+            created_at: entry.created_at.to_system_time(),
+        }
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -901,7 +1589,8 @@ mod tests {
         let auth_host = AuthorizedServiceHost {
             url: "http://localhost:8000".into(),
             id: "SomeTrustedHost".into(),
-            secret: "SomeSecret".into(),
+            secrets: Vec::new(),
+            capabilities: Vec::new(),
             visibility: ServiceHostScope::Public(categories.clone()),
         };
         let host: ServiceHost = auth_host.into();
@@ -915,7 +1604,8 @@ mod tests {
         let auth_host = AuthorizedServiceHost {
             url: "http://localhost:8000".into(),
             id: "SomeTrustedHost".into(),
-            secret: "SomeSecret".into(),
+            secrets: Vec::new(),
+            capabilities: Vec::new(),
             visibility: ServiceHostScope::Private,
         };
         let host: ServiceHost = auth_host.into();
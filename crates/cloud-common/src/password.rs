@@ -0,0 +1,205 @@
+use argon2::{
+    password_hash::{rand_core::OsRng, SaltString},
+    Algorithm, Argon2, Params, PasswordHash, PasswordHasher, PasswordVerifier, Version,
+};
+use derive_more::{Display, Error};
+use serde::Deserialize;
+use sha2::{Digest, Sha512};
+
+/// Parameters for the memory-hard KDF (Argon2id) used for hashing passwords
+/// and other secrets. Hashes are stored as PHC strings so the parameters used
+/// for a given hash are always recoverable.
+#[derive(Deserialize, Clone, Debug, PartialEq, Eq)]
+#[serde(default)]
+pub struct HashParams {
+    /// Memory cost in KiB
+    pub memory_cost: u32,
+    /// Number of iterations
+    pub time_cost: u32,
+    /// Degree of parallelism
+    pub parallelism: u32,
+}
+
+impl Default for HashParams {
+    /// Defaults follow the OWASP recommendations for Argon2id
+    fn default() -> Self {
+        Self {
+            memory_cost: 19 * 1024,
+            time_cost: 2,
+            parallelism: 1,
+        }
+    }
+}
+
+impl HashParams {
+    fn hasher(&self) -> Result<Argon2<'static>, HashError> {
+        let params = Params::new(self.memory_cost, self.time_cost, self.parallelism, None)
+            .map_err(|_err| HashError::InvalidParamsError)?;
+
+        Ok(Argon2::new(Algorithm::Argon2id, Version::V0x13, params))
+    }
+
+    fn matches(&self, hash: &PasswordHash) -> bool {
+        let is_argon2id = hash.algorithm == Algorithm::Argon2id.ident();
+        is_argon2id
+            && Params::try_from(hash)
+                .map(|params| {
+                    params.m_cost() == self.memory_cost
+                        && params.t_cost() == self.time_cost
+                        && params.p_cost() == self.parallelism
+                })
+                .unwrap_or(false)
+    }
+}
+
+#[derive(Debug, Display, Error)]
+pub enum HashError {
+    #[display(fmt = "Invalid password hashing parameters.")]
+    InvalidParamsError,
+    #[display(fmt = "Unable to hash password.")]
+    HashingError,
+}
+
+/// Result of checking a password against a stored hash
+#[derive(Debug, PartialEq, Eq)]
+pub enum Verification {
+    Invalid,
+    /// The password is correct. If `needs_rehash` is set, the stored hash
+    /// uses a legacy scheme (or outdated parameters) and should be replaced.
+    Valid {
+        needs_rehash: bool,
+    },
+}
+
+impl Verification {
+    pub fn is_valid(&self) -> bool {
+        matches!(self, Verification::Valid { .. })
+    }
+}
+
+/// Hash the given password as an Argon2id PHC string
+pub fn hash(password: &str, params: &HashParams) -> Result<String, HashError> {
+    let salt = SaltString::generate(&mut OsRng);
+    let hash = params
+        .hasher()?
+        .hash_password(password.as_bytes(), &salt)
+        .map_err(|_err| HashError::HashingError)?;
+
+    Ok(hash.to_string())
+}
+
+/// Check the password against the stored hash. Hashes which are not PHC strings
+/// are assumed to be legacy `sha512(password + salt)` hashes.
+pub fn verify(password: &str, hash: &str, salt: Option<&str>, params: &HashParams) -> Verification {
+    match PasswordHash::new(hash) {
+        Ok(parsed) => {
+            let is_valid = Argon2::default()
+                .verify_password(password.as_bytes(), &parsed)
+                .is_ok();
+
+            if is_valid {
+                Verification::Valid {
+                    needs_rehash: !params.matches(&parsed),
+                }
+            } else {
+                Verification::Invalid
+            }
+        }
+        Err(_) => {
+            let legacy_hash = sha512(&(password.to_owned() + salt.unwrap_or_default()));
+            if legacy_hash == hash {
+                Verification::Valid { needs_rehash: true }
+            } else {
+                Verification::Invalid
+            }
+        }
+    }
+}
+
+pub(crate) fn sha512(text: &str) -> String {
+    let mut hasher = Sha512::new();
+    hasher.update(text);
+    let hash = hasher.finalize();
+    hex::encode(hash)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn test_params() -> HashParams {
+        HashParams {
+            memory_cost: 1024,
+            time_cost: 1,
+            parallelism: 1,
+        }
+    }
+
+    #[test]
+    fn test_hash_is_phc_string() {
+        let hash = hash("somePassword", &test_params()).unwrap();
+        assert!(hash.starts_with("$argon2id$"));
+    }
+
+    #[test]
+    fn test_verify_hash() {
+        let params = test_params();
+        let hash = hash("somePassword", &params).unwrap();
+        let result = verify("somePassword", &hash, None, &params);
+        assert_eq!(
+            result,
+            Verification::Valid {
+                needs_rehash: false
+            }
+        );
+    }
+
+    #[test]
+    fn test_verify_hash_incorrect() {
+        let params = test_params();
+        let hash = hash("somePassword", &params).unwrap();
+        let result = verify("otherPassword", &hash, None, &params);
+        assert_eq!(result, Verification::Invalid);
+    }
+
+    #[test]
+    fn test_verify_hash_outdated_params() {
+        let hash = hash("somePassword", &test_params()).unwrap();
+        let params = HashParams {
+            time_cost: 3,
+            ..test_params()
+        };
+        let result = verify("somePassword", &hash, None, &params);
+        assert_eq!(result, Verification::Valid { needs_rehash: true });
+    }
+
+    #[test]
+    fn test_verify_legacy_hash() {
+        let hash = sha512("somePasswordsalt");
+        let result = verify("somePassword", &hash, Some("salt"), &test_params());
+        assert_eq!(result, Verification::Valid { needs_rehash: true });
+    }
+
+    #[test]
+    fn test_verify_legacy_hash_no_salt() {
+        let hash = sha512("somePassword");
+        let result = verify("somePassword", &hash, None, &test_params());
+        assert_eq!(result, Verification::Valid { needs_rehash: true });
+    }
+
+    #[test]
+    fn test_verify_legacy_hash_incorrect() {
+        let hash = sha512("somePasswordsalt");
+        let result = verify("somePassword", &hash, Some("pepper"), &test_params());
+        assert_eq!(result, Verification::Invalid);
+    }
+
+    #[test]
+    fn test_invalid_params() {
+        let params = HashParams {
+            memory_cost: 0,
+            ..test_params()
+        };
+        assert!(hash("somePassword", &params).is_err());
+    }
+}
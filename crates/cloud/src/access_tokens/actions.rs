@@ -0,0 +1,309 @@
+use std::time::{Duration, SystemTime};
+
+use actix_web::http::Method;
+use futures::TryStreamExt;
+use mongodb::{
+    bson::{doc, DateTime},
+    options::FindOptions,
+    Collection,
+};
+use netsblox_cloud_common::{api, AccessToken, BannedAccount};
+use uuid::Uuid;
+
+use crate::{
+    auth,
+    errors::{InternalError, UserError},
+    utils,
+};
+
+use super::middleware::AuthenticatedToken;
+
+/// Minimum time between updates to the "last used" time of a token
+const LAST_USED_RESOLUTION: Duration = Duration::from_secs(60);
+const SECS_PER_DAY: u64 = 60 * 60 * 24;
+/// Prefix of personal access tokens (to distinguish them from other bearer
+/// tokens such as OAuth access tokens)
+pub(crate) const TOKEN_PREFIX: &str = "nb_";
+
+pub(crate) struct AccessTokenActions<'a> {
+    tokens: &'a Collection<AccessToken>,
+    banned_accounts: &'a Collection<BannedAccount>,
+}
+
+impl<'a> AccessTokenActions<'a> {
+    pub(crate) fn new(
+        tokens: &'a Collection<AccessToken>,
+        banned_accounts: &'a Collection<BannedAccount>,
+    ) -> Self {
+        Self {
+            tokens,
+            banned_accounts,
+        }
+    }
+
+    pub(crate) async fn create_token(
+        &self,
+        mt: &auth::ManageAccessTokens,
+        data: api::CreateAccessTokenData,
+    ) -> Result<api::CreatedAccessToken, UserError> {
+        let mut scopes = data.scopes;
+        scopes.sort();
+        scopes.dedup();
+        if scopes.is_empty() {
+            return Err(UserError::TokenScopeRequiredError);
+        }
+
+        let secret = new_secret();
+        let ttl = data
+            .expires_in_days
+            .map(|days| Duration::from_secs(days as u64 * SECS_PER_DAY));
+        let token = AccessToken::new(
+            mt.username.to_owned(),
+            utils::sha512(&secret),
+            data.name,
+            scopes,
+            ttl,
+        );
+
+        self.tokens
+            .insert_one(&token, None)
+            .await
+            .map_err(InternalError::DatabaseConnectionError)?;
+
+        Ok(api::CreatedAccessToken {
+            secret,
+            token: token.into(),
+        })
+    }
+
+    pub(crate) async fn list_tokens(
+        &self,
+        mt: &auth::ManageAccessTokens,
+    ) -> Result<Vec<api::AccessToken>, UserError> {
+        let query = doc! {"username": &mt.username};
+        let options = FindOptions::builder().sort(doc! {"createdAt": -1}).build();
+        let tokens = self
+            .tokens
+            .find(query, options)
+            .await
+            .map_err(InternalError::DatabaseConnectionError)?
+            .try_collect::<Vec<_>>()
+            .await
+            .map_err(InternalError::DatabaseConnectionError)?
+            .into_iter()
+            .map(|token| token.into())
+            .collect();
+
+        Ok(tokens)
+    }
+
+    pub(crate) async fn revoke_token(
+        &self,
+        mt: &auth::ManageAccessTokens,
+        id: &str,
+    ) -> Result<api::AccessToken, UserError> {
+        let query = doc! {"id": id, "username": &mt.username};
+        let token = self
+            .tokens
+            .find_one_and_delete(query, None)
+            .await
+            .map_err(InternalError::DatabaseConnectionError)?
+            .ok_or(UserError::AccessTokenNotFoundError)?;
+
+        Ok(token.into())
+    }
+
+    /// Resolve the user for the given token, ensuring it grants access to
+    /// requests using the given method.
+    pub(crate) async fn authenticate(
+        &self,
+        secret: &str,
+        method: &Method,
+    ) -> Result<AuthenticatedToken, UserError> {
+        let query = doc! {"tokenHash": utils::sha512(secret)};
+        let token = self
+            .tokens
+            .find_one(query, None)
+            .await
+            .map_err(InternalError::DatabaseConnectionError)?
+            .filter(|token| !token.is_expired())
+            .ok_or(UserError::InvalidAccessTokenError)?;
+
+        if !is_allowed(&token.scopes, method) {
+            return Err(UserError::InsufficientTokenScopeError);
+        }
+
+        let query = doc! {"$and": [
+            {"username": &token.username},
+            utils::not_expired(),
+        ]};
+        if self
+            .banned_accounts
+            .find_one(query, None)
+            .await
+            .map_err(InternalError::DatabaseConnectionError)?
+            .is_some()
+        {
+            return Err(UserError::BannedUserError);
+        }
+
+        let last_used_cutoff = DateTime::from_system_time(SystemTime::now() - LAST_USED_RESOLUTION);
+        let needs_update = token
+            .last_used_at
+            .map(|time| time < last_used_cutoff)
+            .unwrap_or(true);
+
+        if needs_update {
+            let query = doc! {"id": &token.id};
+            let update = doc! {"$set": {"lastUsedAt": DateTime::now()}};
+            self.tokens
+                .update_one(query, update, None)
+                .await
+                .map_err(InternalError::DatabaseConnectionError)?;
+        }
+
+        Ok(AuthenticatedToken {
+            username: token.username,
+        })
+    }
+}
+
+fn new_secret() -> String {
+    format!(
+        "{}{}{}",
+        TOKEN_PREFIX,
+        Uuid::new_v4().as_simple(),
+        Uuid::new_v4().as_simple()
+    )
+}
+
+/// Read-only tokens can only be used for requests which do not make changes
+fn is_allowed(scopes: &[api::TokenScope], method: &Method) -> bool {
+    let is_read = matches!(*method, Method::GET | Method::HEAD | Method::OPTIONS);
+    scopes.iter().any(|scope| match scope {
+        api::TokenScope::Write => true,
+        api::TokenScope::Read => is_read,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_utils;
+
+    fn token_data(scopes: Vec<api::TokenScope>) -> api::CreateAccessTokenData {
+        api::CreateAccessTokenData {
+            name: "script".into(),
+            scopes,
+            expires_in_days: None,
+        }
+    }
+
+    #[test]
+    fn test_read_scope_allows_get() {
+        assert!(is_allowed(&[api::TokenScope::Read], &Method::GET));
+        assert!(!is_allowed(&[api::TokenScope::Read], &Method::POST));
+        assert!(!is_allowed(&[api::TokenScope::Read], &Method::DELETE));
+    }
+
+    #[test]
+    fn test_write_scope_allows_all() {
+        assert!(is_allowed(&[api::TokenScope::Write], &Method::GET));
+        assert!(is_allowed(&[api::TokenScope::Write], &Method::POST));
+        assert!(!is_allowed(&[], &Method::GET));
+    }
+
+    #[actix_web::test]
+    async fn test_create_token_hashed() {
+        test_utils::setup()
+            .run(|app_data| async move {
+                let actions = app_data.as_access_token_actions();
+                let auth_mt = auth::ManageAccessTokens::test("user".into());
+                let created = actions
+                    .create_token(&auth_mt, token_data(vec![api::TokenScope::Read]))
+                    .await
+                    .unwrap();
+
+                let token = app_data
+                    .access_tokens
+                    .find_one(doc! {"id": &created.token.id}, None)
+                    .await
+                    .unwrap()
+                    .unwrap();
+                assert_ne!(token.token_hash, created.secret);
+                assert_eq!(token.token_hash, utils::sha512(&created.secret));
+
+                let auth = actions
+                    .authenticate(&created.secret, &Method::GET)
+                    .await
+                    .unwrap();
+                assert_eq!(auth.username, "user");
+            })
+            .await;
+    }
+
+    #[actix_web::test]
+    async fn test_authenticate_expired_token() {
+        test_utils::setup()
+            .run(|app_data| async move {
+                let actions = app_data.as_access_token_actions();
+                let auth_mt = auth::ManageAccessTokens::test("user".into());
+                let mut data = token_data(vec![api::TokenScope::Write]);
+                data.expires_in_days = Some(0);
+                let created = actions.create_token(&auth_mt, data).await.unwrap();
+
+                let result = actions.authenticate(&created.secret, &Method::GET).await;
+                assert!(matches!(result, Err(UserError::InvalidAccessTokenError)));
+            })
+            .await;
+    }
+
+    #[actix_web::test]
+    async fn test_authenticate_banned_user() {
+        test_utils::setup()
+            .run(|app_data| async move {
+                let actions = app_data.as_access_token_actions();
+                let auth_mt = auth::ManageAccessTokens::test("user".into());
+                let created = actions
+                    .create_token(&auth_mt, token_data(vec![api::TokenScope::Read]))
+                    .await
+                    .unwrap();
+
+                let account =
+                    BannedAccount::new("user".into(), "user@netsblox.org".into(), None, None, None);
+                app_data
+                    .banned_accounts
+                    .insert_one(account, None)
+                    .await
+                    .unwrap();
+
+                let result = actions.authenticate(&created.secret, &Method::GET).await;
+                assert!(matches!(result, Err(UserError::BannedUserError)));
+            })
+            .await;
+    }
+
+    #[actix_web::test]
+    async fn test_revoke_token_other_user() {
+        test_utils::setup()
+            .run(|app_data| async move {
+                let actions = app_data.as_access_token_actions();
+                let auth_mt = auth::ManageAccessTokens::test("other".into());
+                let created = actions
+                    .create_token(&auth_mt, token_data(vec![api::TokenScope::Read]))
+                    .await
+                    .unwrap();
+
+                let auth_mt = auth::ManageAccessTokens::test("user".into());
+                let result = actions.revoke_token(&auth_mt, &created.token.id).await;
+                assert!(matches!(result, Err(UserError::AccessTokenNotFoundError)));
+
+                let tokens = actions
+                    .list_tokens(&auth::ManageAccessTokens::test("other".into()))
+                    .await
+                    .unwrap();
+                assert_eq!(tokens.len(), 1);
+            })
+            .await;
+    }
+}
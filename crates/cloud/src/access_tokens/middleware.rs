@@ -0,0 +1,92 @@
+use std::rc::Rc;
+
+use actix_web::{
+    body::MessageBody,
+    dev::{forward_ready, Service, ServiceRequest, ServiceResponse, Transform},
+    http::header,
+    web, Error, HttpMessage,
+};
+use futures::future::{ready, LocalBoxFuture, Ready};
+
+use super::actions::TOKEN_PREFIX;
+use crate::{app_data::AppData, errors::UserError};
+
+/// Identity of a request authenticated with a personal access token. This is
+/// stored in the request extensions (and checked by `utils::get_username`).
+#[derive(Clone, Debug)]
+pub(crate) struct AuthenticatedToken {
+    pub(crate) username: String,
+}
+
+/// Authenticate requests with an `Authorization: Bearer <token>` header using
+/// personal access tokens. Requests without the header (or with other bearer
+/// tokens, eg, for the OAuth userinfo endpoint) are unaffected.
+pub(crate) struct AccessTokenAuth;
+
+impl<S, B> Transform<S, ServiceRequest> for AccessTokenAuth
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = Error> + 'static,
+    B: MessageBody + 'static,
+{
+    type Response = ServiceResponse<B>;
+    type Error = Error;
+    type Transform = AccessTokenAuthMiddleware<S>;
+    type InitError = ();
+    type Future = Ready<Result<Self::Transform, Self::InitError>>;
+
+    fn new_transform(&self, service: S) -> Self::Future {
+        ready(Ok(AccessTokenAuthMiddleware {
+            service: Rc::new(service),
+        }))
+    }
+}
+
+pub(crate) struct AccessTokenAuthMiddleware<S> {
+    service: Rc<S>,
+}
+
+impl<S, B> Service<ServiceRequest> for AccessTokenAuthMiddleware<S>
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = Error> + 'static,
+    B: MessageBody + 'static,
+{
+    type Response = ServiceResponse<B>;
+    type Error = Error;
+    type Future = LocalBoxFuture<'static, Result<Self::Response, Self::Error>>;
+
+    forward_ready!(service);
+
+    fn call(&self, req: ServiceRequest) -> Self::Future {
+        let service = Rc::clone(&self.service);
+
+        Box::pin(async move {
+            if let Some(secret) = bearer_token(&req) {
+                let app = req
+                    .app_data::<web::Data<AppData>>()
+                    .cloned()
+                    .ok_or(UserError::InternalError)?;
+
+                let token = app
+                    .as_access_token_actions()
+                    .authenticate(&secret, req.method())
+                    .await?;
+
+                req.extensions_mut().insert(token);
+            }
+
+            service.call(req).await
+        })
+    }
+}
+
+fn bearer_token(req: &ServiceRequest) -> Option<String> {
+    req.headers()
+        .get(header::AUTHORIZATION)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| {
+            let (scheme, token) = value.split_once(' ')?;
+            let token = token.trim();
+            (scheme.eq_ignore_ascii_case("bearer") && token.starts_with(TOKEN_PREFIX))
+                .then(|| token.to_owned())
+        })
+}
@@ -1,29 +1,41 @@
 pub(crate) mod metrics;
 
+use crate::access_tokens::actions::AccessTokenActions;
+use crate::audit::actions::AuditActions;
 use crate::collaboration_invites::actions::CollaborationInviteActions;
-use crate::common::api::{oauth, NewUser, ProjectId, UserRole};
+use crate::common::api::{NewUser, ProjectId, UserRole};
 use crate::friends::actions::FriendActions;
 use crate::groups::actions::GroupActions;
+use crate::ip_reputation::actions::IpReputationActions;
+use crate::ip_reputation::IpLists;
 use crate::libraries::actions::LibraryActions;
+use crate::login_attempts::actions::LoginAttemptActions;
 use crate::login_helper::LoginHelper;
 use crate::magic_links::actions::MagicLinkActions;
 use crate::network::actions::NetworkActions;
-use crate::oauth::actions::OAuthActions;
+use crate::network::secrets::ClientSecrets;
+use crate::oauth::actions::{OAuthActionData, OAuthActions};
+use crate::oauth::oidc::SigningKeys;
 use crate::projects::ProjectActions;
 use crate::services::hosts::actions::HostActions;
+use crate::services::hosts::signing::{self, KeyCipher};
 use crate::services::settings::actions::SettingsActions;
+use crate::sessions::actions::SessionActions;
+use crate::sessions::store::MongoSessionStore;
 use crate::users::actions::{UserActionData, UserActions};
+use crate::users::deletion::AccountDeletionJob;
+use crate::users::strategies::Strategies;
+use crate::utils;
 use actix::dev::OneshotSender;
 use actix_web::rt::time;
 use lettre::message::Mailbox;
 use lettre::transport::smtp::authentication::Credentials;
 use lettre::SmtpTransport;
-use log::{error, info, warn};
+use log::{error, info};
 use lru::LruCache;
 use mongodb::bson::{doc, Document};
 use mongodb::options::{FindOptions, IndexOptions, UpdateOptions};
 use netsblox_cloud_common::{api, MagicLink};
-use serde::{Deserialize, Serialize};
 use std::collections::HashSet;
 use std::net::IpAddr;
 use std::sync::{Arc, RwLock};
@@ -32,12 +44,16 @@ use tokio::sync::RwLock as AsyncRwLock;
 
 use crate::common::api::SaveState;
 use crate::common::{
-    AuthorizedServiceHost, BannedAccount, CollaborationInvite, FriendLink, Group, Library,
-    OAuthClient, OAuthToken, ProjectMetadata, SetPasswordToken, User,
+    AccessToken, AccountDeletion, AuditLogEntry, AuthorizedServiceHost, BannedAccount,
+    BannedAddress, CollaborationInvite, EmailVerificationToken, FriendLink, Group,
+    HostRequestNonce, IpReputationEntry, Library, LoginFailureCounter, OAuthClient, OAuthCode,
+    OAuthRefreshToken, OAuthToken, ProjectMetadata, SetPasswordToken, SignupChallenge, User,
+    UserSession,
 };
 use crate::common::{LogMessage, OccupantInvite, SentMessage};
-use crate::config::Settings;
+use crate::config::{Settings, TopologyBackendKind};
 use crate::errors::{InternalError, UserError};
+use crate::network::topology::backend::{MemoryBackend, MongoBackend, TopologyBackend};
 use crate::network::topology::{SetStorage, TopologyActor, TopologyPanic};
 use actix::{Actor, Addr};
 use aws_config::SdkConfig;
@@ -49,20 +65,30 @@ use mongodb::{Client, Collection, IndexModel};
 #[derive(Clone)]
 pub struct AppData {
     bucket: String,
-    tor_exit_nodes: Collection<TorNode>,
     s3: s3::Client,
     pub(crate) settings: Settings,
     pub(crate) network: Addr<TopologyActor>,
     pub(crate) groups: Collection<Group>,
     pub(crate) users: Collection<User>,
     pub(crate) banned_accounts: Collection<BannedAccount>,
-    friends: Collection<FriendLink>,
+    pub(crate) banned_addresses: Collection<BannedAddress>,
+    pub(crate) sessions: Collection<UserSession>,
+    pub(crate) access_tokens: Collection<AccessToken>,
+    pub(crate) strategies: Arc<Strategies>,
+    pub(crate) friends: Collection<FriendLink>,
     magic_links: Collection<MagicLink>,
     pub(crate) project_metadata: Collection<ProjectMetadata>,
     pub(crate) libraries: Collection<Library>,
     pub(crate) authorized_services: Collection<AuthorizedServiceHost>,
+    pub(crate) host_nonces: Collection<HostRequestNonce>,
+    pub(crate) signup_challenges: Collection<SignupChallenge>,
+    pub(crate) login_failures: Collection<LoginFailureCounter>,
+    pub(crate) ip_reputation_entries: Collection<IpReputationEntry>,
 
     pub(crate) password_tokens: Collection<SetPasswordToken>,
+    pub(crate) verification_tokens: Collection<EmailVerificationToken>,
+    pub(crate) audit_log: Collection<AuditLogEntry>,
+    pub(crate) account_deletions: Collection<AccountDeletion>,
     pub(crate) recorded_messages: Collection<SentMessage>,
     pub(crate) logged_messages: Collection<LogMessage>,
     pub(crate) collab_invites: Collection<CollaborationInvite>,
@@ -70,9 +96,13 @@ pub struct AppData {
 
     pub(crate) oauth_clients: Collection<OAuthClient>,
     pub(crate) oauth_tokens: Collection<OAuthToken>,
-    pub(crate) oauth_codes: Collection<oauth::Code>,
+    pub(crate) oauth_codes: Collection<OAuthCode>,
+    pub(crate) oauth_refresh_tokens: Collection<OAuthRefreshToken>,
+    pub(crate) oauth_signing_keys: Arc<SigningKeys>,
 
     pub(crate) metrics: metrics::Metrics,
+    pub(crate) client_secrets: ClientSecrets,
+    pub(crate) ip_lists: IpLists,
     mailer: SmtpTransport,
     sender: Mailbox,
 
@@ -131,30 +161,60 @@ impl AppData {
         let groups = db.collection::<Group>(&(prefix.to_owned() + "groups"));
         let password_tokens =
             db.collection::<SetPasswordToken>(&(prefix.to_owned() + "passwordTokens"));
+        let verification_tokens = db
+            .collection::<EmailVerificationToken>(&(prefix.to_owned() + "emailVerificationTokens"));
         let users = db.collection::<User>(&(prefix.to_owned() + "users"));
         let banned_accounts =
             db.collection::<BannedAccount>(&(prefix.to_owned() + "bannedAccounts"));
+        let banned_addresses =
+            db.collection::<BannedAddress>(&(prefix.to_owned() + "bannedAddresses"));
+        let sessions = db.collection::<UserSession>(&(prefix.to_owned() + "sessions"));
+        let access_tokens = db.collection::<AccessToken>(&(prefix.to_owned() + "accessTokens"));
         let project_metadata = db.collection::<ProjectMetadata>(&(prefix.to_owned() + "projects"));
         let libraries = db.collection::<Library>(&(prefix.to_owned() + "libraries"));
         let authorized_services =
             db.collection::<AuthorizedServiceHost>(&(prefix.to_owned() + "authorizedServices"));
+        let host_nonces =
+            db.collection::<HostRequestNonce>(&(prefix.to_owned() + "serviceHostNonces"));
+        let signup_challenges =
+            db.collection::<SignupChallenge>(&(prefix.to_owned() + "signupChallenges"));
+        let login_failures =
+            db.collection::<LoginFailureCounter>(&(prefix.to_owned() + "loginFailures"));
+        let ip_reputation_entries =
+            db.collection::<IpReputationEntry>(&(prefix.to_owned() + "ipReputationEntries"));
         let collab_invites =
             db.collection::<CollaborationInvite>(&(prefix.to_owned() + "collaborationInvitations"));
         let occupant_invites =
             db.collection::<OccupantInvite>(&(prefix.to_owned() + "occupantInvites"));
+        let audit_log = db.collection::<AuditLogEntry>(&(prefix.to_owned() + "auditLog"));
+        let account_deletions =
+            db.collection::<AccountDeletion>(&(prefix.to_owned() + "accountDeletions"));
         let friends = db.collection::<FriendLink>(&(prefix.to_owned() + "friends"));
         let magic_links = db.collection::<MagicLink>(&(prefix.to_owned() + "magicLinks"));
         let recorded_messages =
             db.collection::<SentMessage>(&(prefix.to_owned() + "recordedMessages"));
         let logged_messages = db.collection::<LogMessage>(&(prefix.to_owned() + "loggedMessages"));
         let network = network.unwrap_or_else(|| {
-            TopologyActor::new(settings.cache_settings.num_addresses, tx).start()
+            let backend: Arc<dyn TopologyBackend> = match settings.topology.backend {
+                TopologyBackendKind::Memory => Arc::new(MemoryBackend::new()),
+                TopologyBackendKind::Mongo => {
+                    Arc::new(MongoBackend::new(&db, prefix, &settings.topology))
+                }
+            };
+            TopologyActor::new(settings.cache_settings.num_addresses, backend, tx).start()
         });
         let oauth_clients = db.collection::<OAuthClient>(&(prefix.to_owned() + "oauthClients"));
         let oauth_tokens = db.collection::<OAuthToken>(&(prefix.to_owned() + "oauthToken"));
-        let oauth_codes = db.collection::<oauth::Code>(&(prefix.to_owned() + "oauthCode"));
-        let tor_exit_nodes = db.collection::<TorNode>(&(prefix.to_owned() + "torExitNodes"));
+        let oauth_codes = db.collection::<OAuthCode>(&(prefix.to_owned() + "oauthCode"));
+        let oauth_refresh_tokens =
+            db.collection::<OAuthRefreshToken>(&(prefix.to_owned() + "oauthRefreshTokens"));
         let bucket = settings.s3.bucket.clone();
+        let strategies = Arc::new(Strategies::new(&settings.auth));
+        let client_secrets = ClientSecrets::new(&settings.cookie.key);
+        let ip_lists = IpLists::new(&settings.ip_reputation);
+        let oauth_signing_keys = Arc::new(
+            SigningKeys::new(&settings.oauth.signing_keys).expect("Invalid OAuth signing key."),
+        );
 
         let project_cache = Arc::new(RwLock::new(LruCache::new(
             settings.cache_settings.num_projects,
@@ -177,13 +237,24 @@ impl AppData {
             groups,
             users,
             banned_accounts,
+            banned_addresses,
+            sessions,
+            access_tokens,
+            strategies,
             project_metadata,
             libraries,
             authorized_services,
+            host_nonces,
+            signup_challenges,
+            login_failures,
+            ip_reputation_entries,
 
             collab_invites,
             occupant_invites,
             password_tokens,
+            verification_tokens,
+            audit_log,
+            account_deletions,
             friends,
             magic_links,
 
@@ -193,10 +264,13 @@ impl AppData {
             oauth_clients,
             oauth_tokens,
             oauth_codes,
+            oauth_refresh_tokens,
+            oauth_signing_keys,
 
             metrics: metrics::Metrics::new(),
+            client_secrets,
+            ip_lists,
 
-            tor_exit_nodes,
             recorded_messages,
             logged_messages,
             project_cache,
@@ -245,6 +319,263 @@ impl AppData {
             .await
             .map_err(InternalError::DatabaseConnectionError)?;
 
+        self.audit_log
+            .create_indexes(
+                vec![
+                    IndexModel::builder().keys(doc! {"createdAt": -1}).build(),
+                    IndexModel::builder().keys(doc! {"actor": 1}).build(),
+                    IndexModel::builder().keys(doc! {"target": 1}).build(),
+                ],
+                None,
+            )
+            .await
+            .map_err(InternalError::DatabaseConnectionError)?;
+
+        let one_month = Duration::from_secs(60 * 60 * 24 * 30);
+        self.account_deletions
+            .create_indexes(
+                vec![
+                    IndexModel::builder()
+                        .keys(doc! {"username": 1})
+                        .options(IndexOptions::builder().unique(true).build())
+                        .build(),
+                    IndexModel::builder()
+                        .keys(doc! {"updatedAt": 1})
+                        .options(IndexOptions::builder().expire_after(one_month).build())
+                        .build(),
+                ],
+                None,
+            )
+            .await
+            .map_err(InternalError::DatabaseConnectionError)?;
+
+        let one_day = Duration::from_secs(60 * 60 * 24);
+        self.verification_tokens
+            .create_indexes(
+                vec![
+                    IndexModel::builder()
+                        .keys(doc! {"secret": 1})
+                        .options(IndexOptions::builder().unique(true).build())
+                        .build(),
+                    IndexModel::builder()
+                        .keys(doc! {"username": 1})
+                        .options(IndexOptions::builder().unique(true).build())
+                        .build(),
+                    IndexModel::builder()
+                        .keys(doc! {"createdAt": 1})
+                        .options(IndexOptions::builder().expire_after(one_day).build())
+                        .build(),
+                ],
+                None,
+            )
+            .await
+            .map_err(InternalError::DatabaseConnectionError)?;
+
+        // remove temporary bans once they expire (permanent bans are kept)
+        let ban_expiry_index = IndexModel::builder()
+            .keys(doc! {"expiresAt": 1})
+            .options(
+                IndexOptions::builder()
+                    .expire_after(Duration::from_secs(0))
+                    .build(),
+            )
+            .build();
+        self.banned_accounts
+            .create_indexes(
+                vec![
+                    IndexModel::builder().keys(doc! {"username": 1}).build(),
+                    IndexModel::builder().keys(doc! {"email": 1}).build(),
+                    ban_expiry_index.clone(),
+                ],
+                None,
+            )
+            .await
+            .map_err(InternalError::DatabaseConnectionError)?;
+
+        self.banned_addresses
+            .create_indexes(
+                vec![
+                    IndexModel::builder()
+                        .keys(doc! {"id": 1})
+                        .options(IndexOptions::builder().unique(true).build())
+                        .build(),
+                    ban_expiry_index,
+                ],
+                None,
+            )
+            .await
+            .map_err(InternalError::DatabaseConnectionError)?;
+
+        self.sessions
+            .create_indexes(
+                vec![
+                    IndexModel::builder()
+                        .keys(doc! {"keyHash": 1})
+                        .options(IndexOptions::builder().unique(true).build())
+                        .build(),
+                    IndexModel::builder().keys(doc! {"username": 1}).build(),
+                    // remove sessions once they expire
+                    IndexModel::builder()
+                        .keys(doc! {"expiresAt": 1})
+                        .options(
+                            IndexOptions::builder()
+                                .expire_after(Duration::from_secs(0))
+                                .build(),
+                        )
+                        .build(),
+                ],
+                None,
+            )
+            .await
+            .map_err(InternalError::DatabaseConnectionError)?;
+
+        // nonces only need to be kept until the request timestamps would be rejected
+        // (timestamps up to the max age in the future are allowed, too)
+        let max_age = Duration::from_secs(self.settings.service_hosts.signature_max_age_secs);
+        self.host_nonces
+            .create_indexes(
+                vec![
+                    IndexModel::builder()
+                        .keys(doc! {"hostId": 1, "nonce": 1})
+                        .options(IndexOptions::builder().unique(true).build())
+                        .build(),
+                    IndexModel::builder()
+                        .keys(doc! {"createdAt": 1})
+                        .options(IndexOptions::builder().expire_after(max_age * 2).build())
+                        .build(),
+                ],
+                None,
+            )
+            .await
+            .map_err(InternalError::DatabaseConnectionError)?;
+
+        // signups are counted by address (and subnet) to limit new accounts
+        self.users
+            .create_indexes(
+                vec![
+                    IndexModel::builder()
+                        .keys(doc! {"signupIp": 1, "createdAt": 1})
+                        .build(),
+                    IndexModel::builder()
+                        .keys(doc! {"signupSubnet": 1, "createdAt": 1})
+                        .build(),
+                ],
+                None,
+            )
+            .await
+            .map_err(InternalError::DatabaseConnectionError)?;
+
+        let challenge_ttl = Duration::from_secs(self.settings.signup.challenge_ttl_secs);
+        self.signup_challenges
+            .create_indexes(
+                vec![
+                    IndexModel::builder()
+                        .keys(doc! {"id": 1})
+                        .options(IndexOptions::builder().unique(true).build())
+                        .build(),
+                    IndexModel::builder()
+                        .keys(doc! {"createdAt": 1})
+                        .options(IndexOptions::builder().expire_after(challenge_ttl).build())
+                        .build(),
+                ],
+                None,
+            )
+            .await
+            .map_err(InternalError::DatabaseConnectionError)?;
+
+        // counters are reset once no login has failed for a while
+        let counter_ttl = Duration::from_secs(self.settings.login_protection.reset_after_secs);
+        self.login_failures
+            .create_indexes(
+                vec![
+                    IndexModel::builder()
+                        .keys(doc! {"kind": 1, "key": 1})
+                        .options(IndexOptions::builder().unique(true).build())
+                        .build(),
+                    IndexModel::builder()
+                        .keys(doc! {"updatedAt": 1})
+                        .options(IndexOptions::builder().expire_after(counter_ttl).build())
+                        .build(),
+                ],
+                None,
+            )
+            .await
+            .map_err(InternalError::DatabaseConnectionError)?;
+
+        self.access_tokens
+            .create_indexes(
+                vec![
+                    IndexModel::builder()
+                        .keys(doc! {"tokenHash": 1})
+                        .options(IndexOptions::builder().unique(true).build())
+                        .build(),
+                    IndexModel::builder().keys(doc! {"username": 1}).build(),
+                    // remove tokens once they expire (tokens without an expiration are kept)
+                    IndexModel::builder()
+                        .keys(doc! {"expiresAt": 1})
+                        .options(
+                            IndexOptions::builder()
+                                .expire_after(Duration::from_secs(0))
+                                .build(),
+                        )
+                        .build(),
+                ],
+                None,
+            )
+            .await
+            .map_err(InternalError::DatabaseConnectionError)?;
+
+        // remove OAuth codes and tokens once they expire (legacy tokens without an
+        // expiration are kept)
+        let oauth_expiry_index = IndexModel::builder()
+            .keys(doc! {"expiresAt": 1})
+            .options(
+                IndexOptions::builder()
+                    .expire_after(Duration::from_secs(0))
+                    .build(),
+            )
+            .build();
+        self.oauth_codes
+            .create_indexes(
+                vec![
+                    IndexModel::builder()
+                        .keys(doc! {"id": 1})
+                        .options(IndexOptions::builder().unique(true).build())
+                        .build(),
+                    oauth_expiry_index.clone(),
+                ],
+                None,
+            )
+            .await
+            .map_err(InternalError::DatabaseConnectionError)?;
+
+        self.oauth_tokens
+            .create_indexes(
+                vec![
+                    IndexModel::builder().keys(doc! {"id": 1}).build(),
+                    IndexModel::builder().keys(doc! {"username": 1}).build(),
+                    oauth_expiry_index.clone(),
+                ],
+                None,
+            )
+            .await
+            .map_err(InternalError::DatabaseConnectionError)?;
+
+        self.oauth_refresh_tokens
+            .create_indexes(
+                vec![
+                    IndexModel::builder()
+                        .keys(doc! {"tokenHash": 1})
+                        .options(IndexOptions::builder().unique(true).build())
+                        .build(),
+                    IndexModel::builder().keys(doc! {"username": 1}).build(),
+                    oauth_expiry_index,
+                ],
+                None,
+            )
+            .await
+            .map_err(InternalError::DatabaseConnectionError)?;
+
         let one_week = Duration::from_secs(60 * 60 * 24 * 7);
         self.project_metadata
             .create_indexes(
@@ -283,16 +614,44 @@ impl AppData {
         // Initialize Message Logs
         self.initialize_message_log().await?;
 
-        self.tor_exit_nodes
-            .create_index(IndexModel::builder().keys(doc! {"addr": 1}).build(), None)
+        self.ip_reputation_entries
+            .create_indexes(
+                vec![
+                    IndexModel::builder().keys(doc! {"source": 1}).build(),
+                    IndexModel::builder()
+                        .keys(doc! {"id": 1})
+                        .options(IndexOptions::builder().unique(true).build())
+                        .build(),
+                ],
+                None,
+            )
             .await
             .map_err(InternalError::DatabaseConnectionError)?;
 
-        let index_opts = IndexOptions::builder().expire_after(one_hour).build();
-        let magic_link_indexes = vec![IndexModel::builder()
-            .keys(doc! {"createdAt": 1})
-            .options(index_opts)
-            .build()];
+        // Magic links used to expire after an hour (using a TTL index on
+        // createdAt). They now expire at their own expiresAt.
+        if self
+            .magic_links
+            .drop_index("createdAt_1", None)
+            .await
+            .is_ok()
+        {
+            self.magic_links
+                .delete_many(doc! {"expiresAt": {"$exists": false}}, None)
+                .await
+                .map_err(InternalError::DatabaseConnectionError)?;
+        }
+
+        let index_opts = IndexOptions::builder()
+            .expire_after(Duration::from_secs(0))
+            .build();
+        let magic_link_indexes = vec![
+            IndexModel::builder()
+                .keys(doc! {"expiresAt": 1})
+                .options(index_opts)
+                .build(),
+            IndexModel::builder().keys(doc! {"email": 1}).build(),
+        ];
         self.magic_links
             .create_indexes(magic_link_indexes, None)
             .await
@@ -302,19 +661,20 @@ impl AppData {
             app_data: self.clone(),
         });
 
-        if !self.settings.security.allow_tor_login {
-            self.start_update_interval();
-        }
+        self.start_ip_list_refresh();
 
         if let Some(admin) = self.settings.admin.as_ref() {
-            let user: User = NewUser {
-                username: admin.username.to_owned(),
-                password: Some(admin.password.to_owned()),
-                email: admin.email.to_owned(),
-                group_id: None,
-                role: Some(UserRole::Admin),
-            }
-            .into();
+            let user = User::from_new_user(
+                NewUser {
+                    username: admin.username.to_owned(),
+                    password: Some(admin.password.to_owned()),
+                    email: admin.email.to_owned(),
+                    group_id: None,
+                    role: Some(UserRole::Admin),
+                },
+                &self.settings.security.password_hashing,
+            )
+            .map_err(InternalError::PasswordHashError)?;
 
             let query = doc! {"username": &user.username};
             let update = doc! {"$setOnInsert": &user};
@@ -328,20 +688,92 @@ impl AppData {
                 .map_err(InternalError::DatabaseConnectionError)?;
         }
 
+        self.hash_legacy_host_secrets().await?;
+        self.grant_legacy_host_capabilities().await?;
+
         if let Some(host_config) = self.settings.authorized_host.as_ref() {
-            let host: AuthorizedServiceHost = host_config.clone().into();
+            let cipher = KeyCipher::new(&self.settings);
+            let secret = signing::host_secret(&cipher, &host_config.secret)?;
+            let host = host_config.clone().into_host(secret);
+            // The URL and capabilities are updated to match the config (eg, after
+            // changing the capabilities granted to the host)
             let query = doc! {"id": &host.id};
-            let update = doc! {"$setOnInsert": &host};
+            let update = doc! {
+                "$set": {
+                    "url": &host.url,
+                    "capabilities": &host.capabilities,
+                },
+                "$setOnInsert": {
+                    "visibility": &host.visibility,
+                    "secrets": &host.secrets,
+                },
+            };
             let options = UpdateOptions::builder().upsert(true).build();
             self.authorized_services
                 .update_one(query, update, options)
                 .await
                 .map_err(InternalError::DatabaseConnectionError)?;
+
+            // The host may have been added before signing keys were stored
+            utils::store_host_signing_key(
+                &self.authorized_services,
+                &cipher,
+                &host.id,
+                &host_config.secret,
+            )
+            .await?;
+        }
+
+        AccountDeletionJob::resume_all(self).await?;
+
+        Ok(())
+    }
+
+    /// Replace the plaintext secrets of authorized hosts (stored before secrets
+    /// were hashed) with hashed secrets
+    async fn hash_legacy_host_secrets(&self) -> Result<(), InternalError> {
+        let hosts = self.authorized_services.clone_with_type::<Document>();
+        let legacy_hosts = hosts
+            .find(doc! {"secret": {"$type": "string"}}, None)
+            .await
+            .map_err(InternalError::DatabaseConnectionError)?
+            .try_collect::<Vec<_>>()
+            .await
+            .map_err(InternalError::DatabaseConnectionError)?;
+
+        let cipher = KeyCipher::new(&self.settings);
+        for host in legacy_hosts {
+            let (id, secret) = match (host.get_str("id"), host.get_str("secret")) {
+                (Ok(id), Ok(secret)) => (id, secret),
+                _ => continue,
+            };
+            let secret = signing::host_secret(&cipher, secret)?;
+            let update = doc! {
+                "$push": {"secrets": secret},
+                "$unset": {"secret": true}
+            };
+            hosts
+                .update_one(doc! {"id": id}, update, None)
+                .await
+                .map_err(InternalError::DatabaseConnectionError)?;
         }
 
         Ok(())
     }
 
+    /// Grant all capabilities to the hosts authorized before capabilities
+    /// existed (since they previously had full access)
+    async fn grant_legacy_host_capabilities(&self) -> Result<(), InternalError> {
+        let query = doc! {"capabilities": {"$exists": false}};
+        let update = doc! {"$set": {"capabilities": api::HostCapability::all()}};
+        self.authorized_services
+            .update_many(query, update, None)
+            .await
+            .map_err(InternalError::DatabaseConnectionError)?;
+
+        Ok(())
+    }
+
     async fn initialize_message_log(&self) -> Result<(), InternalError> {
         let three_months = Duration::from_secs(60 * 60 * 24 * 30 * 3);
         let index_opts = IndexOptions::builder().expire_after(three_months).build();
@@ -358,16 +790,16 @@ impl AppData {
         Ok(())
     }
 
-    fn start_update_interval(&self) {
-        let tor_exit_nodes = self.tor_exit_nodes.clone();
+    /// Load the IP reputation lists, admin entries and banned addresses and reload
+    /// them periodically (to include changes made elsewhere, eg, by another server)
+    fn start_ip_list_refresh(&self) {
+        let app = self.clone();
         actix_web::rt::spawn(async move {
-            let one_day = Duration::from_secs(60 * 60 * 24);
-            let mut interval = time::interval(one_day);
+            let period = Duration::from_secs(app.settings.ip_reputation.refresh_interval_secs);
+            let mut interval = time::interval(period);
             loop {
-                if let Err(error) = update_tor_nodes(&tor_exit_nodes).await {
-                    warn!("Unable to update Tor nodes: {:?}", error);
-                }
                 interval.tick().await;
+                app.as_ip_reputation_actions().refresh().await;
             }
         });
     }
@@ -524,23 +956,16 @@ impl AppData {
             .unwrap_or(false)
     }
 
-    // Tor-related restrictions
-    pub async fn ensure_not_tor_ip(&self, ip_addr: &IpAddr) -> Result<(), UserError> {
-        let ip_addr = ip_addr.to_string();
-        let query = doc! {"addr": &ip_addr};
-        let node = self
-            .tor_exit_nodes
-            .find_one(query, None)
+    /// Ensure the address hasn't been banned and isn't on an IP reputation list
+    /// blocking the action (eg, Tor exit nodes)
+    pub async fn ensure_ip_allowed(
+        &self,
+        ip_addr: &IpAddr,
+        action: api::IpReputationAction,
+    ) -> Result<(), UserError> {
+        self.as_ip_reputation_actions()
+            .ensure_allowed(ip_addr, action)
             .await
-            .map_err(InternalError::DatabaseConnectionError)?;
-
-        if node.is_some() {
-            Err(UserError::TorAddressError)
-        } else if is_opera_vpn(&ip_addr) {
-            Err(UserError::OperaVPNError)
-        } else {
-            Ok(())
-        }
     }
 
     #[cfg(test)]
@@ -583,7 +1008,7 @@ impl AppData {
 
     // get resource actions (eg, libraries, users, etc)
     pub(crate) fn as_library_actions(&self) -> LibraryActions {
-        LibraryActions::new(&self.libraries)
+        LibraryActions::new(&self.libraries, &self.audit_log)
     }
 
     pub(crate) fn as_project_actions(&self) -> ProjectActions {
@@ -593,11 +1018,12 @@ impl AppData {
             &self.network,
             &self.bucket,
             &self.s3,
+            &self.audit_log,
         )
     }
 
     pub(crate) fn as_group_actions(&self) -> GroupActions {
-        GroupActions::new(&self.groups, &self.users)
+        GroupActions::new(&self.groups, &self.users, &self.audit_log)
     }
 
     pub(crate) fn as_friend_actions(&self) -> FriendActions {
@@ -614,6 +1040,8 @@ impl AppData {
         MagicLinkActions::new(
             &self.magic_links,
             &self.users,
+            &self.settings.magic_links,
+            &self.settings.allowed_redirect_origins,
             &self.mailer,
             &self.sender,
             &self.settings.public_url,
@@ -645,15 +1073,41 @@ impl AppData {
     }
 
     pub(crate) fn as_oauth_actions(&self) -> OAuthActions {
-        OAuthActions::new(&self.oauth_clients, &self.oauth_tokens, &self.oauth_codes)
+        let data = OAuthActionData {
+            clients: &self.oauth_clients,
+            tokens: &self.oauth_tokens,
+            codes: &self.oauth_codes,
+            refresh_tokens: &self.oauth_refresh_tokens,
+            users: &self.users,
+            banned_accounts: &self.banned_accounts,
+            signing_keys: &self.oauth_signing_keys,
+            settings: &self.settings.oauth,
+            hash_params: &self.settings.security.password_hashing,
+            public_url: &self.settings.public_url,
+        };
+
+        OAuthActions::new(data)
     }
 
     pub(crate) fn as_user_actions(&self) -> UserActions {
         let data = UserActionData {
             users: &self.users,
             banned_accounts: &self.banned_accounts,
+            banned_addresses: &self.banned_addresses,
+            ip_lists: &self.ip_lists,
+            sessions: &self.sessions,
+            access_tokens: &self.access_tokens,
+            oauth_tokens: &self.oauth_tokens,
+            oauth_refresh_tokens: &self.oauth_refresh_tokens,
             password_tokens: &self.password_tokens,
+            verification_tokens: &self.verification_tokens,
+            audit_log: &self.audit_log,
+            account_deletions: &self.account_deletions,
+            signup_challenges: &self.signup_challenges,
+            signup_settings: &self.settings.signup,
             metrics: &self.metrics,
+            hash_params: &self.settings.security.password_hashing,
+            strategies: &self.strategies,
 
             network: &self.network,
             friend_cache: &self.friend_cache,
@@ -665,17 +1119,60 @@ impl AppData {
         UserActions::new(data)
     }
 
+    pub(crate) fn as_session_actions(&self) -> SessionActions {
+        SessionActions::new(&self.sessions)
+    }
+
+    pub(crate) fn as_access_token_actions(&self) -> AccessTokenActions {
+        AccessTokenActions::new(&self.access_tokens, &self.banned_accounts)
+    }
+
+    pub(crate) fn as_session_store(&self) -> MongoSessionStore {
+        MongoSessionStore::new(self.sessions.clone())
+    }
+
     pub(crate) fn as_host_actions(&self) -> HostActions {
-        HostActions::new(&self.authorized_services)
+        HostActions::new(
+            &self.authorized_services,
+            &self.audit_log,
+            &self.settings.service_hosts,
+            KeyCipher::new(&self.settings),
+        )
+    }
+
+    pub(crate) fn as_audit_actions(&self) -> AuditActions {
+        AuditActions::new(&self.audit_log)
+    }
+
+    pub(crate) fn as_ip_reputation_actions(&self) -> IpReputationActions {
+        IpReputationActions::new(
+            &self.settings.ip_reputation,
+            &self.ip_lists,
+            &self.ip_reputation_entries,
+            &self.banned_addresses,
+            &self.audit_log,
+        )
+    }
+
+    pub(crate) fn as_login_attempt_actions(&self) -> LoginAttemptActions {
+        LoginAttemptActions::new(
+            &self.login_failures,
+            &self.users,
+            &self.settings.login_protection,
+            &self.mailer,
+            &self.sender,
+        )
     }
 
     pub(crate) fn as_login_helper(&self) -> LoginHelper {
         LoginHelper::new(
             &self.network,
             &self.metrics,
+            &self.client_secrets,
             &self.project_metadata,
             &self.project_cache,
             &self.banned_accounts,
+            &self.settings.security.trusted_proxies,
         )
     }
 
@@ -698,47 +1195,6 @@ impl AppData {
     }
 }
 
-async fn update_tor_nodes(tor_exit_nodes: &Collection<TorNode>) -> Result<(), UserError> {
-    let url = "https://check.torproject.org/torbulkexitlist";
-    let response = reqwest::get(url)
-        .await
-        .map_err(InternalError::TorNodeListFetchError)?;
-
-    let node_list: Vec<TorNode> = response
-        .text()
-        .await
-        .map_err(|_err| UserError::InternalError)?
-        .split_ascii_whitespace()
-        .map(|addr| TorNode {
-            addr: addr.to_string(),
-        })
-        .collect();
-
-    tor_exit_nodes
-        .delete_many(doc! {}, None)
-        .await
-        .map_err(InternalError::DatabaseConnectionError)?;
-
-    tor_exit_nodes
-        .insert_many(node_list, None)
-        .await
-        .map_err(InternalError::DatabaseConnectionError)?;
-
-    Ok(())
-}
-
-#[derive(Deserialize, Serialize)]
-struct TorNode {
-    addr: String,
-}
-
-fn is_opera_vpn(addr: &str) -> bool {
-    let opera_prefixes = ["77.111.244.", "77.111.245.", "77.111.246.", "77.111.247."];
-    opera_prefixes
-        .into_iter()
-        .any(|prefix| addr.starts_with(prefix))
-}
-
 #[cfg(test)]
 mod tests {
 
@@ -0,0 +1,132 @@
+use futures::TryStreamExt;
+use mongodb::{bson::doc, options::FindOptions, Collection};
+use netsblox_cloud_common::{api, AuditLogEntry};
+
+use crate::{
+    auth,
+    errors::{InternalError, UserError},
+};
+
+const DEFAULT_PAGE_SIZE: i64 = 25;
+const MAX_PAGE_SIZE: i64 = 100;
+
+pub(crate) struct AuditActions<'a> {
+    audit_log: &'a Collection<AuditLogEntry>,
+}
+
+impl<'a> AuditActions<'a> {
+    pub(crate) fn new(audit_log: &'a Collection<AuditLogEntry>) -> Self {
+        Self { audit_log }
+    }
+
+    pub(crate) async fn list_entries(
+        &self,
+        _va: &auth::ViewAuditLog,
+        query: &api::AuditLogQuery,
+    ) -> Result<Vec<api::AuditLogEntry>, UserError> {
+        let mut filter = doc! {};
+        if let Some(actor) = query.actor.as_ref() {
+            filter.insert("actor", actor);
+        }
+        if let Some(target) = query.target.as_ref() {
+            filter.insert("target", target);
+        }
+        if let Some(action) = query.action {
+            filter.insert("action", action);
+        }
+
+        let limit = query
+            .limit
+            .unwrap_or(DEFAULT_PAGE_SIZE)
+            .clamp(1, MAX_PAGE_SIZE);
+        let skip = query.page.unwrap_or(0) * limit as u64;
+        let options = FindOptions::builder()
+            .sort(doc! {"createdAt": -1})
+            .skip(skip)
+            .limit(limit)
+            .build();
+
+        let entries = self
+            .audit_log
+            .find(filter, options)
+            .await
+            .map_err(InternalError::DatabaseConnectionError)?
+            .try_collect::<Vec<_>>()
+            .await
+            .map_err(InternalError::DatabaseConnectionError)?
+            .into_iter()
+            .map(|entry| entry.into())
+            .collect();
+
+        Ok(entries)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{audit, test_utils};
+
+    fn ban_entry(actor: &str, target: &str) -> AuditLogEntry {
+        AuditLogEntry::new(
+            actor.into(),
+            api::AuditAction::BanUser,
+            target.into(),
+            &(),
+            &target,
+        )
+    }
+
+    #[actix_web::test]
+    async fn test_list_entries_filter() {
+        test_utils::setup()
+            .run(|app_data| async move {
+                audit::record(&app_data.audit_log, ban_entry("mod1", "user1"))
+                    .await
+                    .unwrap();
+                audit::record(&app_data.audit_log, ban_entry("mod2", "user2"))
+                    .await
+                    .unwrap();
+
+                let actions = app_data.as_audit_actions();
+                let query = api::AuditLogQuery {
+                    actor: Some("mod2".into()),
+                    ..Default::default()
+                };
+                let entries = actions
+                    .list_entries(&auth::ViewAuditLog::test(), &query)
+                    .await
+                    .unwrap();
+
+                assert_eq!(entries.len(), 1);
+                assert_eq!(entries[0].target, "user2");
+                assert!(entries[0].before.is_none());
+            })
+            .await;
+    }
+
+    #[actix_web::test]
+    async fn test_list_entries_paginated() {
+        test_utils::setup()
+            .run(|app_data| async move {
+                for i in 0..5 {
+                    let entry = ban_entry("mod", &format!("user{}", i));
+                    audit::record(&app_data.audit_log, entry).await.unwrap();
+                }
+
+                let actions = app_data.as_audit_actions();
+                let query = api::AuditLogQuery {
+                    page: Some(1),
+                    limit: Some(2),
+                    ..Default::default()
+                };
+                let entries = actions
+                    .list_entries(&auth::ViewAuditLog::test(), &query)
+                    .await
+                    .unwrap();
+
+                assert_eq!(entries.len(), 2);
+            })
+            .await;
+    }
+}
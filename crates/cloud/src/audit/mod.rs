@@ -0,0 +1,21 @@
+pub(crate) mod actions;
+pub(crate) mod routes;
+
+use mongodb::Collection;
+use netsblox_cloud_common::AuditLogEntry;
+
+use crate::errors::{InternalError, UserError};
+
+/// Record a privileged or administrative action in the audit log. This should
+/// be called before making the change so no change is made without being logged.
+pub(crate) async fn record(
+    audit_log: &Collection<AuditLogEntry>,
+    entry: AuditLogEntry,
+) -> Result<(), UserError> {
+    audit_log
+        .insert_one(entry, None)
+        .await
+        .map_err(InternalError::DatabaseConnectionError)?;
+
+    Ok(())
+}
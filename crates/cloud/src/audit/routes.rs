@@ -0,0 +1,110 @@
+use actix_web::{get, web, HttpRequest, HttpResponse};
+
+use crate::app_data::AppData;
+use crate::auth;
+use crate::common::api;
+use crate::errors::UserError;
+
+#[get("/audit")]
+async fn list_audit_log(
+    app: web::Data<AppData>,
+    req: HttpRequest,
+    query: web::Query<api::AuditLogQuery>,
+) -> Result<HttpResponse, UserError> {
+    let auth_va = auth::try_view_audit_log(&app, &req).await?;
+
+    let actions = app.as_audit_actions();
+    let entries = actions.list_entries(&auth_va, &query).await?;
+
+    Ok(HttpResponse::Ok().json(entries))
+}
+
+pub(crate) fn config(cfg: &mut web::ServiceConfig) {
+    cfg.service(list_audit_log);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use actix_web::{http, test, App};
+    use netsblox_cloud_common::{api::UserRole, AuditLogEntry, User};
+
+    use crate::{audit, test_utils};
+
+    #[actix_web::test]
+    async fn test_list_audit_log() {
+        let admin: User = api::NewUser {
+            username: "admin".into(),
+            email: "admin@netsblox.org".into(),
+            password: None,
+            group_id: None,
+            role: Some(UserRole::Admin),
+        }
+        .into();
+
+        test_utils::setup()
+            .with_users(&[admin.clone()])
+            .run(|app_data| async move {
+                let entry = AuditLogEntry::new(
+                    admin.username.clone(),
+                    api::AuditAction::UnbanUser,
+                    "someUser".into(),
+                    &(),
+                    &(),
+                );
+                audit::record(&app_data.audit_log, entry).await.unwrap();
+
+                let app = test::init_service(
+                    App::new()
+                        .wrap(test_utils::cookie::middleware())
+                        .app_data(web::Data::new(app_data.clone()))
+                        .configure(config),
+                )
+                .await;
+
+                let req = test::TestRequest::get()
+                    .uri("/audit?action=unbanUser&target=someUser")
+                    .cookie(test_utils::cookie::new(&admin.username))
+                    .to_request();
+
+                let entries: Vec<api::AuditLogEntry> =
+                    test::call_and_read_body_json(&app, req).await;
+                assert_eq!(entries.len(), 1);
+                assert_eq!(entries[0].actor, admin.username);
+            })
+            .await;
+    }
+
+    #[actix_web::test]
+    async fn test_list_audit_log_403() {
+        let moderator: User = api::NewUser {
+            username: "moderator".into(),
+            email: "moderator@netsblox.org".into(),
+            password: None,
+            group_id: None,
+            role: Some(UserRole::Moderator),
+        }
+        .into();
+
+        test_utils::setup()
+            .with_users(&[moderator.clone()])
+            .run(|app_data| async move {
+                let app = test::init_service(
+                    App::new()
+                        .wrap(test_utils::cookie::middleware())
+                        .app_data(web::Data::new(app_data.clone()))
+                        .configure(config),
+                )
+                .await;
+
+                let req = test::TestRequest::get()
+                    .uri("/audit")
+                    .cookie(test_utils::cookie::new(&moderator.username))
+                    .to_request();
+
+                let response = test::call_service(&app, req).await;
+                assert_eq!(response.status(), http::StatusCode::FORBIDDEN);
+            })
+            .await;
+    }
+}
@@ -0,0 +1,43 @@
+use super::is_super_user;
+use crate::app_data::AppData;
+use crate::errors::UserError;
+use actix_web::HttpRequest;
+
+/// Authorization to view the audit log
+pub(crate) struct ViewAuditLog {
+    _private: (),
+}
+
+#[cfg(test)]
+impl ViewAuditLog {
+    pub(crate) fn test() -> Self {
+        Self { _private: () }
+    }
+}
+
+/// Authorization to view the failed login counters (and lockouts)
+pub(crate) struct ViewLoginFailures {
+    _private: (),
+}
+
+pub(crate) async fn try_view_login_failures(
+    app: &AppData,
+    req: &HttpRequest,
+) -> Result<ViewLoginFailures, UserError> {
+    if is_super_user(app, req).await? {
+        Ok(ViewLoginFailures { _private: () })
+    } else {
+        Err(UserError::PermissionsError)
+    }
+}
+
+pub(crate) async fn try_view_audit_log(
+    app: &AppData,
+    req: &HttpRequest,
+) -> Result<ViewAuditLog, UserError> {
+    if is_super_user(app, req).await? {
+        Ok(ViewAuditLog { _private: () })
+    } else {
+        Err(UserError::PermissionsError)
+    }
+}
@@ -1,8 +1,9 @@
 use crate::{app_data::AppData, errors::InternalError, utils};
 use actix_web::HttpRequest;
 use mongodb::bson::doc;
-use netsblox_cloud_common::api;
+use netsblox_cloud_common::api::{self, HostCapability};
 
+use super::is_host_with;
 use crate::errors::UserError;
 
 // Permissions on groups
@@ -18,6 +19,8 @@ pub(crate) struct EditGroup {
 
 pub(crate) struct DeleteGroup {
     pub(crate) id: api::GroupId,
+    /// The user deleting the group
+    pub(crate) requestor: String,
     _private: (),
 }
 
@@ -27,6 +30,13 @@ pub(crate) async fn try_view_group(
     req: &HttpRequest,
     group_id: &api::GroupId,
 ) -> Result<ViewGroup, UserError> {
+    if is_host_with(app, req, HostCapability::ViewUsers).await? {
+        return Ok(ViewGroup {
+            id: group_id.to_owned(),
+            _private: (),
+        });
+    }
+
     // for now you can only view the group if you are allowed to edit it
     try_edit_group(app, req, group_id)
         .await
@@ -36,6 +46,23 @@ pub(crate) async fn try_view_group(
         })
 }
 
+/// Permissions to view the service settings of a group. Authorized hosts need
+/// the capability to read service settings (rather than to view users).
+pub(crate) async fn try_view_group_settings(
+    app: &AppData,
+    req: &HttpRequest,
+    group_id: &api::GroupId,
+) -> Result<ViewGroup, UserError> {
+    if is_host_with(app, req, HostCapability::ReadServiceSettings).await? {
+        return Ok(ViewGroup {
+            id: group_id.to_owned(),
+            _private: (),
+        });
+    }
+
+    try_view_group(app, req, group_id).await
+}
+
 pub(crate) async fn try_edit_group(
     app: &AppData,
     req: &HttpRequest,
@@ -49,15 +76,7 @@ pub(crate) async fn try_edit_group(
         .map_err(InternalError::DatabaseConnectionError)?
         .ok_or(UserError::GroupNotFoundError)?;
 
-    let authorized = utils::get_authorized_host(&app.authorized_services, req)
-        .await
-        .ok()
-        .flatten()
-        .is_some();
-
-    if !authorized {
-        let _auth = super::try_edit_user(app, req, None, &group.owner).await?;
-    }
+    let _auth = super::try_edit_user(app, req, None, &group.owner).await?;
 
     Ok(EditGroup {
         id: group_id.to_owned(),
@@ -80,9 +99,11 @@ pub(crate) async fn try_delete_group(
         .map_err(InternalError::DatabaseConnectionError)?
         .ok_or(UserError::GroupNotFoundError)?;
     let _auth = super::try_edit_user(app, req, None, &group.owner).await?;
+    let requestor = utils::get_username(req).ok_or(UserError::LoginRequiredError)?;
 
     Ok(DeleteGroup {
         id: group_id.to_owned(),
+        requestor,
         _private: (),
     })
 }
@@ -1,13 +1,16 @@
 use super::is_super_user;
 use crate::app_data::AppData;
 use crate::errors::UserError;
+use crate::utils;
 use actix_web::HttpRequest;
+use netsblox_cloud_common::api::HostCapability;
 
 pub(crate) struct ViewAuthHosts {
     _private: (),
 }
 
 pub(crate) struct AuthorizeHost {
+    pub(crate) admin: String,
     _private: (),
 }
 
@@ -22,12 +25,31 @@ pub(crate) async fn try_view_auth_hosts(
     }
 }
 
+/// Check if the request was sent by an authorized host with the given capability.
+/// Requests from hosts without the capability are rejected (rather than falling
+/// back to checking the permissions of a logged in user).
+pub(super) async fn is_host_with(
+    app: &AppData,
+    req: &HttpRequest,
+    capability: HostCapability,
+) -> Result<bool, UserError> {
+    match utils::get_authorized_host(app, req).await? {
+        Some(host) if host.has_capability(capability) => Ok(true),
+        Some(_host) => Err(UserError::PermissionsError),
+        None => Ok(false),
+    }
+}
+
 pub(crate) async fn try_auth_host(
     app: &AppData,
     req: &HttpRequest,
 ) -> Result<AuthorizeHost, UserError> {
     if is_super_user(app, req).await? {
-        Ok(AuthorizeHost { _private: () })
+        let admin = utils::get_username(req).ok_or(UserError::LoginRequiredError)?;
+        Ok(AuthorizeHost {
+            admin,
+            _private: (),
+        })
     } else {
         Err(UserError::PermissionsError)
     }
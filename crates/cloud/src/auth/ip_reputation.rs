@@ -0,0 +1,37 @@
+use super::is_super_user;
+use crate::app_data::AppData;
+use crate::errors::UserError;
+use crate::utils;
+use actix_web::HttpRequest;
+
+/// Authorization to test addresses against the IP reputation sources and to
+/// manage the entries of admin-managed sources
+pub(crate) struct ManageIpReputation {
+    pub(crate) admin: String,
+    _private: (),
+}
+
+#[cfg(test)]
+impl ManageIpReputation {
+    pub(crate) fn test(admin: &str) -> Self {
+        Self {
+            admin: admin.to_owned(),
+            _private: (),
+        }
+    }
+}
+
+pub(crate) async fn try_manage_ip_reputation(
+    app: &AppData,
+    req: &HttpRequest,
+) -> Result<ManageIpReputation, UserError> {
+    if is_super_user(app, req).await? {
+        let admin = utils::get_username(req).ok_or(UserError::LoginRequiredError)?;
+        Ok(ManageIpReputation {
+            admin,
+            _private: (),
+        })
+    } else {
+        Err(UserError::PermissionsError)
+    }
+}
@@ -1,7 +1,7 @@
 use super::users::is_moderator;
 use crate::app_data::AppData;
 use crate::errors::{InternalError, UserError};
-use actix_session::SessionExt;
+use crate::utils;
 use actix_web::HttpRequest;
 use mongodb::bson::doc;
 use netsblox_cloud_common::api::PublishState;
@@ -30,6 +30,7 @@ pub(crate) struct PublishLibrary {
 }
 
 pub(crate) struct ModerateLibraries {
+    pub(crate) moderator: String,
     _private: (),
 }
 
@@ -95,8 +96,7 @@ pub(crate) async fn try_publish_library(
     req: &HttpRequest,
     owner: &str,
 ) -> Result<PublishLibrary, UserError> {
-    let session = req.get_session();
-    if is_moderator(app, &session).await? {
+    if is_moderator(app, req).await? {
         Ok(PublishLibrary {
             owner: owner.to_owned(),
             can_approve: true,
@@ -104,6 +104,7 @@ pub(crate) async fn try_publish_library(
         })
     } else {
         super::try_edit_user(app, req, None, owner).await?;
+        super::ensure_verified(app, req).await?;
 
         Ok(PublishLibrary {
             owner: owner.to_owned(),
@@ -117,9 +118,12 @@ pub(crate) async fn try_moderate_libraries(
     app: &AppData,
     req: &HttpRequest,
 ) -> Result<ModerateLibraries, UserError> {
-    let session = req.get_session();
-    if is_moderator(app, &session).await? {
-        Ok(ModerateLibraries { _private: () })
+    if is_moderator(app, req).await? {
+        let moderator = utils::get_username(req).ok_or(UserError::LoginRequiredError)?;
+        Ok(ModerateLibraries {
+            moderator,
+            _private: (),
+        })
     } else {
         Err(UserError::PermissionsError)
     }
@@ -149,8 +153,11 @@ mod test_utils {
     }
 
     impl ModerateLibraries {
-        pub(crate) fn test() -> Self {
-            Self { _private: () }
+        pub(crate) fn test(moderator: String) -> Self {
+            Self {
+                moderator,
+                _private: (),
+            }
         }
     }
 }
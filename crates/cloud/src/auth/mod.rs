@@ -1,6 +1,8 @@
+pub(crate) mod audit;
 pub(crate) mod collaboration;
 pub(crate) mod groups;
 pub(crate) mod hosts;
+pub(crate) mod ip_reputation;
 pub(crate) mod libraries;
 pub(crate) mod network;
 pub(crate) mod oauth;
@@ -8,9 +10,11 @@ pub(crate) mod projects;
 pub(crate) mod system;
 pub(crate) mod users;
 
+pub(crate) use crate::auth::audit::*;
 pub(crate) use crate::auth::collaboration::*;
 pub(crate) use crate::auth::groups::*;
 pub(crate) use crate::auth::hosts::*;
+pub(crate) use crate::auth::ip_reputation::*;
 pub(crate) use crate::auth::libraries::*;
 pub(crate) use crate::auth::network::*;
 pub(crate) use crate::auth::oauth::*;
@@ -1,10 +1,10 @@
-use super::{can_edit_project, is_super_user, try_edit_user};
+use super::{can_edit_project, is_host_with, is_super_user, try_edit_user};
 use crate::app_data::AppData;
 use crate::errors::{InternalError, UserError};
 use crate::network::topology;
 use crate::utils;
 use actix_web::HttpRequest;
-use netsblox_cloud_common::api::{self, ClientId};
+use netsblox_cloud_common::api::{self, ClientId, HostCapability};
 use netsblox_cloud_common::ProjectMetadata;
 
 pub(crate) struct ViewClient {
@@ -51,9 +51,7 @@ pub(crate) async fn try_view_client(
     req: &HttpRequest,
     client_id: &api::ClientId,
 ) -> Result<ViewClient, UserError> {
-    let is_auth_host = utils::get_authorized_host(&app.authorized_services, req)
-        .await?
-        .is_some();
+    let is_auth_host = is_host_with(app, req, HostCapability::ViewUsers).await?;
 
     if is_auth_host || is_super_user(app, req).await? {
         Ok(ViewClient {
@@ -67,24 +65,6 @@ pub(crate) async fn try_view_client(
     }
 }
 
-// helper function
-pub(crate) async fn ensure_is_auth_host_or_admin(
-    app: &AppData,
-    req: &HttpRequest,
-) -> Result<(), UserError> {
-    let is_auth_host = utils::get_authorized_host(&app.authorized_services, req)
-        .await?
-        .is_some();
-
-    if is_auth_host || is_super_user(app, req).await? {
-        Ok(())
-    } else if utils::get_username(req).is_some() {
-        Err(UserError::PermissionsError)
-    } else {
-        Err(UserError::LoginRequiredError)
-    }
-}
-
 pub(crate) async fn try_evict_client(
     app: &AppData,
     req: &HttpRequest,
@@ -174,11 +154,9 @@ pub(crate) async fn try_send_message(
         return Ok(SendMessage { _private: (), msg });
     }
 
-    let host = utils::get_authorized_host(&app.authorized_services, req).await?;
-
     // Sending messages is allowed if you:
     // - are an authorized host
-    if let Some(_host) = host {
+    if is_host_with(app, req, HostCapability::SendMessages).await? {
         Ok(SendMessage { _private: (), msg })
     // - or can edit (ie, operate on behalf of) the sender
     } else if let Some(sender) = msg.sender.as_ref() {
@@ -252,7 +230,7 @@ mod tests {
     use serde_json::json;
 
     use super::*;
-    use crate::{errors::UserError, test_utils};
+    use crate::{errors::UserError, services::hosts::signing, test_utils};
 
     #[actix_web::test]
     async fn test_try_send_msg_auth_host() {
@@ -269,6 +247,8 @@ mod tests {
             "http://localhost:5656".into(),
             "TestServices".into(),
             visibility,
+            vec![HostCapability::SendMessages],
+            signing::test_host_secret("secret"),
         );
 
         test_utils::setup()
@@ -282,8 +262,18 @@ mod tests {
                 )
                 .await;
 
-                let req = test::TestRequest::post()
-                    .append_header(host.auth_header())
+                let headers = signing::signed_headers(
+                    &host.id,
+                    "secret",
+                    "POST",
+                    "/send",
+                    &serde_json::to_vec(&msg).unwrap(),
+                );
+                let req = headers
+                    .into_iter()
+                    .fold(test::TestRequest::post(), |req, header| {
+                        req.append_header(header)
+                    })
                     .uri("/send")
                     .set_json(msg)
                     .to_request();
@@ -294,6 +284,114 @@ mod tests {
             .await;
     }
 
+    #[actix_web::test]
+    async fn test_try_send_msg_auth_host_replay() {
+        let msg = api::SendMessage {
+            sender: None,
+            target: api::SendMessageTarget::Client {
+                client_id: ClientId::new("_test_client_id".into()),
+                state: None,
+            },
+            content: json!({"test": "hello!"}),
+        };
+        let host = AuthorizedServiceHost::new(
+            "http://localhost:5656".into(),
+            "TestServices".into(),
+            api::ServiceHostScope::Private,
+            vec![HostCapability::SendMessages],
+            signing::test_host_secret("secret"),
+        );
+
+        test_utils::setup()
+            .with_authorized_services(&[host.clone()])
+            .run(|app_data| async move {
+                let app = test::init_service(
+                    App::new()
+                        .wrap(test_utils::cookie::middleware())
+                        .app_data(web::Data::new(app_data.clone()))
+                        .service(send_msg_test),
+                )
+                .await;
+
+                let headers = signing::signed_headers(
+                    &host.id,
+                    "secret",
+                    "POST",
+                    "/send",
+                    &serde_json::to_vec(&msg).unwrap(),
+                );
+                let send_request = |msg| {
+                    headers
+                        .iter()
+                        .cloned()
+                        .fold(test::TestRequest::post(), |req, header| {
+                            req.append_header(header)
+                        })
+                        .uri("/send")
+                        .set_json(msg)
+                        .to_request()
+                };
+
+                let response = test::call_service(&app, send_request(msg.clone())).await;
+                assert_eq!(response.status(), http::StatusCode::OK);
+
+                let response = test::call_service(&app, send_request(msg)).await;
+                assert_eq!(response.status(), http::StatusCode::UNAUTHORIZED);
+            })
+            .await;
+    }
+
+    #[actix_web::test]
+    async fn test_try_send_msg_auth_host_missing_capability() {
+        let msg = api::SendMessage {
+            sender: None,
+            target: api::SendMessageTarget::Client {
+                client_id: ClientId::new("_test_client_id".into()),
+                state: None,
+            },
+            content: json!({"test": "hello!"}),
+        };
+        let host = AuthorizedServiceHost::new(
+            "http://localhost:5656".into(),
+            "TestServices".into(),
+            api::ServiceHostScope::Private,
+            vec![HostCapability::ViewUsers],
+            signing::test_host_secret("secret"),
+        );
+
+        test_utils::setup()
+            .with_authorized_services(&[host.clone()])
+            .run(|app_data| async move {
+                let app = test::init_service(
+                    App::new()
+                        .wrap(test_utils::cookie::middleware())
+                        .app_data(web::Data::new(app_data.clone()))
+                        .service(send_msg_test),
+                )
+                .await;
+
+                let headers = signing::signed_headers(
+                    &host.id,
+                    "secret",
+                    "POST",
+                    "/send",
+                    &serde_json::to_vec(&msg).unwrap(),
+                );
+                let req = headers
+                    .into_iter()
+                    .fold(test::TestRequest::post(), |req, header| {
+                        req.append_header(header)
+                    })
+                    .uri("/send")
+                    .set_json(msg)
+                    .to_request();
+
+                let response = test::call_service(&app, req).await;
+                assert_eq!(response.status(), http::StatusCode::FORBIDDEN);
+            })
+            .await;
+    }
+
     #[actix_web::test]
     async fn test_try_send_msg_self() {
         let user: User = api::NewUser {
@@ -1,18 +1,173 @@
 use actix_web::HttpRequest;
+use mongodb::bson::doc;
+use netsblox_cloud_common::api::{oauth, HostCapability};
 
-use crate::{app_data::AppData, errors::UserError};
+use crate::{
+    app_data::AppData,
+    errors::{InternalError, UserError},
+    utils,
+};
 
-use super::ensure_is_auth_host_or_admin;
+use super::hosts::is_host_with;
+use super::users::{is_moderator, is_super_user};
 
+/// Permission to register an OAuth client. Clients registered by admins (or
+/// authorized service hosts) do not have an owner.
+pub(crate) struct CreateClient {
+    pub(crate) owner: Option<String>,
+    _private: (),
+}
+
+/// Permission to list the registered OAuth clients. If an owner is set, only
+/// the clients registered by the owner can be viewed.
+pub(crate) struct ListOAuthClients {
+    pub(crate) owner: Option<String>,
+    _private: (),
+}
+
+/// Permission to edit, delete, or rotate the secret of an OAuth client
 pub(crate) struct ManageClient {
+    pub(crate) client_id: oauth::ClientId,
+    _private: (),
+}
+
+#[cfg(test)]
+impl CreateClient {
+    pub(crate) fn test(owner: Option<String>) -> Self {
+        Self {
+            owner,
+            _private: (),
+        }
+    }
+}
+
+#[cfg(test)]
+impl ListOAuthClients {
+    pub(crate) fn test(owner: Option<String>) -> Self {
+        Self {
+            owner,
+            _private: (),
+        }
+    }
+}
+
+#[cfg(test)]
+impl ManageClient {
+    pub(crate) fn test(client_id: oauth::ClientId) -> Self {
+        Self {
+            client_id,
+            _private: (),
+        }
+    }
+}
+
+/// Permission to view (and revoke) the OAuth clients authorized by a user
+pub(crate) struct ManageAuthorizedClients {
+    pub(crate) username: String,
     _private: (),
 }
 
+#[cfg(test)]
+impl ManageAuthorizedClients {
+    pub(crate) fn test(username: String) -> Self {
+        Self {
+            username,
+            _private: (),
+        }
+    }
+}
+
+pub(crate) async fn try_create_client(
+    app: &AppData,
+    req: &HttpRequest,
+) -> Result<CreateClient, UserError> {
+    let owner = if can_manage_all_clients(app, req).await? {
+        None
+    } else {
+        Some(get_client_owner(req)?)
+    };
+
+    Ok(CreateClient {
+        owner,
+        _private: (),
+    })
+}
+
+pub(crate) async fn try_list_oauth_clients(
+    app: &AppData,
+    req: &HttpRequest,
+) -> Result<ListOAuthClients, UserError> {
+    let owner = if can_manage_all_clients(app, req).await? {
+        None
+    } else {
+        Some(get_client_owner(req)?)
+    };
+
+    Ok(ListOAuthClients {
+        owner,
+        _private: (),
+    })
+}
+
 pub(crate) async fn try_manage_client(
     app: &AppData,
     req: &HttpRequest,
+    client_id: &oauth::ClientId,
 ) -> Result<ManageClient, UserError> {
-    ensure_is_auth_host_or_admin(app, req)
-        .await
-        .map(|_| ManageClient { _private: () })
+    if !can_manage_all_clients(app, req).await? {
+        let username = get_client_owner(req)?;
+        let query = doc! {"id": client_id};
+        let client = app
+            .oauth_clients
+            .find_one(query, None)
+            .await
+            .map_err(InternalError::DatabaseConnectionError)?
+            .ok_or(UserError::OAuthClientNotFoundError)?;
+
+        if client.owner.as_ref() != Some(&username) {
+            return Err(UserError::PermissionsError);
+        }
+    }
+
+    Ok(ManageClient {
+        client_id: client_id.to_owned(),
+        _private: (),
+    })
+}
+
+/// Admins and authorized service hosts (with the capability) can manage all OAuth clients
+async fn can_manage_all_clients(app: &AppData, req: &HttpRequest) -> Result<bool, UserError> {
+    let is_auth_host = is_host_with(app, req, HostCapability::ManageOAuthClients).await?;
+
+    Ok(is_auth_host || (utils::get_username(req).is_some() && is_super_user(app, req).await?))
+}
+
+/// Other users can only manage their own clients. This requires a login
+/// session (rather than an access token) since client secrets are returned.
+fn get_client_owner(req: &HttpRequest) -> Result<String, UserError> {
+    if utils::is_token_auth(req) {
+        return Err(UserError::PermissionsError);
+    }
+
+    utils::get_username(req).ok_or(UserError::LoginRequiredError)
+}
+
+pub(crate) async fn try_manage_authorized_clients(
+    app: &AppData,
+    req: &HttpRequest,
+    username: &str,
+) -> Result<ManageAuthorizedClients, UserError> {
+    if utils::is_token_auth(req) {
+        return Err(UserError::PermissionsError);
+    }
+
+    let requestor = utils::get_username(req).ok_or(UserError::LoginRequiredError)?;
+    if requestor == username || is_moderator(app, req).await? {
+        Ok(ManageAuthorizedClients {
+            username: username.to_owned(),
+            _private: (),
+        })
+    } else {
+        Err(UserError::PermissionsError)
+    }
 }
@@ -1,13 +1,15 @@
-use actix_session::SessionExt;
 use actix_web::HttpRequest;
 use mongodb::bson::doc;
-use netsblox_cloud_common::{api, ProjectMetadata};
+use netsblox_cloud_common::{
+    api::{self, HostCapability},
+    ProjectMetadata,
+};
 
 use crate::app_data::AppData;
 use crate::errors::UserError;
 use crate::utils;
 
-use super::{is_moderator, ManageSystem};
+use super::{is_host_with, is_moderator, DeleteUser, ExportUserData, ManageSystem};
 
 /// Permissions to view a specific project
 pub(crate) struct ViewProject {
@@ -15,6 +17,23 @@ pub(crate) struct ViewProject {
     _private: (),
 }
 
+impl ViewProject {
+    /// Get permissions to view a project owned by a user whose data is being exported.
+    pub(crate) fn from_export_user_data(
+        ed: &ExportUserData,
+        metadata: ProjectMetadata,
+    ) -> Option<Self> {
+        if metadata.owner == ed.username {
+            Some(Self {
+                metadata,
+                _private: (),
+            })
+        } else {
+            None
+        }
+    }
+}
+
 /// Permissions to list projects for a given owner or with a given collaborator
 pub(crate) struct ListProjects {
     pub(crate) username: String,
@@ -37,10 +56,23 @@ impl DeleteProject {
     pub(crate) fn from_manage_system(_witness: &ManageSystem, id: api::ProjectId) -> Self {
         Self { id, _private: () }
     }
+
+    /// Get project deletion permissions for a project owned by a user being deleted.
+    pub(crate) fn from_delete_user(du: &DeleteUser, metadata: &ProjectMetadata) -> Option<Self> {
+        if metadata.owner == du.username {
+            Some(Self {
+                id: metadata.id.clone(),
+                _private: (),
+            })
+        } else {
+            None
+        }
+    }
 }
 
 /// Permissions to approve projects that require manual approval
 pub(crate) struct ModerateProjects {
+    pub(crate) moderator: String,
     _private: (),
 }
 
@@ -52,11 +84,7 @@ pub(crate) async fn try_view_project(
 ) -> Result<ViewProject, UserError> {
     // FIXME: if owned by guest account, should everyone be able to see it?
     let metadata = app.get_project_metadatum(project_id).await?;
-    let is_auth_host = utils::get_authorized_host(&app.authorized_services, req)
-        .await?
-        .is_some();
-
-    if is_auth_host {
+    if is_host_with(app, req, HostCapability::ViewProjects).await? {
         return Ok(ViewProject {
             metadata,
             _private: (),
@@ -111,6 +139,19 @@ pub(crate) async fn try_edit_project(
     can_edit_project(app, req, client_id.as_ref(), &metadata).await
 }
 
+/// Try to get privileges to publish (or request approval for) the given project.
+/// Requires a verified email address.
+pub(crate) async fn try_publish_project(
+    app: &AppData,
+    req: &HttpRequest,
+    project_id: &api::ProjectId,
+) -> Result<EditProject, UserError> {
+    let ep = try_edit_project(app, req, None, project_id).await?;
+    super::ensure_verified(app, req).await?;
+
+    Ok(ep)
+}
+
 pub(crate) async fn try_delete_project(
     app: &AppData,
     req: &HttpRequest,
@@ -175,9 +216,12 @@ pub(crate) async fn try_moderate_projects(
     app: &AppData,
     req: &HttpRequest,
 ) -> Result<ModerateProjects, UserError> {
-    let session = req.get_session();
-    if is_moderator(app, &session).await? {
-        Ok(ModerateProjects { _private: () })
+    if is_moderator(app, req).await? {
+        let moderator = utils::get_username(req).ok_or(UserError::LoginRequiredError)?;
+        Ok(ModerateProjects {
+            moderator,
+            _private: (),
+        })
     } else {
         Err(UserError::PermissionsError)
     }
@@ -1,10 +1,13 @@
 use std::collections::HashSet;
 
-use actix_session::{Session, SessionExt};
+use actix_session::SessionExt;
 use actix_web::HttpRequest;
 use futures::TryStreamExt;
 use mongodb::bson::doc;
-use netsblox_cloud_common::api::{self, ClientId, UpdateUserData, UserRole};
+use netsblox_cloud_common::{
+    api::{self, ClientId, HostCapability, UpdateUserData, UserRole},
+    AccountDeletion,
+};
 
 use crate::{
     app_data::AppData,
@@ -13,9 +16,14 @@ use crate::{
     utils,
 };
 
+use super::is_host_with;
+
 #[derive(Debug)]
 pub(crate) struct CreateUser {
     pub(crate) data: api::NewUser,
+    /// Whether the account is created via public signup (rather than by a
+    /// group owner or moderator) in which case signup limits apply
+    pub(crate) public_signup: bool,
     _private: (),
 }
 
@@ -31,6 +39,27 @@ pub(crate) struct ListUsers {
     _private: (),
 }
 
+/// Authorization to delete the user with the given username (and all the
+/// data owned by the user)
+pub(crate) struct DeleteUser {
+    pub(crate) username: String,
+    /// The user performing the deletion
+    pub(crate) requestor: String,
+    _private: (),
+}
+
+/// Authorization to export all the data stored about a user
+pub(crate) struct ExportUserData {
+    pub(crate) username: String,
+    _private: (),
+}
+
+/// Authorization to view the progress of the deletion of an account
+pub(crate) struct ViewAccountDeletion {
+    pub(crate) username: String,
+    _private: (),
+}
+
 /// Authorization to edit the user with the given username
 pub(crate) struct EditUser {
     pub(crate) username: String,
@@ -51,15 +80,18 @@ impl EditUser {
 pub(crate) struct UpdateUser {
     pub(crate) username: String,
     pub(crate) update: UpdateUserData,
+    /// The user applying the update
+    pub(crate) requestor: String,
     _private: (),
 }
 
 #[cfg(test)]
 impl UpdateUser {
-    pub(crate) fn test(username: String, update: UpdateUserData) -> Self {
+    pub(crate) fn test(username: String, update: UpdateUserData, requestor: String) -> Self {
         Self {
             username,
             update,
+            requestor,
             _private: (),
         }
     }
@@ -70,14 +102,123 @@ pub(crate) struct SetPassword {
     _private: (),
 }
 
+/// Authorization to mark the given email address as verified for the user
+pub(crate) struct VerifyEmail {
+    pub(crate) username: String,
+    pub(crate) email: String,
+    _private: (),
+}
+
 pub(crate) struct BanUser {
     pub(crate) username: String,
+    pub(crate) moderator: String,
+    _private: (),
+}
+
+/// Authorization to view account bans and to ban (or unban) IP addresses
+pub(crate) struct ManageBans {
+    pub(crate) moderator: String,
+    _private: (),
+}
+
+/// Authorization to enroll in (or disable) two-factor authentication. Only
+/// permitted for the user themselves.
+pub(crate) struct ManageTwoFactor {
+    pub(crate) username: String,
+    _private: (),
+}
+
+/// Authorization to remove two-factor authentication from an account (eg, if
+/// the user has lost their device and recovery codes).
+pub(crate) struct ResetTwoFactor {
+    pub(crate) username: String,
+    /// The admin performing the reset
+    pub(crate) admin: String,
     _private: (),
 }
 
+/// Authorization to view and revoke the login sessions of a user
+pub(crate) struct ManageSessions {
+    pub(crate) username: String,
+    _private: (),
+}
+
+/// Authorization to create, view, and revoke the personal access tokens of a
+/// user. Only permitted for the user themselves (using a session rather than
+/// another access token).
+pub(crate) struct ManageAccessTokens {
+    pub(crate) username: String,
+    _private: (),
+}
+
+impl DeleteUser {
+    /// Get permissions to resume a deletion which was authorized (and recorded)
+    /// before the account was removed.
+    pub(crate) fn from_account_deletion(deletion: &AccountDeletion) -> Self {
+        Self {
+            username: deletion.username.clone(),
+            requestor: deletion.requestor.clone(),
+            _private: (),
+        }
+    }
+}
+
 // TODO: make a macro for making it when testing?
 #[cfg(test)]
 impl BanUser {
+    pub(crate) fn test(username: String, moderator: String) -> Self {
+        Self {
+            username,
+            moderator,
+            _private: (),
+        }
+    }
+}
+
+#[cfg(test)]
+impl DeleteUser {
+    pub(crate) fn test(username: String, requestor: String) -> Self {
+        Self {
+            username,
+            requestor,
+            _private: (),
+        }
+    }
+}
+
+#[cfg(test)]
+impl VerifyEmail {
+    pub(crate) fn test(username: String, email: String) -> Self {
+        Self {
+            username,
+            email,
+            _private: (),
+        }
+    }
+}
+
+#[cfg(test)]
+impl ManageTwoFactor {
+    pub(crate) fn test(username: String) -> Self {
+        Self {
+            username,
+            _private: (),
+        }
+    }
+}
+
+#[cfg(test)]
+impl ManageSessions {
+    pub(crate) fn test(username: String) -> Self {
+        Self {
+            username,
+            _private: (),
+        }
+    }
+}
+
+#[cfg(test)]
+impl ManageAccessTokens {
     pub(crate) fn test(username: String) -> Self {
         Self {
             username,
@@ -99,7 +240,19 @@ impl ViewUser {
 #[cfg(test)]
 impl CreateUser {
     pub(crate) fn test(data: api::NewUser) -> Self {
-        Self { data, _private: () }
+        Self {
+            data,
+            public_signup: false,
+            _private: (),
+        }
+    }
+
+    pub(crate) fn test_signup(data: api::NewUser) -> Self {
+        Self {
+            data,
+            public_signup: true,
+            _private: (),
+        }
     }
 }
 
@@ -120,9 +273,15 @@ pub(crate) async fn try_create_user(
     }
 
     let new_user_role = data.role.unwrap_or(UserRole::User);
-    try_assign_role(app, req, &new_user_role)
-        .await
-        .map(|_| CreateUser { data, _private: () })
+    try_assign_role(app, req, &new_user_role).await?;
+
+    let is_mod = utils::get_username(req).is_some() && is_moderator(app, req).await?;
+    let public_signup = data.group_id.is_none() && !is_mod;
+    Ok(CreateUser {
+        data,
+        public_signup,
+        _private: (),
+    })
 }
 
 /// Permissions for assigning a given role. Used as a helper method for related functions.
@@ -175,10 +334,7 @@ pub(crate) async fn try_view_user(
     }
 
     // Check if authorized host
-    let is_authorized_host = utils::get_authorized_host(&app.authorized_services, req)
-        .await?
-        .is_some();
-    if is_authorized_host {
+    if is_host_with(app, req, HostCapability::ViewUsers).await? {
         return Ok(ViewUser {
             username: username.to_owned(),
             _private: (),
@@ -205,6 +361,23 @@ pub(crate) async fn try_view_user(
     }
 }
 
+/// Permissions to view the service settings of a user. Authorized hosts need
+/// the capability to read service settings (rather than to view users).
+pub(crate) async fn try_view_user_settings(
+    app: &AppData,
+    req: &HttpRequest,
+    username: &str,
+) -> Result<ViewUser, UserError> {
+    if is_host_with(app, req, HostCapability::ReadServiceSettings).await? {
+        return Ok(ViewUser {
+            username: username.to_owned(),
+            _private: (),
+        });
+    }
+
+    try_view_user(app, req, None, username).await
+}
+
 pub(crate) async fn try_list_users(
     app: &AppData,
     req: &HttpRequest,
@@ -252,6 +425,76 @@ pub(crate) async fn try_edit_user(
     }
 }
 
+/// Users can be deleted by themselves, moderators, or the owner of their group.
+pub(crate) async fn try_delete_user(
+    app: &AppData,
+    req: &HttpRequest,
+    username: &str,
+) -> Result<DeleteUser, UserError> {
+    let requestor = utils::get_username(req).ok_or(UserError::LoginRequiredError)?;
+    let can_delete = requestor == username
+        || get_user_role(app, &requestor).await? >= UserRole::Moderator
+        || has_group_containing(app, &requestor, username).await?;
+
+    if can_delete {
+        Ok(DeleteUser {
+            username: username.to_owned(),
+            requestor,
+            _private: (),
+        })
+    } else {
+        Err(UserError::PermissionsError)
+    }
+}
+
+/// User data can be exported by the user, moderators, or the owner of the user's
+/// group (eg, a parent or teacher).
+pub(crate) async fn try_export_user_data(
+    app: &AppData,
+    req: &HttpRequest,
+    username: &str,
+) -> Result<ExportUserData, UserError> {
+    let requestor = utils::get_username(req).ok_or(UserError::LoginRequiredError)?;
+    let can_export = requestor == username
+        || get_user_role(app, &requestor).await? >= UserRole::Moderator
+        || has_group_containing(app, &requestor, username).await?;
+
+    if can_export {
+        Ok(ExportUserData {
+            username: username.to_owned(),
+            _private: (),
+        })
+    } else {
+        Err(UserError::PermissionsError)
+    }
+}
+
+/// The progress of an account deletion can be viewed by moderators or the user
+/// who deleted the account (since the account itself no longer exists).
+pub(crate) async fn try_view_account_deletion(
+    app: &AppData,
+    req: &HttpRequest,
+    username: &str,
+) -> Result<ViewAccountDeletion, UserError> {
+    let requestor = utils::get_username(req).ok_or(UserError::LoginRequiredError)?;
+    let query = doc! {"username": username, "requestor": &requestor};
+    let is_requestor = app
+        .account_deletions
+        .find_one(query, None)
+        .await
+        .map_err(InternalError::DatabaseConnectionError)?
+        .is_some();
+
+    if is_requestor || is_moderator(app, req).await? {
+        Ok(ViewAccountDeletion {
+            username: username.to_owned(),
+            _private: (),
+        })
+    } else {
+        Err(UserError::PermissionsError)
+    }
+}
+
 /// Try to get privileges to apply the given updates to the specified user.
 pub(crate) async fn try_update_user(
     app: &AppData,
@@ -269,13 +512,15 @@ pub(crate) async fn try_update_user(
         try_assign_role(app, req, role).await?;
     }
 
-    try_edit_user(app, req, None, username)
-        .await
-        .map(|eu| UpdateUser {
-            username: eu.username.to_owned(),
-            update,
-            _private: (),
-        })
+    let eu = try_edit_user(app, req, None, username).await?;
+    let requestor = utils::get_username(req).ok_or(UserError::LoginRequiredError)?;
+
+    Ok(UpdateUser {
+        username: eu.username,
+        update,
+        requestor,
+        _private: (),
+    })
 }
 
 pub(crate) async fn try_set_password(
@@ -312,15 +557,155 @@ pub(crate) async fn try_set_password(
     }
 }
 
+pub(crate) async fn try_verify_email(
+    app: &AppData,
+    username: &str,
+    token: &str,
+) -> Result<VerifyEmail, UserError> {
+    let query = doc! {"secret": token};
+    let token = app
+        .verification_tokens
+        .find_one_and_delete(query, None)
+        .await
+        .map_err(InternalError::DatabaseConnectionError)?
+        .filter(|token| token.username == username)
+        .ok_or(UserError::VerificationLinkNotFoundError)?;
+
+    Ok(VerifyEmail {
+        username: token.username,
+        email: token.email,
+        _private: (),
+    })
+}
+
+/// Ensure the user making the request has verified their email address.
+/// Used for actions which could be abused by throwaway accounts (eg, publishing).
+pub(crate) async fn ensure_verified(app: &AppData, req: &HttpRequest) -> Result<(), UserError> {
+    let username = utils::get_username(req).ok_or(UserError::LoginRequiredError)?;
+    let query = doc! {"username": &username};
+    let user = app
+        .users
+        .find_one(query, None)
+        .await
+        .map_err(InternalError::DatabaseConnectionError)?
+        .ok_or(UserError::UserNotFoundError)?;
+
+    if user.verified {
+        Ok(())
+    } else {
+        Err(UserError::EmailVerificationRequiredError)
+    }
+}
+
+/// Try to get privileges to send friend invites on behalf of the given user
+pub(crate) async fn try_send_friend_invite(
+    app: &AppData,
+    req: &HttpRequest,
+    username: &str,
+) -> Result<EditUser, UserError> {
+    let eu = try_edit_user(app, req, None, username).await?;
+    ensure_verified(app, req).await?;
+
+    Ok(eu)
+}
+
 pub(crate) async fn try_ban_user(
     app: &AppData,
     req: &HttpRequest,
     username: &str,
 ) -> Result<BanUser, UserError> {
-    let session = req.get_session();
-    if is_moderator(app, &session).await? {
+    if is_moderator(app, req).await? {
+        let moderator = utils::get_username(req).ok_or(UserError::LoginRequiredError)?;
         Ok(BanUser {
             username: username.to_owned(),
+            moderator,
+            _private: (),
+        })
+    } else {
+        Err(UserError::PermissionsError)
+    }
+}
+
+pub(crate) async fn try_manage_bans(
+    app: &AppData,
+    req: &HttpRequest,
+) -> Result<ManageBans, UserError> {
+    if is_moderator(app, req).await? {
+        let moderator = utils::get_username(req).ok_or(UserError::LoginRequiredError)?;
+        Ok(ManageBans {
+            moderator,
+            _private: (),
+        })
+    } else {
+        Err(UserError::PermissionsError)
+    }
+}
+
+/// Sessions can be managed by the user or by moderators (eg, to log out a
+/// compromised account).
+pub(crate) async fn try_manage_sessions(
+    app: &AppData,
+    req: &HttpRequest,
+    username: &str,
+) -> Result<ManageSessions, UserError> {
+    let requestor = utils::get_username(req).ok_or(UserError::LoginRequiredError)?;
+    let authorized =
+        requestor == username || get_user_role(app, &requestor).await? >= UserRole::Moderator;
+
+    if authorized {
+        Ok(ManageSessions {
+            username: username.to_owned(),
+            _private: (),
+        })
+    } else {
+        Err(UserError::PermissionsError)
+    }
+}
+
+pub(crate) async fn try_manage_access_tokens(
+    req: &HttpRequest,
+    username: &str,
+) -> Result<ManageAccessTokens, UserError> {
+    if utils::is_token_auth(req) {
+        return Err(UserError::PermissionsError);
+    }
+
+    let requestor = utils::get_username(req).ok_or(UserError::LoginRequiredError)?;
+    if requestor == username {
+        Ok(ManageAccessTokens {
+            username: username.to_owned(),
+            _private: (),
+        })
+    } else {
+        Err(UserError::PermissionsError)
+    }
+}
+
+pub(crate) async fn try_manage_two_factor(
+    req: &HttpRequest,
+    username: &str,
+) -> Result<ManageTwoFactor, UserError> {
+    let requestor = utils::get_username(req).ok_or(UserError::LoginRequiredError)?;
+    if requestor == username {
+        Ok(ManageTwoFactor {
+            username: username.to_owned(),
+            _private: (),
+        })
+    } else {
+        Err(UserError::PermissionsError)
+    }
+}
+
+pub(crate) async fn try_reset_two_factor(
+    app: &AppData,
+    req: &HttpRequest,
+    username: &str,
+) -> Result<ResetTwoFactor, UserError> {
+    if is_super_user(app, req).await? {
+        let admin = utils::get_username(req).ok_or(UserError::LoginRequiredError)?;
+        Ok(ResetTwoFactor {
+            username: username.to_owned(),
+            admin,
             _private: (),
         })
     } else {
@@ -329,18 +714,17 @@ pub(crate) async fn try_ban_user(
 }
 
 pub(super) async fn is_super_user(app: &AppData, req: &HttpRequest) -> Result<bool, UserError> {
-    let session = req.get_session();
-    match get_session_role(app, &session).await? {
+    match get_request_role(app, req).await? {
         UserRole::Admin => Ok(true),
         _ => Ok(false),
     }
 }
 
-async fn get_session_role(app: &AppData, session: &Session) -> Result<UserRole, UserError> {
-    if let Some(username) = session.get::<String>("username").unwrap_or(None) {
+async fn get_request_role(app: &AppData, req: &HttpRequest) -> Result<UserRole, UserError> {
+    if let Some(username) = utils::get_username(req) {
         get_user_role(app, &username).await
     } else {
-        session.purge();
+        req.get_session().purge();
         Err(UserError::LoginRequiredError)
     }
 }
@@ -356,8 +740,8 @@ async fn get_user_role(app: &AppData, username: &str) -> Result<UserRole, UserEr
         .unwrap_or(UserRole::User))
 }
 
-pub(super) async fn is_moderator(app: &AppData, session: &Session) -> Result<bool, UserError> {
-    let role = get_session_role(app, session).await?;
+pub(super) async fn is_moderator(app: &AppData, req: &HttpRequest) -> Result<bool, UserError> {
+    let role = get_request_role(app, req).await?;
     Ok(role >= UserRole::Moderator)
 }
 
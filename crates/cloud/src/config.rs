@@ -1,10 +1,14 @@
-use std::{env, num::NonZeroUsize};
+use std::{collections::HashMap, env, num::NonZeroUsize};
 
 use figment::{
     providers::{Format, Toml},
     Figment,
 };
-use netsblox_cloud_common::api::ServiceHostScope;
+use netsblox_cloud_common::{
+    api::{HostCapability, IpReputationAction, ServiceHostScope},
+    password::HashParams,
+    HostSecret,
+};
 use serde::Deserialize;
 
 #[derive(Clone, Deserialize, Debug)]
@@ -49,7 +53,434 @@ pub struct SMTPSettings {
 
 #[derive(Clone, Deserialize, Debug)]
 pub struct SecuritySettings {
-    pub allow_tor_login: bool,
+    /// Argon2id parameters used for hashing passwords and client secrets
+    #[serde(default)]
+    pub password_hashing: HashParams,
+    /// Addresses (or CIDR ranges) of reverse proxies in front of the server.
+    /// The client address is only read from the `Forwarded` (or
+    /// `X-Forwarded-For`) header on requests from these addresses so the
+    /// proxies must overwrite any value sent by the client.
+    #[serde(default)]
+    pub trusted_proxies: Vec<String>,
+}
+
+/// Lists of addresses (eg, Tor exit nodes) which should be blocked or flagged
+#[derive(Clone, Deserialize, Debug)]
+pub struct IpReputationSettings {
+    /// How often remote lists and local files are reloaded
+    #[serde(default = "default_ip_list_refresh")]
+    pub refresh_interval_secs: u64,
+    #[serde(default)]
+    pub sources: Vec<IpReputationSource>,
+}
+
+impl Default for IpReputationSettings {
+    fn default() -> Self {
+        Self {
+            refresh_interval_secs: default_ip_list_refresh(),
+            sources: Vec::new(),
+        }
+    }
+}
+
+fn default_ip_list_refresh() -> u64 {
+    24 * 60 * 60
+}
+
+#[derive(Clone, Deserialize, Debug)]
+pub struct IpReputationSource {
+    /// Unique name of the source (eg, "tor")
+    pub name: String,
+    #[serde(flatten)]
+    pub list: IpListConfig,
+    /// Actions to take for requests from addresses on the list
+    pub actions: Vec<IpReputationAction>,
+}
+
+/// Where the addresses of a source come from. Lists contain one address or
+/// CIDR range per line (blank lines and comments starting with '#' are ignored).
+#[derive(Clone, Deserialize, Debug)]
+#[serde(tag = "type", rename_all = "lowercase")]
+pub enum IpListConfig {
+    /// List fetched from a URL
+    Remote { url: String },
+    /// List read from a local file
+    File { path: String },
+    /// Addresses listed in the configuration
+    Static { entries: Vec<String> },
+    /// Addresses added (and removed) by admins using the API
+    Admin,
+}
+
+/// External identity providers which can be used to login (in addition to
+/// Snap!, which is always supported)
+#[derive(Clone, Deserialize, Debug, Default)]
+pub struct AuthSettings {
+    #[serde(default)]
+    pub strategies: Vec<StrategyConfig>,
+}
+
+#[derive(Clone, Deserialize, Debug)]
+#[serde(tag = "type", rename_all = "lowercase")]
+pub enum StrategyConfig {
+    /// Login using a generic OpenID Connect provider (eg, a school's IdP)
+    Oidc(OidcConfig),
+}
+
+#[derive(Clone, Deserialize, Debug)]
+pub struct OidcConfig {
+    /// Name of the strategy (used when linking accounts)
+    pub name: String,
+    /// Issuer URL. Endpoints are discovered using `/.well-known/openid-configuration`
+    pub issuer: String,
+    pub client_id: String,
+    pub client_secret: String,
+    #[serde(default = "default_oidc_scopes")]
+    pub scopes: Vec<String>,
+}
+
+fn default_oidc_scopes() -> Vec<String> {
+    vec!["openid".into(), "email".into(), "profile".into()]
+}
+
+/// Lifetimes of the codes and tokens issued to OAuth clients
+#[derive(Clone, Deserialize, Debug)]
+pub struct OAuthSettings {
+    #[serde(default = "default_oauth_code_lifetime")]
+    pub code_lifetime_secs: u64,
+    #[serde(default = "default_oauth_access_token_lifetime")]
+    pub access_token_lifetime_secs: u64,
+    #[serde(default = "default_oauth_refresh_token_lifetime")]
+    pub refresh_token_lifetime_days: u64,
+    /// Keys used to sign OpenID Connect ID tokens. The first key is used for
+    /// signing; the others are only published (eg, while rotating keys).
+    /// The `openid` scope is unavailable if no keys are configured.
+    #[serde(default)]
+    pub signing_keys: Vec<SigningKeyConfig>,
+}
+
+impl Default for OAuthSettings {
+    fn default() -> Self {
+        Self {
+            code_lifetime_secs: default_oauth_code_lifetime(),
+            access_token_lifetime_secs: default_oauth_access_token_lifetime(),
+            refresh_token_lifetime_days: default_oauth_refresh_token_lifetime(),
+            signing_keys: Vec::new(),
+        }
+    }
+}
+
+#[derive(Clone, Deserialize, Debug)]
+pub struct SigningKeyConfig {
+    /// Key ID (published in the JWKS)
+    pub id: String,
+    pub algorithm: SigningAlgorithm,
+    /// Path to the PEM-encoded private key (PKCS#8 or, for RSA, PKCS#1)
+    pub private_key_file: String,
+}
+
+#[derive(Clone, Copy, Deserialize, Debug, PartialEq, Eq)]
+pub enum SigningAlgorithm {
+    RS256,
+    EdDSA,
+}
+
+fn default_oauth_code_lifetime() -> u64 {
+    60
+}
+
+fn default_oauth_access_token_lifetime() -> u64 {
+    60 * 60
+}
+
+fn default_oauth_refresh_token_lifetime() -> u64 {
+    30
+}
+
+/// Authentication of requests from authorized service hosts
+#[derive(Clone, Deserialize, Debug)]
+pub struct ServiceHostSettings {
+    /// Maximum difference (in seconds) between the timestamp of a signed
+    /// request and the current time
+    #[serde(default = "default_signature_max_age")]
+    pub signature_max_age_secs: u64,
+    /// Number of seconds the previous secrets remain valid after rotating a
+    /// host's secret
+    #[serde(default = "default_secret_overlap")]
+    pub secret_overlap_secs: u64,
+    /// Reject requests which use the secret directly (`X-Authorization: id:secret`)
+    /// rather than signing the request
+    #[serde(default)]
+    pub require_signed_requests: bool,
+    /// Key used to encrypt the signing keys of hosts (the cookie key is used if unset)
+    pub key: Option<String>,
+}
+
+impl Default for ServiceHostSettings {
+    fn default() -> Self {
+        Self {
+            signature_max_age_secs: default_signature_max_age(),
+            secret_overlap_secs: default_secret_overlap(),
+            require_signed_requests: false,
+            key: None,
+        }
+    }
+}
+
+fn default_signature_max_age() -> u64 {
+    5 * 60
+}
+
+fn default_secret_overlap() -> u64 {
+    24 * 60 * 60
+}
+
+/// Limits for creating new accounts. These do not apply to accounts created
+/// by group owners (ie, members) or moderators.
+#[derive(Clone, Deserialize, Debug)]
+pub struct SignupSettings {
+    /// Number of seconds in which recent signups are counted
+    #[serde(default = "default_signup_window")]
+    pub window_secs: u64,
+    /// Maximum signups from a single IP address during the window
+    #[serde(default = "default_max_signups_per_ip")]
+    pub max_per_ip: u64,
+    /// Maximum signups from a single subnet during the window
+    #[serde(default = "default_max_signups_per_subnet")]
+    pub max_per_subnet: u64,
+    /// Prefix length used to group IPv4 addresses into subnets
+    #[serde(default = "default_ipv4_subnet_prefix")]
+    pub ipv4_subnet_prefix: u8,
+    /// Prefix length used to group IPv6 addresses into subnets
+    #[serde(default = "default_ipv6_subnet_prefix")]
+    pub ipv6_subnet_prefix: u8,
+    /// Number of leading zero bits required in the solution to the
+    /// proof-of-work challenge. The challenge is not required if 0.
+    #[serde(default)]
+    pub challenge_difficulty: u8,
+    /// Number of seconds a challenge can be used after it is issued
+    #[serde(default = "default_challenge_ttl")]
+    pub challenge_ttl_secs: u64,
+}
+
+impl Default for SignupSettings {
+    fn default() -> Self {
+        Self {
+            window_secs: default_signup_window(),
+            max_per_ip: default_max_signups_per_ip(),
+            max_per_subnet: default_max_signups_per_subnet(),
+            ipv4_subnet_prefix: default_ipv4_subnet_prefix(),
+            ipv6_subnet_prefix: default_ipv6_subnet_prefix(),
+            challenge_difficulty: 0,
+            challenge_ttl_secs: default_challenge_ttl(),
+        }
+    }
+}
+
+fn default_signup_window() -> u64 {
+    60 * 60
+}
+
+fn default_max_signups_per_ip() -> u64 {
+    20
+}
+
+fn default_max_signups_per_subnet() -> u64 {
+    50
+}
+
+fn default_ipv4_subnet_prefix() -> u8 {
+    24
+}
+
+fn default_ipv6_subnet_prefix() -> u8 {
+    64
+}
+
+fn default_challenge_ttl() -> u64 {
+    10 * 60
+}
+
+/// Lockouts after repeated failed logins (for a username or IP address)
+#[derive(Clone, Deserialize, Debug)]
+pub struct LoginProtectionSettings {
+    /// Number of seconds in which failed logins are counted
+    #[serde(default = "default_login_window")]
+    pub window_secs: u64,
+    /// Failed logins for a username (during the window) before it is locked
+    #[serde(default = "default_max_failures_per_user")]
+    pub max_failures_per_user: u32,
+    /// Failed logins from an IP address (during the window) before it is locked
+    #[serde(default = "default_max_failures_per_address")]
+    pub max_failures_per_address: u32,
+    /// Duration of the first lockout. Each subsequent lockout is twice as long.
+    #[serde(default = "default_lockout")]
+    pub lockout_secs: u64,
+    #[serde(default = "default_max_lockout")]
+    pub max_lockout_secs: u64,
+    /// Number of seconds without a failed login after which the counters
+    /// (including the number of lockouts) are reset
+    #[serde(default = "default_login_counter_ttl")]
+    pub reset_after_secs: u64,
+}
+
+impl Default for LoginProtectionSettings {
+    fn default() -> Self {
+        Self {
+            window_secs: default_login_window(),
+            max_failures_per_user: default_max_failures_per_user(),
+            max_failures_per_address: default_max_failures_per_address(),
+            lockout_secs: default_lockout(),
+            max_lockout_secs: default_max_lockout(),
+            reset_after_secs: default_login_counter_ttl(),
+        }
+    }
+}
+
+fn default_login_window() -> u64 {
+    15 * 60
+}
+
+fn default_max_failures_per_user() -> u32 {
+    5
+}
+
+fn default_max_failures_per_address() -> u32 {
+    20
+}
+
+fn default_lockout() -> u64 {
+    60
+}
+
+fn default_max_lockout() -> u64 {
+    60 * 60
+}
+
+fn default_login_counter_ttl() -> u64 {
+    24 * 60 * 60
+}
+
+/// Password-less login using links (or codes) sent by email
+#[derive(Clone, Deserialize, Debug)]
+pub struct MagicLinkSettings {
+    /// Number of seconds until a link (and its code) expires
+    #[serde(default = "default_magic_link_expiry")]
+    pub expiry_secs: u64,
+    /// Number of times a code can be entered before the link is invalidated
+    #[serde(default = "default_magic_link_code_attempts")]
+    pub max_code_attempts: u32,
+}
+
+impl Default for MagicLinkSettings {
+    fn default() -> Self {
+        Self {
+            expiry_secs: default_magic_link_expiry(),
+            max_code_attempts: default_magic_link_code_attempts(),
+        }
+    }
+}
+
+fn default_magic_link_expiry() -> u64 {
+    60 * 60
+}
+
+fn default_magic_link_code_attempts() -> u32 {
+    5
+}
+
+/// How the nodes of a cluster share their clients, rooms and messages
+#[derive(Clone, Deserialize, Debug)]
+pub struct TopologySettings {
+    #[serde(default)]
+    pub backend: TopologyBackendKind,
+    /// Number of seconds until the clients of an unresponsive node are
+    /// considered disconnected (mongo backend only)
+    #[serde(default = "default_presence_ttl")]
+    pub presence_ttl_secs: u64,
+}
+
+impl Default for TopologySettings {
+    fn default() -> Self {
+        Self {
+            backend: TopologyBackendKind::default(),
+            presence_ttl_secs: default_presence_ttl(),
+        }
+    }
+}
+
+fn default_presence_ttl() -> u64 {
+    2 * 60
+}
+
+#[derive(Clone, Copy, Deserialize, Debug, Default, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum TopologyBackendKind {
+    /// A single server (no other nodes)
+    #[default]
+    Memory,
+    /// Nodes sharing the database. Requires a replica set (for change streams).
+    Mongo,
+}
+
+/// Cross-site request forgery protection
+#[derive(Clone, Deserialize, Debug, Default)]
+pub struct CsrfSettings {
+    /// Origins (in addition to the public URL) allowed to make state-changing
+    /// requests. If empty, the origin is not checked.
+    #[serde(default)]
+    pub allowed_origins: Vec<String>,
+}
+
+/// Cross-origin resource sharing (CORS) policy. Scopes (eg, "oauth" or "services")
+/// can override the default policy used for the rest of the API.
+#[derive(Clone, Deserialize, Debug, Default)]
+pub struct CorsSettings {
+    #[serde(flatten)]
+    pub default: CorsPolicy,
+    #[serde(default)]
+    pub scopes: HashMap<String, CorsPolicy>,
+}
+
+#[derive(Clone, Deserialize, Debug)]
+pub struct CorsPolicy {
+    /// Allowed origins. These can include a wildcard such as "https://*.netsblox.org"
+    /// or "*" to allow any origin.
+    #[serde(default)]
+    pub allowed_origins: Vec<String>,
+    #[serde(default = "default_cors_methods")]
+    pub allowed_methods: Vec<String>,
+    /// Allowed request headers. Any header is allowed if unset.
+    pub allowed_headers: Option<Vec<String>>,
+    /// Number of seconds preflight responses can be cached
+    pub max_age: Option<usize>,
+    /// Allow requests with credentials (eg, cookies). This is ignored if any origin
+    /// ("*") is allowed.
+    #[serde(default = "default_cors_credentials")]
+    pub supports_credentials: bool,
+}
+
+impl Default for CorsPolicy {
+    fn default() -> Self {
+        Self {
+            allowed_origins: Vec::new(),
+            allowed_methods: default_cors_methods(),
+            allowed_headers: None,
+            max_age: None,
+            supports_credentials: default_cors_credentials(),
+        }
+    }
+}
+
+fn default_cors_methods() -> Vec<String> {
+    ["GET", "POST", "PUT", "PATCH", "DELETE"]
+        .into_iter()
+        .map(|method| method.to_owned())
+        .collect()
+}
+
+fn default_cors_credentials() -> bool {
+    true
 }
 
 #[derive(Clone, Deserialize, Debug)]
@@ -74,17 +505,24 @@ pub struct AuthorizedServiceHost {
     pub(crate) url: String,
     pub(crate) secret: String,
     pub(crate) category: Option<String>,
+    /// Capabilities granted to the host (all capabilities if unset)
+    pub(crate) capabilities: Option<Vec<HostCapability>>,
 }
 
-impl From<AuthorizedServiceHost> for netsblox_cloud_common::AuthorizedServiceHost {
-    fn from(config: AuthorizedServiceHost) -> Self {
-        let categories = config.category.map(|cat| vec![cat]).unwrap_or_default();
-        Self {
-            id: config.id,
-            url: config.url,
-            secret: config.secret,
-            visibility: ServiceHostScope::Public(categories),
-        }
+impl AuthorizedServiceHost {
+    /// Create the host with the given (hashed) secret
+    pub(crate) fn into_host(
+        self,
+        secret: HostSecret,
+    ) -> netsblox_cloud_common::AuthorizedServiceHost {
+        let categories = self.category.map(|cat| vec![cat]).unwrap_or_default();
+        netsblox_cloud_common::AuthorizedServiceHost::new(
+            self.url,
+            self.id,
+            ServiceHostScope::Public(categories),
+            self.capabilities.unwrap_or_else(HostCapability::all),
+            secret,
+        )
     }
 }
 
@@ -93,13 +531,37 @@ pub struct Settings {
     pub address: String,
     pub public_url: String,
     pub login_url: Option<String>,
+    /// Origins (in addition to the public URL) which can be redirected to after
+    /// logging in (eg, "https://editor.netsblox.org")
+    #[serde(default)]
+    pub allowed_redirect_origins: Vec<String>,
     pub database: Database,
     pub s3: S3,
     pub cookie: CookieSettings,
     pub email: EmailSettings,
     pub security: SecuritySettings,
+    #[serde(default)]
+    pub auth: AuthSettings,
+    #[serde(default)]
+    pub oauth: OAuthSettings,
+    #[serde(default)]
+    pub csrf: CsrfSettings,
+    #[serde(default)]
+    pub cors: CorsSettings,
     pub admin: Option<UserCreds>,
     pub authorized_host: Option<AuthorizedServiceHost>,
+    #[serde(default)]
+    pub service_hosts: ServiceHostSettings,
+    #[serde(default)]
+    pub signup: SignupSettings,
+    #[serde(default)]
+    pub login_protection: LoginProtectionSettings,
+    #[serde(default)]
+    pub ip_reputation: IpReputationSettings,
+    #[serde(default)]
+    pub magic_links: MagicLinkSettings,
+    #[serde(default)]
+    pub topology: TopologySettings,
     pub cache_settings: CacheSettings,
 }
 
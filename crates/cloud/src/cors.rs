@@ -0,0 +1,257 @@
+use actix_cors::Cors;
+use log::warn;
+
+use crate::config::{CorsPolicy, CorsSettings};
+
+/// Build the CORS middleware for the given scope (eg, "oauth"). The default
+/// policy is used unless the scope has been overridden in the settings.
+pub(crate) fn for_scope(settings: &CorsSettings, scope: &str) -> Cors {
+    let policy = settings.scopes.get(scope).unwrap_or(&settings.default);
+    build(policy)
+}
+
+fn build(policy: &CorsPolicy) -> Cors {
+    let allow_any_origin = policy.allowed_origins.iter().any(|origin| origin == "*");
+    let mut cors = if allow_any_origin {
+        Cors::default().allow_any_origin()
+    } else {
+        let allowed_origins = policy.allowed_origins.clone();
+        Cors::default().allowed_origin_fn(move |origin, _req| {
+            origin
+                .to_str()
+                .map(|origin| {
+                    allowed_origins
+                        .iter()
+                        .any(|pattern| origin_matches(pattern, origin))
+                })
+                .unwrap_or(false)
+        })
+    };
+
+    cors = cors.allowed_methods(policy.allowed_methods.iter().map(|method| method.as_str()));
+    cors = match &policy.allowed_headers {
+        Some(headers) => cors.allowed_headers(headers.iter().map(|header| header.as_str())),
+        None => cors.allow_any_header(),
+    };
+    cors = cors.max_age(policy.max_age);
+
+    // Any site could make requests using the user's session if credentials were
+    // allowed from any origin
+    if policy.supports_credentials && allow_any_origin {
+        warn!("Credentials are not supported for CORS policies allowing any origin (\"*\")");
+    } else if policy.supports_credentials {
+        cors = cors.supports_credentials();
+    }
+
+    cors
+}
+
+/// Check if the origin matches the pattern. Patterns may contain a single wildcard
+/// (eg, "https://*.netsblox.org") which matches any (non-empty) part of the host.
+fn origin_matches(pattern: &str, origin: &str) -> bool {
+    let pattern = pattern.trim_end_matches('/');
+    match pattern.split_once('*') {
+        Some((prefix, suffix)) => {
+            origin.len() > prefix.len() + suffix.len()
+                && origin.starts_with(prefix)
+                && origin.ends_with(suffix)
+                && !origin[prefix.len()..origin.len() - suffix.len()].contains('/')
+        }
+        None => pattern == origin,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use actix_web::{
+        get,
+        http::{header, StatusCode},
+        test, web, App, HttpResponse,
+    };
+
+    use super::*;
+
+    #[get("/test")]
+    async fn handler() -> HttpResponse {
+        HttpResponse::Ok().finish()
+    }
+
+    fn settings() -> CorsSettings {
+        let default = CorsPolicy {
+            allowed_origins: vec!["https://*.netsblox.org".into()],
+            max_age: Some(3600),
+            ..Default::default()
+        };
+        let oauth = CorsPolicy {
+            allowed_origins: vec!["*".into()],
+            allowed_methods: vec!["GET".into(), "POST".into()],
+            supports_credentials: false,
+            ..Default::default()
+        };
+
+        CorsSettings {
+            default,
+            scopes: HashMap::from([("oauth".into(), oauth)]),
+        }
+    }
+
+    fn preflight(uri: &str, origin: &str, method: &str) -> test::TestRequest {
+        test::TestRequest::default()
+            .method(actix_web::http::Method::OPTIONS)
+            .uri(uri)
+            .insert_header((header::ORIGIN, origin))
+            .insert_header((header::ACCESS_CONTROL_REQUEST_METHOD, method))
+    }
+
+    #[actix_web::test]
+    async fn test_origin_matches() {
+        assert!(origin_matches(
+            "https://editor.netsblox.org",
+            "https://editor.netsblox.org"
+        ));
+        assert!(origin_matches(
+            "https://*.netsblox.org",
+            "https://editor.netsblox.org"
+        ));
+        assert!(origin_matches(
+            "http://localhost:*",
+            "http://localhost:8000"
+        ));
+    }
+
+    #[actix_web::test]
+    async fn test_origin_matches_invalid() {
+        assert!(!origin_matches(
+            "https://*.netsblox.org",
+            "https://netsblox.org"
+        ));
+        assert!(!origin_matches(
+            "https://*.netsblox.org",
+            "https://evil.com/.netsblox.org"
+        ));
+        assert!(!origin_matches(
+            "https://*.netsblox.org",
+            "http://editor.netsblox.org"
+        ));
+        assert!(!origin_matches(
+            "https://*.netsblox.org",
+            "https://editor.netsblox.org.evil.com"
+        ));
+    }
+
+    #[actix_web::test]
+    async fn test_preflight_allowed() {
+        let settings = settings();
+        let app = test::init_service(
+            App::new().service(
+                web::scope("/users")
+                    .wrap(for_scope(&settings, "users"))
+                    .service(handler),
+            ),
+        )
+        .await;
+
+        let req = preflight("/users/test", "https://editor.netsblox.org", "POST").to_request();
+        let response = test::call_service(&app, req).await;
+        assert_eq!(response.status(), StatusCode::OK);
+
+        let headers = response.headers();
+        assert_eq!(
+            headers.get(header::ACCESS_CONTROL_ALLOW_ORIGIN).unwrap(),
+            "https://editor.netsblox.org"
+        );
+        assert_eq!(
+            headers
+                .get(header::ACCESS_CONTROL_ALLOW_CREDENTIALS)
+                .unwrap(),
+            "true"
+        );
+        assert_eq!(headers.get(header::ACCESS_CONTROL_MAX_AGE).unwrap(), "3600");
+    }
+
+    #[actix_web::test]
+    async fn test_preflight_origin_not_allowed() {
+        let settings = settings();
+        let app = test::init_service(
+            App::new().service(
+                web::scope("/users")
+                    .wrap(for_scope(&settings, "users"))
+                    .service(handler),
+            ),
+        )
+        .await;
+
+        let req = preflight("/users/test", "https://evil.com", "POST").to_request();
+        let response = test::call_service(&app, req).await;
+        assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+        assert!(response
+            .headers()
+            .get(header::ACCESS_CONTROL_ALLOW_ORIGIN)
+            .is_none());
+    }
+
+    #[actix_web::test]
+    async fn test_preflight_method_not_allowed() {
+        let settings = settings();
+        let app = test::init_service(
+            App::new().service(
+                web::scope("/oauth")
+                    .wrap(for_scope(&settings, "oauth"))
+                    .service(handler),
+            ),
+        )
+        .await;
+
+        let req = preflight("/oauth/test", "https://evil.com", "DELETE").to_request();
+        let response = test::call_service(&app, req).await;
+        assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+    }
+
+    #[actix_web::test]
+    async fn test_preflight_any_origin_without_credentials() {
+        let policy = CorsPolicy {
+            allowed_origins: vec!["*".into()],
+            supports_credentials: true,
+            ..Default::default()
+        };
+        let app = test::init_service(
+            App::new().service(web::scope("/users").wrap(build(&policy)).service(handler)),
+        )
+        .await;
+
+        let req = preflight("/users/test", "https://example.com", "POST").to_request();
+        let response = test::call_service(&app, req).await;
+        assert_eq!(response.status(), StatusCode::OK);
+
+        let headers = response.headers();
+        assert!(headers.get(header::ACCESS_CONTROL_ALLOW_ORIGIN).is_some());
+        assert!(headers
+            .get(header::ACCESS_CONTROL_ALLOW_CREDENTIALS)
+            .is_none());
+    }
+
+    #[actix_web::test]
+    async fn test_preflight_scope_override() {
+        let settings = settings();
+        let app = test::init_service(
+            App::new().service(
+                web::scope("/oauth")
+                    .wrap(for_scope(&settings, "oauth"))
+                    .service(handler),
+            ),
+        )
+        .await;
+
+        let req = preflight("/oauth/test", "https://example.com", "POST").to_request();
+        let response = test::call_service(&app, req).await;
+        assert_eq!(response.status(), StatusCode::OK);
+
+        let headers = response.headers();
+        assert!(headers.get(header::ACCESS_CONTROL_ALLOW_ORIGIN).is_some());
+        assert!(headers
+            .get(header::ACCESS_CONTROL_ALLOW_CREDENTIALS)
+            .is_none());
+    }
+}
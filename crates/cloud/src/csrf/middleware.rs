@@ -0,0 +1,333 @@
+use std::rc::Rc;
+
+use actix_session::SessionExt;
+use actix_web::{
+    body::MessageBody,
+    dev::{forward_ready, Payload, Service, ServiceRequest, ServiceResponse, Transform},
+    error::PayloadError,
+    http::{header, Method},
+    web, Error, HttpMessage,
+};
+use futures::{
+    future::{ready, LocalBoxFuture, Ready},
+    stream,
+};
+use reqwest::Url;
+
+use crate::{app_data::AppData, config::Settings, errors::UserError, utils};
+
+/// Protect state-changing requests from cross-site request forgery. Requests
+/// using the session must include the CSRF token bound to the session (in the
+/// `X-CSRF-Token` header or, for form submissions, the `csrfToken` field) and,
+/// if allowed origins are configured, come from one of them. Requests
+/// authenticated with an access token or as an authorized service host are
+/// exempt as they do not rely on cookies.
+///
+/// This must be wrapped by the session and access token middleware.
+pub(crate) struct CsrfProtection;
+
+impl<S, B> Transform<S, ServiceRequest> for CsrfProtection
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = Error> + 'static,
+    B: MessageBody + 'static,
+{
+    type Response = ServiceResponse<B>;
+    type Error = Error;
+    type Transform = CsrfProtectionMiddleware<S>;
+    type InitError = ();
+    type Future = Ready<Result<Self::Transform, Self::InitError>>;
+
+    fn new_transform(&self, service: S) -> Self::Future {
+        ready(Ok(CsrfProtectionMiddleware {
+            service: Rc::new(service),
+        }))
+    }
+}
+
+pub(crate) struct CsrfProtectionMiddleware<S> {
+    service: Rc<S>,
+}
+
+impl<S, B> Service<ServiceRequest> for CsrfProtectionMiddleware<S>
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = Error> + 'static,
+    B: MessageBody + 'static,
+{
+    type Response = ServiceResponse<B>;
+    type Error = Error;
+    type Future = LocalBoxFuture<'static, Result<Self::Response, Self::Error>>;
+
+    forward_ready!(service);
+
+    fn call(&self, mut req: ServiceRequest) -> Self::Future {
+        let service = Rc::clone(&self.service);
+
+        Box::pin(async move {
+            if is_state_changing(req.method()) && !utils::is_token_auth(req.request()) {
+                let app = req
+                    .app_data::<web::Data<AppData>>()
+                    .cloned()
+                    .ok_or(UserError::InternalError)?;
+
+                let is_host_auth = utils::get_authorized_host(&app, req.request())
+                    .await?
+                    .is_some();
+
+                if !is_host_auth {
+                    check_request(&app.settings, &mut req).await?;
+                }
+            }
+
+            service.call(req).await
+        })
+    }
+}
+
+fn is_state_changing(method: &Method) -> bool {
+    !matches!(*method, Method::GET | Method::HEAD | Method::OPTIONS)
+}
+
+async fn check_request(settings: &Settings, req: &mut ServiceRequest) -> Result<(), UserError> {
+    if let Some(origin) = request_origin(req) {
+        if !is_allowed_origin(settings, &origin) {
+            return Err(UserError::CsrfOriginError);
+        }
+    }
+
+    // Requests without a session don't carry any credentials to forge
+    let session = req.get_session();
+    if session.entries().is_empty() {
+        return Ok(());
+    }
+
+    let header_token = req
+        .headers()
+        .get(super::CSRF_HEADER)
+        .and_then(|value| value.to_str().ok())
+        .map(|token| token.to_owned());
+
+    let token = match header_token {
+        Some(token) => token,
+        None => form_token(req).await?.ok_or(UserError::CsrfTokenError)?,
+    };
+
+    if super::is_valid_token(&session, &token) {
+        Ok(())
+    } else {
+        Err(UserError::CsrfTokenError)
+    }
+}
+
+/// Get the CSRF token from the body of a form submission. The body is restored
+/// afterwards so it can still be used by the handler.
+async fn form_token(req: &mut ServiceRequest) -> Result<Option<String>, UserError> {
+    if req.content_type() != "application/x-www-form-urlencoded" {
+        return Ok(None);
+    }
+
+    let body = req
+        .extract::<web::Bytes>()
+        .await
+        .map_err(|_err| UserError::CsrfTokenError)?;
+
+    let token = serde_urlencoded::from_bytes::<Vec<(String, String)>>(&body)
+        .map_err(|_err| UserError::CsrfTokenError)?
+        .into_iter()
+        .find(|(name, _value)| name == super::CSRF_FIELD)
+        .map(|(_name, value)| value);
+
+    let stream = stream::once(async move { Ok::<_, PayloadError>(body) });
+    req.set_payload(Payload::Stream {
+        payload: Box::pin(stream),
+    });
+
+    Ok(token)
+}
+
+/// Get the origin of the request from the Origin header (falling back to the Referer)
+fn request_origin(req: &ServiceRequest) -> Option<String> {
+    let headers = req.headers();
+    let origin = headers
+        .get(header::ORIGIN)
+        .and_then(|value| value.to_str().ok())
+        .filter(|origin| *origin != "null")
+        .map(|origin| origin.to_owned());
+
+    origin.or_else(|| {
+        headers
+            .get(header::REFERER)
+            .and_then(|value| value.to_str().ok())
+            .and_then(|referer| Url::parse(referer).ok())
+            .map(|url| url.origin().ascii_serialization())
+    })
+}
+
+/// Check the origin against the allowed origins. If none have been configured,
+/// any origin is allowed (and only the CSRF token is checked).
+fn is_allowed_origin(settings: &Settings, origin: &str) -> bool {
+    let allowed_origins = &settings.csrf.allowed_origins;
+    if allowed_origins.is_empty() {
+        return true;
+    }
+
+    let public_origin = Url::parse(&settings.public_url)
+        .ok()
+        .map(|url| url.origin().ascii_serialization());
+
+    public_origin.as_deref() == Some(origin)
+        || allowed_origins
+            .iter()
+            .any(|allowed| allowed.trim_end_matches('/') == origin)
+}
+
+#[cfg(test)]
+mod tests {
+    use actix_session::Session;
+    use actix_web::{http, post, test, App, HttpResponse};
+    use netsblox_cloud_common::{api, AuthorizedServiceHost};
+
+    use super::*;
+    use crate::{access_tokens::middleware::AccessTokenAuth, test_utils};
+
+    #[actix_web::get("/token")]
+    async fn get_token(session: Session) -> HttpResponse {
+        HttpResponse::Ok().body(crate::csrf::get_or_create_token(&session))
+    }
+
+    #[post("/change")]
+    async fn change() -> HttpResponse {
+        HttpResponse::Ok().finish()
+    }
+
+    #[actix_web::test]
+    async fn test_token_required() {
+        test_utils::setup()
+            .run(|app_data| async {
+                let app = test::init_service(
+                    App::new()
+                        .app_data(web::Data::new(app_data))
+                        .wrap(CsrfProtection)
+                        .wrap(test_utils::cookie::middleware())
+                        .service(get_token)
+                        .service(change),
+                )
+                .await;
+
+                let req = test::TestRequest::get().uri("/token").to_request();
+                let response = test::call_service(&app, req).await;
+                let cookie = response.response().cookies().next().unwrap().into_owned();
+
+                let req = test::TestRequest::post()
+                    .uri("/change")
+                    .cookie(cookie)
+                    .to_request();
+                let response = test::call_service(&app, req).await;
+                assert_eq!(response.status(), http::StatusCode::FORBIDDEN);
+            })
+            .await;
+    }
+
+    #[actix_web::test]
+    async fn test_valid_token() {
+        test_utils::setup()
+            .run(|app_data| async {
+                let app = test::init_service(
+                    App::new()
+                        .app_data(web::Data::new(app_data))
+                        .wrap(CsrfProtection)
+                        .wrap(test_utils::cookie::middleware())
+                        .service(get_token)
+                        .service(change),
+                )
+                .await;
+
+                let req = test::TestRequest::get().uri("/token").to_request();
+                let response = test::call_service(&app, req).await;
+                let cookie = response.response().cookies().next().unwrap().into_owned();
+                let token = test::read_body(response).await;
+                let token = std::str::from_utf8(&token).unwrap();
+
+                let req = test::TestRequest::post()
+                    .uri("/change")
+                    .cookie(cookie.clone())
+                    .insert_header((crate::csrf::CSRF_HEADER, "invalid"))
+                    .to_request();
+                let response = test::call_service(&app, req).await;
+                assert_eq!(response.status(), http::StatusCode::FORBIDDEN);
+
+                let req = test::TestRequest::post()
+                    .uri("/change")
+                    .cookie(cookie)
+                    .insert_header((crate::csrf::CSRF_HEADER, token))
+                    .to_request();
+                let response = test::call_service(&app, req).await;
+                assert_eq!(response.status(), http::StatusCode::OK);
+            })
+            .await;
+    }
+
+    #[actix_web::test]
+    async fn test_origin_not_allowed() {
+        test_utils::setup()
+            .run(|mut app_data| async move {
+                app_data.settings.csrf.allowed_origins = vec!["https://editor.netsblox.org".into()];
+                let app = test::init_service(
+                    App::new()
+                        .app_data(web::Data::new(app_data))
+                        .wrap(CsrfProtection)
+                        .wrap(test_utils::cookie::middleware())
+                        .service(change),
+                )
+                .await;
+
+                let req = test::TestRequest::post()
+                    .uri("/change")
+                    .insert_header((header::ORIGIN, "https://evil.example.com"))
+                    .to_request();
+                let response = test::call_service(&app, req).await;
+                assert_eq!(response.status(), http::StatusCode::FORBIDDEN);
+
+                let req = test::TestRequest::post()
+                    .uri("/change")
+                    .insert_header((header::ORIGIN, "https://editor.netsblox.org"))
+                    .to_request();
+                let response = test::call_service(&app, req).await;
+                assert_eq!(response.status(), http::StatusCode::OK);
+            })
+            .await;
+    }
+
+    #[actix_web::test]
+    async fn test_host_auth_exempt() {
+        let host = AuthorizedServiceHost::new(
+            "http://localhost:8000".into(),
+            "TestHost".into(),
+            api::ServiceHostScope::Private,
+            Vec::new(),
+            crate::services::hosts::signing::test_host_secret("SuperSecret"),
+        );
+        test_utils::setup()
+            .with_authorized_services(&[host.clone()])
+            .run(|app_data| async move {
+                let app = test::init_service(
+                    App::new()
+                        .app_data(web::Data::new(app_data))
+                        .wrap(CsrfProtection)
+                        .wrap(AccessTokenAuth)
+                        .wrap(test_utils::cookie::middleware())
+                        .service(change),
+                )
+                .await;
+
+                let auth = format!("{}:SuperSecret", host.id);
+                let req = test::TestRequest::post()
+                    .uri("/change")
+                    .cookie(test_utils::cookie::new("someUser"))
+                    .insert_header(("X-Authorization", auth))
+                    .to_request();
+                let response = test::call_service(&app, req).await;
+                assert_eq!(response.status(), http::StatusCode::OK);
+            })
+            .await;
+    }
+}
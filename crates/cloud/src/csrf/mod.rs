@@ -0,0 +1,55 @@
+pub(crate) mod middleware;
+
+use actix_session::Session;
+use uuid::Uuid;
+
+/// Header used to submit the CSRF token with state-changing requests
+pub(crate) const CSRF_HEADER: &str = "X-CSRF-Token";
+/// Form field used to submit the CSRF token from HTML forms (which can't set headers)
+pub(crate) const CSRF_FIELD: &str = "csrfToken";
+const SESSION_KEY: &str = "csrfToken";
+
+/// Get the CSRF token bound to the given session (creating one if needed).
+/// The token is issued to clients in the `/configuration` response and must
+/// be submitted back in the `X-CSRF-Token` header (or the `csrfToken` form field)
+/// for any state-changing request.
+pub(crate) fn get_or_create_token(session: &Session) -> String {
+    match session.get::<String>(SESSION_KEY).unwrap_or(None) {
+        Some(token) => token,
+        None => {
+            let token = format!(
+                "{}{}",
+                Uuid::new_v4().as_simple(),
+                Uuid::new_v4().as_simple()
+            );
+            session.insert(SESSION_KEY, &token).unwrap();
+            token
+        }
+    }
+}
+
+/// Check the submitted token against the one bound to the session
+pub(crate) fn is_valid_token(session: &Session, submitted: &str) -> bool {
+    session
+        .get::<String>(SESSION_KEY)
+        .unwrap_or(None)
+        .map(|token| constant_time_eq(token.as_bytes(), submitted.as_bytes()))
+        .unwrap_or(false)
+}
+
+fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    a.len() == b.len() && a.iter().zip(b).fold(0, |acc, (x, y)| acc | (x ^ y)) == 0
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_constant_time_eq() {
+        assert!(constant_time_eq(b"abc", b"abc"));
+        assert!(!constant_time_eq(b"abc", b"abd"));
+        assert!(!constant_time_eq(b"abc", b"abcd"));
+        assert!(!constant_time_eq(b"", b"a"));
+    }
+}
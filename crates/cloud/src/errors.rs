@@ -1,4 +1,8 @@
-use actix_web::{error, http::StatusCode, HttpResponse, HttpResponseBuilder};
+use actix_web::{
+    error,
+    http::{header, StatusCode},
+    HttpResponse, HttpResponseBuilder,
+};
 use derive_more::{Display, Error};
 use log::warn;
 use serde::Serialize;
@@ -9,7 +13,8 @@ pub enum InternalError {
     TimeoutError,
     S3Error,
     S3ContentError,
-    TorNodeListFetchError(reqwest::Error),
+    IpListFetchError(reqwest::Error),
+    IpListReadError(std::io::Error),
     ActixMessageError(actix::MailboxError),
     SendEmailError(lettre::transport::smtp::Error),
     EmailBuildError,
@@ -17,6 +22,12 @@ pub enum InternalError {
     ThumbnailDecodeError(image::ImageError),
     ThumbnailEncodeError(image::ImageError),
     PasswordGenerationError,
+    PasswordHashError(netsblox_cloud_common::password::HashError),
+    TwoFactorSecretError,
+    ArchiveError(zip::result::ZipError),
+    NoSigningKeyError,
+    IdTokenSigningError(jsonwebtoken::errors::Error),
+    HostKeyEncryptionError,
 }
 
 #[derive(Debug, Display, Error)]
@@ -39,10 +50,18 @@ pub enum UserError {
     UserUpdateFieldRequiredError,
     #[display(fmt = "Password reset link already sent. Only 1 can be sent per hour.")]
     PasswordResetLinkSentError,
-    #[display(fmt = "Magic link already sent. Only 1 can be sent per hour.")]
+    #[display(fmt = "Magic link already sent. Please use it (or wait for it to expire) first.")]
     MagicLinkSentError,
     #[display(fmt = "Magic link not found or no longer active.")]
     MagicLinkNotFoundError,
+    #[display(fmt = "Verification email already sent. Only 1 can be sent per hour.")]
+    VerificationEmailSentError,
+    #[display(fmt = "Verification link not found or no longer active.")]
+    VerificationLinkNotFoundError,
+    #[display(fmt = "Email address already verified.")]
+    EmailAlreadyVerifiedError,
+    #[display(fmt = "Email address must be verified first.")]
+    EmailVerificationRequiredError,
     #[display(fmt = "Network trace not found.")]
     NetworkTraceNotFoundError,
     #[display(fmt = "Library not found.")]
@@ -75,6 +94,42 @@ pub enum UserError {
     IncorrectUsernameOrPasswordError,
     #[display(fmt = "User has been banned.")]
     BannedUserError,
+    #[display(fmt = "Access from this address has been banned.")]
+    BannedAddressError,
+    #[display(
+        fmt = "Requests from this address are not allowed (listed by {}).",
+        list
+    )]
+    BlockedAddressError { list: String },
+    #[display(fmt = "IP reputation source not found.")]
+    IpReputationSourceNotFoundError,
+    #[display(fmt = "IP reputation entry not found.")]
+    IpReputationEntryNotFoundError,
+    #[display(
+        fmt = "Too many failed login attempts. Please try again in {} seconds.",
+        retry_after
+    )]
+    LoginLockedError { retry_after: u64 },
+    #[display(
+        fmt = "Too many accounts have been created from this network. Please try again later."
+    )]
+    SignupRateLimitError,
+    #[display(fmt = "A signup challenge must be solved to create an account.")]
+    SignupChallengeRequiredError,
+    #[display(fmt = "Invalid or expired signup challenge solution.")]
+    InvalidSignupChallengeError,
+    #[display(fmt = "Ban not found.")]
+    BanNotFoundError,
+    #[display(fmt = "Account deletion not found.")]
+    AccountDeletionNotFoundError,
+    #[display(fmt = "Two-factor authentication code required.")]
+    TwoFactorRequiredError,
+    #[display(fmt = "Invalid two-factor authentication code.")]
+    InvalidTwoFactorCodeError,
+    #[display(fmt = "Two-factor authentication already enabled.")]
+    TwoFactorAlreadyEnabledError,
+    #[display(fmt = "Two-factor authentication not enabled.")]
+    TwoFactorNotEnabledError,
     #[display(fmt = "User already exists.")]
     UserExistsError,
     // FIXME: use a different status code or something so the client can
@@ -95,30 +150,72 @@ pub enum UserError {
     InvalidEmailAddress,
     #[display(fmt = "Invalid client ID.")]
     InvalidClientIdError,
+    #[display(fmt = "Invalid client secret.")]
+    InvalidClientSecretError,
     #[display(fmt = "Invalid app ID.")]
     InvalidAppIdError,
     #[display(fmt = "Invalid service host ID.")]
     InvalidServiceHostIDError,
+    #[display(fmt = "Invalid IP address or range.")]
+    InvalidAddressError,
     #[display(fmt = "Unable to connect to Snap! Please try again later.")]
     SnapConnectionError,
+    #[display(fmt = "Unable to connect to the identity provider. Please try again later.")]
+    IdentityProviderConnectionError,
+    #[display(fmt = "Unable to verify login with the identity provider.")]
+    InvalidIdentityTokenError,
+    #[display(fmt = "Login request is invalid or has expired. Please try again.")]
+    LoginStateMismatchError,
+    #[display(fmt = "Redirecting to the given URL is not allowed.")]
+    RedirectNotAllowedError,
     #[display(fmt = "Account already linked to NetsBlox user.")]
     AccountAlreadyLinkedError,
     #[display(fmt = "Invalid account type.")]
     InvalidAccountTypeError,
-    #[display(fmt = "Login from Tor not allowed.")]
-    TorAddressError,
-    #[display(fmt = "Login from Opera VPN not allowed.")]
-    OperaVPNError,
     #[display(fmt = "An internal error occurred. Please try again later.")]
     InternalError,
     #[display(fmt = "Services endpoint already authorized.")]
     ServiceHostAlreadyAuthorizedError,
+    #[display(fmt = "Invalid request signature.")]
+    InvalidSignatureError,
+    #[display(fmt = "Request timestamp is missing or too far from the current time.")]
+    RequestTimestampError,
+    #[display(fmt = "Request has already been received.")]
+    ReplayedRequestError,
+    #[display(fmt = "Requests from service hosts must be signed.")]
+    SignedRequestRequiredError,
     #[display(fmt = "OAuth client with the given name already exists.")]
     OAuthClientAlreadyExistsError,
+    #[display(fmt = "Invalid OAuth client name.")]
+    InvalidOAuthClientNameError,
     #[display(fmt = "OAuth client not found.")]
     OAuthClientNotFoundError,
+    #[display(fmt = "Must specify name, allowedScopes, or redirectUris to update.")]
+    OAuthClientUpdateFieldRequiredError,
+    #[display(fmt = "Public OAuth clients do not have a secret.")]
+    PublicOAuthClientError,
+    #[display(
+        fmt = "Invalid redirect URI. Redirect URIs must use https (or http for localhost) and cannot contain a fragment."
+    )]
+    InvalidRedirectUriError,
+    #[display(fmt = "Invalid sign-in code.")]
+    InvalidMagicLinkCodeError,
     #[display(fmt = "OAuth token not found.")]
     OAuthTokenNotFoundError,
+    #[display(fmt = "Session not found.")]
+    SessionNotFoundError,
+    #[display(fmt = "Access token not found.")]
+    AccessTokenNotFoundError,
+    #[display(fmt = "Missing or invalid CSRF token.")]
+    CsrfTokenError,
+    #[display(fmt = "Request origin not allowed.")]
+    CsrfOriginError,
+    #[display(fmt = "Invalid or expired access token.")]
+    InvalidAccessTokenError,
+    #[display(fmt = "Access token does not have the required scope.")]
+    InsufficientTokenScopeError,
+    #[display(fmt = "At least one token scope is required.")]
+    TokenScopeRequiredError,
 
     #[display(fmt = "Error occurred during OAuth authentication")]
     OAuthFlowError(OAuthFlowError),
@@ -130,6 +227,13 @@ pub enum OAuthFlowError {
     InvalidRedirectUrlError,
     InvalidGrantTypeError,
     InvalidAuthorizationCodeError,
+    InvalidScopeError,
+    InvalidClientError,
+    CodeChallengeRequiredError,
+    UnsupportedCodeChallengeMethodError,
+    InvalidCodeVerifierError,
+    NoRefreshTokenError,
+    InvalidRefreshTokenError,
 }
 
 #[derive(Serialize)]
@@ -154,10 +258,31 @@ impl From<&OAuthFlowError> for OAuthErrorBody {
                 ("invalid_request", "No authorization code")
             }
             OAuthFlowError::InvalidAuthorizationCodeError => {
-                ("invalid_client", "Invalid authorization code")
+                ("invalid_grant", "Invalid or expired authorization code")
+            }
+            OAuthFlowError::InvalidGrantTypeError => {
+                ("unsupported_grant_type", "Unsupported grant type")
             }
-            OAuthFlowError::InvalidGrantTypeError => ("invalid_grant", "Invalid grant type"),
             OAuthFlowError::InvalidRedirectUrlError => ("invalid_grant", "Invalid redirect URI"),
+            OAuthFlowError::InvalidScopeError => (
+                "invalid_scope",
+                "Requested scope is invalid or not allowed for the client",
+            ),
+            OAuthFlowError::InvalidClientError => {
+                ("invalid_client", "Client authentication failed")
+            }
+            OAuthFlowError::CodeChallengeRequiredError => {
+                ("invalid_request", "PKCE code challenge required")
+            }
+            OAuthFlowError::UnsupportedCodeChallengeMethodError => (
+                "invalid_request",
+                "Unsupported code challenge method. Only S256 is supported",
+            ),
+            OAuthFlowError::InvalidCodeVerifierError => ("invalid_grant", "Invalid code verifier"),
+            OAuthFlowError::NoRefreshTokenError => ("invalid_request", "No refresh token"),
+            OAuthFlowError::InvalidRefreshTokenError => {
+                ("invalid_grant", "Invalid or expired refresh token")
+            }
         };
         OAuthErrorBody::new(name, desc)
     }
@@ -169,7 +294,20 @@ impl error::ResponseError for UserError {
         match self {
             UserError::OAuthFlowError(err) => {
                 let body: OAuthErrorBody = err.into();
-                HttpResponse::BadRequest().json(body)
+                HttpResponseBuilder::new(self.status_code())
+                    .insert_header(header::CacheControl(vec![header::CacheDirective::NoStore]))
+                    .json(body)
+            }
+            UserError::TwoFactorRequiredError => HttpResponseBuilder::new(self.status_code())
+                .insert_header((header::WWW_AUTHENTICATE, "TOTP"))
+                .body(self.to_string()),
+            UserError::InvalidAccessTokenError => HttpResponseBuilder::new(self.status_code())
+                .insert_header((header::WWW_AUTHENTICATE, "Bearer"))
+                .body(self.to_string()),
+            UserError::LoginLockedError { retry_after } => {
+                HttpResponseBuilder::new(self.status_code())
+                    .insert_header((header::RETRY_AFTER, retry_after.to_string()))
+                    .body(self.to_string())
             }
             _ => HttpResponseBuilder::new(self.status_code()).body(self.to_string()),
         }
@@ -177,10 +315,27 @@ impl error::ResponseError for UserError {
 
     fn status_code(&self) -> StatusCode {
         match *self {
-            Self::LoginRequiredError => StatusCode::UNAUTHORIZED,
+            Self::LoginRequiredError
+            | Self::TwoFactorRequiredError
+            | Self::InvalidAccessTokenError
+            | Self::InvalidSignatureError
+            | Self::RequestTimestampError
+            | Self::ReplayedRequestError
+            | Self::SignedRequestRequiredError
+            | Self::InvalidClientSecretError
+            | Self::OAuthFlowError(OAuthFlowError::InvalidClientError) => StatusCode::UNAUTHORIZED,
             Self::PermissionsError
+            | Self::InsufficientTokenScopeError
+            | Self::InvalidIdentityTokenError
             | Self::IncorrectUsernameOrPasswordError
+            | Self::InvalidMagicLinkCodeError
             | Self::BannedUserError
+            | Self::BannedAddressError
+            | Self::BlockedAddressError { .. }
+            | Self::CsrfTokenError
+            | Self::CsrfOriginError
+            | Self::EmailVerificationRequiredError
+            | Self::InvalidTwoFactorCodeError
             | Self::IncorrectPasswordError => StatusCode::FORBIDDEN,
 
             Self::ProjectNotFoundError
@@ -192,13 +347,22 @@ impl error::ResponseError for UserError {
             | Self::RoleNotFoundError
             | Self::InviteNotFoundError
             | Self::MagicLinkNotFoundError
+            | Self::VerificationLinkNotFoundError
             | Self::UserNotFoundError
             | Self::MessageNotFoundError
             | Self::FriendNotFoundError
             | Self::OAuthClientNotFoundError
             | Self::OAuthTokenNotFoundError
+            | Self::SessionNotFoundError
+            | Self::AccessTokenNotFoundError
+            | Self::BanNotFoundError
+            | Self::IpReputationSourceNotFoundError
+            | Self::IpReputationEntryNotFoundError
+            | Self::AccountDeletionNotFoundError
             | Self::GroupNotFoundError => StatusCode::NOT_FOUND,
-            Self::InternalError | Self::SnapConnectionError => StatusCode::INTERNAL_SERVER_ERROR,
+            Self::InternalError
+            | Self::SnapConnectionError
+            | Self::IdentityProviderConnectionError => StatusCode::INTERNAL_SERVER_ERROR,
             Self::InvalidUsername
             | Self::InvalidRoleOrProjectName
             | Self::RoleOrProjectNameExists
@@ -207,15 +371,25 @@ impl error::ResponseError for UserError {
             | Self::InvalidLibraryName
             | Self::InvalidAppIdError
             | Self::InvalidServiceHostIDError
+            | Self::InvalidAddressError
             | Self::AccountAlreadyLinkedError
             | Self::PasswordResetLinkSentError
             | Self::MagicLinkSentError
+            | Self::VerificationEmailSentError
+            | Self::EmailAlreadyVerifiedError
             | Self::InvalidAccountTypeError
-            | Self::TorAddressError
-            | Self::OperaVPNError
+            | Self::LoginStateMismatchError
+            | Self::RedirectNotAllowedError
             | Self::UserExistsError
+            | Self::TwoFactorAlreadyEnabledError
+            | Self::TwoFactorNotEnabledError
+            | Self::TokenScopeRequiredError
             | Self::UsernameExists
             | Self::OAuthClientAlreadyExistsError
+            | Self::OAuthClientUpdateFieldRequiredError
+            | Self::PublicOAuthClientError
+            | Self::InvalidRedirectUriError
+            | Self::InvalidOAuthClientNameError
             | Self::GroupExistsError
             | Self::CannotDeleteLastRoleError
             | Self::ServiceHostAlreadyAuthorizedError
@@ -224,8 +398,13 @@ impl error::ResponseError for UserError {
             | Self::ProjectUnavailableError
             | Self::MissingUrlOrXmlError
             | Self::UserUpdateFieldRequiredError
+            | Self::SignupChallengeRequiredError
+            | Self::InvalidSignupChallengeError
             | Self::ProjectNotActiveError => StatusCode::BAD_REQUEST,
             Self::InviteAlreadyExistsError => StatusCode::CONFLICT,
+            Self::SignupRateLimitError | Self::LoginLockedError { .. } => {
+                StatusCode::TOO_MANY_REQUESTS
+            }
         }
     }
 }
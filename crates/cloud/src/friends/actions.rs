@@ -120,6 +120,41 @@ impl<'a> FriendActions<'a> {
         Ok(())
     }
 
+    /// Remove all friend links (including pending invites and blocks) for a
+    /// deleted user. Returns the number of links removed.
+    pub(crate) async fn remove_all_links(&self, du: &auth::DeleteUser) -> Result<u32, UserError> {
+        let query = doc! {
+            "$or": [
+                {"sender": &du.username},
+                {"recipient": &du.username}
+            ]
+        };
+        let links: Vec<_> = self
+            .friends
+            .find(query.clone(), None)
+            .await
+            .map_err(InternalError::DatabaseConnectionError)?
+            .try_collect()
+            .await
+            .map_err(InternalError::DatabaseConnectionError)?;
+
+        let result = self
+            .friends
+            .delete_many(query, None)
+            .await
+            .map_err(InternalError::DatabaseConnectionError)?;
+
+        // invalidate friend cache
+        let mut cache = self.friend_cache.write().unwrap();
+        cache.pop(&du.username);
+        links.iter().for_each(|link| {
+            cache.pop(&link.sender);
+            cache.pop(&link.recipient);
+        });
+
+        Ok(result.deleted_count as u32)
+    }
+
     pub(crate) async fn block(
         &self,
         eu: &auth::users::EditUser,
@@ -111,7 +111,7 @@ async fn send_invite(
 ) -> Result<HttpResponse, UserError> {
     let (owner,) = path.into_inner();
     let recipient = recipient.into_inner();
-    let auth_eu = auth::try_edit_user(&app, &req, None, &owner).await?;
+    let auth_eu = auth::try_send_friend_invite(&app, &req, &owner).await?;
 
     let actions: FriendActions = app.as_friend_actions();
     let state = actions.send_invite(&auth_eu, &recipient).await?;
@@ -226,6 +226,50 @@ mod tests {
             .await;
     }
 
+    #[actix_web::test]
+    async fn test_send_invite_unverified() {
+        let mut user: User = api::NewUser {
+            username: "user".into(),
+            email: "user@netsblox.org".into(),
+            password: None,
+            group_id: None,
+            role: Some(UserRole::User),
+        }
+        .into();
+        user.verified = false;
+        let other: User = api::NewUser {
+            username: "other".into(),
+            email: "other@netsblox.org".into(),
+            password: None,
+            group_id: None,
+            role: Some(UserRole::User),
+        }
+        .into();
+
+        test_utils::setup()
+            .with_users(&[user.clone(), other.clone()])
+            .run(|app_data| async move {
+                let app = test::init_service(
+                    App::new()
+                        .wrap(test_utils::cookie::middleware())
+                        .app_data(web::Data::new(app_data.clone()))
+                        .configure(config),
+                )
+                .await;
+
+                let cookie = test_utils::cookie::new(&user.username);
+                let req = test::TestRequest::post()
+                    .uri(&format!("/{}/invite/", &user.username))
+                    .cookie(cookie)
+                    .set_json(&other.username)
+                    .to_request();
+
+                let response = test::call_service(&app, req).await;
+                assert_eq!(response.status(), http::StatusCode::FORBIDDEN);
+            })
+            .await;
+    }
+
     #[actix_web::test]
     async fn test_list_friends_403() {
         let user: User = api::NewUser {
@@ -2,19 +2,28 @@ use std::collections::HashMap;
 
 use futures::TryStreamExt;
 use mongodb::{bson::doc, options::ReturnDocument, Collection};
-use netsblox_cloud_common::{api, Group, User};
+use netsblox_cloud_common::{api, AuditLogEntry, Group, User};
 
-use crate::auth;
 use crate::errors::{InternalError, UserError};
+use crate::{audit, auth};
 
 pub(crate) struct GroupActions<'a> {
     groups: &'a Collection<Group>,
     users: &'a Collection<User>,
+    audit_log: &'a Collection<AuditLogEntry>,
 }
 
 impl<'a> GroupActions<'a> {
-    pub(crate) fn new(groups: &'a Collection<Group>, users: &'a Collection<User>) -> Self {
-        Self { groups, users }
+    pub(crate) fn new(
+        groups: &'a Collection<Group>,
+        users: &'a Collection<User>,
+        audit_log: &'a Collection<AuditLogEntry>,
+    ) -> Self {
+        Self {
+            groups,
+            users,
+            audit_log,
+        }
     }
 
     pub(crate) async fn create_group(
@@ -186,14 +195,29 @@ impl<'a> GroupActions<'a> {
         vg: &auth::groups::DeleteGroup,
     ) -> Result<api::Group, UserError> {
         let query = doc! {"id": &vg.id};
-        let group = self
+        let group: api::Group = self
             .groups
-            .find_one_and_delete(query, None)
+            .find_one(query.clone(), None)
             .await
             .map_err(InternalError::DatabaseConnectionError)?
-            .ok_or(UserError::GroupNotFoundError)?;
+            .ok_or(UserError::GroupNotFoundError)?
+            .into();
+
+        let entry = AuditLogEntry::new(
+            vg.requestor.clone(),
+            api::AuditAction::DeleteGroup,
+            group.id.to_string(),
+            &group,
+            &(),
+        );
+        audit::record(self.audit_log, entry).await?;
+
+        self.groups
+            .delete_one(query, None)
+            .await
+            .map_err(InternalError::DatabaseConnectionError)?;
 
-        Ok(group.into())
+        Ok(group)
     }
 
     // TODO: move this to the user actions??
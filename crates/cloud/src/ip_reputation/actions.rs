@@ -0,0 +1,413 @@
+use std::net::IpAddr;
+
+use futures::TryStreamExt;
+use log::warn;
+use mongodb::{bson::doc, options::FindOptions, Collection};
+use netsblox_cloud_common::{api, AuditLogEntry, BannedAddress, IpReputationEntry};
+
+use crate::{
+    audit, auth,
+    config::{IpListConfig, IpReputationSettings, IpReputationSource},
+    errors::{InternalError, UserError},
+    utils,
+};
+
+use super::IpLists;
+
+pub(crate) struct IpReputationActions<'a> {
+    settings: &'a IpReputationSettings,
+    lists: &'a IpLists,
+    entries: &'a Collection<IpReputationEntry>,
+    banned_addresses: &'a Collection<BannedAddress>,
+    audit_log: &'a Collection<AuditLogEntry>,
+}
+
+impl<'a> IpReputationActions<'a> {
+    pub(crate) fn new(
+        settings: &'a IpReputationSettings,
+        lists: &'a IpLists,
+        entries: &'a Collection<IpReputationEntry>,
+        banned_addresses: &'a Collection<BannedAddress>,
+        audit_log: &'a Collection<AuditLogEntry>,
+    ) -> Self {
+        Self {
+            settings,
+            lists,
+            entries,
+            banned_addresses,
+            audit_log,
+        }
+    }
+
+    /// Ensure the address hasn't been banned (directly or as part of a banned
+    /// range) and isn't on a list blocking the given action. Requests from
+    /// addresses on lists which flag them are logged (but allowed).
+    pub(crate) async fn ensure_allowed(
+        &self,
+        addr: &IpAddr,
+        action: api::IpReputationAction,
+    ) -> Result<(), UserError> {
+        if self.lists.is_banned(addr) {
+            return Err(UserError::BannedAddressError);
+        }
+
+        let matches = self.find_matches(addr);
+
+        matches
+            .iter()
+            .filter(|m| m.actions.contains(&api::IpReputationAction::Flag))
+            .for_each(|m| {
+                warn!(
+                    "Request from {} flagged by {} ({})",
+                    addr, m.source, m.range
+                )
+            });
+
+        match matches.into_iter().find(|m| m.actions.contains(&action)) {
+            Some(m) => Err(UserError::BlockedAddressError { list: m.source }),
+            None => Ok(()),
+        }
+    }
+
+    /// Check which sources list the given address (and what they do about it)
+    pub(crate) async fn test_address(
+        &self,
+        _mr: &auth::ManageIpReputation,
+        addr: &str,
+    ) -> Result<api::IpReputationReport, UserError> {
+        let addr = addr
+            .parse::<IpAddr>()
+            .map_err(|_err| UserError::InvalidAddressError)?;
+
+        let matches = self.find_matches(&addr);
+        Ok(api::IpReputationReport {
+            addr: addr.to_string(),
+            matches,
+        })
+    }
+
+    /// Reload the remote lists and local files along with the admin entries
+    /// and banned addresses. Sources which cannot be loaded keep their previous
+    /// addresses.
+    pub(crate) async fn refresh(&self) {
+        if let Err(err) = self.refresh_stored().await {
+            warn!("Unable to load admin IP entries and bans: {:?}", err);
+        }
+
+        for source in &self.settings.sources {
+            if let Err(err) = self.refresh_source(source).await {
+                warn!("Unable to update IP list {}: {:?}", source.name, err);
+            }
+        }
+    }
+
+    /// Reload the admin entries and banned addresses from the database
+    async fn refresh_stored(&self) -> Result<(), UserError> {
+        let version = self.lists.stored_version();
+        let entries: Vec<_> = self
+            .entries
+            .find(doc! {}, None)
+            .await
+            .map_err(InternalError::DatabaseConnectionError)?
+            .try_collect()
+            .await
+            .map_err(InternalError::DatabaseConnectionError)?;
+        let bans: Vec<_> = self
+            .banned_addresses
+            .find(utils::not_expired(), None)
+            .await
+            .map_err(InternalError::DatabaseConnectionError)?
+            .try_collect()
+            .await
+            .map_err(InternalError::DatabaseConnectionError)?;
+
+        self.lists.set_stored(version, entries, bans);
+        Ok(())
+    }
+
+    async fn refresh_source(&self, source: &IpReputationSource) -> Result<(), UserError> {
+        let text = match &source.list {
+            IpListConfig::Remote { url } => reqwest::get(url)
+                .await
+                .and_then(|response| response.error_for_status())
+                .map_err(InternalError::IpListFetchError)?
+                .text()
+                .await
+                .map_err(InternalError::IpListFetchError)?,
+            IpListConfig::File { path } => tokio::fs::read_to_string(path)
+                .await
+                .map_err(InternalError::IpListReadError)?,
+            IpListConfig::Static { .. } | IpListConfig::Admin => return Ok(()),
+        };
+
+        self.lists.set(&source.name, super::parse_list(&text));
+        Ok(())
+    }
+
+    pub(crate) async fn list_entries(
+        &self,
+        _mr: &auth::ManageIpReputation,
+        source: &str,
+    ) -> Result<Vec<api::IpReputationEntry>, UserError> {
+        let source = self.admin_source(source)?;
+        let query = doc! {"source": &source.name};
+        let options = FindOptions::builder().sort(doc! {"createdAt": -1}).build();
+        let entries = self
+            .entries
+            .find(query, options)
+            .await
+            .map_err(InternalError::DatabaseConnectionError)?
+            .try_collect::<Vec<_>>()
+            .await
+            .map_err(InternalError::DatabaseConnectionError)?
+            .into_iter()
+            .map(|entry| entry.into())
+            .collect();
+
+        Ok(entries)
+    }
+
+    pub(crate) async fn add_entry(
+        &self,
+        mr: &auth::ManageIpReputation,
+        source: &str,
+        data: api::IpReputationEntryData,
+    ) -> Result<api::IpReputationEntry, UserError> {
+        let source = self.admin_source(source)?;
+        let addr = utils::parse_address_range(&data.addr)?;
+        let entry = IpReputationEntry::new(
+            source.name.clone(),
+            addr.to_string(),
+            data.reason,
+            mr.admin.clone(),
+        );
+
+        let log_entry = AuditLogEntry::new(
+            mr.admin.clone(),
+            api::AuditAction::AddIpReputationEntry,
+            entry.addr.clone(),
+            &(),
+            &api::IpReputationEntry::from(entry.clone()),
+        );
+        audit::record(self.audit_log, log_entry).await?;
+
+        self.entries
+            .insert_one(&entry, None)
+            .await
+            .map_err(InternalError::DatabaseConnectionError)?;
+        self.lists.add_entry(&entry);
+
+        Ok(entry.into())
+    }
+
+    pub(crate) async fn remove_entry(
+        &self,
+        mr: &auth::ManageIpReputation,
+        source: &str,
+        id: &str,
+    ) -> Result<api::IpReputationEntry, UserError> {
+        let source = self.admin_source(source)?;
+        let query = doc! {"source": &source.name, "id": id};
+        let entry: api::IpReputationEntry = self
+            .entries
+            .find_one(query.clone(), None)
+            .await
+            .map_err(InternalError::DatabaseConnectionError)?
+            .ok_or(UserError::IpReputationEntryNotFoundError)?
+            .into();
+
+        let log_entry = AuditLogEntry::new(
+            mr.admin.clone(),
+            api::AuditAction::RemoveIpReputationEntry,
+            entry.addr.clone(),
+            &entry,
+            &(),
+        );
+        audit::record(self.audit_log, log_entry).await?;
+
+        self.entries
+            .delete_one(query, None)
+            .await
+            .map_err(InternalError::DatabaseConnectionError)?;
+        self.lists.remove_entry(&entry.id);
+
+        Ok(entry)
+    }
+
+    fn find_matches(&self, addr: &IpAddr) -> Vec<api::IpReputationMatch> {
+        self.settings
+            .sources
+            .iter()
+            .filter_map(|source| {
+                self.lists
+                    .find(&source.name, addr)
+                    .map(|range| api::IpReputationMatch {
+                        source: source.name.clone(),
+                        range: range.to_string(),
+                        actions: source.actions.clone(),
+                    })
+            })
+            .collect()
+    }
+
+    fn admin_source(&self, name: &str) -> Result<&IpReputationSource, UserError> {
+        self.settings
+            .sources
+            .iter()
+            .find(|source| source.name == name && matches!(source.list, IpListConfig::Admin))
+            .ok_or(UserError::IpReputationSourceNotFoundError)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{ip_reputation::parse_list, test_utils};
+
+    #[actix_web::test]
+    async fn test_block_admin_entry() {
+        test_utils::setup()
+            .run(|app_data| async move {
+                let actions = app_data.as_ip_reputation_actions();
+                let auth_mr = auth::ManageIpReputation::test("admin");
+                let data = api::IpReputationEntryData {
+                    addr: "10.1.0.0/16".into(),
+                    reason: None,
+                };
+                actions.add_entry(&auth_mr, "admin", data).await.unwrap();
+
+                let addr: IpAddr = "10.1.2.3".parse().unwrap();
+                let result = actions
+                    .ensure_allowed(&addr, api::IpReputationAction::BlockLogin)
+                    .await;
+                assert!(matches!(result, Err(UserError::BlockedAddressError { .. })));
+
+                let addr: IpAddr = "10.2.0.1".parse().unwrap();
+                actions
+                    .ensure_allowed(&addr, api::IpReputationAction::BlockLogin)
+                    .await
+                    .unwrap();
+            })
+            .await;
+    }
+
+    #[actix_web::test]
+    async fn test_block_banned_address() {
+        test_utils::setup()
+            .run(|app_data| async move {
+                let ban = BannedAddress::new("10.0.0.0/8".into(), "moderator".into(), None, None);
+                app_data
+                    .banned_addresses
+                    .insert_one(&ban, None)
+                    .await
+                    .unwrap();
+                app_data.ip_lists.add_ban(&ban);
+
+                let actions = app_data.as_ip_reputation_actions();
+                let addr: IpAddr = "10.1.2.3".parse().unwrap();
+                let result = actions
+                    .ensure_allowed(&addr, api::IpReputationAction::BlockSignup)
+                    .await;
+                assert!(matches!(result, Err(UserError::BannedAddressError)));
+
+                let addr: IpAddr = "192.168.0.1".parse().unwrap();
+                actions
+                    .ensure_allowed(&addr, api::IpReputationAction::BlockSignup)
+                    .await
+                    .unwrap();
+            })
+            .await;
+    }
+
+    #[actix_web::test]
+    async fn test_remove_admin_entry() {
+        test_utils::setup()
+            .run(|app_data| async move {
+                let actions = app_data.as_ip_reputation_actions();
+                let auth_mr = auth::ManageIpReputation::test("admin");
+                let data = api::IpReputationEntryData {
+                    addr: "10.1.2.3".into(),
+                    reason: Some("spam".into()),
+                };
+                let entry = actions.add_entry(&auth_mr, "admin", data).await.unwrap();
+                actions
+                    .remove_entry(&auth_mr, "admin", &entry.id)
+                    .await
+                    .unwrap();
+
+                let addr: IpAddr = "10.1.2.3".parse().unwrap();
+                actions
+                    .ensure_allowed(&addr, api::IpReputationAction::BlockSignup)
+                    .await
+                    .unwrap();
+            })
+            .await;
+    }
+
+    #[actix_web::test]
+    async fn test_add_entry_not_admin_source() {
+        test_utils::setup()
+            .run(|app_data| async move {
+                let actions = app_data.as_ip_reputation_actions();
+                let auth_mr = auth::ManageIpReputation::test("admin");
+                let data = api::IpReputationEntryData {
+                    addr: "10.1.2.3".into(),
+                    reason: None,
+                };
+                let result = actions.add_entry(&auth_mr, "tor", data).await;
+                assert!(matches!(
+                    result,
+                    Err(UserError::IpReputationSourceNotFoundError)
+                ));
+            })
+            .await;
+    }
+
+    #[actix_web::test]
+    async fn test_per_source_actions() {
+        test_utils::setup()
+            .run(|mut app_data| async move {
+                app_data.settings.ip_reputation.sources = vec![IpReputationSource {
+                    name: "suspicious".into(),
+                    list: IpListConfig::Static {
+                        entries: Vec::new(),
+                    },
+                    actions: vec![
+                        api::IpReputationAction::BlockSignup,
+                        api::IpReputationAction::Flag,
+                    ],
+                }];
+                app_data.ip_lists.set("suspicious", parse_list("10.0.0.1"));
+                let actions = app_data.as_ip_reputation_actions();
+                let addr: IpAddr = "10.0.0.1".parse().unwrap();
+
+                let result = actions
+                    .ensure_allowed(&addr, api::IpReputationAction::BlockSignup)
+                    .await;
+                assert!(matches!(result, Err(UserError::BlockedAddressError { .. })));
+
+                actions
+                    .ensure_allowed(&addr, api::IpReputationAction::BlockLogin)
+                    .await
+                    .unwrap();
+            })
+            .await;
+    }
+
+    #[actix_web::test]
+    async fn test_static_source() {
+        test_utils::setup()
+            .run(|app_data| async move {
+                let actions = app_data.as_ip_reputation_actions();
+                let auth_mr = auth::ManageIpReputation::test("admin");
+
+                let report = actions
+                    .test_address(&auth_mr, "77.111.245.1")
+                    .await
+                    .unwrap();
+                assert_eq!(report.matches.len(), 1);
+                assert_eq!(report.matches[0].source, "opera-vpn");
+            })
+            .await;
+    }
+}
@@ -0,0 +1,236 @@
+pub(crate) mod actions;
+pub(crate) mod routes;
+
+use std::{
+    collections::HashMap,
+    net::IpAddr,
+    sync::{Arc, RwLock},
+};
+
+use ipnet::IpNet;
+use mongodb::bson::DateTime;
+use netsblox_cloud_common::{BannedAddress, IpReputationEntry};
+
+use crate::{
+    config::{IpListConfig, IpReputationSettings},
+    utils,
+};
+
+/// Addresses of the remote, file and static sources (by source name) along with
+/// the entries of admin sources and the banned addresses. These are kept in memory
+/// so CIDR ranges can be matched without a database query.
+#[derive(Clone)]
+pub(crate) struct IpLists {
+    lists: Arc<RwLock<HashMap<String, Vec<IpNet>>>>,
+    stored: Arc<RwLock<StoredRanges>>,
+}
+
+/// Ranges which are stored in the database (by id). These are updated when
+/// changed and reloaded periodically. The version is incremented on each change
+/// so a reload started before the change doesn't overwrite it.
+#[derive(Default)]
+struct StoredRanges {
+    version: u64,
+    entries: HashMap<String, (String, IpNet)>,
+    bans: HashMap<String, (IpNet, Option<DateTime>)>,
+}
+
+impl IpLists {
+    /// Create the lists, loading the static sources from the configuration.
+    /// The other sources are loaded when refreshed.
+    pub(crate) fn new(settings: &IpReputationSettings) -> Self {
+        let lists = settings
+            .sources
+            .iter()
+            .filter_map(|source| match &source.list {
+                IpListConfig::Static { entries } => Some((
+                    source.name.clone(),
+                    entries
+                        .iter()
+                        .filter_map(|entry| utils::parse_address_range(entry).ok())
+                        .collect(),
+                )),
+                _ => None,
+            })
+            .collect();
+
+        Self {
+            lists: Arc::new(RwLock::new(lists)),
+            stored: Arc::new(RwLock::new(StoredRanges::default())),
+        }
+    }
+
+    pub(crate) fn set(&self, source: &str, ranges: Vec<IpNet>) {
+        let mut lists = self.lists.write().unwrap();
+        lists.insert(source.to_owned(), ranges);
+    }
+
+    /// Get the entry of the given source containing the address (if any)
+    pub(crate) fn find(&self, source: &str, addr: &IpAddr) -> Option<IpNet> {
+        let lists = self.lists.read().unwrap();
+        let range = lists
+            .get(source)
+            .and_then(|ranges| ranges.iter().find(|range| range.contains(addr)).copied());
+
+        range.or_else(|| {
+            let stored = self.stored.read().unwrap();
+            stored
+                .entries
+                .values()
+                .find(|(entry_source, range)| entry_source == source && range.contains(addr))
+                .map(|(_source, range)| *range)
+        })
+    }
+
+    /// Check if the address is in an (unexpired) banned range
+    pub(crate) fn is_banned(&self, addr: &IpAddr) -> bool {
+        let now = DateTime::now();
+        let stored = self.stored.read().unwrap();
+        stored.bans.values().any(|(range, expires_at)| {
+            range.contains(addr) && expires_at.map(|time| time > now).unwrap_or(true)
+        })
+    }
+
+    pub(crate) fn add_entry(&self, entry: &IpReputationEntry) {
+        if let Ok(range) = utils::parse_address_range(&entry.addr) {
+            let mut stored = self.stored.write().unwrap();
+            stored.version += 1;
+            stored
+                .entries
+                .insert(entry.id.clone(), (entry.source.clone(), range));
+        }
+    }
+
+    pub(crate) fn remove_entry(&self, id: &str) {
+        let mut stored = self.stored.write().unwrap();
+        stored.version += 1;
+        stored.entries.remove(id);
+    }
+
+    pub(crate) fn add_ban(&self, ban: &BannedAddress) {
+        if let Ok(range) = utils::parse_address_range(&ban.addr) {
+            let mut stored = self.stored.write().unwrap();
+            stored.version += 1;
+            stored.bans.insert(ban.id.clone(), (range, ban.expires_at));
+        }
+    }
+
+    pub(crate) fn remove_ban(&self, id: &str) {
+        let mut stored = self.stored.write().unwrap();
+        stored.version += 1;
+        stored.bans.remove(id);
+    }
+
+    /// Version of the stored ranges to pass to `set_stored` when reloading them
+    pub(crate) fn stored_version(&self) -> u64 {
+        self.stored.read().unwrap().version
+    }
+
+    /// Replace the stored ranges unless they have changed since the given version
+    pub(crate) fn set_stored(
+        &self,
+        version: u64,
+        entries: Vec<IpReputationEntry>,
+        bans: Vec<BannedAddress>,
+    ) {
+        let mut stored = self.stored.write().unwrap();
+        if stored.version != version {
+            return;
+        }
+
+        stored.entries = entries
+            .into_iter()
+            .filter_map(|entry| {
+                let range = utils::parse_address_range(&entry.addr).ok()?;
+                Some((entry.id, (entry.source, range)))
+            })
+            .collect();
+        stored.bans = bans
+            .into_iter()
+            .filter_map(|ban| {
+                let range = utils::parse_address_range(&ban.addr).ok()?;
+                Some((ban.id, (range, ban.expires_at)))
+            })
+            .collect();
+    }
+}
+
+/// Parse a list of addresses (or CIDR ranges), one per line. Blank lines and
+/// comments are ignored as are invalid entries.
+pub(crate) fn parse_list(text: &str) -> Vec<IpNet> {
+    text.lines()
+        .map(|line| line.split('#').next().unwrap_or_default().trim())
+        .filter(|line| !line.is_empty())
+        .filter_map(|line| utils::parse_address_range(line).ok())
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::IpReputationSource;
+    use netsblox_cloud_common::api;
+
+    #[test]
+    fn test_parse_list() {
+        let text = "# Tor exit nodes\n10.0.0.1\n\n192.168.0.0/16 # a range\nnotAnAddress\n::1\n";
+        let ranges: Vec<_> = parse_list(text)
+            .into_iter()
+            .map(|range| range.to_string())
+            .collect();
+
+        assert_eq!(ranges, vec!["10.0.0.1/32", "192.168.0.0/16", "::1/128"]);
+    }
+
+    #[test]
+    fn test_find_in_range() {
+        let settings = IpReputationSettings {
+            refresh_interval_secs: 60,
+            sources: vec![IpReputationSource {
+                name: "vpn".into(),
+                list: IpListConfig::Static {
+                    entries: vec!["77.111.244.0/22".into()],
+                },
+                actions: vec![api::IpReputationAction::BlockLogin],
+            }],
+        };
+        let lists = IpLists::new(&settings);
+
+        let addr: IpAddr = "77.111.247.12".parse().unwrap();
+        let range = lists.find("vpn", &addr).unwrap();
+        assert_eq!(range.to_string(), "77.111.244.0/22");
+
+        let addr: IpAddr = "77.111.248.1".parse().unwrap();
+        assert!(lists.find("vpn", &addr).is_none());
+    }
+
+    #[test]
+    fn test_set_list() {
+        let lists = IpLists::new(&IpReputationSettings::default());
+        let addr: IpAddr = "10.0.0.1".parse().unwrap();
+        assert!(lists.find("tor", &addr).is_none());
+
+        lists.set("tor", parse_list("10.0.0.1"));
+        assert!(lists.find("tor", &addr).is_some());
+    }
+
+    #[test]
+    fn test_stored_bans() {
+        let lists = IpLists::new(&IpReputationSettings::default());
+        let addr: IpAddr = "10.1.2.3".parse().unwrap();
+        let ban = BannedAddress::new("10.0.0.0/8".into(), "moderator".into(), None, None);
+
+        // reloads started before a change don't overwrite it
+        let version = lists.stored_version();
+        lists.add_ban(&ban);
+        lists.set_stored(version, Vec::new(), Vec::new());
+        assert!(lists.is_banned(&addr));
+
+        lists.remove_ban(&ban.id);
+        assert!(!lists.is_banned(&addr));
+
+        let version = lists.stored_version();
+        lists.set_stored(version, Vec::new(), vec![ban]);
+        assert!(lists.is_banned(&addr));
+    }
+}
@@ -0,0 +1,165 @@
+use actix_web::{delete, get, post, web, HttpRequest, HttpResponse};
+
+use crate::app_data::AppData;
+use crate::auth;
+use crate::common::api;
+use crate::errors::UserError;
+
+/// Check which IP reputation sources list the given address
+#[get("/ip-reputation/{addr}")]
+async fn test_address(
+    app: web::Data<AppData>,
+    path: web::Path<(String,)>,
+    req: HttpRequest,
+) -> Result<HttpResponse, UserError> {
+    let (addr,) = path.into_inner();
+    let auth_mr = auth::try_manage_ip_reputation(&app, &req).await?;
+
+    let actions = app.as_ip_reputation_actions();
+    let report = actions.test_address(&auth_mr, &addr).await?;
+
+    Ok(HttpResponse::Ok().json(report))
+}
+
+#[get("/ip-reputation/sources/{source}/entries")]
+async fn list_entries(
+    app: web::Data<AppData>,
+    path: web::Path<(String,)>,
+    req: HttpRequest,
+) -> Result<HttpResponse, UserError> {
+    let (source,) = path.into_inner();
+    let auth_mr = auth::try_manage_ip_reputation(&app, &req).await?;
+
+    let actions = app.as_ip_reputation_actions();
+    let entries = actions.list_entries(&auth_mr, &source).await?;
+
+    Ok(HttpResponse::Ok().json(entries))
+}
+
+#[post("/ip-reputation/sources/{source}/entries")]
+async fn add_entry(
+    app: web::Data<AppData>,
+    path: web::Path<(String,)>,
+    data: web::Json<api::IpReputationEntryData>,
+    req: HttpRequest,
+) -> Result<HttpResponse, UserError> {
+    let (source,) = path.into_inner();
+    let auth_mr = auth::try_manage_ip_reputation(&app, &req).await?;
+
+    let actions = app.as_ip_reputation_actions();
+    let entry = actions
+        .add_entry(&auth_mr, &source, data.into_inner())
+        .await?;
+
+    Ok(HttpResponse::Ok().json(entry))
+}
+
+#[delete("/ip-reputation/sources/{source}/entries/{id}")]
+async fn remove_entry(
+    app: web::Data<AppData>,
+    path: web::Path<(String, String)>,
+    req: HttpRequest,
+) -> Result<HttpResponse, UserError> {
+    let (source, id) = path.into_inner();
+    let auth_mr = auth::try_manage_ip_reputation(&app, &req).await?;
+
+    let actions = app.as_ip_reputation_actions();
+    let entry = actions.remove_entry(&auth_mr, &source, &id).await?;
+
+    Ok(HttpResponse::Ok().json(entry))
+}
+
+pub(crate) fn config(cfg: &mut web::ServiceConfig) {
+    cfg.service(list_entries)
+        .service(add_entry)
+        .service(remove_entry)
+        .service(test_address);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use actix_web::{http, test, App};
+    use netsblox_cloud_common::{api::UserRole, User};
+
+    use crate::test_utils;
+
+    #[actix_web::test]
+    async fn test_test_address() {
+        let admin: User = api::NewUser {
+            username: "admin".into(),
+            email: "admin@netsblox.org".into(),
+            password: None,
+            group_id: None,
+            role: Some(UserRole::Admin),
+        }
+        .into();
+
+        test_utils::setup()
+            .with_users(&[admin.clone()])
+            .run(|app_data| async move {
+                let app = test::init_service(
+                    App::new()
+                        .wrap(test_utils::cookie::middleware())
+                        .app_data(web::Data::new(app_data.clone()))
+                        .configure(config),
+                )
+                .await;
+
+                let data = api::IpReputationEntryData {
+                    addr: "10.0.0.0/8".into(),
+                    reason: None,
+                };
+                let req = test::TestRequest::post()
+                    .uri("/ip-reputation/sources/admin/entries")
+                    .cookie(test_utils::cookie::new(&admin.username))
+                    .set_json(&data)
+                    .to_request();
+                let response = test::call_service(&app, req).await;
+                assert_eq!(response.status(), http::StatusCode::OK);
+
+                let req = test::TestRequest::get()
+                    .uri("/ip-reputation/10.1.2.3")
+                    .cookie(test_utils::cookie::new(&admin.username))
+                    .to_request();
+                let report: api::IpReputationReport =
+                    test::call_and_read_body_json(&app, req).await;
+                assert_eq!(report.matches.len(), 1);
+                assert_eq!(report.matches[0].source, "admin");
+                assert_eq!(report.matches[0].range, "10.0.0.0/8");
+            })
+            .await;
+    }
+
+    #[actix_web::test]
+    async fn test_test_address_403() {
+        let moderator: User = api::NewUser {
+            username: "moderator".into(),
+            email: "moderator@netsblox.org".into(),
+            password: None,
+            group_id: None,
+            role: Some(UserRole::Moderator),
+        }
+        .into();
+
+        test_utils::setup()
+            .with_users(&[moderator.clone()])
+            .run(|app_data| async move {
+                let app = test::init_service(
+                    App::new()
+                        .wrap(test_utils::cookie::middleware())
+                        .app_data(web::Data::new(app_data.clone()))
+                        .configure(config),
+                )
+                .await;
+
+                let req = test::TestRequest::get()
+                    .uri("/ip-reputation/10.1.2.3")
+                    .cookie(test_utils::cookie::new(&moderator.username))
+                    .to_request();
+                let response = test::call_service(&app, req).await;
+                assert_eq!(response.status(), http::StatusCode::FORBIDDEN);
+            })
+            .await;
+    }
+}
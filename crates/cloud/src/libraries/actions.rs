@@ -7,24 +7,31 @@ use mongodb::{
 };
 use netsblox_cloud_common::{
     api::{self, PublishState},
-    Library,
+    AuditLogEntry, Library,
 };
 use regex::Regex;
 use rustrict::CensorStr;
 
 use crate::{
-    auth,
+    audit, auth,
     errors::{InternalError, UserError},
     utils,
 };
 
 pub(crate) struct LibraryActions<'a> {
     libraries: &'a Collection<Library>,
+    audit_log: &'a Collection<AuditLogEntry>,
 }
 
 impl<'a> LibraryActions<'a> {
-    pub(crate) fn new(libraries: &'a Collection<Library>) -> Self {
-        Self { libraries }
+    pub(crate) fn new(
+        libraries: &'a Collection<Library>,
+        audit_log: &'a Collection<AuditLogEntry>,
+    ) -> Self {
+        Self {
+            libraries,
+            audit_log,
+        }
     }
 
     pub(crate) async fn list_community_libraries(
@@ -237,15 +244,30 @@ impl<'a> LibraryActions<'a> {
 
     pub(crate) async fn set_library_state(
         &self,
-        _ml: &auth::ModerateLibraries,
+        ml: &auth::ModerateLibraries,
         owner: &str,
         name: &str,
         state: api::PublishState,
     ) -> Result<api::LibraryMetadata, UserError> {
         let query = doc! {"owner": owner, "name": name};
+        let library = self
+            .libraries
+            .find_one(query.clone(), None)
+            .await
+            .map_err(InternalError::DatabaseConnectionError)?
+            .ok_or(UserError::LibraryNotFoundError)?;
+
+        let entry = AuditLogEntry::new(
+            ml.moderator.clone(),
+            api::AuditAction::SetLibraryState,
+            format!("{}/{}", owner, name),
+            &library.state,
+            &state,
+        );
+        audit::record(self.audit_log, entry).await?;
+
         let update = doc! {"$set": {"state": state}};
         let options = FindOneAndUpdateOptions::builder()
-            .upsert(true)
             .return_document(ReturnDocument::After)
             .build();
         let library = self
@@ -462,7 +484,7 @@ mod tests {
             .with_libraries(&[lib.clone()])
             .run(|app_data| async move {
                 let actions = app_data.as_library_actions();
-                let auth_ml = auth::ModerateLibraries::test();
+                let auth_ml = auth::ModerateLibraries::test("moderator".into());
 
                 let metadata = actions
                     .set_library_state(&auth_ml, &user.username, &lib.name, PublishState::Public)
@@ -0,0 +1,444 @@
+use std::{
+    net::IpAddr,
+    time::{Duration, SystemTime},
+};
+
+use futures::TryStreamExt;
+use lettre::{
+    message::{Mailbox, MultiPart},
+    Address, Message, SmtpTransport,
+};
+use log::warn;
+use mongodb::{
+    bson::{doc, DateTime},
+    options::{FindOneAndUpdateOptions, FindOptions, ReturnDocument},
+    Collection,
+};
+use netsblox_cloud_common::{api, LoginFailureCounter, User};
+
+use crate::{
+    auth,
+    config::LoginProtectionSettings,
+    errors::{InternalError, UserError},
+    utils,
+};
+
+use super::email_template;
+
+pub(crate) struct LoginAttemptActions<'a> {
+    counters: &'a Collection<LoginFailureCounter>,
+    users: &'a Collection<User>,
+    settings: &'a LoginProtectionSettings,
+
+    // email support
+    mailer: &'a SmtpTransport,
+    sender: &'a Mailbox,
+}
+
+impl<'a> LoginAttemptActions<'a> {
+    pub(crate) fn new(
+        counters: &'a Collection<LoginFailureCounter>,
+        users: &'a Collection<User>,
+        settings: &'a LoginProtectionSettings,
+        mailer: &'a SmtpTransport,
+        sender: &'a Mailbox,
+    ) -> Self {
+        Self {
+            counters,
+            users,
+            settings,
+            mailer,
+            sender,
+        }
+    }
+
+    /// Ensure neither the username nor the address of the client is locked out
+    pub(crate) async fn ensure_not_locked(
+        &self,
+        username: &str,
+        addr: Option<&IpAddr>,
+    ) -> Result<(), UserError> {
+        let now = DateTime::now();
+        let keys = counter_keys(username, addr)
+            .into_iter()
+            .map(|(kind, key)| doc! {"kind": kind, "key": key})
+            .collect::<Vec<_>>();
+        let query = doc! {
+            "$or": keys,
+            "lockedUntil": {"$gt": now},
+        };
+
+        let retry_after = self
+            .counters
+            .find(query, None)
+            .await
+            .map_err(InternalError::DatabaseConnectionError)?
+            .try_collect::<Vec<_>>()
+            .await
+            .map_err(InternalError::DatabaseConnectionError)?
+            .into_iter()
+            .filter_map(|counter| counter.locked_until)
+            .map(|until| {
+                let millis = until.timestamp_millis() - now.timestamp_millis();
+                (millis.max(0) as u64).div_ceil(1000)
+            })
+            .max();
+
+        match retry_after {
+            Some(retry_after) => Err(UserError::LoginLockedError { retry_after }),
+            None => Ok(()),
+        }
+    }
+
+    /// Record the result of a login attempt. Failures are counted for both the
+    /// username and the address. A successful login only resets the counter for
+    /// the username so an account cannot be used to clear the address counter.
+    pub(crate) async fn record<T>(
+        &self,
+        username: &str,
+        addr: Option<&IpAddr>,
+        result: &Result<T, UserError>,
+    ) -> Result<(), UserError> {
+        match result {
+            Ok(_) => {
+                let query = doc! {"kind": api::LoginCounterKind::Username, "key": username};
+                self.counters
+                    .delete_one(query, None)
+                    .await
+                    .map_err(InternalError::DatabaseConnectionError)?;
+            }
+            Err(err) if is_login_failure(err) => {
+                for (kind, key) in counter_keys(username, addr) {
+                    self.record_failure(kind, &key).await?;
+                }
+            }
+            Err(_) => {}
+        }
+
+        Ok(())
+    }
+
+    pub(crate) async fn list(
+        &self,
+        _vf: &auth::ViewLoginFailures,
+    ) -> Result<Vec<api::LoginFailureCounter>, UserError> {
+        let options = FindOptions::builder().sort(doc! {"updatedAt": -1}).build();
+        let counters = self
+            .counters
+            .find(doc! {}, options)
+            .await
+            .map_err(InternalError::DatabaseConnectionError)?
+            .try_collect::<Vec<_>>()
+            .await
+            .map_err(InternalError::DatabaseConnectionError)?
+            .into_iter()
+            .map(|counter| counter.into())
+            .collect();
+
+        Ok(counters)
+    }
+
+    async fn record_failure(
+        &self,
+        kind: api::LoginCounterKind,
+        key: &str,
+    ) -> Result<(), UserError> {
+        let max_failures = match kind {
+            api::LoginCounterKind::Username => self.settings.max_failures_per_user,
+            api::LoginCounterKind::Address => self.settings.max_failures_per_address,
+        };
+
+        let now = SystemTime::now();
+        let query = doc! {"kind": kind, "key": key};
+        let update = doc! {
+            "$push": {
+                "failures": {
+                    "$each": [DateTime::from_system_time(now)],
+                    "$slice": -i64::from(max_failures),
+                }
+            },
+            "$set": {"updatedAt": DateTime::from_system_time(now)},
+            "$setOnInsert": {"lockouts": 0, "lockedUntil": null},
+        };
+        let options = FindOneAndUpdateOptions::builder()
+            .upsert(true)
+            .return_document(ReturnDocument::After)
+            .build();
+
+        let counter = self
+            .counters
+            .find_one_and_update(query, update, options)
+            .await
+            .map_err(InternalError::DatabaseConnectionError)?
+            .ok_or(UserError::InternalError)?; // this shouldn't happen since we are upserting
+
+        let window_start =
+            DateTime::from_system_time(now - Duration::from_secs(self.settings.window_secs));
+        let recent_failures = counter
+            .failures
+            .iter()
+            .filter(|time| **time > window_start)
+            .count();
+
+        if recent_failures < max_failures as usize {
+            return Ok(());
+        }
+
+        let lockout_secs = lockout_duration(self.settings, counter.lockouts);
+        let locked_until = DateTime::from_system_time(now + Duration::from_secs(lockout_secs));
+        // Only lock if no concurrent request has already done so
+        let query = doc! {"kind": kind, "key": key, "lockouts": counter.lockouts};
+        let update = doc! {
+            "$set": {"lockedUntil": locked_until, "failures": []},
+            "$inc": {"lockouts": 1},
+        };
+        let result = self
+            .counters
+            .update_one(query, update, None)
+            .await
+            .map_err(InternalError::DatabaseConnectionError)?;
+
+        let is_first_lockout = result.modified_count == 1 && counter.lockouts == 0;
+        if is_first_lockout && kind == api::LoginCounterKind::Username {
+            self.notify_owner(key, lockout_secs).await?;
+        }
+
+        Ok(())
+    }
+
+    /// Let the owner of the account know that someone may be guessing the password
+    async fn notify_owner(&self, username: &str, lockout_secs: u64) -> Result<(), UserError> {
+        let user = self
+            .users
+            .find_one(doc! {"username": username}, None)
+            .await
+            .map_err(InternalError::DatabaseConnectionError)?;
+
+        if let Some(user) = user {
+            let email = AccountLockedEmail {
+                sender: self.sender.clone(),
+                user,
+                lockout_secs,
+            };
+            // The lockout still applies if the notification cannot be sent
+            if let Err(err) = utils::send_email(self.mailer, email) {
+                warn!(
+                    "Unable to send login lockout email to {}: {:?}",
+                    username, err
+                );
+            }
+        }
+
+        Ok(())
+    }
+}
+
+fn counter_keys(username: &str, addr: Option<&IpAddr>) -> Vec<(api::LoginCounterKind, String)> {
+    let mut keys = vec![(api::LoginCounterKind::Username, username.to_owned())];
+    if let Some(addr) = addr {
+        keys.push((api::LoginCounterKind::Address, addr.to_string()));
+    }
+    keys
+}
+
+/// Check if the error was caused by invalid credentials (rather than an
+/// internal error, for example)
+fn is_login_failure(err: &UserError) -> bool {
+    matches!(
+        err,
+        UserError::IncorrectPasswordError
+            | UserError::IncorrectUsernameOrPasswordError
+            | UserError::UserNotFoundError
+            | UserError::InvalidTwoFactorCodeError
+            | UserError::MagicLinkNotFoundError
+            | UserError::InvalidMagicLinkCodeError
+    )
+}
+
+/// Lockouts double in length each time (up to the configured maximum)
+fn lockout_duration(settings: &LoginProtectionSettings, lockouts: u32) -> u64 {
+    settings
+        .lockout_secs
+        .saturating_mul(1u64.checked_shl(lockouts).unwrap_or(u64::MAX))
+        .min(settings.max_lockout_secs)
+}
+
+struct AccountLockedEmail {
+    sender: Mailbox,
+    user: User,
+    lockout_secs: u64,
+}
+
+impl AccountLockedEmail {
+    fn render(&self) -> MultiPart {
+        email_template::account_locked_email(&self.user.username, self.lockout_secs)
+    }
+}
+
+impl TryFrom<AccountLockedEmail> for lettre::Message {
+    type Error = UserError;
+
+    fn try_from(email: AccountLockedEmail) -> Result<Self, UserError> {
+        let subject = "Failed login attempts for your NetsBlox account";
+        let body = email.render();
+        let to_email = email.user.email;
+        let message = Message::builder()
+            .from(email.sender)
+            .to(Mailbox::new(
+                None,
+                to_email
+                    .parse::<Address>()
+                    .map_err(|_err| UserError::InvalidEmailAddress)?,
+            ))
+            .subject(subject.to_string())
+            .date_now()
+            .multipart(body)
+            .map_err(|_err| InternalError::EmailBuildError)?;
+
+        Ok(message)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_utils;
+
+    #[test]
+    fn test_lockout_duration() {
+        let settings = LoginProtectionSettings::default();
+        assert_eq!(lockout_duration(&settings, 0), settings.lockout_secs);
+        assert_eq!(lockout_duration(&settings, 1), settings.lockout_secs * 2);
+        assert_eq!(lockout_duration(&settings, 2), settings.lockout_secs * 4);
+        assert_eq!(lockout_duration(&settings, 100), settings.max_lockout_secs);
+    }
+
+    #[actix_web::test]
+    async fn test_lock_username_after_failures() {
+        test_utils::setup()
+            .run(|app_data| async move {
+                let actions = app_data.as_login_attempt_actions();
+                let addr: IpAddr = "10.0.0.1".parse().unwrap();
+                let failure: Result<(), _> = Err(UserError::IncorrectPasswordError);
+                let max = app_data.settings.login_protection.max_failures_per_user;
+
+                for _ in 0..max - 1 {
+                    actions
+                        .record("someUser", Some(&addr), &failure)
+                        .await
+                        .unwrap();
+                }
+                actions.ensure_not_locked("someUser", None).await.unwrap();
+
+                actions
+                    .record("someUser", Some(&addr), &failure)
+                    .await
+                    .unwrap();
+                let result = actions.ensure_not_locked("someUser", None).await;
+                assert!(matches!(result, Err(UserError::LoginLockedError { .. })));
+
+                // other users can still log in from the address
+                actions
+                    .ensure_not_locked("otherUser", Some(&addr))
+                    .await
+                    .unwrap();
+            })
+            .await;
+    }
+
+    #[actix_web::test]
+    async fn test_lock_address_after_failures() {
+        test_utils::setup()
+            .run(|mut app_data| async move {
+                app_data.settings.login_protection.max_failures_per_address = 2;
+                let actions = app_data.as_login_attempt_actions();
+                let addr: IpAddr = "10.0.0.1".parse().unwrap();
+                let failure: Result<(), _> = Err(UserError::UserNotFoundError);
+
+                actions
+                    .record("user1", Some(&addr), &failure)
+                    .await
+                    .unwrap();
+                actions
+                    .record("user2", Some(&addr), &failure)
+                    .await
+                    .unwrap();
+
+                let result = actions.ensure_not_locked("user3", Some(&addr)).await;
+                assert!(matches!(result, Err(UserError::LoginLockedError { .. })));
+            })
+            .await;
+    }
+
+    #[actix_web::test]
+    async fn test_lockouts_back_off() {
+        test_utils::setup()
+            .run(|mut app_data| async move {
+                app_data.settings.login_protection.max_failures_per_user = 1;
+                let actions = app_data.as_login_attempt_actions();
+                let failure: Result<(), _> = Err(UserError::IncorrectPasswordError);
+                let lockout_secs = app_data.settings.login_protection.lockout_secs;
+
+                actions.record("someUser", None, &failure).await.unwrap();
+                let result = actions.ensure_not_locked("someUser", None).await;
+                assert!(matches!(
+                    result,
+                    Err(UserError::LoginLockedError { retry_after }) if retry_after <= lockout_secs
+                ));
+
+                // expire the lockout
+                app_data
+                    .login_failures
+                    .update_one(
+                        doc! {"key": "someUser"},
+                        doc! {"$set": {"lockedUntil": DateTime::now()}},
+                        None,
+                    )
+                    .await
+                    .unwrap();
+                actions.ensure_not_locked("someUser", None).await.unwrap();
+
+                actions.record("someUser", None, &failure).await.unwrap();
+                let result = actions.ensure_not_locked("someUser", None).await;
+                assert!(matches!(
+                    result,
+                    Err(UserError::LoginLockedError { retry_after }) if retry_after > lockout_secs
+                ));
+            })
+            .await;
+    }
+
+    #[actix_web::test]
+    async fn test_success_resets_username() {
+        test_utils::setup()
+            .run(|app_data| async move {
+                let actions = app_data.as_login_attempt_actions();
+                let failure: Result<(), _> = Err(UserError::IncorrectPasswordError);
+                let max = app_data.settings.login_protection.max_failures_per_user;
+
+                for _ in 0..max - 1 {
+                    actions.record("someUser", None, &failure).await.unwrap();
+                }
+                actions.record("someUser", None, &Ok(())).await.unwrap();
+                actions.record("someUser", None, &failure).await.unwrap();
+
+                actions.ensure_not_locked("someUser", None).await.unwrap();
+            })
+            .await;
+    }
+
+    #[actix_web::test]
+    async fn test_ignore_other_errors() {
+        test_utils::setup()
+            .run(|mut app_data| async move {
+                app_data.settings.login_protection.max_failures_per_user = 1;
+                let actions = app_data.as_login_attempt_actions();
+                let failure: Result<(), _> = Err(UserError::InvalidClientSecretError);
+
+                actions.record("someUser", None, &failure).await.unwrap();
+
+                actions.ensure_not_locked("someUser", None).await.unwrap();
+            })
+            .await;
+    }
+}
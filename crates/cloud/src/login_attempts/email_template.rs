@@ -0,0 +1,31 @@
+use lettre::message::MultiPart;
+
+pub(crate) fn account_locked_email(username: &str, lockout_secs: u64) -> MultiPart {
+    let minutes = lockout_secs.div_ceil(60);
+    let html = format!(
+        "<h1>Failed Login Attempts</h1>
+        <p>
+            There have been several failed attempts to log in as {username} so logins have been disabled for the next {minutes} minute(s). If these were not you, someone may be trying to guess your password. Consider choosing a stronger password (or enabling two-factor authentication).
+            <br/>
+            <br/>
+            Cheers,<br/>
+            the NetsBlox team
+        </p>
+        ",
+        username = username,
+        minutes = minutes
+    );
+    let txt = format!(
+        "Failed Login Attempts
+
+        There have been several failed attempts to log in as {username} so logins have been disabled for the next {minutes} minute(s). If these were not you, someone may be trying to guess your password. Consider choosing a stronger password (or enabling two-factor authentication).
+
+
+        Cheers,
+        the NetsBlox team",
+        username = username,
+        minutes = minutes
+    );
+
+    MultiPart::alternative_plain_html(txt, html)
+}
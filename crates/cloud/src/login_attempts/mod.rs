@@ -0,0 +1,3 @@
+pub(crate) mod actions;
+mod email_template;
+pub(crate) mod routes;
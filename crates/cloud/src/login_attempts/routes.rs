@@ -0,0 +1,110 @@
+use actix_web::{get, web, HttpRequest, HttpResponse};
+
+use crate::app_data::AppData;
+use crate::auth;
+use crate::errors::UserError;
+
+#[get("/login-failures")]
+async fn list_login_failures(
+    app: web::Data<AppData>,
+    req: HttpRequest,
+) -> Result<HttpResponse, UserError> {
+    let auth_vf = auth::try_view_login_failures(&app, &req).await?;
+
+    let actions = app.as_login_attempt_actions();
+    let counters = actions.list(&auth_vf).await?;
+
+    Ok(HttpResponse::Ok().json(counters))
+}
+
+pub(crate) fn config(cfg: &mut web::ServiceConfig) {
+    cfg.service(list_login_failures);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use actix_web::{http, test, App};
+    use netsblox_cloud_common::{
+        api::{self, UserRole},
+        User,
+    };
+
+    use crate::test_utils;
+
+    #[actix_web::test]
+    async fn test_list_login_failures() {
+        let admin: User = api::NewUser {
+            username: "admin".into(),
+            email: "admin@netsblox.org".into(),
+            password: None,
+            group_id: None,
+            role: Some(UserRole::Admin),
+        }
+        .into();
+
+        test_utils::setup()
+            .with_users(&[admin.clone()])
+            .run(|app_data| async move {
+                let failure: Result<(), _> = Err(UserError::IncorrectPasswordError);
+                app_data
+                    .as_login_attempt_actions()
+                    .record("someUser", None, &failure)
+                    .await
+                    .unwrap();
+
+                let app = test::init_service(
+                    App::new()
+                        .wrap(test_utils::cookie::middleware())
+                        .app_data(web::Data::new(app_data.clone()))
+                        .configure(config),
+                )
+                .await;
+
+                let req = test::TestRequest::get()
+                    .uri("/login-failures")
+                    .cookie(test_utils::cookie::new(&admin.username))
+                    .to_request();
+
+                let counters: Vec<api::LoginFailureCounter> =
+                    test::call_and_read_body_json(&app, req).await;
+                assert_eq!(counters.len(), 1);
+                assert_eq!(counters[0].key, "someUser");
+                assert_eq!(counters[0].failures.len(), 1);
+            })
+            .await;
+    }
+
+    #[actix_web::test]
+    async fn test_list_login_failures_403() {
+        let moderator: User = api::NewUser {
+            username: "moderator".into(),
+            email: "moderator@netsblox.org".into(),
+            password: None,
+            group_id: None,
+            role: Some(UserRole::Moderator),
+        }
+        .into();
+
+        test_utils::setup()
+            .with_users(&[moderator.clone()])
+            .run(|app_data| async move {
+                let app = test::init_service(
+                    App::new()
+                        .wrap(test_utils::cookie::middleware())
+                        .app_data(web::Data::new(app_data.clone()))
+                        .configure(config),
+                )
+                .await;
+
+                let req = test::TestRequest::get()
+                    .uri("/login-failures")
+                    .cookie(test_utils::cookie::new(&moderator.username))
+                    .to_request();
+
+                let response = test::call_service(&app, req).await;
+                assert_eq!(response.status(), http::StatusCode::FORBIDDEN);
+            })
+            .await;
+    }
+}
@@ -2,6 +2,7 @@ use std::sync::{Arc, RwLock};
 
 use actix::Addr;
 use actix_session::Session;
+use actix_web::{http::header, HttpRequest};
 use lru::LruCache;
 use mongodb::{
     bson::{doc, DateTime},
@@ -16,7 +17,10 @@ use netsblox_cloud_common::{
 use crate::{
     app_data::metrics,
     errors::{InternalError, UserError},
-    network::topology::{self, TopologyActor},
+    network::{
+        secrets::ClientSecrets,
+        topology::{self, TopologyActor},
+    },
     utils,
 };
 
@@ -27,40 +31,55 @@ use crate::{
 pub(crate) struct LoginHelper<'a> {
     network: &'a Addr<TopologyActor>,
     metrics: &'a metrics::Metrics,
+    client_secrets: &'a ClientSecrets,
     project_metadata: &'a Collection<ProjectMetadata>,
     project_cache: &'a Arc<RwLock<LruCache<api::ProjectId, ProjectMetadata>>>,
 
     banned_accounts: &'a Collection<BannedAccount>,
+    trusted_proxies: &'a [String],
 }
 
 impl<'a> LoginHelper<'a> {
     pub(crate) fn new(
         network: &'a Addr<TopologyActor>,
         metrics: &'a metrics::Metrics,
+        client_secrets: &'a ClientSecrets,
         project_metadata: &'a Collection<ProjectMetadata>,
         project_cache: &'a Arc<RwLock<LruCache<api::ProjectId, ProjectMetadata>>>,
         banned_accounts: &'a Collection<BannedAccount>,
+        trusted_proxies: &'a [String],
     ) -> Self {
         Self {
             network,
             metrics,
+            client_secrets,
             project_metadata,
             project_cache,
             banned_accounts,
+            trusted_proxies,
         }
     }
 
-    /// Login as the given user for the current session
+    /// Login as the given user for the current session. The client (if any)
+    /// must provide the secret issued with its ID.
     pub(crate) async fn login(
         &self,
+        req: &HttpRequest,
         session: Session,
         user: &api::User,
         client_id: Option<ClientId>,
+        client_secret: Option<&str>,
     ) -> Result<(), UserError> {
-        // TODO: make sure the user isn't banned
-        let query = doc! {"$or": [
-            {"username": &user.username},
-            {"email": &user.email},
+        if let Some(client_id) = &client_id {
+            self.client_secrets.verify(client_id, client_secret)?;
+        }
+
+        let query = doc! {"$and": [
+            {"$or": [
+                {"username": &user.username},
+                {"email": &user.email},
+            ]},
+            utils::not_expired(),
         ]};
 
         if self
@@ -83,8 +102,22 @@ impl<'a> LoginHelper<'a> {
         }
         self.metrics.record_login();
 
+        // Use a new session key on login (so an existing key cannot be reused)
+        session.renew();
         session.insert("username", &user.username).unwrap();
 
+        // Record info about the device so the user can review their active sessions
+        let device = req
+            .headers()
+            .get(header::USER_AGENT)
+            .and_then(|agent| agent.to_str().ok());
+        if let Some(device) = device {
+            session.insert("device", device).unwrap();
+        }
+        if let Some(addr) = utils::get_client_addr(req, self.trusted_proxies) {
+            session.insert("ip", addr.to_string()).unwrap();
+        }
+
         Ok(())
     }
 
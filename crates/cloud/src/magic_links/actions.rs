@@ -1,9 +1,15 @@
-use crate::utils;
+use std::time::Duration;
+
+use crate::{config::MagicLinkSettings, utils};
 use lettre::{
     message::{Mailbox, MultiPart},
     Address, Message, SmtpTransport,
 };
-use mongodb::{bson::doc, options::ReturnDocument, Collection};
+use mongodb::{
+    bson::{doc, DateTime},
+    options::ReturnDocument,
+    Collection,
+};
 use netsblox_cloud_common::api;
 use netsblox_cloud_common::{MagicLink, User};
 use nonempty::NonEmpty;
@@ -15,6 +21,8 @@ use super::email_template;
 pub(crate) struct MagicLinkActions<'a> {
     links: &'a Collection<MagicLink>,
     users: &'a Collection<User>,
+    settings: &'a MagicLinkSettings,
+    allowed_redirect_origins: &'a [String],
 
     // email support
     mailer: &'a SmtpTransport,
@@ -26,6 +34,8 @@ impl<'a> MagicLinkActions<'a> {
     pub(crate) fn new(
         links: &'a Collection<MagicLink>,
         users: &'a Collection<User>,
+        settings: &'a MagicLinkSettings,
+        allowed_redirect_origins: &'a [String],
         mailer: &'a SmtpTransport,
         sender: &'a Mailbox,
         public_url: &'a String,
@@ -33,6 +43,8 @@ impl<'a> MagicLinkActions<'a> {
         Self {
             links,
             users,
+            settings,
+            allowed_redirect_origins,
             mailer,
             sender,
             public_url,
@@ -55,10 +67,15 @@ impl<'a> MagicLinkActions<'a> {
         &self,
         data: &api::CreateMagicLinkData,
     ) -> Result<MagicLinkEmail, UserError> {
+        if let Some(uri) = data.redirect_uri.as_ref() {
+            self.ensure_allowed_redirect(uri)?;
+        }
+
         let usernames: NonEmpty<String> = utils::find_usernames(self.users, &data.email).await?;
 
-        let query = doc! {"email": &data.email};
-        let link = MagicLink::new(data.email.clone());
+        let query = doc! {"email": &data.email, "expiresAt": {"$gt": DateTime::now()}};
+        let ttl = Duration::from_secs(self.settings.expiry_secs);
+        let link = MagicLink::new(data.email.clone(), ttl);
         let update = doc! {"$setOnInsert": &link};
         let options = mongodb::options::FindOneAndUpdateOptions::builder()
             .return_document(ReturnDocument::Before)
@@ -88,7 +105,8 @@ impl<'a> MagicLinkActions<'a> {
         username: &str,
         link_id: &api::MagicLinkId,
     ) -> Result<api::User, UserError> {
-        let query = doc! {"id": &link_id};
+        // Expired links are removed by a TTL index but this isn't immediate
+        let query = doc! {"id": &link_id, "expiresAt": {"$gt": DateTime::now()}};
         let link = self
             .links
             .find_one_and_delete(query, None)
@@ -96,14 +114,75 @@ impl<'a> MagicLinkActions<'a> {
             .map_err(InternalError::DatabaseConnectionError)?
             .ok_or(UserError::MagicLinkNotFoundError)?;
 
-        let query = doc! {"username": username, "email": &link.email};
+        self.find_user(username, &link.email).await
+    }
+
+    /// Login using the code sent with the magic link for the email address.
+    /// The link is invalidated after too many incorrect codes.
+    pub(crate) async fn login_with_code(
+        &self,
+        email: &str,
+        username: &str,
+        code: &str,
+    ) -> Result<api::User, UserError> {
+        let query = doc! {"email": email, "expiresAt": {"$gt": DateTime::now()}};
+        let update = doc! {"$inc": {"codeAttempts": 1}};
+        let options = mongodb::options::FindOneAndUpdateOptions::builder()
+            .return_document(ReturnDocument::After)
+            .build();
+        let link = self
+            .links
+            .find_one_and_update(query, update, options)
+            .await
+            .map_err(InternalError::DatabaseConnectionError)?
+            .ok_or(UserError::MagicLinkNotFoundError)?;
+
+        let is_valid =
+            link.code_attempts <= self.settings.max_code_attempts && codes_match(&link.code, code);
+
+        if link.code_attempts >= self.settings.max_code_attempts || is_valid {
+            let query = doc! {"id": &link.id};
+            let deleted = self
+                .links
+                .find_one_and_delete(query, None)
+                .await
+                .map_err(InternalError::DatabaseConnectionError)?;
+
+            // The link may have been used concurrently
+            if is_valid && deleted.is_none() {
+                return Err(UserError::MagicLinkNotFoundError);
+            }
+        }
+
+        if !is_valid {
+            return Err(UserError::InvalidMagicLinkCodeError);
+        }
 
-        self.users
+        self.find_user(username, &link.email).await
+    }
+
+    /// Ensure the URL is on the public URL or an allowed origin (eg, the editor)
+    pub(crate) fn ensure_allowed_redirect(&self, uri: &str) -> Result<(), UserError> {
+        utils::ensure_allowed_redirect(uri, self.public_url, self.allowed_redirect_origins)
+    }
+
+    async fn find_user(&self, username: &str, email: &str) -> Result<api::User, UserError> {
+        let query = doc! {"username": username, "email": email};
+
+        let user = self
+            .users
             .find_one(query, None)
             .await
             .map_err(InternalError::DatabaseConnectionError)?
-            .ok_or(UserError::UserNotFoundError)
-            .map(|user| user.into())
+            .ok_or(UserError::UserNotFoundError)?;
+
+        // Access to the email address is not a second factor so users with
+        // two-factor authentication need to login with their password instead
+        if user.has_two_factor() {
+            return Err(UserError::TwoFactorRequiredError);
+        }
+
+        Ok(user.into())
     }
 }
 
@@ -121,6 +200,7 @@ impl MagicLinkEmail {
             &self.public_url,
             &self.usernames,
             &self.link.id,
+            &self.link.code,
             self.redirect_uri.clone(),
         )
     }
@@ -150,6 +230,16 @@ impl TryFrom<MagicLinkEmail> for lettre::Message {
     }
 }
 
+/// Compare the codes in constant time so guesses can't be timed
+fn codes_match(expected: &str, code: &str) -> bool {
+    expected.len() == code.len()
+        && expected
+            .bytes()
+            .zip(code.bytes())
+            .fold(0, |diff, (a, b)| diff | (a ^ b))
+            == 0
+}
+
 #[cfg(test)]
 mod tests {
     use crate::test_utils;
@@ -197,7 +287,7 @@ mod tests {
         }
         .into();
 
-        let l1 = MagicLink::new(user.email.clone());
+        let l1 = MagicLink::new(user.email.clone(), Duration::from_secs(60 * 60));
 
         test_utils::setup()
             .with_users(&[user.clone()])
@@ -242,7 +332,7 @@ mod tests {
         }
         .into();
 
-        let l1 = MagicLink::new(user.email.clone());
+        let l1 = MagicLink::new(user.email.clone(), Duration::from_secs(60 * 60));
 
         test_utils::setup()
             .with_magic_links(&[l1.clone()])
@@ -267,7 +357,7 @@ mod tests {
         }
         .into();
 
-        let l1 = MagicLink::new(user.email.clone());
+        let l1 = MagicLink::new(user.email.clone(), Duration::from_secs(60 * 60));
 
         test_utils::setup()
             .with_magic_links(&[l1.clone()])
@@ -283,4 +373,184 @@ mod tests {
             })
             .await;
     }
+
+    #[actix_web::test]
+    async fn test_create_link_redirect_not_allowed() {
+        let user: User = api::NewUser {
+            username: "user".into(),
+            email: "user@netsblox.org".into(),
+            password: None,
+            group_id: None,
+            role: None,
+        }
+        .into();
+
+        test_utils::setup()
+            .with_users(&[user.clone()])
+            .run(|app_data| async move {
+                let actions = app_data.as_magic_link_actions();
+
+                let data = api::CreateMagicLinkData {
+                    email: user.email.clone(),
+                    redirect_uri: Some("https://evil.example.com/netsblox".into()),
+                };
+                let result = actions.try_create_link(&data).await;
+                assert!(matches!(result, Err(UserError::RedirectNotAllowedError)));
+            })
+            .await;
+    }
+
+    #[actix_web::test]
+    async fn test_create_link_redirect_allowed_origin() {
+        let user: User = api::NewUser {
+            username: "user".into(),
+            email: "user@netsblox.org".into(),
+            password: None,
+            group_id: None,
+            role: None,
+        }
+        .into();
+
+        test_utils::setup()
+            .with_users(&[user.clone()])
+            .run(|mut app_data| async move {
+                app_data.settings.allowed_redirect_origins =
+                    vec!["https://editor.netsblox.org/".into()];
+                let actions = app_data.as_magic_link_actions();
+
+                let data = api::CreateMagicLinkData {
+                    email: user.email.clone(),
+                    redirect_uri: Some("https://editor.netsblox.org/?action=present".into()),
+                };
+                actions.try_create_link(&data).await.unwrap();
+            })
+            .await;
+    }
+
+    #[actix_web::test]
+    async fn test_create_link_expiry() {
+        let user: User = api::NewUser {
+            username: "user".into(),
+            email: "user@netsblox.org".into(),
+            password: None,
+            group_id: None,
+            role: None,
+        }
+        .into();
+
+        test_utils::setup()
+            .with_users(&[user.clone()])
+            .run(|mut app_data| async move {
+                app_data.settings.magic_links.expiry_secs = 5 * 60;
+                let actions = app_data.as_magic_link_actions();
+
+                let data = api::CreateMagicLinkData {
+                    email: user.email.clone(),
+                    redirect_uri: None,
+                };
+                let email = actions.try_create_link(&data).await.unwrap();
+                let link = email.link;
+
+                let ttl = link.expires_at.timestamp_millis() - link.created_at.timestamp_millis();
+                assert_eq!(ttl, 5 * 60 * 1000);
+            })
+            .await;
+    }
+
+    #[actix_web::test]
+    async fn test_login_expired() {
+        let user: User = api::NewUser {
+            username: "user".into(),
+            email: "user@netsblox.org".into(),
+            password: None,
+            group_id: None,
+            role: None,
+        }
+        .into();
+
+        let l1 = MagicLink::new(user.email.clone(), Duration::from_secs(0));
+
+        test_utils::setup()
+            .with_magic_links(&[l1.clone()])
+            .with_users(&[user.clone()])
+            .run(|app_data| async move {
+                let actions = app_data.as_magic_link_actions();
+
+                let result = actions.login(&user.username, &l1.id).await;
+                assert!(matches!(result, Err(UserError::MagicLinkNotFoundError)));
+            })
+            .await;
+    }
+
+    #[actix_web::test]
+    async fn test_login_with_code() {
+        let user: User = api::NewUser {
+            username: "user".into(),
+            email: "user@netsblox.org".into(),
+            password: None,
+            group_id: None,
+            role: None,
+        }
+        .into();
+
+        let l1 = MagicLink::new(user.email.clone(), Duration::from_secs(60 * 60));
+
+        test_utils::setup()
+            .with_magic_links(&[l1.clone()])
+            .with_users(&[user.clone()])
+            .run(|app_data| async move {
+                let actions = app_data.as_magic_link_actions();
+
+                let data = actions
+                    .login_with_code(&user.email, &user.username, &l1.code)
+                    .await
+                    .unwrap();
+                assert_eq!(data.username, user.username);
+
+                // the link can only be used once
+                let result = actions.login(&user.username, &l1.id).await;
+                assert!(matches!(result, Err(UserError::MagicLinkNotFoundError)));
+            })
+            .await;
+    }
+
+    #[actix_web::test]
+    async fn test_login_with_code_too_many_attempts() {
+        let user: User = api::NewUser {
+            username: "user".into(),
+            email: "user@netsblox.org".into(),
+            password: None,
+            group_id: None,
+            role: None,
+        }
+        .into();
+
+        let l1 = MagicLink::new(user.email.clone(), Duration::from_secs(60 * 60));
+        let bad_code = if l1.code == "000000" {
+            "000001"
+        } else {
+            "000000"
+        };
+
+        test_utils::setup()
+            .with_magic_links(&[l1.clone()])
+            .with_users(&[user.clone()])
+            .run(|app_data| async move {
+                let actions = app_data.as_magic_link_actions();
+                let max_attempts = app_data.settings.magic_links.max_code_attempts;
+
+                for _ in 0..max_attempts {
+                    let result = actions
+                        .login_with_code(&user.email, &user.username, bad_code)
+                        .await;
+                    assert!(matches!(result, Err(UserError::InvalidMagicLinkCodeError)));
+                }
+
+                let result = actions
+                    .login_with_code(&user.email, &user.username, &l1.code)
+                    .await;
+                assert!(matches!(result, Err(UserError::MagicLinkNotFoundError)));
+            })
+            .await;
+    }
 }
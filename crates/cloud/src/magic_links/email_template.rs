@@ -6,6 +6,7 @@ pub(crate) fn magic_link_email(
     cloud_url: &str,
     usernames: &NonEmpty<String>,
     link_id: &MagicLinkId,
+    code: &str,
     redirect_uri: Option<String>,
 ) -> MultiPart {
     let uri_param = redirect_uri
@@ -30,11 +31,15 @@ pub(crate) fn magic_link_email(
             Please click <a href=\"{url}\">here</a> to \"auto-magically\" sign-in to NetsBlox as {name}. Link can only be used once.
             <br/>
             <br/>
+            Alternatively, enter the code <b>{code}</b> to sign in.
+            <br/>
+            <br/>
 
         Cheers,
         the NetsBlox team</p>",
             name=usernames.first(),
-            url=url
+            url=url,
+            code=code
         );
 
         let txt = format!(
@@ -43,11 +48,14 @@ pub(crate) fn magic_link_email(
         Please click the link below to \"auto-magically\" sign-in to NetsBlox as {name}. Link can only be used once.
 
         {url}
+
+        Alternatively, enter the code {code} to sign in.
             
         Cheers,
         the NetsBlox team",
             url = url,
             name = usernames.first(),
+            code = code,
         );
 
         (txt, html)
@@ -69,6 +77,8 @@ pub(crate) fn magic_link_email(
 
             {login_links}
             
+            <br/>
+            Alternatively, enter the code <b>{code}</b> (with one of the usernames above) to sign in.
             <br/>
             <br/>
         Cheers,
@@ -89,10 +99,13 @@ pub(crate) fn magic_link_email(
         Please select an account below to \"auto-magically\" sign-in. These links can only be used once (combined).
 
         {urlText}
+
+        Alternatively, enter the code {code} (with one of the usernames above) to sign in.
             
         Cheers,
         the NetsBlox team",
             urlText = url_text,
+            code = code,
         );
 
         (txt, html)
@@ -1,5 +1,6 @@
 use crate::app_data::AppData;
 use crate::errors::UserError;
+use crate::utils;
 use actix_session::Session;
 use actix_web::{get, post, HttpRequest};
 use actix_web::{web, HttpResponse};
@@ -26,17 +27,39 @@ async fn login(
     session: Session,
     params: web::Query<api::MagicLinkLoginData>,
 ) -> Result<HttpResponse, UserError> {
-    let req_addr = req.peer_addr().map(|addr| addr.ip());
+    let req_addr = utils::get_client_addr(&req, &app.settings.security.trusted_proxies);
     if let Some(addr) = req_addr {
-        app.ensure_not_tor_ip(&addr).await?;
+        app.ensure_ip_allowed(&addr, api::IpReputationAction::BlockLogin)
+            .await?;
     }
 
     let data = params.into_inner();
     let actions = app.as_magic_link_actions();
-    let user = actions.login(&data.username, &data.link_id).await?;
+    if let Some(uri) = data.redirect_uri.as_ref() {
+        actions.ensure_allowed_redirect(uri)?;
+    }
+
+    let attempts = app.as_login_attempt_actions();
+    attempts
+        .ensure_not_locked(&data.username, req_addr.as_ref())
+        .await?;
+
+    let result = actions.login(&data.username, &data.link_id).await;
+    attempts
+        .record(&data.username, req_addr.as_ref(), &result)
+        .await?;
+    let user = result?;
 
     let helper = app.as_login_helper();
-    helper.login(session, &user, data.client_id).await?;
+    helper
+        .login(
+            &req,
+            session,
+            &user,
+            data.client_id,
+            data.client_secret.as_deref(),
+        )
+        .await?;
 
     if let Some(url) = data.redirect_uri {
         Ok(HttpResponse::Found()
@@ -47,14 +70,63 @@ async fn login(
     }
 }
 
+/// Login using the code sent with a magic link (eg, on devices where it is
+/// inconvenient to open the link from the email)
+#[post("/login/code")]
+async fn login_with_code(
+    app: web::Data<AppData>,
+    req: HttpRequest,
+    session: Session,
+    body: web::Json<api::MagicLinkCodeLoginData>,
+) -> Result<HttpResponse, UserError> {
+    let req_addr = utils::get_client_addr(&req, &app.settings.security.trusted_proxies);
+    if let Some(addr) = req_addr {
+        app.ensure_ip_allowed(&addr, api::IpReputationAction::BlockLogin)
+            .await?;
+    }
+
+    let data = body.into_inner();
+    let attempts = app.as_login_attempt_actions();
+    attempts
+        .ensure_not_locked(&data.username, req_addr.as_ref())
+        .await?;
+
+    let actions = app.as_magic_link_actions();
+    let result = actions
+        .login_with_code(&data.email, &data.username, &data.code)
+        .await;
+    attempts
+        .record(&data.username, req_addr.as_ref(), &result)
+        .await?;
+    let user = result?;
+
+    let helper = app.as_login_helper();
+    helper
+        .login(
+            &req,
+            session,
+            &user,
+            data.client_id,
+            data.client_secret.as_deref(),
+        )
+        .await?;
+
+    Ok(HttpResponse::Ok().json(user))
+}
+
 pub fn config(cfg: &mut web::ServiceConfig) {
-    cfg.service(create_link).service(login);
+    cfg.service(create_link)
+        .service(login)
+        .service(login_with_code);
 }
 
 #[cfg(test)]
 mod tests {
     use actix_web::{http, test, App};
-    use netsblox_cloud_common::{MagicLink, User};
+    use mongodb::bson::DateTime;
+    use netsblox_cloud_common::{BannedAddress, MagicLink, TwoFactorAuth, User};
+
+    use std::time::Duration;
 
     use super::*;
     use crate::test_utils;
@@ -69,7 +141,7 @@ mod tests {
             role: None,
         }
         .into();
-        let l1 = MagicLink::new(user.email.clone());
+        let l1 = MagicLink::new(user.email.clone(), Duration::from_secs(60 * 60));
 
         test_utils::setup()
             .with_users(&[user.clone()])
@@ -109,7 +181,7 @@ mod tests {
             role: None,
         }
         .into();
-        let l1 = MagicLink::new(user.email.clone());
+        let l1 = MagicLink::new(user.email.clone(), Duration::from_secs(60 * 60));
 
         test_utils::setup()
             .with_users(&[user.clone()])
@@ -134,6 +206,50 @@ mod tests {
             .await;
     }
 
+    #[actix_web::test]
+    async fn test_login_banned_address() {
+        let user: User = api::NewUser {
+            username: "user".into(),
+            email: "user@netsblox.org".into(),
+            password: None,
+            group_id: None,
+            role: None,
+        }
+        .into();
+        let l1 = MagicLink::new(user.email.clone(), Duration::from_secs(60 * 60));
+        let ban = BannedAddress::new("10.0.0.0/8".into(), "moderator".into(), None, None);
+
+        test_utils::setup()
+            .with_users(&[user.clone()])
+            .with_magic_links(&[l1.clone()])
+            .run(|app_data| async move {
+                app_data
+                    .banned_addresses
+                    .insert_one(&ban, None)
+                    .await
+                    .unwrap();
+                app_data.ip_lists.add_ban(&ban);
+                let app = test::init_service(
+                    App::new()
+                        .app_data(web::Data::new(app_data.clone()))
+                        .wrap(test_utils::cookie::middleware())
+                        .configure(config),
+                )
+                .await;
+
+                let req = test::TestRequest::get()
+                    .uri(&format!("/login?linkId={}&username=user", &l1.id.as_str()))
+                    .peer_addr("10.1.2.3:8080".parse().unwrap())
+                    .to_request();
+
+                let response = test::call_service(&app, req).await;
+                assert_eq!(response.status(), http::StatusCode::FORBIDDEN);
+                let cookie = response.headers().get(http::header::SET_COOKIE);
+                assert!(cookie.is_none());
+            })
+            .await;
+    }
+
     #[actix_web::test]
     async fn test_login_bad_id() {
         let user: User = api::NewUser {
@@ -197,4 +313,172 @@ mod tests {
             })
             .await;
     }
+
+    #[actix_web::test]
+    async fn test_login_redirect_not_allowed() {
+        let user: User = api::NewUser {
+            username: "user".into(),
+            email: "user@netsblox.org".into(),
+            password: None,
+            group_id: None,
+            role: None,
+        }
+        .into();
+        let l1 = MagicLink::new(user.email.clone(), Duration::from_secs(60 * 60));
+
+        test_utils::setup()
+            .with_users(&[user.clone()])
+            .with_magic_links(&[l1.clone()])
+            .run(|app_data| async move {
+                let app = test::init_service(
+                    App::new()
+                        .app_data(web::Data::new(app_data.clone()))
+                        .wrap(test_utils::cookie::middleware())
+                        .configure(config),
+                )
+                .await;
+
+                let req = test::TestRequest::get()
+                    .uri(&format!(
+                        "/login?linkId={}&username=user&redirectUri=https://evil.example.com",
+                        &l1.id.as_str()
+                    ))
+                    .to_request();
+
+                let response = test::call_service(&app, req).await;
+                assert_eq!(response.status(), http::StatusCode::BAD_REQUEST);
+                let cookie = response.headers().get(http::header::SET_COOKIE);
+                assert!(cookie.is_none());
+            })
+            .await;
+    }
+
+    #[actix_web::test]
+    async fn test_login_with_code() {
+        let user: User = api::NewUser {
+            username: "user".into(),
+            email: "user@netsblox.org".into(),
+            password: None,
+            group_id: None,
+            role: None,
+        }
+        .into();
+        let l1 = MagicLink::new(user.email.clone(), Duration::from_secs(60 * 60));
+
+        test_utils::setup()
+            .with_users(&[user.clone()])
+            .with_magic_links(&[l1.clone()])
+            .run(|app_data| async move {
+                let app = test::init_service(
+                    App::new()
+                        .app_data(web::Data::new(app_data.clone()))
+                        .wrap(test_utils::cookie::middleware())
+                        .configure(config),
+                )
+                .await;
+
+                let data = api::MagicLinkCodeLoginData {
+                    email: user.email.clone(),
+                    username: user.username.clone(),
+                    code: l1.code.clone(),
+                    client_id: None,
+                    client_secret: None,
+                };
+                let req = test::TestRequest::post()
+                    .uri("/login/code")
+                    .set_json(&data)
+                    .to_request();
+
+                let response = test::call_service(&app, req).await;
+                assert_eq!(response.status(), http::StatusCode::OK);
+                let cookie = response.headers().get(http::header::SET_COOKIE);
+                assert!(cookie.is_some());
+            })
+            .await;
+    }
+
+    fn two_factor_user() -> User {
+        let mut user: User = api::NewUser {
+            username: "user".into(),
+            email: "user@netsblox.org".into(),
+            password: None,
+            group_id: None,
+            role: None,
+        }
+        .into();
+        user.two_factor = Some(TwoFactorAuth {
+            secret: "JBSWY3DPEHPK3PXPJBSWY3DPEHPK3PXP".into(),
+            enabled: true,
+            recovery_codes: Vec::new(),
+            last_used_step: None,
+            created_at: DateTime::now(),
+        });
+        user
+    }
+
+    #[actix_web::test]
+    async fn test_login_two_factor_user() {
+        let user = two_factor_user();
+        let l1 = MagicLink::new(user.email.clone(), Duration::from_secs(60 * 60));
+
+        test_utils::setup()
+            .with_users(&[user.clone()])
+            .with_magic_links(&[l1.clone()])
+            .run(|app_data| async move {
+                let app = test::init_service(
+                    App::new()
+                        .app_data(web::Data::new(app_data.clone()))
+                        .wrap(test_utils::cookie::middleware())
+                        .configure(config),
+                )
+                .await;
+
+                let req = test::TestRequest::get()
+                    .uri(&format!("/login?linkId={}&username=user", &l1.id.as_str()))
+                    .to_request();
+
+                let response = test::call_service(&app, req).await;
+                assert_eq!(response.status(), http::StatusCode::UNAUTHORIZED);
+                let cookie = response.headers().get(http::header::SET_COOKIE);
+                assert!(cookie.is_none());
+            })
+            .await;
+    }
+
+    #[actix_web::test]
+    async fn test_login_with_code_two_factor_user() {
+        let user = two_factor_user();
+        let l1 = MagicLink::new(user.email.clone(), Duration::from_secs(60 * 60));
+
+        test_utils::setup()
+            .with_users(&[user.clone()])
+            .with_magic_links(&[l1.clone()])
+            .run(|app_data| async move {
+                let app = test::init_service(
+                    App::new()
+                        .app_data(web::Data::new(app_data.clone()))
+                        .wrap(test_utils::cookie::middleware())
+                        .configure(config),
+                )
+                .await;
+
+                let data = api::MagicLinkCodeLoginData {
+                    email: user.email.clone(),
+                    username: user.username.clone(),
+                    code: l1.code.clone(),
+                    client_id: None,
+                    client_secret: None,
+                };
+                let req = test::TestRequest::post()
+                    .uri("/login/code")
+                    .set_json(&data)
+                    .to_request();
+
+                let response = test::call_service(&app, req).await;
+                assert_eq!(response.status(), http::StatusCode::UNAUTHORIZED);
+                let cookie = response.headers().get(http::header::SET_COOKIE);
+                assert!(cookie.is_none());
+            })
+            .await;
+    }
 }
@@ -1,36 +1,45 @@
+mod access_tokens;
 mod app_data;
+mod audit;
 mod auth;
 mod collaboration_invites;
 mod common;
 mod config;
+mod cors;
+mod csrf;
 mod errors;
 mod friends;
 mod groups;
+mod ip_reputation;
 mod libraries;
+mod login_attempts;
 mod login_helper;
 mod magic_links;
 mod network;
 mod oauth;
 mod projects;
 mod services;
+mod sessions;
 #[cfg(test)]
 mod test_utils;
 mod users;
 mod utils;
 
+use crate::access_tokens::middleware::AccessTokenAuth;
 use crate::common::api;
 use crate::config::Settings;
+use crate::csrf::middleware::CsrfProtection;
 use crate::errors::UserError;
+use crate::services::hosts::middleware::SignedBodyCheck;
+use crate::sessions::store::MongoSessionStore;
 use crate::{app_data::AppData, errors::InternalError};
-use actix_cors::Cors;
 use actix_session::{
-    config::CookieContentSecurity, config::PersistentSession, storage::CookieSessionStore, Session,
-    SessionMiddleware,
+    config::{CookieContentSecurity, PersistentSession, TtlExtensionPolicy},
+    Session, SessionMiddleware,
 };
 use actix_web::cookie::time::Duration;
 use actix_web::{
-    cookie::Key, cookie::SameSite, dev::Service, error::ErrorForbidden, get, http::Method,
-    middleware, web, App, HttpResponse, HttpServer,
+    cookie::Key, cookie::SameSite, get, middleware, web, App, HttpResponse, HttpServer,
 };
 use futures::TryStreamExt;
 use log::error;
@@ -57,11 +66,14 @@ async fn get_client_config(
         .map(|host| host.into())
         .collect();
 
+    let client_id = api::ClientId::new(format!("_netsblox{}", Uuid::new_v4()));
     let config = api::ClientConfig {
-        client_id: format!("_netsblox{}", Uuid::new_v4()),
+        client_secret: app.client_secrets.issue(&client_id),
+        client_id: client_id.as_str().to_owned(),
         username: session.get::<String>("username").unwrap_or(None),
         services_hosts: default_hosts,
         cloud_url: app.settings.public_url.to_owned(),
+        csrf_token: csrf::get_or_create_token(&session),
     };
 
     Ok(HttpResponse::Ok().json(config))
@@ -89,51 +101,88 @@ async fn main() -> std::io::Result<()> {
 
     let address = config.address.clone();
     let server = HttpServer::new(move || {
-        let cors = Cors::default()
-            .allow_any_origin()
-            .allow_any_header()
-            .allow_any_method()
-            .supports_credentials();
-
+        // CORS is applied per scope so the policy can be overridden (eg, for OAuth)
+        let cors = |scope| cors::for_scope(&config.cors, scope);
         let size_32_mb = 1 << 25;
         App::new()
-            .wrap(cors)
             .wrap(app_data.metrics.handler())
-            .wrap(session_middleware(&config))
-            .wrap(middleware::Logger::default())
-            .wrap_fn(|req, srv| {
-                let source = req
-                    .headers()
-                    .get("x-source")
-                    .and_then(|v| v.to_str().ok())
-                    .unwrap_or("unknown");
-                let allow = *req.method() == Method::GET || source != "NetsBlox";
-
-                let fut = if allow { Some(srv.call(req)) } else { None };
-                async {
-                    match fut {
-                        Some(x) => x.await,
-                        None => Err(ErrorForbidden("Operation is not allowed")),
-                    }
-                }
-            })
+            .wrap(CsrfProtection)
+            .wrap(SignedBodyCheck)
+            .wrap(AccessTokenAuth)
+            .wrap(session_middleware(&config, app_data.as_session_store()))
+            .wrap(
+                // Same as the default format but the websocket secret is omitted
+                middleware::Logger::new(
+                    r#"%a "%{request}xi" %s %b "%{Referer}i" "%{User-Agent}i" %T"#,
+                )
+                .custom_request_replace("request", network::secrets::redacted_request_line),
+            )
             .app_data(web::PayloadConfig::new(size_32_mb))
             .app_data(web::JsonConfig::default().limit(size_32_mb))
             .app_data(web::Data::new(app_data.clone()))
-            .service(web::scope("/libraries").configure(libraries::routes::config))
-            .service(web::scope("/users").configure(users::routes::config))
-            .service(web::scope("/projects").configure(projects::routes::config))
-            .service(web::scope("/groups").configure(groups::routes::config))
-            .service(web::scope("/friends").configure(friends::routes::config))
-            .service(web::scope("/magic-links").configure(magic_links::routes::config))
-            .service(web::scope("/network").configure(network::routes::config))
-            .service(web::scope("/oauth").configure(oauth::routes::config))
+            .service(
+                web::scope("/admin")
+                    .wrap(cors("admin"))
+                    .configure(audit::routes::config)
+                    .configure(login_attempts::routes::config)
+                    .configure(ip_reputation::routes::config),
+            )
+            .service(
+                web::scope("/libraries")
+                    .wrap(cors("libraries"))
+                    .configure(libraries::routes::config),
+            )
+            .service(
+                web::scope("/users")
+                    .wrap(cors("users"))
+                    .configure(users::routes::config),
+            )
+            .service(
+                web::scope("/projects")
+                    .wrap(cors("projects"))
+                    .configure(projects::routes::config),
+            )
+            .service(
+                web::scope("/groups")
+                    .wrap(cors("groups"))
+                    .configure(groups::routes::config),
+            )
+            .service(
+                web::scope("/friends")
+                    .wrap(cors("friends"))
+                    .configure(friends::routes::config),
+            )
+            .service(
+                web::scope("/magic-links")
+                    .wrap(cors("magic-links"))
+                    .configure(magic_links::routes::config),
+            )
+            .service(
+                web::scope("/network")
+                    .wrap(cors("network"))
+                    .configure(network::routes::config),
+            )
+            .service(
+                web::scope("/oauth")
+                    .wrap(cors("oauth"))
+                    .configure(oauth::routes::config),
+            )
             .service(
                 web::scope("/collaboration-invites")
+                    .wrap(cors("collaboration-invites"))
                     .configure(collaboration_invites::routes::config),
             )
-            .service(web::scope("/services").configure(services::config))
-            .service(get_client_config)
+            .service(
+                web::scope("/services")
+                    .wrap(cors("services"))
+                    .configure(services::config),
+            )
+            .service(
+                web::scope("")
+                    .wrap(cors(""))
+                    .service(get_client_config)
+                    .service(oauth::routes::openid_configuration),
+            )
     })
     .client_request_timeout(std::time::Duration::from_secs(60))
     .bind(&address)?
@@ -149,11 +198,14 @@ async fn main() -> std::io::Result<()> {
     server.await
 }
 
-fn session_middleware(config: &Settings) -> SessionMiddleware<CookieSessionStore> {
+fn session_middleware(
+    config: &Settings,
+    store: MongoSessionStore,
+) -> SessionMiddleware<MongoSessionStore> {
     let secret_key = Key::from(config.cookie.key.as_bytes());
     let secs_in_week: i64 = 60 * 60 * 24 * 7;
 
-    let mut builder = SessionMiddleware::builder(CookieSessionStore::default(), secret_key)
+    let mut builder = SessionMiddleware::builder(store, secret_key)
         .cookie_name(config.cookie.name.clone())
         .cookie_same_site(SameSite::None)
         .cookie_secure(true)
@@ -161,7 +213,10 @@ fn session_middleware(config: &Settings) -> SessionMiddleware<CookieSessionStore
         .cookie_domain(Some(config.cookie.domain.clone()))
         .cookie_content_security(CookieContentSecurity::Private)
         .session_lifecycle(
-            PersistentSession::default().session_ttl(Duration::seconds(secs_in_week)),
+            PersistentSession::default()
+                .session_ttl(Duration::seconds(secs_in_week))
+                // keep the "last seen" time of the session up to date
+                .session_ttl_extension_policy(TtlExtensionPolicy::OnEveryRequest),
         );
 
     let domain = config.cookie.domain.clone();
@@ -1,3 +1,4 @@
 pub(crate) mod actions;
 pub(crate) mod routes;
+pub(crate) mod secrets;
 pub mod topology;
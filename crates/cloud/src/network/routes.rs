@@ -1,3 +1,4 @@
+use super::secrets;
 use super::topology::{self, ClientCommand};
 use crate::app_data::AppData;
 use crate::common::api::{ClientId, ClientState, ClientStateData, OccupantInviteData, ProjectId};
@@ -13,7 +14,7 @@ use mongodb::bson::doc;
 use serde::Deserialize;
 use serde_json::{json, Value};
 
-#[post("/{client}/state")] // TODO: add token here (in a header), too?
+#[post("/{client}/state")]
 async fn set_client_state(
     app: web::Data<AppData>,
     path: web::Path<(ClientId,)>,
@@ -27,6 +28,8 @@ async fn set_client_state(
         // TODO: move this to the struct parsing
         return Err(UserError::InvalidClientIdError);
     }
+    let secret = secrets::from_request(&req);
+    app.client_secrets.verify(&client_id, secret.as_deref())?;
 
     let mut response = None;
 
@@ -77,12 +80,13 @@ async fn connect_client(
     stream: web::Payload,
     path: web::Path<(ClientId,)>,
 ) -> Result<HttpResponse, UserError> {
-    // TODO: validate client secret?
     let (client_id,) = path.into_inner();
 
     if !client_id.as_str().starts_with('_') {
         return Err(UserError::InvalidClientIdError);
     }
+    let secret = secrets::from_request(&req);
+    app.client_secrets.verify(&client_id, secret.as_deref())?;
 
     // close any existing client with the same ID
     app.network
@@ -97,7 +101,10 @@ async fn connect_client(
         topology_addr: app.network.clone(),
     };
 
-    ws::start(handler, &req, stream).map_err(|_err| UserError::InternalError)
+    ws::WsResponseBuilder::new(handler, &req, stream)
+        .protocols(&[secrets::WS_PROTOCOL])
+        .start()
+        .map_err(|_err| UserError::InternalError)
 }
 
 #[get("/id/{projectID}")]
@@ -484,6 +491,79 @@ mod tests {
         todo!();
     }
 
+    #[actix_web::test]
+    async fn test_set_client_state() {
+        let client_id = ClientId::new("_netsblox_client".into());
+        let state = ClientStateData {
+            state: ClientState::External(ExternalClientState {
+                address: "test".into(),
+                app_id: api::AppId::new("TestApp"),
+            }),
+        };
+
+        test_utils::setup()
+            .run(|app_data| async move {
+                let app = test::init_service(
+                    App::new()
+                        .app_data(web::Data::new(app_data.clone()))
+                        .configure(config),
+                )
+                .await;
+
+                let secret = app_data.client_secrets.issue(&client_id);
+                let req = test::TestRequest::post()
+                    .uri(&format!("/{}/state", client_id.as_str()))
+                    .insert_header((secrets::SECRET_HEADER, secret))
+                    .set_json(&state)
+                    .to_request();
+
+                let response = test::call_service(&app, req).await;
+                assert_eq!(response.status(), http::StatusCode::OK);
+            })
+            .await;
+    }
+
+    #[actix_web::test]
+    async fn test_set_client_state_invalid_secret() {
+        let client_id = ClientId::new("_netsblox_client".into());
+        let other_id = ClientId::new("_netsblox_other".into());
+        let state = ClientStateData {
+            state: ClientState::External(ExternalClientState {
+                address: "test".into(),
+                app_id: api::AppId::new("TestApp"),
+            }),
+        };
+
+        test_utils::setup()
+            .run(|app_data| async move {
+                let app = test::init_service(
+                    App::new()
+                        .app_data(web::Data::new(app_data.clone()))
+                        .configure(config),
+                )
+                .await;
+
+                let secret = app_data.client_secrets.issue(&other_id);
+                let req = test::TestRequest::post()
+                    .uri(&format!("/{}/state", client_id.as_str()))
+                    .insert_header((secrets::SECRET_HEADER, secret))
+                    .set_json(&state)
+                    .to_request();
+
+                let response = test::call_service(&app, req).await;
+                assert_eq!(response.status(), http::StatusCode::UNAUTHORIZED);
+
+                let req = test::TestRequest::post()
+                    .uri(&format!("/{}/state", client_id.as_str()))
+                    .set_json(&state)
+                    .to_request();
+
+                let response = test::call_service(&app, req).await;
+                assert_eq!(response.status(), http::StatusCode::UNAUTHORIZED);
+            })
+            .await;
+    }
+
     #[actix_web::test]
     async fn test_invite_occupant() {
         let sender: User = api::NewUser {
@@ -0,0 +1,197 @@
+//! Secrets issued to clients (alongside their ID) so client IDs cannot be
+//! spoofed. The secret is an HMAC of the client ID so it can be checked by
+//! any server without being stored.
+use actix_web::{dev::ServiceRequest, http::header, web, HttpRequest};
+use netsblox_cloud_common::api::ClientId;
+use ring::hmac;
+use serde::Deserialize;
+
+use crate::errors::UserError;
+
+pub(crate) const SECRET_HEADER: &str = "X-Client-Secret";
+/// Websocket subprotocol selected by the server when connecting
+pub(crate) const WS_PROTOCOL: &str = "netsblox";
+/// Websockets can send the secret as an additional subprotocol (which is
+/// never echoed back by the server)
+const WS_SECRET_PREFIX: &str = "netsblox.secret.";
+
+#[derive(Clone)]
+pub(crate) struct ClientSecrets {
+    key: hmac::Key,
+}
+
+impl ClientSecrets {
+    pub(crate) fn new(server_key: &str) -> Self {
+        // Derive a separate key so client secrets cannot be used for anything else
+        let server_key = hmac::Key::new(hmac::HMAC_SHA256, server_key.as_bytes());
+        let key = hmac::sign(&server_key, b"netsblox client secrets");
+        Self {
+            key: hmac::Key::new(hmac::HMAC_SHA256, key.as_ref()),
+        }
+    }
+
+    pub(crate) fn issue(&self, client_id: &ClientId) -> String {
+        hex::encode(hmac::sign(&self.key, client_id.as_str().as_bytes()))
+    }
+
+    /// Ensure the secret was issued for the given client
+    pub(crate) fn verify(
+        &self,
+        client_id: &ClientId,
+        secret: Option<&str>,
+    ) -> Result<(), UserError> {
+        let secret = secret
+            .and_then(|secret| hex::decode(secret).ok())
+            .ok_or(UserError::InvalidClientSecretError)?;
+
+        hmac::verify(&self.key, client_id.as_str().as_bytes(), &secret)
+            .map_err(|_err| UserError::InvalidClientSecretError)
+    }
+}
+
+#[derive(Deserialize)]
+struct SecretQuery {
+    secret: Option<String>,
+}
+
+/// Get the client secret from the request. Websockets cannot set headers (in
+/// the browser) so the secret can also be provided as a subprotocol or, for
+/// older clients, as a query parameter.
+pub(crate) fn from_request(req: &HttpRequest) -> Option<String> {
+    req.headers()
+        .get(SECRET_HEADER)
+        .and_then(|value| value.to_str().ok())
+        .map(|secret| secret.to_owned())
+        .or_else(|| from_protocols(req))
+        .or_else(|| {
+            web::Query::<SecretQuery>::from_query(req.query_string())
+                .ok()
+                .and_then(|query| query.into_inner().secret)
+        })
+}
+
+fn from_protocols(req: &HttpRequest) -> Option<String> {
+    req.headers()
+        .get_all(header::SEC_WEBSOCKET_PROTOCOL)
+        .filter_map(|value| value.to_str().ok())
+        .flat_map(|protocols| protocols.split(','))
+        .find_map(|protocol| protocol.trim().strip_prefix(WS_SECRET_PREFIX))
+        .map(|secret| secret.to_owned())
+}
+
+/// Request line for the access log with the client secret removed from the
+/// query string.
+pub(crate) fn redacted_request_line(req: &ServiceRequest) -> String {
+    let query = req
+        .query_string()
+        .split('&')
+        .filter(|param| !param.is_empty())
+        .map(|param| match param.split_once('=') {
+            Some(("secret", _)) => "secret=[REDACTED]",
+            _ => param,
+        })
+        .collect::<Vec<_>>()
+        .join("&");
+
+    if query.is_empty() {
+        format!("{} {} {:?}", req.method(), req.path(), req.version())
+    } else {
+        format!(
+            "{} {}?{} {:?}",
+            req.method(),
+            req.path(),
+            query,
+            req.version()
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use actix_web::test::TestRequest;
+
+    use super::*;
+
+    #[test]
+    fn test_verify_issued_secret() {
+        let secrets = ClientSecrets::new("serverKey");
+        let client_id = ClientId::new("_netsblox_client".into());
+        let secret = secrets.issue(&client_id);
+
+        assert!(secrets.verify(&client_id, Some(&secret)).is_ok());
+    }
+
+    #[test]
+    fn test_reject_other_client_secret() {
+        let secrets = ClientSecrets::new("serverKey");
+        let client_id = ClientId::new("_netsblox_client".into());
+        let other_id = ClientId::new("_netsblox_other".into());
+        let secret = secrets.issue(&other_id);
+
+        let result = secrets.verify(&client_id, Some(&secret));
+        assert!(matches!(result, Err(UserError::InvalidClientSecretError)));
+    }
+
+    #[test]
+    fn test_reject_missing_secret() {
+        let secrets = ClientSecrets::new("serverKey");
+        let client_id = ClientId::new("_netsblox_client".into());
+
+        let result = secrets.verify(&client_id, None);
+        assert!(matches!(result, Err(UserError::InvalidClientSecretError)));
+    }
+
+    #[test]
+    fn test_reject_secret_from_other_server() {
+        let client_id = ClientId::new("_netsblox_client".into());
+        let secret = ClientSecrets::new("otherKey").issue(&client_id);
+
+        let secrets = ClientSecrets::new("serverKey");
+        assert!(secrets.verify(&client_id, Some(&secret)).is_err());
+    }
+
+    #[test]
+    fn test_secret_from_query() {
+        let req = TestRequest::get()
+            .uri("/_netsblox_client/connect?secret=abc")
+            .to_http_request();
+
+        assert_eq!(from_request(&req), Some("abc".to_owned()));
+    }
+
+    #[test]
+    fn test_secret_from_protocol() {
+        let req = TestRequest::get()
+            .uri("/_netsblox_client/connect")
+            .insert_header((
+                header::SEC_WEBSOCKET_PROTOCOL,
+                "netsblox, netsblox.secret.abc",
+            ))
+            .to_http_request();
+
+        assert_eq!(from_request(&req), Some("abc".to_owned()));
+    }
+
+    #[test]
+    fn test_redact_secret_from_request_line() {
+        let req = TestRequest::get()
+            .uri("/network/_netsblox_client/connect?secret=abc&other=1")
+            .to_srv_request();
+
+        let line = redacted_request_line(&req);
+        assert!(!line.contains("abc"));
+        assert_eq!(
+            line,
+            "GET /network/_netsblox_client/connect?secret=[REDACTED]&other=1 HTTP/1.1"
+        );
+    }
+
+    #[test]
+    fn test_secret_from_header() {
+        let req = TestRequest::post()
+            .insert_header((SECRET_HEADER, "abc"))
+            .to_http_request();
+
+        assert_eq!(from_request(&req), Some("abc".to_owned()));
+    }
+}
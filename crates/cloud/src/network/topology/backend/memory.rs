@@ -0,0 +1,95 @@
+use std::sync::{Arc, RwLock};
+
+use async_trait::async_trait;
+use futures::stream::{self, BoxStream, StreamExt};
+use log::warn;
+use tokio::sync::broadcast::{self, error::RecvError};
+
+use super::{ClientPresence, TopologyBackend, TopologyMessage};
+use crate::common::api::{ClientId, ProjectId};
+use crate::errors::InternalError;
+
+const EVENT_BUFFER: usize = 1024;
+
+/// Backend for nodes in the same process. This is used when running a single
+/// server (and to test multiple nodes).
+#[derive(Clone)]
+pub(crate) struct MemoryBackend {
+    events: broadcast::Sender<TopologyMessage>,
+    clients: Arc<RwLock<Vec<ClientPresence>>>,
+}
+
+impl MemoryBackend {
+    pub(crate) fn new() -> Self {
+        let (events, _) = broadcast::channel(EVENT_BUFFER);
+        Self {
+            events,
+            clients: Arc::new(RwLock::new(Vec::new())),
+        }
+    }
+}
+
+#[async_trait]
+impl TopologyBackend for MemoryBackend {
+    async fn publish(&self, message: TopologyMessage) -> Result<(), InternalError> {
+        // Sending only fails if no node is subscribed (so there is no one to notify)
+        let _ = self.events.send(message);
+        Ok(())
+    }
+
+    async fn subscribe(&self) -> Result<BoxStream<'static, TopologyMessage>, InternalError> {
+        let events = stream::unfold(self.events.subscribe(), |mut rx| async move {
+            loop {
+                match rx.recv().await {
+                    Ok(message) => return Some((message, rx)),
+                    Err(RecvError::Lagged(count)) => {
+                        warn!("Topology subscriber lagging. Skipped {} events.", count)
+                    }
+                    Err(RecvError::Closed) => return None,
+                }
+            }
+        });
+
+        Ok(events.boxed())
+    }
+
+    async fn set_presence(&self, presence: ClientPresence) -> Result<(), InternalError> {
+        let mut clients = self.clients.write().unwrap();
+        match clients.iter_mut().find(|client| client.id == presence.id) {
+            Some(client) => *client = presence,
+            None => clients.push(presence),
+        }
+        Ok(())
+    }
+
+    async fn remove_presence(&self, id: &ClientId) -> Result<(), InternalError> {
+        let mut clients = self.clients.write().unwrap();
+        clients.retain(|client| &client.id != id);
+        Ok(())
+    }
+
+    async fn get_presence(&self, id: &ClientId) -> Result<Option<ClientPresence>, InternalError> {
+        let clients = self.clients.read().unwrap();
+        Ok(clients.iter().find(|client| &client.id == id).cloned())
+    }
+
+    async fn get_room_occupants(
+        &self,
+        project_id: &ProjectId,
+    ) -> Result<Vec<ClientPresence>, InternalError> {
+        let clients = self.clients.read().unwrap();
+        Ok(clients
+            .iter()
+            .filter(|client| client.project_id() == Some(project_id))
+            .cloned()
+            .collect())
+    }
+
+    async fn get_online_users(&self) -> Result<Vec<String>, InternalError> {
+        let clients = self.clients.read().unwrap();
+        Ok(clients
+            .iter()
+            .filter_map(|client| client.username.clone())
+            .collect())
+    }
+}
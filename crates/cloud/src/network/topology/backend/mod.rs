@@ -0,0 +1,104 @@
+mod memory;
+mod mongo;
+
+pub(crate) use self::memory::MemoryBackend;
+pub(crate) use self::mongo::MongoBackend;
+
+use async_trait::async_trait;
+use futures::stream::BoxStream;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+use crate::common::api::{self, ClientId, ClientState, ProjectId, RoomState};
+use crate::errors::InternalError;
+
+/// An update which needs to be delivered to the clients connected to the other
+/// nodes. Each node delivers it to its own clients.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase", tag = "type")]
+pub(crate) enum TopologyEvent {
+    #[serde(rename_all = "camelCase")]
+    Message {
+        /// Username of the sender (used for logging the delivered message)
+        #[serde(default)]
+        sender: Option<String>,
+        addresses: Vec<String>,
+        content: Value,
+    },
+    #[serde(rename_all = "camelCase")]
+    ServicesMessage { message: api::SendMessage },
+    #[serde(rename_all = "camelCase")]
+    UserMessage { username: String, content: Value },
+    #[serde(rename_all = "camelCase")]
+    RoomMessage {
+        project_id: ProjectId,
+        content: Value,
+    },
+    #[serde(rename_all = "camelCase")]
+    RoomState { state: RoomState },
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub(crate) struct TopologyMessage {
+    /// ID of the node which published the event
+    pub(crate) origin: String,
+    pub(crate) event: TopologyEvent,
+}
+
+/// A client connected to one of the nodes
+#[derive(Clone, Debug, PartialEq)]
+pub(crate) struct ClientPresence {
+    pub(crate) id: ClientId,
+    pub(crate) node: String,
+    pub(crate) username: Option<String>,
+    pub(crate) state: Option<ClientState>,
+}
+
+impl ClientPresence {
+    pub(crate) fn project_id(&self) -> Option<&ProjectId> {
+        match &self.state {
+            Some(ClientState::Browser(state)) => Some(&state.project_id),
+            _ => None,
+        }
+    }
+}
+
+/// Broker shared by the nodes of a cluster. Each node keeps track of its own
+/// clients (and their connections) but their presence is shared so the room
+/// state and online users include the clients of every node. Messages for
+/// clients are published so the other nodes can deliver them.
+#[async_trait]
+pub(crate) trait TopologyBackend: Send + Sync {
+    /// Prepare the backend (eg, create indexes) before it is used
+    async fn initialize(&self) -> Result<(), InternalError> {
+        Ok(())
+    }
+
+    /// Publish an event to all the nodes (including the sender)
+    async fn publish(&self, message: TopologyMessage) -> Result<(), InternalError>;
+
+    /// Receive the events published after subscribing
+    async fn subscribe(&self) -> Result<BoxStream<'static, TopologyMessage>, InternalError>;
+
+    async fn set_presence(&self, presence: ClientPresence) -> Result<(), InternalError>;
+
+    async fn remove_presence(&self, id: &ClientId) -> Result<(), InternalError>;
+
+    async fn get_presence(&self, id: &ClientId) -> Result<Option<ClientPresence>, InternalError>;
+
+    /// Get the clients (on any node) occupying a role in the given room
+    async fn get_room_occupants(
+        &self,
+        project_id: &ProjectId,
+    ) -> Result<Vec<ClientPresence>, InternalError>;
+
+    /// Get the usernames of the clients connected to any node
+    async fn get_online_users(&self) -> Result<Vec<String>, InternalError>;
+
+    /// Let the other nodes know the given node (and its clients) are still
+    /// around. Called periodically by each node.
+    async fn keep_alive(&self, _node: &str) -> Result<(), InternalError> {
+        Ok(())
+    }
+}
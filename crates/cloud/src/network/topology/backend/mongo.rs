@@ -0,0 +1,289 @@
+use std::time::Duration;
+
+use async_trait::async_trait;
+use futures::future;
+use futures::stream::{BoxStream, StreamExt, TryStreamExt};
+use log::warn;
+use mongodb::{
+    bson::{doc, DateTime},
+    change_stream::event::ChangeStreamEvent,
+    options::{IndexOptions, ReplaceOptions},
+    Collection, Database, IndexModel,
+};
+use serde::{Deserialize, Serialize};
+
+use super::{ClientPresence, TopologyBackend, TopologyEvent, TopologyMessage};
+use crate::common::api::{ClientId, ClientState, ProjectId};
+use crate::config::TopologySettings;
+use crate::errors::InternalError;
+
+/// Events only need to be stored until they are picked up by the change streams
+const EVENT_TTL: Duration = Duration::from_secs(60);
+
+#[derive(Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct StoredEvent {
+    origin: String,
+    event: TopologyEvent,
+    created_at: DateTime,
+}
+
+#[derive(Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct StoredPresence {
+    id: ClientId,
+    node: String,
+    username: Option<String>,
+    state: Option<ClientState>,
+    project_id: Option<ProjectId>,
+    updated_at: DateTime,
+}
+
+impl From<ClientPresence> for StoredPresence {
+    fn from(presence: ClientPresence) -> Self {
+        let project_id = presence.project_id().cloned();
+        Self {
+            id: presence.id,
+            node: presence.node,
+            username: presence.username,
+            state: presence.state,
+            project_id,
+            updated_at: DateTime::now(),
+        }
+    }
+}
+
+impl From<StoredPresence> for ClientPresence {
+    fn from(presence: StoredPresence) -> Self {
+        Self {
+            id: presence.id,
+            node: presence.node,
+            username: presence.username,
+            state: presence.state,
+        }
+    }
+}
+
+/// Backend for nodes sharing a database. Events are stored in a collection and
+/// delivered to the nodes using change streams (so the database must be a
+/// replica set).
+pub(crate) struct MongoBackend {
+    events: Collection<StoredEvent>,
+    clients: Collection<StoredPresence>,
+    presence_ttl: Duration,
+}
+
+impl MongoBackend {
+    pub(crate) fn new(db: &Database, prefix: &str, settings: &TopologySettings) -> Self {
+        let events = db.collection::<StoredEvent>(&(prefix.to_owned() + "topologyEvents"));
+        let clients = db.collection::<StoredPresence>(&(prefix.to_owned() + "topologyClients"));
+        let presence_ttl = Duration::from_secs(settings.presence_ttl_secs);
+
+        Self {
+            events,
+            clients,
+            presence_ttl,
+        }
+    }
+}
+
+#[async_trait]
+impl TopologyBackend for MongoBackend {
+    async fn initialize(&self) -> Result<(), InternalError> {
+        let index_opts = IndexOptions::builder().expire_after(EVENT_TTL).build();
+        let index = IndexModel::builder()
+            .keys(doc! {"createdAt": 1})
+            .options(index_opts)
+            .build();
+        self.events
+            .create_index(index, None)
+            .await
+            .map_err(InternalError::DatabaseConnectionError)?;
+
+        let index_opts = IndexOptions::builder()
+            .expire_after(self.presence_ttl)
+            .build();
+        let client_indexes = vec![
+            IndexModel::builder()
+                .keys(doc! {"id": 1})
+                .options(IndexOptions::builder().unique(true).build())
+                .build(),
+            IndexModel::builder().keys(doc! {"projectId": 1}).build(),
+            IndexModel::builder().keys(doc! {"node": 1}).build(),
+            IndexModel::builder()
+                .keys(doc! {"updatedAt": 1})
+                .options(index_opts)
+                .build(),
+        ];
+        self.clients
+            .create_indexes(client_indexes, None)
+            .await
+            .map_err(InternalError::DatabaseConnectionError)?;
+
+        Ok(())
+    }
+
+    async fn publish(&self, message: TopologyMessage) -> Result<(), InternalError> {
+        let event = StoredEvent {
+            origin: message.origin,
+            event: message.event,
+            created_at: DateTime::now(),
+        };
+        self.events
+            .insert_one(event, None)
+            .await
+            .map_err(InternalError::DatabaseConnectionError)?;
+
+        Ok(())
+    }
+
+    async fn subscribe(&self) -> Result<BoxStream<'static, TopologyMessage>, InternalError> {
+        let pipeline = vec![doc! {"$match": {"operationType": "insert"}}];
+        let events = self
+            .events
+            .watch(pipeline, None)
+            .await
+            .map_err(InternalError::DatabaseConnectionError)?
+            // End the stream on errors so the subscriber can subscribe again
+            .take_while(|result| {
+                if let Err(err) = result {
+                    warn!("Unable to receive topology event: {:?}", err);
+                }
+                future::ready(result.is_ok())
+            })
+            .filter_map(|result| async move {
+                match result {
+                    Ok(ChangeStreamEvent {
+                        full_document: Some(event),
+                        ..
+                    }) => Some(TopologyMessage {
+                        origin: event.origin,
+                        event: event.event,
+                    }),
+                    _ => None,
+                }
+            });
+
+        Ok(events.boxed())
+    }
+
+    async fn set_presence(&self, presence: ClientPresence) -> Result<(), InternalError> {
+        let query = doc! {"id": presence.id.as_str()};
+        let options = ReplaceOptions::builder().upsert(true).build();
+        self.clients
+            .replace_one(query, StoredPresence::from(presence), options)
+            .await
+            .map_err(InternalError::DatabaseConnectionError)?;
+
+        Ok(())
+    }
+
+    async fn remove_presence(&self, id: &ClientId) -> Result<(), InternalError> {
+        let query = doc! {"id": id.as_str()};
+        self.clients
+            .delete_one(query, None)
+            .await
+            .map_err(InternalError::DatabaseConnectionError)?;
+
+        Ok(())
+    }
+
+    async fn get_presence(&self, id: &ClientId) -> Result<Option<ClientPresence>, InternalError> {
+        let query = doc! {"id": id.as_str()};
+        let presence = self
+            .clients
+            .find_one(query, None)
+            .await
+            .map_err(InternalError::DatabaseConnectionError)?
+            .map(|presence| presence.into());
+
+        Ok(presence)
+    }
+
+    async fn get_room_occupants(
+        &self,
+        project_id: &ProjectId,
+    ) -> Result<Vec<ClientPresence>, InternalError> {
+        let query = doc! {"projectId": project_id};
+        let occupants = self
+            .clients
+            .find(query, None)
+            .await
+            .map_err(InternalError::DatabaseConnectionError)?
+            .map_ok(|presence| presence.into())
+            .try_collect::<Vec<_>>()
+            .await
+            .map_err(InternalError::DatabaseConnectionError)?;
+
+        Ok(occupants)
+    }
+
+    async fn get_online_users(&self) -> Result<Vec<String>, InternalError> {
+        let query = doc! {"username": {"$ne": null}};
+        let usernames = self
+            .clients
+            .distinct("username", query, None)
+            .await
+            .map_err(InternalError::DatabaseConnectionError)?
+            .into_iter()
+            .filter_map(|name| name.as_str().map(|name| name.to_owned()))
+            .collect();
+
+        Ok(usernames)
+    }
+
+    async fn keep_alive(&self, node: &str) -> Result<(), InternalError> {
+        let query = doc! {"node": node};
+        let update = doc! {"$set": {"updatedAt": DateTime::now()}};
+        self.clients
+            .update_many(query, update, None)
+            .await
+            .map_err(InternalError::DatabaseConnectionError)?;
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use mongodb::bson;
+    use serde_json::json;
+
+    use super::*;
+    use crate::common::api::RoomState;
+
+    #[test]
+    fn test_event_round_trip() {
+        let state = RoomState {
+            id: ProjectId::new("someProject".into()),
+            owner: "owner".into(),
+            name: "project".into(),
+            roles: HashMap::new(),
+            collaborators: Vec::new(),
+            version: 1700000000,
+        };
+        let events = vec![
+            TopologyEvent::UserMessage {
+                username: "alice".into(),
+                content: json!({"type": "message", "data": [1, 2.5, {"nested": null}]}),
+            },
+            TopologyEvent::RoomState { state },
+        ];
+
+        for event in events {
+            let stored = StoredEvent {
+                origin: "node".into(),
+                event: event.clone(),
+                created_at: DateTime::now(),
+            };
+            let doc = bson::to_document(&stored).unwrap();
+            let stored: StoredEvent = bson::from_document(doc).unwrap();
+            assert_eq!(
+                serde_json::to_value(stored.event).unwrap(),
+                serde_json::to_value(event).unwrap()
+            );
+        }
+    }
+}
@@ -1,4 +1,5 @@
 mod address;
+pub(crate) mod backend;
 mod client;
 pub(crate) mod network;
 
@@ -8,6 +9,7 @@ use crate::common::{api, OccupantInvite, ProjectMetadata};
 use actix::dev::OneshotSender;
 use actix::prelude::*;
 use actix::{Actor, AsyncContext, Context, Handler};
+use futures::StreamExt;
 use log::warn;
 use netsblox_cloud_common::api::CollaborationInvite;
 use serde::Serialize;
@@ -15,29 +17,122 @@ use serde_json::Value;
 use std::num::NonZeroUsize;
 use std::sync::Arc;
 use std::thread;
+use std::time::Duration;
+use tokio::sync::mpsc::{self, UnboundedReceiver};
 use tokio::sync::RwLock;
 use uuid::Uuid;
 
+use self::backend::{TopologyBackend, TopologyEvent};
 use self::client::{RoleDataResponseState, RoleRequest, RESPONSE_BUFFER};
-use self::network::Topology;
 pub use self::network::DEFAULT_APP_ID;
+use self::network::{Topology, TopologyUpdate};
 use crate::common::api::{BrowserClientState, ClientState};
 
+/// How often each node lets the others know it is still running
+const KEEP_ALIVE_INTERVAL: Duration = Duration::from_secs(30);
+/// Delay before subscribing to the topology events again (doubled after each
+/// failed attempt, up to the maximum)
+const MIN_RESUBSCRIBE_DELAY: Duration = Duration::from_secs(1);
+const MAX_RESUBSCRIBE_DELAY: Duration = Duration::from_secs(60);
+
 pub struct TopologyActor {
     network: Arc<RwLock<Topology>>,
+    node: String,
+    backend: Arc<dyn TopologyBackend>,
+    updates: Option<UnboundedReceiver<TopologyUpdate>>,
     tx: Option<OneshotSender<TopologyPanic>>,
 }
 
 pub struct TopologyPanic;
 impl TopologyActor {
-    pub(crate) fn new(cache_size: NonZeroUsize, tx: Option<OneshotSender<TopologyPanic>>) -> Self {
-        let network = Arc::new(RwLock::new(Topology::new(cache_size)));
-        Self { network, tx }
+    pub(crate) fn new(
+        cache_size: NonZeroUsize,
+        backend: Arc<dyn TopologyBackend>,
+        tx: Option<OneshotSender<TopologyPanic>>,
+    ) -> Self {
+        let node = Uuid::new_v4().to_string();
+        let (updates_tx, updates) = mpsc::unbounded_channel();
+        let network = Arc::new(RwLock::new(Topology::new(
+            cache_size,
+            node.clone(),
+            updates_tx,
+        )));
+        Self {
+            network,
+            node,
+            backend,
+            updates: Some(updates),
+            tx,
+        }
     }
 }
 
 impl Actor for TopologyActor {
     type Context = Context<Self>;
+
+    fn started(&mut self, ctx: &mut Context<Self>) {
+        if let Some(updates) = self.updates.take() {
+            let fut = network::apply_updates(
+                self.network.clone(),
+                self.backend.clone(),
+                self.node.clone(),
+                updates,
+            );
+            ctx.spawn(actix::fut::wrap_future(fut));
+        }
+
+        // Deliver the events published by the other nodes
+        let network = self.network.clone();
+        let backend = self.backend.clone();
+        let node = self.node.clone();
+        let fut = async move {
+            if let Err(error) = backend.initialize().await {
+                warn!("Unable to initialize topology backend: {:?}", error);
+            }
+
+            // Events published while resubscribing are missed by this node
+            let mut delay = MIN_RESUBSCRIBE_DELAY;
+            loop {
+                match backend.subscribe().await {
+                    Ok(mut events) => {
+                        while let Some(message) = events.next().await {
+                            delay = MIN_RESUBSCRIBE_DELAY;
+                            if message.origin == node {
+                                continue;
+                            }
+                            if let TopologyEvent::RoomState { state } = &message.event {
+                                let mut topology = network.write().await;
+                                topology.invalidate_cached_addresses(&state.id);
+                            }
+                            let topology = network.read().await;
+                            topology.handle_event(message.event).await;
+                        }
+                        warn!("Topology events closed. Subscribing again in {:?}.", delay);
+                    }
+                    Err(error) => {
+                        warn!(
+                            "Unable to subscribe to topology events (retrying in {:?}): {:?}",
+                            delay, error
+                        );
+                    }
+                }
+                tokio::time::sleep(delay).await;
+                delay = (delay * 2).min(MAX_RESUBSCRIBE_DELAY);
+            }
+        };
+        ctx.spawn(actix::fut::wrap_future(fut));
+
+        ctx.run_interval(KEEP_ALIVE_INTERVAL, |act, ctx| {
+            let backend = act.backend.clone();
+            let node = act.node.clone();
+            let fut = async move {
+                if let Err(error) = backend.keep_alive(&node).await {
+                    warn!("Unable to refresh presence of topology node: {:?}", error);
+                }
+            };
+            ctx.spawn(actix::fut::wrap_future(fut));
+        });
+    }
 }
 
 impl Drop for TopologyActor {
@@ -138,7 +233,7 @@ impl Handler<BrokenClient> for TopologyActor {
     fn handle(&mut self, msg: BrokenClient, ctx: &mut Context<Self>) -> Self::Result {
         let network = self.network.clone();
         let fut = async move {
-            let mut topology = network.write().await;
+            let topology = network.read().await;
             if let Err(error) = topology.set_broken_client(msg).await {
                 warn!("Unable to record broken client: {:?}", error);
             }
@@ -155,7 +250,7 @@ impl Handler<RemoveClient> for TopologyActor {
         let network = self.network.clone();
         let fut = async move {
             let mut topology = network.write().await;
-            topology.remove_client(msg).await;
+            topology.remove_client(msg);
         };
         let fut = actix::fut::wrap_future(fut);
         ctx.spawn(fut);
@@ -169,7 +264,7 @@ impl Handler<SetClientState> for TopologyActor {
         let network = self.network.clone();
         let fut = async move {
             let mut topology = network.write().await;
-            topology.set_client_state(msg).await;
+            topology.set_client_state(msg);
         };
         let fut = actix::fut::wrap_future(fut);
         ctx.spawn(fut);
@@ -184,6 +279,7 @@ impl Handler<SetClientUsername> for TopologyActor {
         let fut = async move {
             let mut topology = network.write().await;
             topology.set_client_username(&msg.id, msg.username);
+            topology.update_presence(&msg.id);
         };
         let fut = actix::fut::wrap_future(fut);
         ctx.spawn(fut);
@@ -223,7 +319,7 @@ impl Handler<SendRoomState> for TopologyActor {
     fn handle(&mut self, msg: SendRoomState, ctx: &mut Context<Self>) -> Self::Result {
         let network = self.network.clone();
         let fut = async move {
-            let mut topology = network.write().await;
+            let topology = network.read().await;
             topology.send_room_state(msg);
         };
         let fut = actix::fut::wrap_future(fut);
@@ -260,7 +356,7 @@ impl Handler<EvictOccupant> for TopologyActor {
         let network = self.network.clone();
         let fut = async move {
             let mut topology = network.write().await;
-            topology.evict_client(msg.client_id).await;
+            topology.evict_client(msg.client_id);
         };
         let fut = actix::fut::wrap_future(fut);
         ctx.spawn(fut);
@@ -535,13 +631,13 @@ pub(crate) struct GetOnlineUsers(pub Option<Vec<String>>);
 #[rtype(result = "()")]
 pub struct GetOnlineUsersTask {
     network: Arc<RwLock<Topology>>,
+    backend: Arc<dyn TopologyBackend>,
     allow_names: Option<Vec<String>>,
 }
 
 impl GetOnlineUsersTask {
     pub(crate) async fn run(self) -> Vec<String> {
-        let topology = self.network.read().await;
-        topology.get_online_users(self.allow_names)
+        network::get_online_users(&self.network, self.backend.as_ref(), self.allow_names).await
     }
 }
 
@@ -551,6 +647,7 @@ impl Handler<GetOnlineUsers> for TopologyActor {
     fn handle(&mut self, msg: GetOnlineUsers, _ctx: &mut Context<Self>) -> Self::Result {
         MessageResult(GetOnlineUsersTask {
             network: self.network.clone(),
+            backend: self.backend.clone(),
             allow_names: msg.0,
         })
     }
@@ -624,13 +721,13 @@ pub(crate) struct GetClientInfo(pub ClientId);
 #[rtype(result = "()")]
 pub struct GetClientInfoTask {
     network: Arc<RwLock<Topology>>,
+    backend: Arc<dyn TopologyBackend>,
     client_id: ClientId,
 }
 
 impl GetClientInfoTask {
     pub(crate) async fn run(self) -> Option<api::ClientInfo> {
-        let topology = self.network.read().await;
-        topology.get_client_info(&self.client_id)
+        network::get_client_info(&self.network, self.backend.as_ref(), &self.client_id).await
     }
 }
 
@@ -640,6 +737,7 @@ impl Handler<GetClientInfo> for TopologyActor {
     fn handle(&mut self, msg: GetClientInfo, _ctx: &mut Context<Self>) -> Self::Result {
         MessageResult(GetClientInfoTask {
             network: self.network.clone(),
+            backend: self.backend.clone(),
             client_id: msg.0,
         })
     }
@@ -653,13 +751,13 @@ pub(crate) struct GetRoomState(pub ProjectMetadata);
 #[rtype(result = "()")]
 pub struct GetRoomStateTask {
     network: Arc<RwLock<Topology>>,
+    backend: Arc<dyn TopologyBackend>,
     project: ProjectMetadata,
 }
 
 impl GetRoomStateTask {
     pub(crate) async fn run(self) -> Option<RoomState> {
-        let topology = self.network.read().await;
-        topology.get_room_state(self.project)
+        network::get_room_state(&self.network, self.backend.as_ref(), self.project).await
     }
 }
 
@@ -669,6 +767,7 @@ impl Handler<GetRoomState> for TopologyActor {
     fn handle(&mut self, msg: GetRoomState, _ctx: &mut Context<Self>) -> Self::Result {
         MessageResult(GetRoomStateTask {
             network: self.network.clone(),
+            backend: self.backend.clone(),
             project: msg.0,
         })
     }
@@ -700,3 +799,265 @@ impl Handler<GetExternalClients> for TopologyActor {
         })
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use std::{collections::HashMap, time::SystemTime};
+
+    use actix_web::rt::time::sleep;
+    use mongodb::bson::DateTime;
+    use netsblox_cloud_common::{
+        api::{AppId, ExternalClientState, RoleId, SaveState},
+        RoleMetadata,
+    };
+    use serde_json::json;
+
+    use super::*;
+    use crate::errors::InternalError;
+    use crate::test_utils::network::Client;
+    use async_trait::async_trait;
+    use backend::{ClientPresence, MemoryBackend, TopologyMessage};
+    use futures::stream::BoxStream;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    /// Backend which fails to subscribe the first time
+    struct FlakyBackend {
+        inner: MemoryBackend,
+        subscriptions: AtomicUsize,
+    }
+
+    #[async_trait]
+    impl TopologyBackend for FlakyBackend {
+        async fn publish(&self, message: TopologyMessage) -> Result<(), InternalError> {
+            self.inner.publish(message).await
+        }
+
+        async fn subscribe(&self) -> Result<BoxStream<'static, TopologyMessage>, InternalError> {
+            if self.subscriptions.fetch_add(1, Ordering::SeqCst) == 0 {
+                return Err(InternalError::TimeoutError);
+            }
+            self.inner.subscribe().await
+        }
+
+        async fn set_presence(&self, presence: ClientPresence) -> Result<(), InternalError> {
+            self.inner.set_presence(presence).await
+        }
+
+        async fn remove_presence(&self, id: &ClientId) -> Result<(), InternalError> {
+            self.inner.remove_presence(id).await
+        }
+
+        async fn get_presence(
+            &self,
+            id: &ClientId,
+        ) -> Result<Option<ClientPresence>, InternalError> {
+            self.inner.get_presence(id).await
+        }
+
+        async fn get_room_occupants(
+            &self,
+            project_id: &ProjectId,
+        ) -> Result<Vec<ClientPresence>, InternalError> {
+            self.inner.get_room_occupants(project_id).await
+        }
+
+        async fn get_online_users(&self) -> Result<Vec<String>, InternalError> {
+            self.inner.get_online_users().await
+        }
+    }
+
+    fn start_node(backend: &MemoryBackend) -> Addr<TopologyActor> {
+        let cache_size = NonZeroUsize::new(10).unwrap();
+        TopologyActor::new(cache_size, Arc::new(backend.clone()), None).start()
+    }
+
+    /// Wait for a client to receive (at least) the given number of messages
+    async fn wait_for_msgs(client: &Client, count: usize) -> Vec<Value> {
+        for _ in 0..100 {
+            if client.received().len() >= count {
+                break;
+            }
+            sleep(Duration::from_millis(10)).await;
+        }
+        client.received()
+    }
+
+    #[actix_web::test]
+    async fn test_send_to_user_across_nodes() {
+        let backend = MemoryBackend::new();
+        let node_a = start_node(&backend);
+        let node_b = start_node(&backend);
+        let client = Client::new(Some("alice".into()), None);
+        client.clone().add_into(&node_b).await;
+
+        let invite = api::FriendInvite {
+            id: "someInvite".into(),
+            sender: "bob".into(),
+            recipient: "alice".into(),
+            created_at: SystemTime::now(),
+        };
+        node_a
+            .send(FriendRequestChangeMsg::new(ChangeType::Add, invite))
+            .await
+            .unwrap();
+
+        let msgs = wait_for_msgs(&client, 1).await;
+        assert_eq!(msgs.len(), 1);
+        assert_eq!(msgs[0]["type"], "friend-request");
+    }
+
+    #[actix_web::test]
+    async fn test_resubscribe_after_error() {
+        let backend = MemoryBackend::new();
+        let flaky = Arc::new(FlakyBackend {
+            inner: backend.clone(),
+            subscriptions: AtomicUsize::new(0),
+        });
+        let cache_size = NonZeroUsize::new(10).unwrap();
+        let node_a = start_node(&backend);
+        let node_b = TopologyActor::new(cache_size, flaky.clone(), None).start();
+        let client = Client::new(Some("alice".into()), None);
+        client.clone().add_into(&node_b).await;
+
+        for _ in 0..300 {
+            if flaky.subscriptions.load(Ordering::SeqCst) > 1 {
+                break;
+            }
+            sleep(Duration::from_millis(10)).await;
+        }
+        assert_eq!(flaky.subscriptions.load(Ordering::SeqCst), 2);
+
+        let invite = api::FriendInvite {
+            id: "someInvite".into(),
+            sender: "bob".into(),
+            recipient: "alice".into(),
+            created_at: SystemTime::now(),
+        };
+        node_a
+            .send(FriendRequestChangeMsg::new(ChangeType::Add, invite))
+            .await
+            .unwrap();
+
+        let msgs = wait_for_msgs(&client, 1).await;
+        assert_eq!(msgs.len(), 1);
+        assert_eq!(msgs[0]["type"], "friend-request");
+    }
+
+    #[actix_web::test]
+    async fn test_send_msg_across_nodes() {
+        let backend = MemoryBackend::new();
+        let node_a = start_node(&backend);
+        let node_b = start_node(&backend);
+        let sender = Client::new(None, None);
+        let state = ClientState::External(ExternalClientState {
+            address: "listener@tester".into(),
+            app_id: AppId::new("TestApp"),
+        });
+        let listener = Client::new(None, Some(state));
+        sender.clone().add_into(&node_a).await;
+        listener.clone().add_into(&node_b).await;
+
+        node_a
+            .send(SendMessage {
+                sender: sender.id.clone(),
+                addresses: vec!["listener@tester #TestApp".into()],
+                content: json!({"type": "message", "msgType": "greeting"}),
+            })
+            .await
+            .unwrap();
+
+        let msgs = wait_for_msgs(&listener, 1).await;
+        assert_eq!(msgs.len(), 1);
+        assert_eq!(msgs[0]["msgType"], "greeting");
+        assert!(sender.received().is_empty());
+    }
+
+    #[actix_web::test]
+    async fn test_room_state_across_nodes() {
+        let backend = MemoryBackend::new();
+        let node_a = start_node(&backend);
+        let node_b = start_node(&backend);
+
+        let role_id = RoleId::new("someRole".into());
+        let role = RoleMetadata {
+            name: "myRole".into(),
+            code: String::new(),
+            media: String::new(),
+            updated: DateTime::now(),
+        };
+        let roles = HashMap::from([(role_id.clone(), role)]);
+        let project = ProjectMetadata::new("owner", "someProject", roles, SaveState::Saved);
+        let state = ClientState::Browser(BrowserClientState {
+            project_id: project.id.clone(),
+            role_id: role_id.clone(),
+        });
+        let c1 = Client::new(Some("alice".into()), Some(state.clone()));
+        let c2 = Client::new(Some("bob".into()), Some(state));
+        c1.clone().add_into(&node_a).await;
+        c2.clone().add_into(&node_b).await;
+
+        // Both occupants should be visible from either node
+        let mut room_state = None;
+        for _ in 0..100 {
+            let task = node_a.send(GetRoomState(project.clone())).await.unwrap();
+            room_state = task.run().await;
+            let count = room_state
+                .as_ref()
+                .map(|state| state.roles[&role_id].occupants.len())
+                .unwrap_or_default();
+            if count == 2 {
+                break;
+            }
+            sleep(Duration::from_millis(10)).await;
+        }
+        let room_state = room_state.unwrap();
+        assert_eq!(room_state.roles[&role_id].occupants.len(), 2);
+
+        node_a
+            .send(SendRoomState {
+                project: project.clone(),
+            })
+            .await
+            .unwrap();
+
+        for client in [c1, c2] {
+            let msgs = wait_for_msgs(&client, 1).await;
+            let msg = msgs.last().unwrap();
+            assert_eq!(msg["type"], "room-roles");
+            let occupants = msg["roles"][role_id.as_str()]["occupants"]
+                .as_array()
+                .unwrap();
+            assert_eq!(occupants.len(), 2);
+        }
+    }
+
+    #[actix_web::test]
+    async fn test_online_users_across_nodes() {
+        let backend = MemoryBackend::new();
+        let node_a = start_node(&backend);
+        let node_b = start_node(&backend);
+        let client = Client::new(Some("alice".into()), None);
+        client.clone().add_into(&node_b).await;
+
+        let mut online = Vec::new();
+        for _ in 0..100 {
+            let task = node_a.send(GetOnlineUsers(None)).await.unwrap();
+            online = task.run().await;
+            if !online.is_empty() {
+                break;
+            }
+            sleep(Duration::from_millis(10)).await;
+        }
+        assert_eq!(online, vec!["alice".to_owned()]);
+
+        node_b
+            .send(RemoveClient {
+                id: client.id.clone(),
+            })
+            .await
+            .unwrap();
+        sleep(Duration::from_millis(50)).await;
+        let task = node_a.send(GetOnlineUsers(None)).await.unwrap();
+        assert!(task.run().await.is_empty());
+    }
+}
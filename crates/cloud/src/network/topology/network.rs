@@ -15,6 +15,7 @@ use std::num::NonZeroUsize;
 use std::str::FromStr;
 use std::sync::{Arc, RwLock};
 use std::time::{Duration, SystemTime};
+use tokio::sync::mpsc::{UnboundedReceiver, UnboundedSender};
 
 use crate::app_data::AppData;
 use crate::common::api::{ProjectId, SaveState};
@@ -23,12 +24,27 @@ use crate::errors::InternalError;
 use crate::network::topology::address::ClientAddress;
 
 pub use super::address::DEFAULT_APP_ID;
+use super::backend::{ClientPresence, TopologyBackend, TopologyEvent, TopologyMessage};
 use super::client::{Client, ClientId, RoleRequest};
 use super::{
     AddClient, BrokenClient, ClientCommand, RemoveClient, SendIDEMessage, SendMessage,
     SendOccupantInvite, SendRoomState, SetClientState,
 };
 
+/// Changes which need to be shared with the other nodes (using the backend).
+/// They are queued while the topology is locked and applied in order once the
+/// lock has been released so the clients aren't blocked waiting on the backend.
+pub(crate) enum TopologyUpdate {
+    Publish(TopologyMessage),
+    SetPresence(ClientPresence),
+    RemovePresence(ClientId),
+    /// The last occupant (on this node) left the room
+    RemoveRoom(ProjectId),
+    /// The occupants of the room changed
+    RoomChanged(ProjectId),
+    RoomState(ProjectMetadata),
+}
+
 #[derive(Clone, Debug)]
 struct BrowserAddress {
     role_id: RoleId,
@@ -56,15 +72,15 @@ impl From<RoomState> for ClientCommand {
     }
 }
 
-impl From<SendOccupantInvite> for ClientCommand {
-    fn from(msg: SendOccupantInvite) -> ClientCommand {
-        ClientCommand::SendMessage(json!({
+impl From<SendOccupantInvite> for Value {
+    fn from(msg: SendOccupantInvite) -> Value {
+        json!({
             "type": "room-invitation",
             "projectId": msg.invite.project_id,
             "roleId": msg.invite.role_id,
             "projectName": msg.project.name,
             "inviter": msg.inviter,
-        }))
+        })
     }
 }
 
@@ -76,69 +92,74 @@ impl From<EvictionNotice> for ClientCommand {
     }
 }
 
+/// The clients connected to this node which are occupying a room
 #[derive(Debug)]
 struct ProjectNetwork {
-    id: ProjectId,
     roles: HashMap<RoleId, Vec<ClientId>>,
 }
 
 impl ProjectNetwork {
-    fn new(id: ProjectId) -> ProjectNetwork {
+    fn new() -> ProjectNetwork {
         ProjectNetwork {
-            id,
             roles: HashMap::new(),
         }
     }
+}
 
-    fn get_state(
-        &self,
-        project: ProjectMetadata,
-        usernames: &HashMap<ClientId, String>,
-    ) -> RoomState {
-        let empty = Vec::new();
-        let roles: HashMap<RoleId, RoleState> = project
-            .roles
-            .into_iter()
-            .map(|(id, role)| {
-                let client_ids = self.roles.get(&id).unwrap_or(&empty);
-                let occupants = client_ids
-                    .iter()
-                    .map(|id| OccupantState {
-                        id: id.to_owned(),
-                        name: usernames.get(id).unwrap_or(&"guest".to_owned()).to_owned(),
-                    })
-                    .collect();
-
-                let state = RoleState {
-                    name: role.name,
-                    occupants,
-                };
-                (id, state)
-            })
-            .collect();
-
-        let version = SystemTime::now()
-            .duration_since(SystemTime::UNIX_EPOCH)
-            .map(|dur| dur.as_secs())
-            .map_err(|err| {
-                log::error!("Unable to compute unix timestamp: {}", &err);
-                err
-            })
-            .unwrap_or_default();
-
-        RoomState {
-            id: self.id.to_owned(),
-            owner: project.owner,
-            name: project.name,
-            roles,
-            collaborators: project.collaborators,
-            version,
+/// Get the state of a room given its occupants (on any node)
+fn build_room_state(project: ProjectMetadata, occupants: Vec<ClientPresence>) -> RoomState {
+    let mut role_occupants: HashMap<RoleId, Vec<OccupantState>> = HashMap::new();
+    for client in occupants {
+        if let Some(ClientState::Browser(state)) = client.state {
+            role_occupants
+                .entry(state.role_id)
+                .or_default()
+                .push(OccupantState {
+                    id: client.id,
+                    name: client.username.unwrap_or_else(|| "guest".to_owned()),
+                });
         }
     }
+
+    let roles: HashMap<RoleId, RoleState> = project
+        .roles
+        .into_iter()
+        .map(|(id, role)| {
+            let occupants = role_occupants.remove(&id).unwrap_or_default();
+            let state = RoleState {
+                name: role.name,
+                occupants,
+            };
+            (id, state)
+        })
+        .collect();
+
+    let version = SystemTime::now()
+        .duration_since(SystemTime::UNIX_EPOCH)
+        .map(|dur| dur.as_secs())
+        .map_err(|err| {
+            log::error!("Unable to compute unix timestamp: {}", &err);
+            err
+        })
+        .unwrap_or_default();
+
+    RoomState {
+        id: project.id,
+        owner: project.owner,
+        name: project.name,
+        roles,
+        collaborators: project.collaborators,
+        version,
+    }
 }
 
+type TopologyLock = tokio::sync::RwLock<Topology>;
+
 pub(crate) struct Topology {
     app_data: Option<AppData>,
+    /// ID of this node (unique within the cluster)
+    node: String,
+    updates: UnboundedSender<TopologyUpdate>,
 
     clients: HashMap<ClientId, Client>,
     states: HashMap<ClientId, ClientState>,
@@ -159,10 +180,16 @@ enum ProjectCleanup {
 }
 
 impl Topology {
-    pub fn new(cache_size: NonZeroUsize) -> Topology {
+    pub fn new(
+        cache_size: NonZeroUsize,
+        node: String,
+        updates: UnboundedSender<TopologyUpdate>,
+    ) -> Topology {
         Topology {
             clients: HashMap::new(),
             app_data: None,
+            node,
+            updates,
             rooms: HashMap::new(),
             states: HashMap::new(),
             usernames: HashMap::new(),
@@ -293,19 +320,69 @@ impl Topology {
             .unwrap_or_default()
     }
 
+    async fn get_clients_at_addresses(&self, addresses: &[String]) -> Vec<&Client> {
+        join_all(
+            addresses
+                .iter()
+                .filter_map(|addr_str| ClientAddress::from_str(addr_str).ok())
+                .map(|address| self.get_clients_at(address)),
+        )
+        .await
+        .into_iter()
+        .flatten()
+        .collect()
+    }
+
+    fn queue(&self, update: TopologyUpdate) {
+        if self.updates.send(update).is_err() {
+            log::error!("Unable to queue topology update. Update worker has stopped.");
+        }
+    }
+
+    fn publish(&self, event: TopologyEvent) {
+        let message = TopologyMessage {
+            origin: self.node.clone(),
+            event,
+        };
+        self.queue(TopologyUpdate::Publish(message));
+    }
+
+    /// Deliver an event published by another node to the clients on this node.
+    /// Room states should invalidate the cached addresses first (see
+    /// `invalidate_cached_addresses`).
+    pub async fn handle_event(&self, event: TopologyEvent) {
+        match event {
+            TopologyEvent::Message {
+                sender,
+                addresses,
+                content,
+            } => self.deliver_msg(sender, &addresses, content).await,
+            TopologyEvent::ServicesMessage { message } => {
+                self.deliver_msg_from_services(message).await
+            }
+            TopologyEvent::UserMessage { username, content } => {
+                self.deliver_to_user(content, &username)
+            }
+            TopologyEvent::RoomMessage {
+                project_id,
+                content,
+            } => self.deliver_to_room(content, &project_id),
+            TopologyEvent::RoomState { state } => self.deliver_room_state(&state),
+        }
+    }
+
     pub async fn send_msg(&self, msg: SendMessage) {
+        let sender = self.usernames.get(&msg.sender);
+        let event = TopologyEvent::Message {
+            sender: sender.cloned(),
+            addresses: msg.addresses.clone(),
+            content: msg.content.clone(),
+        };
+        self.publish(event);
+
         if let Some(app) = &self.app_data {
             let message = ClientCommand::SendMessage(msg.content.clone());
-            let recipients: Vec<_> = join_all(
-                msg.addresses
-                    .iter()
-                    .filter_map(|addr_str| ClientAddress::from_str(addr_str).ok())
-                    .map(|address| self.get_clients_at(address)),
-            )
-            .await
-            .into_iter()
-            .flatten()
-            .collect();
+            let recipients = self.get_clients_at_addresses(&msg.addresses).await;
 
             // check if the message is allowed
             // Since the likelihood of malicious projects being able to send a meaningful
@@ -313,7 +390,9 @@ impl Topology {
             // target) is quite low, we will allow all messages to be sent for now.
             //let recipients = self.allowed_recipients(app, &msg.sender, recipients).await;
 
-            let sender = self.usernames.get(&msg.sender);
+            // Only the recipients connected to this node are logged (and recorded).
+            // The recipients on the other nodes are logged by the node delivering
+            // the message (see `deliver_msg`).
             let mut recipient_names: Vec<String> = Vec::new();
 
             recipients.iter().for_each(|client| {
@@ -331,7 +410,9 @@ impl Topology {
                     content: msg.content.clone(),
                     created_at: DateTime::now(),
                 };
-                app.logged_messages.insert_one(msg_log, None).await;
+                if let Err(err) = app.logged_messages.insert_one(msg_log, None).await {
+                    warn!("Failed to log sent message: {}", err);
+                }
             }
 
             // maybe record the message
@@ -399,6 +480,34 @@ impl Topology {
         }
     }
 
+    async fn deliver_msg(&self, sender: Option<String>, addresses: &[String], content: Value) {
+        let message = ClientCommand::SendMessage(content.clone());
+        let recipients = self.get_clients_at_addresses(addresses).await;
+        let mut recipient_names: Vec<String> = Vec::new();
+        recipients.iter().for_each(|client| {
+            if let Err(err) = client.addr.do_send(message.clone()) {
+                log::error!("Unable to send message to client: {}", err);
+            } else if let Some(recname) = self.usernames.get(&client.id) {
+                recipient_names.push(recname.to_owned());
+            }
+        });
+
+        // Log the recipients on this node (the sending node logs its own)
+        if let (Some(app), Some(sender)) = (&self.app_data, sender) {
+            if !recipient_names.is_empty() {
+                let msg_log = LogMessage {
+                    sender,
+                    recipients: recipient_names,
+                    content,
+                    created_at: DateTime::now(),
+                };
+                if let Err(err) = app.logged_messages.insert_one(msg_log, None).await {
+                    warn!("Failed to log delivered message: {}", err);
+                }
+            }
+        }
+    }
+
     /// Get the allowed recipients of a message. If the recipient is a
     /// member of a group, ensure that the sender can message that group.
     #[allow(dead_code)] // This is temporarily disabled until msg filtering is fleshed out further
@@ -467,7 +576,27 @@ impl Topology {
         }
     }
 
-    pub async fn set_client_state(&mut self, msg: SetClientState) {
+    fn get_local_presence(&self, id: &ClientId) -> ClientPresence {
+        ClientPresence {
+            id: id.to_owned(),
+            node: self.node.clone(),
+            username: self.usernames.get(id).cloned(),
+            state: self.states.get(id).cloned(),
+        }
+    }
+
+    /// Share the current username and state of a client with the other nodes
+    pub fn update_presence(&self, id: &ClientId) {
+        let presence = self.get_local_presence(id);
+        let is_known = presence.username.is_some() || presence.state.is_some();
+        if self.has_client(id) && is_known {
+            self.queue(TopologyUpdate::SetPresence(presence));
+        } else {
+            self.queue(TopologyUpdate::RemovePresence(id.to_owned()));
+        }
+    }
+
+    pub fn set_client_state(&mut self, msg: SetClientState) {
         if !self.has_client(&msg.id) {
             return;
         }
@@ -476,7 +605,7 @@ impl Topology {
             ClientState::Browser(ref state) => Some(state.project_id.clone()),
             _ => None,
         };
-        self.reset_client_state(&msg.id, new_project_id).await;
+        self.reset_client_state(&msg.id, new_project_id.clone());
         self.set_client_username(&msg.id, msg.username);
 
         match &msg.state {
@@ -484,7 +613,7 @@ impl Topology {
                 let room = self
                     .rooms
                     .entry(state.project_id.clone())
-                    .or_insert(ProjectNetwork::new(state.project_id.to_owned()));
+                    .or_insert_with(ProjectNetwork::new);
 
                 if let Some(occupants) = room.roles.get_mut(&state.role_id) {
                     occupants.push(msg.id.clone());
@@ -492,8 +621,6 @@ impl Topology {
                     room.roles
                         .insert(state.role_id.clone(), vec![msg.id.clone()]);
                 }
-                let project_id = state.project_id.to_owned();
-                self.send_room_state_for(&project_id).await;
             }
             ClientState::External(state) => {
                 let app_net = self
@@ -504,7 +631,12 @@ impl Topology {
                 app_net.insert(state.address.to_owned(), msg.id.to_owned());
             }
         }
-        self.states.insert(msg.id, msg.state);
+        self.states.insert(msg.id.clone(), msg.state);
+        self.update_presence(&msg.id);
+
+        if let Some(project_id) = new_project_id {
+            self.queue(TopologyUpdate::RoomChanged(project_id));
+        }
     }
 
     pub fn add_client(&mut self, msg: AddClient) {
@@ -518,7 +650,7 @@ impl Topology {
         }
     }
 
-    pub async fn set_broken_client(&mut self, msg: BrokenClient) -> Result<(), InternalError> {
+    pub async fn set_broken_client(&self, msg: BrokenClient) -> Result<(), InternalError> {
         if let Some(app) = &self.app_data {
             if let Some(ClientState::Browser(state)) = self.states.get(&msg.id) {
                 let query = doc! {
@@ -537,9 +669,9 @@ impl Topology {
         // TODO: Record a list of broken clients for the project?
     }
 
-    pub async fn remove_client(&mut self, msg: RemoveClient) {
+    pub fn remove_client(&mut self, msg: RemoveClient) {
         self.clients.remove(&msg.id);
-        self.reset_client_state(&msg.id, None).await;
+        self.reset_client_state(&msg.id, None);
 
         let app_data = &self.app_data;
         if let Some(app_data) = app_data {
@@ -549,13 +681,14 @@ impl Topology {
         }
     }
 
-    async fn reset_client_state(
+    fn reset_client_state(
         &mut self,
         id: &ClientId,
         new_project_id: Option<ProjectId>,
     ) -> Option<ClientState> {
         self.usernames.remove(id);
         let state = self.states.remove(id);
+        self.update_presence(id);
         match &state {
             Some(ClientState::Browser(state)) => {
                 let room = self.rooms.get_mut(&state.project_id);
@@ -575,12 +708,8 @@ impl Topology {
                             .unwrap_or(true);
                         let remove_room = role_count == 1 && is_leaving_project;
                         if remove_room {
-                            if let Err(error) = self.remove_room(&state.project_id).await {
-                                warn!(
-                                    "Unable to remove project {}: {:?}",
-                                    &state.project_id, error
-                                );
-                            }
+                            self.rooms.remove(&state.project_id);
+                            self.queue(TopologyUpdate::RemoveRoom(state.project_id.clone()));
                             update_needed = false;
                         } else {
                             // remove the role
@@ -592,7 +721,7 @@ impl Topology {
                 }
 
                 if update_needed {
-                    self.send_room_state_for(&state.project_id).await;
+                    self.queue(TopologyUpdate::RoomChanged(state.project_id.clone()));
                 }
             }
             Some(ClientState::External(state)) => {
@@ -614,91 +743,19 @@ impl Topology {
         state
     }
 
-    async fn remove_room(&mut self, project_id: &ProjectId) -> Result<(), InternalError> {
-        // Set the entry to be removed. After how long?
-        //   - If the room has only one role, it can be deleted immediately
-        //     - the client may need to be updated
-        //   - if multiple roles and there is a broken connection:
-        //     - delete after an amount of time with no activity - maybe 10 minutes?
-        self.rooms.remove(project_id);
-        if let Some(app) = &self.app_data {
-            // If it has no broken connections, delete it!
-            let query = doc! {"id": &project_id};
-            let cleanup = app
-                .project_metadata
-                .find_one(query.clone(), None)
-                .await
-                .map_err(InternalError::DatabaseConnectionError)?
-                .map(|md| match md.save_state {
-                    SaveState::Created => unreachable!(), // Cannot reach here since this is triggered when the last user leaves
-                    SaveState::Transient => ProjectCleanup::Immediately,
-                    SaveState::Broken => ProjectCleanup::Delayed,
-                    SaveState::Saved => ProjectCleanup::None,
-                })
-                .unwrap_or(ProjectCleanup::None);
-
-            match cleanup {
-                ProjectCleanup::Immediately => {
-                    let actions = app.as_project_actions();
-                    let system_auth = auth::try_manage_system(self);
-                    let dp =
-                        auth::DeleteProject::from_manage_system(&system_auth, project_id.clone());
-
-                    if let Err(err) = actions.delete_project(&dp).await {
-                        log::error!("Unable to delete project {}: {}", project_id, &err);
-                    }
-                }
-                ProjectCleanup::Delayed => {
-                    let ten_minutes = Duration::new(10 * 60, 0);
-                    let delete_at = SystemTime::now() + ten_minutes;
-                    let update = doc! {"$set": {
-                        "deleteAt": DateTime::from_system_time(delete_at)}
-                    };
-
-                    // FIXME: this should call delete_project since it:
-                    //   - can leave data on s3 if deleted by MongoDB
-                    //   - won't invalidate the cache
-                    // We need to remove the index from app data
-                    app.project_metadata
-                        .update_one(query, update, None)
-                        .await
-                        .map_err(InternalError::DatabaseConnectionError)?;
-                }
-                _ => {}
-            }
-        }
-        Ok(())
+    /// Send the state of the room to its occupants (on any node)
+    pub fn send_room_state(&self, msg: SendRoomState) {
+        self.queue(TopologyUpdate::RoomState(msg.project));
     }
 
-    async fn send_room_state_for(&mut self, project_id: &ProjectId) {
-        if let Some(app) = &self.app_data {
-            let query = doc! {"id": project_id};
-            if let Some(project) = app
-                .project_metadata
-                .find_one(query, None)
-                .await
-                .map_err(InternalError::DatabaseConnectionError)
-                .ok()
-                .flatten()
-            {
-                self.send_room_state(SendRoomState { project });
-            }
-        }
-    }
-
-    pub fn send_room_state(&mut self, msg: SendRoomState) {
-        // The room changed so the address cache may contain stale data
-        // (ie, the room or role may have been renamed - or the occupancy changed)
-        self.invalidate_cached_addresses(&msg.project);
-
-        if let Some(room) = self.rooms.get(&msg.project.id) {
+    fn deliver_room_state(&self, room_state: &RoomState) {
+        if let Some(room) = self.rooms.get(&room_state.id) {
             let clients = room
                 .roles
                 .values()
                 .flatten()
                 .filter_map(|id| self.clients.get(id));
 
-            let room_state = room.get_state(msg.project, &self.usernames);
             clients.for_each(|client| {
                 if let Err(err) = client.addr.do_send(room_state.clone().into()) {
                     log::error!("Unable to send room state to client: {}", err);
@@ -707,13 +764,27 @@ impl Topology {
         }
     }
 
+    /// Get the occupants of a room connected to this node
+    fn get_local_occupants(&self, project_id: &ProjectId) -> Vec<ClientPresence> {
+        self.rooms
+            .get(project_id)
+            .map(|room| {
+                room.roles
+                    .values()
+                    .flatten()
+                    .map(|id| self.get_local_presence(id))
+                    .collect()
+            })
+            .unwrap_or_default()
+    }
+
     fn reset_address_cache(&mut self) {
         self.address_cache = Arc::new(RwLock::new(LruCache::new(self.cache_size)));
     }
 
     /// Invalidate the cached addresses for the given project as it (or the
     // occupancy) has changed.
-    fn invalidate_cached_addresses(&mut self, project: &ProjectMetadata) {
+    pub(crate) fn invalidate_cached_addresses(&mut self, project_id: &ProjectId) {
         // reset the whole cache if mutex is poisoned
         if self.address_cache.is_poisoned() {
             self.reset_address_cache();
@@ -725,7 +796,7 @@ impl Topology {
                 .filter_map(|(client_addr, browser_addrs)| {
                     browser_addrs
                         .iter()
-                        .find(|addr| addr.project_id == project.id)
+                        .find(|addr| &addr.project_id == project_id)
                         .map(|_| client_addr.clone())
                 })
                 .collect();
@@ -767,40 +838,19 @@ impl Topology {
             .collect::<Vec<_>>()
     }
 
-    /// Get a list of online users from a list of usernames. If no usernames are provided,
-    /// all online users will be returned
-    pub fn get_online_users(&self, from_names: Option<Vec<String>>) -> Vec<String> {
-        let online = self.usernames.values().collect::<HashSet<_>>();
-        match from_names {
-            Some(usernames) => usernames
-                .into_iter()
-                .filter(|username| online.contains(&username))
-                .collect(),
-            None => online
-                .into_iter()
-                .map(|username| username.to_owned())
-                .collect(),
-        }
-    }
-
-    pub fn get_room_state(&self, metadata: ProjectMetadata) -> Option<RoomState> {
-        self.rooms
-            .get(&metadata.id)
-            .map(|room| room.get_state(metadata, &self.usernames))
-    }
-
-    pub async fn evict_client(&mut self, id: ClientId) -> Option<ClientState> {
+    pub fn evict_client(&mut self, id: ClientId) -> Option<ClientState> {
         let username = self.usernames.remove(&id);
-        let state = self.reset_client_state(&id, None).await;
+        let state = self.reset_client_state(&id, None);
         self.clients
             .get(&id)
             .map(|client| client.addr.do_send(EvictionNotice.into()));
 
         if let Some(username) = username {
             if self.usernames.get(&id).is_none() {
-                self.usernames.insert(id, username);
+                self.usernames.insert(id.clone(), username);
             }
         }
+        self.update_presence(&id);
 
         state
     }
@@ -813,8 +863,8 @@ impl Topology {
         self.usernames.get(id)
     }
 
-    /// Get info about a client. Returns None if no client connected.
-    pub(crate) fn get_client_info(&self, id: &ClientId) -> Option<api::ClientInfo> {
+    /// Get info about a client connected to this node
+    fn get_local_client_info(&self, id: &ClientId) -> Option<api::ClientInfo> {
         self.has_client(id).then(|| api::ClientInfo {
             username: self.get_client_username(id).cloned(),
             state: self.get_client_state(id).cloned(),
@@ -822,22 +872,19 @@ impl Topology {
     }
 
     pub fn send_occupant_invite(&self, msg: SendOccupantInvite) {
-        let clients = self.usernames.iter().filter_map(|(client_id, username)| {
-            if username == &msg.invite.username {
-                self.clients.get(client_id)
-            } else {
-                None
-            }
-        });
-
-        clients.for_each(|client| {
-            if let Err(err) = client.addr.do_send(msg.clone().into()) {
-                warn!("Unable to send invite to client: {}", err);
-            }
-        });
+        let username = msg.invite.username.clone();
+        self.send_to_user(msg.into(), &username);
     }
 
     pub async fn send_msg_from_services(&self, msg: api::SendMessage) {
+        let event = TopologyEvent::ServicesMessage {
+            message: msg.clone(),
+        };
+        self.publish(event);
+        self.deliver_msg_from_services(msg).await;
+    }
+
+    async fn deliver_msg_from_services(&self, msg: api::SendMessage) {
         let recipients = match msg.target {
             api::SendMessageTarget::Address { address } => {
                 if let Ok(address) = ClientAddress::from_str(&address) {
@@ -909,7 +956,17 @@ impl Topology {
         });
     }
 
+    /// Send a message to the clients of a user (on any node)
     pub fn send_to_user(&self, msg: Value, username: &str) {
+        self.deliver_to_user(msg.clone(), username);
+        let event = TopologyEvent::UserMessage {
+            username: username.to_owned(),
+            content: msg,
+        };
+        self.publish(event);
+    }
+
+    fn deliver_to_user(&self, msg: Value, username: &str) {
         let recipients = self
             .usernames
             .iter()
@@ -930,7 +987,17 @@ impl Topology {
         });
     }
 
+    /// Send a message to the occupants of a room (on any node)
     pub fn send_to_room(&self, msg: Value, id: &ProjectId) {
+        self.deliver_to_room(msg.clone(), id);
+        let event = TopologyEvent::RoomMessage {
+            project_id: id.to_owned(),
+            content: msg,
+        };
+        self.publish(event);
+    }
+
+    fn deliver_to_room(&self, msg: Value, id: &ProjectId) {
         let recipients = self
             .rooms
             .get(id)
@@ -955,6 +1022,225 @@ impl Topology {
     }
 }
 
+/// Get the occupants of a room from all nodes. If the other nodes cannot be
+/// reached, only the occupants on this node are returned.
+async fn get_room_occupants(
+    network: &TopologyLock,
+    backend: &dyn TopologyBackend,
+    project_id: &ProjectId,
+) -> Vec<ClientPresence> {
+    match backend.get_room_occupants(project_id).await {
+        Ok(occupants) => occupants,
+        Err(err) => {
+            warn!("Unable to get occupants of {}: {:?}", project_id, err);
+            network.read().await.get_local_occupants(project_id)
+        }
+    }
+}
+
+pub(crate) async fn get_room_state(
+    network: &TopologyLock,
+    backend: &dyn TopologyBackend,
+    metadata: ProjectMetadata,
+) -> Option<RoomState> {
+    let occupants = get_room_occupants(network, backend, &metadata.id).await;
+    (!occupants.is_empty()).then(|| build_room_state(metadata, occupants))
+}
+
+/// Get a list of online users from a list of usernames. If no usernames are provided,
+/// all online users will be returned
+pub(crate) async fn get_online_users(
+    network: &TopologyLock,
+    backend: &dyn TopologyBackend,
+    from_names: Option<Vec<String>>,
+) -> Vec<String> {
+    let online = match backend.get_online_users().await {
+        Ok(usernames) => usernames.into_iter().collect::<HashSet<_>>(),
+        Err(err) => {
+            warn!("Unable to get online users from all nodes: {:?}", err);
+            let topology = network.read().await;
+            topology.usernames.values().cloned().collect::<HashSet<_>>()
+        }
+    };
+    match from_names {
+        Some(usernames) => usernames
+            .into_iter()
+            .filter(|username| online.contains(username))
+            .collect(),
+        None => online.into_iter().collect(),
+    }
+}
+
+/// Get info about a client. Returns None if no client connected (to any node).
+pub(crate) async fn get_client_info(
+    network: &TopologyLock,
+    backend: &dyn TopologyBackend,
+    id: &ClientId,
+) -> Option<api::ClientInfo> {
+    if let Some(info) = network.read().await.get_local_client_info(id) {
+        return Some(info);
+    }
+
+    backend
+        .get_presence(id)
+        .await
+        .map_err(|err| warn!("Unable to get presence of {}: {:?}", id.as_str(), err))
+        .ok()
+        .flatten()
+        .map(|presence| api::ClientInfo {
+            username: presence.username,
+            state: presence.state,
+        })
+}
+
+/// Apply the queued updates (in order) without holding the lock on the
+/// topology while waiting on the backend or the database.
+pub(crate) async fn apply_updates(
+    network: Arc<TopologyLock>,
+    backend: Arc<dyn TopologyBackend>,
+    node: String,
+    mut updates: UnboundedReceiver<TopologyUpdate>,
+) {
+    while let Some(update) = updates.recv().await {
+        match update {
+            TopologyUpdate::Publish(message) => {
+                if let Err(err) = backend.publish(message).await {
+                    warn!("Unable to publish topology event: {:?}", err);
+                }
+            }
+            TopologyUpdate::SetPresence(presence) => {
+                let id = presence.id.clone();
+                if let Err(err) = backend.set_presence(presence).await {
+                    warn!("Unable to update presence of {}: {:?}", id.as_str(), err);
+                }
+            }
+            TopologyUpdate::RemovePresence(id) => {
+                if let Err(err) = backend.remove_presence(&id).await {
+                    warn!("Unable to update presence of {}: {:?}", id.as_str(), err);
+                }
+            }
+            TopologyUpdate::RemoveRoom(project_id) => {
+                if let Err(error) = remove_room(&network, &project_id).await {
+                    warn!("Unable to remove project {}: {:?}", &project_id, error);
+                }
+            }
+            TopologyUpdate::RoomChanged(project_id) => {
+                let app = network.read().await.app_data.clone();
+                if let Some(app) = app {
+                    let query = doc! {"id": &project_id};
+                    if let Some(project) = app
+                        .project_metadata
+                        .find_one(query, None)
+                        .await
+                        .map_err(InternalError::DatabaseConnectionError)
+                        .ok()
+                        .flatten()
+                    {
+                        send_room_state(&network, backend.as_ref(), &node, project).await;
+                    }
+                }
+            }
+            TopologyUpdate::RoomState(project) => {
+                send_room_state(&network, backend.as_ref(), &node, project).await;
+            }
+        }
+    }
+}
+
+async fn send_room_state(
+    network: &TopologyLock,
+    backend: &dyn TopologyBackend,
+    node: &str,
+    project: ProjectMetadata,
+) {
+    // The room changed so the address cache may contain stale data
+    // (ie, the room or role may have been renamed - or the occupancy changed)
+    network
+        .write()
+        .await
+        .invalidate_cached_addresses(&project.id);
+
+    let occupants = get_room_occupants(network, backend, &project.id).await;
+    if occupants.is_empty() {
+        return;
+    }
+
+    let is_shared = occupants.iter().any(|client| client.node != node);
+    let room_state = build_room_state(project, occupants);
+    network.read().await.deliver_room_state(&room_state);
+
+    if is_shared {
+        let message = TopologyMessage {
+            origin: node.to_owned(),
+            event: TopologyEvent::RoomState { state: room_state },
+        };
+        if let Err(err) = backend.publish(message).await {
+            warn!("Unable to publish topology event: {:?}", err);
+        }
+    }
+}
+
+async fn remove_room(network: &TopologyLock, project_id: &ProjectId) -> Result<(), InternalError> {
+    // Set the entry to be removed. After how long?
+    //   - If the room has only one role, it can be deleted immediately
+    //     - the client may need to be updated
+    //   - if multiple roles and there is a broken connection:
+    //     - delete after an amount of time with no activity - maybe 10 minutes?
+    let (app, system_auth) = {
+        let topology = network.read().await;
+        (
+            topology.app_data.clone(),
+            auth::try_manage_system(&topology),
+        )
+    };
+
+    if let Some(app) = app {
+        // If it has no broken connections, delete it!
+        let query = doc! {"id": &project_id};
+        let cleanup = app
+            .project_metadata
+            .find_one(query.clone(), None)
+            .await
+            .map_err(InternalError::DatabaseConnectionError)?
+            .map(|md| match md.save_state {
+                SaveState::Created => unreachable!(), // Cannot reach here since this is triggered when the last user leaves
+                SaveState::Transient => ProjectCleanup::Immediately,
+                SaveState::Broken => ProjectCleanup::Delayed,
+                SaveState::Saved => ProjectCleanup::None,
+            })
+            .unwrap_or(ProjectCleanup::None);
+
+        match cleanup {
+            ProjectCleanup::Immediately => {
+                let actions = app.as_project_actions();
+                let dp = auth::DeleteProject::from_manage_system(&system_auth, project_id.clone());
+
+                if let Err(err) = actions.delete_project(&dp).await {
+                    log::error!("Unable to delete project {}: {}", project_id, &err);
+                }
+            }
+            ProjectCleanup::Delayed => {
+                let ten_minutes = Duration::new(10 * 60, 0);
+                let delete_at = SystemTime::now() + ten_minutes;
+                let update = doc! {"$set": {
+                    "deleteAt": DateTime::from_system_time(delete_at)}
+                };
+
+                // FIXME: this should call delete_project since it:
+                //   - can leave data on s3 if deleted by MongoDB
+                //   - won't invalidate the cache
+                // We need to remove the index from app data
+                app.project_metadata
+                    .update_one(query, update, None)
+                    .await
+                    .map_err(InternalError::DatabaseConnectionError)?;
+            }
+            _ => {}
+        }
+    }
+    Ok(())
+}
+
 #[cfg(test)]
 mod tests {
     use std::num::NonZeroUsize;
@@ -1026,7 +1312,9 @@ mod tests {
         test_utils::setup()
             .with_users(&[owner, member.clone(), outsider.clone()])
             .run(|app_data| async move {
-                let topology = Topology::new(NonZeroUsize::new(10).unwrap());
+                let (updates, _rx) = tokio::sync::mpsc::unbounded_channel();
+                let topology =
+                    Topology::new(NonZeroUsize::new(10).unwrap(), "test".into(), updates);
                 // topology.set_app_data(app_data);
 
                 // TODO: mock the clients?
@@ -1,41 +1,74 @@
-use std::time::SystemTime;
+use std::{collections::HashMap, time::Duration};
 
 use futures::TryStreamExt;
+use jsonwebtoken::jwk::JwkSet;
 use mongodb::{bson::doc, options::ReturnDocument, Collection};
 use netsblox_cloud_common::{
     api::{self, oauth},
-    OAuthClient, OAuthToken,
+    password::{self, HashParams, Verification},
+    BannedAccount, OAuthClient, OAuthCode, OAuthRefreshToken, OAuthToken, User,
 };
 use passwords::PasswordGenerator;
+use reqwest::Url;
+use sha2::{Digest, Sha256};
 use uuid::Uuid;
 
 use crate::{
     auth,
+    config::OAuthSettings,
     errors::{InternalError, OAuthFlowError, UserError},
-    utils::sha512,
+    utils,
 };
 
 use super::{
     html_template,
-    routes::{AuthorizeClientParams, Scope},
+    oidc::{IdTokenClaims, ProviderMetadata, SigningKeys, UserInfo},
+    routes::AuthorizeClientParams,
 };
 
+const SECS_PER_DAY: u64 = 60 * 60 * 24;
+
 pub(crate) struct OAuthActions<'a> {
     clients: &'a Collection<OAuthClient>,
     tokens: &'a Collection<OAuthToken>,
-    codes: &'a Collection<oauth::Code>,
+    codes: &'a Collection<OAuthCode>,
+    refresh_tokens: &'a Collection<OAuthRefreshToken>,
+    users: &'a Collection<User>,
+    banned_accounts: &'a Collection<BannedAccount>,
+    signing_keys: &'a SigningKeys,
+    settings: &'a OAuthSettings,
+    hash_params: &'a HashParams,
+    public_url: &'a String,
+}
+
+/// A struct for passing data to the constructor of `OAuthActions` w/o having
+/// too many arguments
+pub(crate) struct OAuthActionData<'a> {
+    pub(crate) clients: &'a Collection<OAuthClient>,
+    pub(crate) tokens: &'a Collection<OAuthToken>,
+    pub(crate) codes: &'a Collection<OAuthCode>,
+    pub(crate) refresh_tokens: &'a Collection<OAuthRefreshToken>,
+    pub(crate) users: &'a Collection<User>,
+    pub(crate) banned_accounts: &'a Collection<BannedAccount>,
+    pub(crate) signing_keys: &'a SigningKeys,
+    pub(crate) settings: &'a OAuthSettings,
+    pub(crate) hash_params: &'a HashParams,
+    pub(crate) public_url: &'a String,
 }
 
 impl<'a> OAuthActions<'a> {
-    pub(crate) fn new(
-        clients: &'a Collection<OAuthClient>,
-        tokens: &'a Collection<OAuthToken>,
-        codes: &'a Collection<oauth::Code>,
-    ) -> Self {
+    pub(crate) fn new(data: OAuthActionData<'a>) -> Self {
         Self {
-            clients,
-            tokens,
-            codes,
+            clients: data.clients,
+            tokens: data.tokens,
+            codes: data.codes,
+            refresh_tokens: data.refresh_tokens,
+            users: data.users,
+            banned_accounts: data.banned_accounts,
+            signing_keys: data.signing_keys,
+            settings: data.settings,
+            hash_params: data.hash_params,
+            public_url: data.public_url,
         }
     }
 
@@ -43,6 +76,8 @@ impl<'a> OAuthActions<'a> {
         &self,
         eu: &auth::EditUser,
         client_id: &oauth::ClientId,
+        scope: Option<&str>,
+        csrf_token: &str,
     ) -> Result<String, UserError> {
         let query = doc! {"id": &client_id};
         let client = self
@@ -52,12 +87,12 @@ impl<'a> OAuthActions<'a> {
             .map_err(InternalError::DatabaseConnectionError)?
             .ok_or(UserError::OAuthClientNotFoundError)?;
 
-        // FIXME: the requestor should pass the scopes
-        let scopes = [Scope::ViewAlexaSkills, Scope::ExecuteBlocks];
+        let scopes = self.valid_scopes(&client, scope)?;
         Ok(html_template::authorize_page(
             &eu.username,
             &client.name,
             &scopes,
+            csrf_token,
         ))
     }
 
@@ -66,11 +101,21 @@ impl<'a> OAuthActions<'a> {
         eu: &auth::EditUser,
         params: &AuthorizeClientParams,
     ) -> Result<String, UserError> {
+        // Check that the client exists
+        let query = doc! {"id": &params.client_id};
+        let client = self
+            .clients
+            .find_one(query, None)
+            .await
+            .map_err(InternalError::DatabaseConnectionError)?
+            .ok_or(UserError::OAuthClientNotFoundError)?;
+
+        // Only redirect to URIs registered for the client (even for errors)
         let redirect_uri = params
             .redirect_uri
             .as_ref()
+            .filter(|uri| client.redirect_uris.contains(uri))
             .ok_or(OAuthFlowError::InvalidRedirectUrlError)?;
-        // TODO: Make sure the uri error is correct
 
         let url = if let Some(error) = &params.error {
             params
@@ -84,34 +129,31 @@ impl<'a> OAuthActions<'a> {
                 })
                 .unwrap_or_else(|| format!("{}?error={}", redirect_uri, error))
         } else {
-            // Check that the client exists
-            // FIXME: this needs to use the salt, too!
-            let hashed_secret = sha512(&params.client_secret);
-            let query = doc! {
-                "id": &params.client_id,
-                "hash": hashed_secret
-            };
+            // Confidential clients must authenticate. Public clients cannot keep a
+            // secret so they must use PKCE instead.
+            if !client.public {
+                let secret = params
+                    .client_secret
+                    .as_ref()
+                    .ok_or(OAuthFlowError::InvalidClientError)?;
 
-            let client_exists = self
-                .clients
-                .find_one(query, None)
-                .await
-                .map_err(InternalError::DatabaseConnectionError)?
-                .is_some();
-
-            // TODO: is incorrect secret a different error?
-            if !client_exists {
-                return Err(UserError::OAuthClientNotFoundError);
+                if !self.verify_client_secret(&client, secret).await? {
+                    return Err(UserError::OAuthClientNotFoundError);
+                }
             }
+            let code_challenge = code_challenge(&client, params)?;
 
-            // create a new code for the user
-            let code = oauth::Code {
-                id: oauth::CodeId::new(Uuid::new_v4().to_string()),
-                username: eu.username.clone(),
-                client_id: params.client_id.to_owned(),
-                redirect_uri: redirect_uri.to_owned(),
-                created_at: SystemTime::now(),
-            };
+            // create a new code for the user with the scopes they consented to
+            let scopes = self.valid_scopes(&client, params.scope.as_deref())?;
+            let code = OAuthCode::new(
+                eu.username.clone(),
+                client.id,
+                redirect_uri.to_owned(),
+                scopes,
+                code_challenge,
+                params.nonce.clone(),
+                Duration::from_secs(self.settings.code_lifetime_secs),
+            );
 
             self.codes
                 .insert_one(&code, None)
@@ -132,18 +174,28 @@ impl<'a> OAuthActions<'a> {
 
     pub(crate) async fn create_client(
         &self,
-        _cc: &auth::ManageClient,
-        name: &str,
+        cc: &auth::CreateClient,
+        data: &oauth::CreateClientData,
     ) -> Result<oauth::CreatedClientData, UserError> {
-        let query = doc! {"name": &name};
-        let secret = PasswordGenerator::new()
-            .length(12)
-            .spaces(false)
-            .exclude_similar_characters(true)
-            .generate_one()
-            .map_err(|_err| InternalError::PasswordGenerationError)?;
-
-        let client = OAuthClient::new(name.to_owned(), secret.clone());
+        ensure_valid_client_name(&data.name)?;
+        ensure_valid_redirect_uris(&data.redirect_uris)?;
+
+        let query = doc! {"name": &data.name};
+        let secret = if data.public {
+            None
+        } else {
+            Some(new_client_secret()?)
+        };
+
+        let client = OAuthClient::new(
+            data.name.to_owned(),
+            cc.owner.clone(),
+            secret.clone(),
+            data.allowed_scopes.clone(),
+            data.redirect_uris.clone(),
+            self.hash_params,
+        )
+        .map_err(InternalError::PasswordHashError)?;
         let client_id = client.id.clone();
 
         let update = doc! {"$setOnInsert": client};
@@ -169,12 +221,82 @@ impl<'a> OAuthActions<'a> {
         }
     }
 
+    pub(crate) async fn update_client(
+        &self,
+        mc: &auth::ManageClient,
+        data: oauth::UpdateClientData,
+    ) -> Result<oauth::Client, UserError> {
+        let mut update = doc! {};
+        if let Some(name) = data.name {
+            ensure_valid_client_name(&name)?;
+            // Client names are shown to users when authorizing so they must be unique
+            let query = doc! {"name": &name, "id": {"$ne": &mc.client_id}};
+            let existing = self
+                .clients
+                .find_one(query, None)
+                .await
+                .map_err(InternalError::DatabaseConnectionError)?;
+
+            if existing.is_some() {
+                return Err(UserError::OAuthClientAlreadyExistsError);
+            }
+            update.insert("name", name);
+        }
+
+        if let Some(scopes) = data.allowed_scopes {
+            update.insert("allowedScopes", scopes);
+        }
+
+        if let Some(redirect_uris) = data.redirect_uris {
+            ensure_valid_redirect_uris(&redirect_uris)?;
+            update.insert("redirectUris", redirect_uris);
+        }
+
+        if update.is_empty() {
+            return Err(UserError::OAuthClientUpdateFieldRequiredError);
+        }
+
+        let query = doc! {"id": &mc.client_id};
+        let update = doc! {"$set": update};
+        let options = mongodb::options::FindOneAndUpdateOptions::builder()
+            .return_document(ReturnDocument::After)
+            .build();
+
+        let client = self
+            .clients
+            .find_one_and_update(query, update, options)
+            .await
+            .map_err(InternalError::DatabaseConnectionError)?
+            .ok_or(UserError::OAuthClientNotFoundError)?;
+
+        Ok(client.into())
+    }
+
+    /// Replace the secret of a confidential client. The previous secret is no
+    /// longer valid once the new one is issued.
+    pub(crate) async fn rotate_client_secret(
+        &self,
+        mc: &auth::ManageClient,
+    ) -> Result<oauth::CreatedClientData, UserError> {
+        let client = self.get_client(&mc.client_id).await?;
+        if client.public {
+            return Err(UserError::PublicOAuthClientError);
+        }
+
+        let secret = new_client_secret()?;
+        self.update_client_secret(&client, &secret).await?;
+
+        Ok(oauth::CreatedClientData {
+            id: client.id,
+            secret: Some(secret),
+        })
+    }
+
     pub(crate) async fn delete_client(
         &self,
-        _cc: &auth::ManageClient,
-        client_id: &oauth::ClientId,
+        mc: &auth::ManageClient,
     ) -> Result<oauth::Client, UserError> {
-        let query = doc! {"id": client_id};
+        let query = doc! {"id": &mc.client_id};
         let client = self
             .clients
             .find_one_and_delete(query, None)
@@ -182,16 +304,66 @@ impl<'a> OAuthActions<'a> {
             .map_err(InternalError::DatabaseConnectionError)?
             .ok_or(UserError::OAuthClientNotFoundError)?;
 
+        self.revoke_client_grants(&client.id).await?;
+
         Ok(client.into())
     }
 
+    /// Delete the clients registered by a user whose account is being deleted
+    pub(crate) async fn delete_owned_clients(
+        &self,
+        du: &auth::DeleteUser,
+    ) -> Result<u32, UserError> {
+        let query = doc! {"owner": &du.username};
+        let clients: Vec<_> = self
+            .clients
+            .find(query, None)
+            .await
+            .map_err(InternalError::DatabaseConnectionError)?
+            .try_collect()
+            .await
+            .map_err(InternalError::DatabaseConnectionError)?;
+
+        for client in &clients {
+            let query = doc! {"id": &client.id};
+            self.clients
+                .delete_one(query, None)
+                .await
+                .map_err(InternalError::DatabaseConnectionError)?;
+
+            self.revoke_client_grants(&client.id).await?;
+        }
+
+        Ok(clients.len() as u32)
+    }
+
+    /// Revoke all tokens (and codes) issued to a client
+    async fn revoke_client_grants(&self, client_id: &oauth::ClientId) -> Result<(), UserError> {
+        let query = doc! {"clientId": client_id};
+        futures::try_join!(
+            self.tokens.delete_many(query.clone(), None),
+            self.refresh_tokens.delete_many(query.clone(), None),
+            self.codes.delete_many(query, None),
+        )
+        .map_err(InternalError::DatabaseConnectionError)?;
+
+        Ok(())
+    }
+
+    /// List the registered clients (only the user's own clients unless the
+    /// requestor can view all clients)
     pub(crate) async fn list_clients(
         &self,
-        _cc: &auth::ManageClient,
+        lc: &auth::ListOAuthClients,
     ) -> Result<Vec<oauth::Client>, UserError> {
+        let query = lc
+            .owner
+            .as_ref()
+            .map(|owner| doc! {"owner": owner})
+            .unwrap_or_default();
         let cursor = self
             .clients
-            .find(doc! {}, None)
+            .find(query, None)
             .await
             .map_err(InternalError::DatabaseConnectionError)?;
 
@@ -206,21 +378,183 @@ impl<'a> OAuthActions<'a> {
         Ok(clients)
     }
 
-    // There is no witness used here since it is covered by the auth code in the params
-    pub(crate) async fn create_token(
+    /// List the clients holding (unexpired) tokens for the user's account
+    pub(crate) async fn list_authorized_clients(
         &self,
-        params: api::oauth::CreateTokenParams,
-    ) -> Result<OAuthToken, UserError> {
-        let is_valid_grant = params
-            .grant_type
+        mc: &auth::ManageAuthorizedClients,
+    ) -> Result<Vec<oauth::AuthorizedClient>, UserError> {
+        let mut query = utils::not_expired();
+        query.insert("username", &mc.username);
+
+        let tokens: Vec<_> = self
+            .tokens
+            .find(query.clone(), None)
+            .await
+            .map_err(InternalError::DatabaseConnectionError)?
+            .try_collect()
+            .await
+            .map_err(InternalError::DatabaseConnectionError)?;
+
+        let refresh_tokens: Vec<_> = self
+            .refresh_tokens
+            .find(query, None)
+            .await
+            .map_err(InternalError::DatabaseConnectionError)?
+            .try_collect()
+            .await
+            .map_err(InternalError::DatabaseConnectionError)?;
+
+        let grants = tokens
+            .into_iter()
+            .map(|token| (token.client_id, token.scopes, token.created_at, 1))
+            .chain(
+                refresh_tokens
+                    .into_iter()
+                    .map(|token| (token.client_id, token.scopes, token.created_at, 0)),
+            );
+
+        let mut authorized: HashMap<oauth::ClientId, oauth::AuthorizedClient> = HashMap::new();
+        for (client_id, scopes, created_at, token_count) in grants {
+            let created_at = created_at.to_system_time();
+            let client =
+                authorized
+                    .entry(client_id.clone())
+                    .or_insert_with(|| oauth::AuthorizedClient {
+                        id: client_id,
+                        name: String::new(),
+                        scopes: Vec::new(),
+                        token_count: 0,
+                        last_authorized_at: created_at,
+                    });
+
+            client.token_count += token_count;
+            client.last_authorized_at = client.last_authorized_at.max(created_at);
+            for scope in scopes {
+                if !client.scopes.contains(&scope) {
+                    client.scopes.push(scope);
+                }
+            }
+        }
+
+        let ids: Vec<_> = authorized.keys().cloned().collect();
+        let query = doc! {"id": {"$in": ids}};
+        let clients: Vec<_> = self
+            .clients
+            .find(query, None)
+            .await
+            .map_err(InternalError::DatabaseConnectionError)?
+            .try_collect()
+            .await
+            .map_err(InternalError::DatabaseConnectionError)?;
+
+        // Tokens for clients which have since been deleted are not listed
+        let mut authorized_clients: Vec<_> = clients
+            .into_iter()
+            .filter_map(|client| {
+                authorized.remove(&client.id).map(|mut authorized| {
+                    authorized.name = client.name;
+                    authorized
+                })
+            })
+            .collect();
+        authorized_clients.sort_by_key(|client| std::cmp::Reverse(client.last_authorized_at));
+
+        Ok(authorized_clients)
+    }
+
+    /// Revoke all the tokens (and codes) issued to the client for the user's account
+    pub(crate) async fn revoke_authorized_client(
+        &self,
+        mc: &auth::ManageAuthorizedClients,
+        client_id: &oauth::ClientId,
+    ) -> Result<(), UserError> {
+        let query = doc! {"username": &mc.username, "clientId": client_id};
+        let results = futures::try_join!(
+            self.tokens.delete_many(query.clone(), None),
+            self.refresh_tokens.delete_many(query.clone(), None),
+            self.codes.delete_many(query, None),
+        )
+        .map_err(InternalError::DatabaseConnectionError)?;
+
+        let count = results.0.deleted_count + results.1.deleted_count + results.2.deleted_count;
+        if count == 0 {
+            return Err(UserError::OAuthTokenNotFoundError);
+        }
+
+        Ok(())
+    }
+
+    /// Revoke an access or refresh token (RFC 7009). Revoking either one also revokes
+    /// the other token issued with it. Unknown tokens (or tokens issued to another
+    /// client) are ignored as required by the RFC.
+    // There is no witness used here since the client authenticates in the params
+    pub(crate) async fn revoke_token(
+        &self,
+        params: api::oauth::RevokeTokenParams,
+    ) -> Result<(), UserError> {
+        let client_id = params
+            .client_id
             .as_ref()
-            .map(|grant_type| grant_type == "authorization_code")
-            .unwrap_or(false);
+            .ok_or(OAuthFlowError::InvalidClientError)?;
+        let client = self.get_client(client_id).await?;
+        if !client.public {
+            let secret = params
+                .client_secret
+                .as_ref()
+                .ok_or(OAuthFlowError::InvalidClientError)?;
+            self.authenticate_client(&client, secret).await?;
+        }
+
+        let query = doc! {"tokenHash": utils::sha512(&params.token), "clientId": &client.id};
+        let refresh_token = self
+            .refresh_tokens
+            .find_one_and_delete(query, None)
+            .await
+            .map_err(InternalError::DatabaseConnectionError)?;
+
+        if let Some(refresh_token) = refresh_token {
+            let query = doc! {"id": refresh_token.token_id};
+            self.tokens
+                .delete_one(query, None)
+                .await
+                .map_err(InternalError::DatabaseConnectionError)?;
+        } else {
+            let query = doc! {"id": &params.token, "clientId": &client.id};
+            let token = self
+                .tokens
+                .find_one_and_delete(query, None)
+                .await
+                .map_err(InternalError::DatabaseConnectionError)?;
 
-        if !is_valid_grant {
-            return Err(OAuthFlowError::InvalidGrantTypeError.into());
+            if let Some(token) = token {
+                let query = doc! {"tokenId": token.id};
+                self.refresh_tokens
+                    .delete_many(query, None)
+                    .await
+                    .map_err(InternalError::DatabaseConnectionError)?;
+            }
         }
 
+        Ok(())
+    }
+
+    // There is no witness used here since it is covered by the auth code (or
+    // refresh token) in the params
+    pub(crate) async fn create_token(
+        &self,
+        params: api::oauth::CreateTokenParams,
+    ) -> Result<oauth::TokenResponse, UserError> {
+        match params.grant_type.as_deref() {
+            Some("authorization_code") => self.exchange_code(params).await,
+            Some("refresh_token") => self.refresh_token(params).await,
+            _ => Err(OAuthFlowError::InvalidGrantTypeError.into()),
+        }
+    }
+
+    async fn exchange_code(
+        &self,
+        params: api::oauth::CreateTokenParams,
+    ) -> Result<oauth::TokenResponse, UserError> {
         let code_id = params
             .code
             .as_ref()
@@ -233,21 +567,1337 @@ impl<'a> OAuthActions<'a> {
         let query = doc! {"id": &code_id};
         let code = self
             .codes
-            .find_one(query, None)
+            .find_one(query.clone(), None)
             .await
             .map_err(InternalError::DatabaseConnectionError)?
+            .filter(|code| !code.is_expired())
             .ok_or(OAuthFlowError::InvalidAuthorizationCodeError)?;
 
         if redirect_uri != &code.redirect_uri {
             return Err(OAuthFlowError::InvalidRedirectUrlError.into());
         }
 
-        let token = OAuthToken::new(code.client_id, code.username);
+        let is_other_client = params
+            .client_id
+            .as_ref()
+            .map(|id| id != &code.client_id)
+            .unwrap_or(false);
+        if is_other_client {
+            return Err(OAuthFlowError::InvalidAuthorizationCodeError.into());
+        }
+
+        // Confidential clients must authenticate here, too, so a leaked code can't
+        // be exchanged without the client secret. The client is authenticated (and
+        // the code verifier checked) before the code is used so failed attempts
+        // can't revoke it.
+        let client = self.get_client(&code.client_id).await?;
+        if !client.public {
+            let secret = params
+                .client_secret
+                .as_ref()
+                .ok_or(OAuthFlowError::InvalidClientError)?;
+            self.authenticate_client(&client, secret).await?;
+        }
+
+        self.ensure_not_banned(&code.username).await?;
+
+        if let Some(challenge) = &code.code_challenge {
+            let verifier = params
+                .code_verifier
+                .as_ref()
+                .ok_or(OAuthFlowError::InvalidCodeVerifierError)?;
+
+            if !is_valid_code_verifier(challenge, verifier) {
+                return Err(OAuthFlowError::InvalidCodeVerifierError.into());
+            }
+        }
+
+        // Codes are removed when exchanged so they can only be used once
+        let code = self
+            .codes
+            .find_one_and_delete(query, None)
+            .await
+            .map_err(InternalError::DatabaseConnectionError)?
+            .ok_or(OAuthFlowError::InvalidAuthorizationCodeError)?;
+
+        let ttl = Duration::from_secs(self.settings.access_token_lifetime_secs);
+        let token = OAuthToken::new(code.client_id, code.username, code.scopes, ttl);
+        self.issue_tokens(token, code.nonce).await
+    }
+
+    async fn refresh_token(
+        &self,
+        params: api::oauth::CreateTokenParams,
+    ) -> Result<oauth::TokenResponse, UserError> {
+        let secret = params
+            .refresh_token
+            .as_ref()
+            .ok_or(OAuthFlowError::NoRefreshTokenError)?;
+
+        let query = doc! {"tokenHash": utils::sha512(secret)};
+        let refresh_token = self
+            .refresh_tokens
+            .find_one(query.clone(), None)
+            .await
+            .map_err(InternalError::DatabaseConnectionError)?
+            .filter(|token| !token.is_expired())
+            .ok_or(OAuthFlowError::InvalidRefreshTokenError)?;
+
+        let is_other_client = params
+            .client_id
+            .as_ref()
+            .map(|id| id != &refresh_token.client_id)
+            .unwrap_or(false);
+        if is_other_client {
+            return Err(OAuthFlowError::InvalidRefreshTokenError.into());
+        }
+
+        // The client is authenticated before the refresh token is used so failed
+        // attempts can't revoke it
+        let client = self.get_client(&refresh_token.client_id).await?;
+        if !client.public {
+            let secret = params
+                .client_secret
+                .as_ref()
+                .ok_or(OAuthFlowError::InvalidClientError)?;
+            self.authenticate_client(&client, secret).await?;
+        }
+
+        self.ensure_not_banned(&refresh_token.username).await?;
+
+        // Refresh tokens are rotated so each one can only be used once
+        let refresh_token = self
+            .refresh_tokens
+            .find_one_and_delete(query, None)
+            .await
+            .map_err(InternalError::DatabaseConnectionError)?
+            .ok_or(OAuthFlowError::InvalidRefreshTokenError)?;
+
+        // The scopes can be narrowed (but not extended) when refreshing
+        let scopes = match params.scope.as_deref() {
+            Some(scope) if !scope.trim().is_empty() => {
+                let scopes = oauth::Scope::parse_list(scope)
+                    .map_err(|_err| OAuthFlowError::InvalidScopeError)?;
+
+                let is_granted = scopes
+                    .iter()
+                    .all(|scope| refresh_token.scopes.contains(scope));
+                if !is_granted {
+                    return Err(OAuthFlowError::InvalidScopeError.into());
+                }
+                scopes
+            }
+            _ => refresh_token.scopes,
+        };
+
+        // The access token issued with the refresh token is replaced by the new one
+        let query = doc! {"id": &refresh_token.token_id};
+        self.tokens
+            .delete_one(query, None)
+            .await
+            .map_err(InternalError::DatabaseConnectionError)?;
+
+        let ttl = Duration::from_secs(self.settings.access_token_lifetime_secs);
+        let token = OAuthToken::new(refresh_token.client_id, refresh_token.username, scopes, ttl);
+        self.issue_tokens(token, None).await
+    }
+
+    /// Store the access token along with a new refresh token (and create an ID
+    /// token for OpenID Connect requests)
+    async fn issue_tokens(
+        &self,
+        token: OAuthToken,
+        nonce: Option<String>,
+    ) -> Result<oauth::TokenResponse, UserError> {
+        let id_token = if token.scopes.contains(&oauth::Scope::OpenId) {
+            Some(self.id_token(&token, nonce).await?)
+        } else {
+            None
+        };
+
+        let secret = format!(
+            "{}{}",
+            Uuid::new_v4().as_simple(),
+            Uuid::new_v4().as_simple()
+        );
+        let ttl = Duration::from_secs(self.settings.refresh_token_lifetime_days * SECS_PER_DAY);
+        let refresh_token = OAuthRefreshToken::new(utils::sha512(&secret), &token, ttl);
+
         self.tokens
             .insert_one(&token, None)
             .await
             .map_err(InternalError::DatabaseConnectionError)?;
+        self.refresh_tokens
+            .insert_one(&refresh_token, None)
+            .await
+            .map_err(InternalError::DatabaseConnectionError)?;
 
-        Ok(token)
+        Ok(oauth::TokenResponse {
+            scope: oauth::Scope::format_list(&token.scopes),
+            access_token: token.id,
+            token_type: "Bearer".into(),
+            expires_in: self.settings.access_token_lifetime_secs,
+            refresh_token: secret,
+            id_token,
+        })
+    }
+
+    async fn id_token(
+        &self,
+        token: &OAuthToken,
+        nonce: Option<String>,
+    ) -> Result<String, UserError> {
+        let now = jsonwebtoken::get_current_timestamp();
+        let claims = IdTokenClaims {
+            iss: self.issuer(),
+            aud: token.client_id.as_str().to_owned(),
+            exp: now + self.settings.access_token_lifetime_secs,
+            iat: now,
+            nonce,
+            user_info: self.user_info(token).await?,
+        };
+
+        Ok(self.signing_keys.sign(&claims)?)
+    }
+
+    /// Get the claims about the user for an access token with the `openid` scope.
+    // There is no witness used here since the access token is the credential
+    pub(crate) async fn get_user_info(
+        &self,
+        token_id: &oauth::TokenId,
+    ) -> Result<UserInfo, UserError> {
+        let mut query = utils::not_expired();
+        query.insert("id", token_id);
+        let token = self
+            .tokens
+            .find_one(query, None)
+            .await
+            .map_err(InternalError::DatabaseConnectionError)?
+            .ok_or(UserError::InvalidAccessTokenError)?;
+
+        if !token.scopes.contains(&oauth::Scope::OpenId) {
+            return Err(UserError::InsufficientTokenScopeError);
+        }
+
+        self.ensure_not_banned(&token.username).await?;
+        self.user_info(&token).await
+    }
+
+    pub(crate) fn provider_metadata(&self) -> ProviderMetadata {
+        ProviderMetadata::new(&self.issuer(), self.signing_keys)
+    }
+
+    pub(crate) fn jwks(&self) -> JwkSet {
+        self.signing_keys.jwks()
+    }
+
+    async fn user_info(&self, token: &OAuthToken) -> Result<UserInfo, UserError> {
+        let query = doc! {"username": &token.username};
+        let user = self
+            .users
+            .find_one(query, None)
+            .await
+            .map_err(InternalError::DatabaseConnectionError)?
+            .ok_or(UserError::UserNotFoundError)?;
+
+        // The email address is only shared if the user consented to it
+        let (email, email_verified) = if token.scopes.contains(&oauth::Scope::Email) {
+            (Some(user.email), Some(user.verified))
+        } else {
+            (None, None)
+        };
+
+        let sub = user.id.ok_or(UserError::InternalError)?.to_hex();
+        Ok(UserInfo {
+            sub,
+            preferred_username: user.username,
+            email,
+            email_verified,
+        })
+    }
+
+    fn issuer(&self) -> String {
+        self.public_url.trim_end_matches('/').to_owned()
+    }
+
+    /// Check the requested scopes. The `openid` scope is only available if ID
+    /// tokens can be signed.
+    fn valid_scopes(
+        &self,
+        client: &OAuthClient,
+        scope: Option<&str>,
+    ) -> Result<Vec<oauth::Scope>, UserError> {
+        let scopes = requested_scopes(client, scope)?;
+        if scopes.contains(&oauth::Scope::OpenId) && !self.signing_keys.is_enabled() {
+            return Err(OAuthFlowError::InvalidScopeError.into());
+        }
+
+        Ok(scopes)
+    }
+
+    async fn get_client(&self, client_id: &oauth::ClientId) -> Result<OAuthClient, UserError> {
+        let query = doc! {"id": client_id};
+        let client = self
+            .clients
+            .find_one(query, None)
+            .await
+            .map_err(InternalError::DatabaseConnectionError)?
+            .ok_or(OAuthFlowError::InvalidClientError)?;
+
+        Ok(client)
+    }
+
+    async fn ensure_not_banned(&self, username: &str) -> Result<(), UserError> {
+        let query = doc! {"$and": [
+            {"username": username},
+            utils::not_expired(),
+        ]};
+        if self
+            .banned_accounts
+            .find_one(query, None)
+            .await
+            .map_err(InternalError::DatabaseConnectionError)?
+            .is_some()
+        {
+            return Err(UserError::BannedUserError);
+        }
+
+        Ok(())
+    }
+
+    /// Authenticate a client at the token endpoint
+    async fn authenticate_client(
+        &self,
+        client: &OAuthClient,
+        secret: &str,
+    ) -> Result<(), UserError> {
+        if self.verify_client_secret(client, secret).await? {
+            Ok(())
+        } else {
+            Err(OAuthFlowError::InvalidClientError.into())
+        }
+    }
+
+    async fn verify_client_secret(
+        &self,
+        client: &OAuthClient,
+        secret: &str,
+    ) -> Result<bool, UserError> {
+        match client.verify_secret(secret, self.hash_params) {
+            Verification::Invalid => Ok(false),
+            Verification::Valid { needs_rehash } => {
+                if needs_rehash {
+                    self.update_client_secret(client, secret).await?;
+                }
+                Ok(true)
+            }
+        }
+    }
+
+    /// Store the hash of a new client secret. This is also used to replace legacy
+    /// (or outdated) hashes after a successful check.
+    async fn update_client_secret(
+        &self,
+        client: &OAuthClient,
+        secret: &str,
+    ) -> Result<(), UserError> {
+        let hash =
+            password::hash(secret, self.hash_params).map_err(InternalError::PasswordHashError)?;
+        let query = doc! {"id": &client.id};
+        let update = doc! {"$set": {"hash": hash, "salt": null}};
+        self.clients
+            .update_one(query, update, None)
+            .await
+            .map_err(InternalError::DatabaseConnectionError)?;
+
+        Ok(())
+    }
+}
+
+fn new_client_secret() -> Result<String, InternalError> {
+    PasswordGenerator::new()
+        .length(12)
+        .spaces(false)
+        .exclude_similar_characters(true)
+        .generate_one()
+        .map_err(|_err| InternalError::PasswordGenerationError)
+}
+
+/// Client names are shown to users on the consent page so they cannot contain
+/// markup or control characters.
+fn ensure_valid_client_name(name: &str) -> Result<(), UserError> {
+    let is_valid = !name.trim().is_empty()
+        && name.chars().count() <= 50
+        && !name
+            .chars()
+            .any(|c| c.is_control() || matches!(c, '<' | '>' | '&' | '"' | '\''));
+
+    if is_valid {
+        Ok(())
+    } else {
+        Err(UserError::InvalidOAuthClientNameError)
+    }
+}
+
+/// Redirect URIs must be absolute without a fragment (RFC 6749 section 3.1.2).
+/// Only https is allowed except for loopback addresses and private-use schemes
+/// for native apps (eg, "org.example.app:/callback" as in RFC 8252).
+fn ensure_valid_redirect_uris(uris: &[String]) -> Result<(), UserError> {
+    let is_valid = |uri: &String| {
+        let url = match Url::parse(uri) {
+            Ok(url) => url,
+            Err(_) => return false,
+        };
+
+        if url.fragment().is_some() {
+            return false;
+        }
+
+        match url.scheme() {
+            "https" => url.host().is_some(),
+            "http" => matches!(
+                url.host_str(),
+                Some("localhost") | Some("127.0.0.1") | Some("[::1]")
+            ),
+            scheme => scheme.contains('.'),
+        }
+    };
+
+    if uris.iter().all(is_valid) {
+        Ok(())
+    } else {
+        Err(UserError::InvalidRedirectUriError)
+    }
+}
+
+/// Get the PKCE code challenge for the authorization request. Only S256 is supported
+/// and public clients are required to use PKCE.
+fn code_challenge(
+    client: &OAuthClient,
+    params: &AuthorizeClientParams,
+) -> Result<Option<String>, UserError> {
+    match (
+        &params.code_challenge,
+        params.code_challenge_method.as_deref(),
+    ) {
+        (Some(challenge), Some("S256")) => Ok(Some(challenge.to_owned())),
+        (Some(_), _) => Err(OAuthFlowError::UnsupportedCodeChallengeMethodError.into()),
+        (None, _) if client.public => Err(OAuthFlowError::CodeChallengeRequiredError.into()),
+        (None, _) => Ok(None),
+    }
+}
+
+fn is_valid_code_verifier(challenge: &str, verifier: &str) -> bool {
+    let hash = Sha256::digest(verifier.as_bytes());
+    base64::encode_config(hash, base64::URL_SAFE_NO_PAD) == challenge
+}
+
+/// Get the scopes requested using the (space-delimited) `scope` parameter. If no
+/// scopes are requested, the client is granted all the scopes it is allowed.
+fn requested_scopes(
+    client: &OAuthClient,
+    scope: Option<&str>,
+) -> Result<Vec<oauth::Scope>, UserError> {
+    let scopes = match scope {
+        Some(scope) if !scope.trim().is_empty() => {
+            oauth::Scope::parse_list(scope).map_err(|_err| OAuthFlowError::InvalidScopeError)?
+        }
+        _ => client.allowed_scopes.clone(),
+    };
+
+    let is_allowed = scopes
+        .iter()
+        .all(|scope| client.allowed_scopes.contains(scope));
+
+    if is_allowed {
+        Ok(scopes)
+    } else {
+        Err(OAuthFlowError::InvalidScopeError.into())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_utils;
+
+    fn test_user() -> User {
+        api::NewUser {
+            username: "user".into(),
+            email: "user@netsblox.org".into(),
+            password: None,
+            group_id: None,
+            role: None,
+        }
+        .into()
+    }
+
+    fn authorize_params(client_id: oauth::ClientId) -> AuthorizeClientParams {
+        AuthorizeClientParams {
+            client_id,
+            client_secret: None,
+            redirect_uri: Some("http://localhost:8000".into()),
+            code_challenge: None,
+            code_challenge_method: None,
+            nonce: None,
+            error: None,
+            error_description: None,
+            scope: None,
+            state: "someState".into(),
+        }
+    }
+
+    fn code_params(url: &str) -> api::oauth::CreateTokenParams {
+        let code = url
+            .split("code=")
+            .nth(1)
+            .unwrap()
+            .split('&')
+            .next()
+            .unwrap();
+        api::oauth::CreateTokenParams {
+            grant_type: Some("authorization_code".into()),
+            code: Some(code.to_owned()),
+            redirect_uri: Some("http://localhost:8000".into()),
+            client_secret: Some("secret".into()),
+            ..Default::default()
+        }
+    }
+
+    #[actix_web::test]
+    async fn test_authorize_scopes() {
+        let user = test_user();
+
+        test_utils::setup()
+            .with_users(&[user.clone()])
+            .run(|app_data| async move {
+                let params = &app_data.settings.security.password_hashing;
+                let allowed_scopes = vec![oauth::Scope::ReadUser, oauth::Scope::ReadProjects];
+                let client = OAuthClient::new(
+                    "client".into(),
+                    None,
+                    Some("secret".into()),
+                    allowed_scopes,
+                    vec!["http://localhost:8000".into()],
+                    params,
+                )
+                .unwrap();
+                let client_id = client.id.clone();
+                app_data
+                    .oauth_clients
+                    .insert_one(client, None)
+                    .await
+                    .unwrap();
+
+                let actions = app_data.as_oauth_actions();
+                let eu = auth::EditUser::test(user.username.clone());
+                let params = AuthorizeClientParams {
+                    client_secret: Some("secret".into()),
+                    scope: Some("user:read".into()),
+                    ..authorize_params(client_id)
+                };
+                let url = actions.authorize(&eu, &params).await.unwrap();
+
+                let token = actions.create_token(code_params(&url)).await.unwrap();
+                assert_eq!(token.scope, "user:read");
+                assert_eq!(token.token_type, "Bearer");
+
+                let query = doc! {"id": &token.access_token};
+                let token = app_data
+                    .oauth_tokens
+                    .find_one(query, None)
+                    .await
+                    .unwrap()
+                    .unwrap();
+                assert_eq!(token.scopes, vec![oauth::Scope::ReadUser]);
+                assert!(token.expires_at.is_some());
+            })
+            .await;
+    }
+
+    #[actix_web::test]
+    async fn test_authorize_scope_not_allowed() {
+        let user = test_user();
+
+        test_utils::setup()
+            .with_users(&[user.clone()])
+            .run(|app_data| async move {
+                let params = &app_data.settings.security.password_hashing;
+                let allowed_scopes = vec![oauth::Scope::ReadUser];
+                let client = OAuthClient::new(
+                    "client".into(),
+                    None,
+                    Some("secret".into()),
+                    allowed_scopes,
+                    vec!["http://localhost:8000".into()],
+                    params,
+                )
+                .unwrap();
+                let client_id = client.id.clone();
+                app_data
+                    .oauth_clients
+                    .insert_one(client, None)
+                    .await
+                    .unwrap();
+
+                let actions = app_data.as_oauth_actions();
+                let eu = auth::EditUser::test(user.username.clone());
+                let result = actions
+                    .render_auth_page(&eu, &client_id, Some("user:read projects:write"), "token")
+                    .await;
+
+                assert!(matches!(
+                    result,
+                    Err(UserError::OAuthFlowError(OAuthFlowError::InvalidScopeError))
+                ));
+            })
+            .await;
+    }
+
+    #[actix_web::test]
+    async fn test_public_client_pkce() {
+        let user = test_user();
+
+        test_utils::setup()
+            .with_users(&[user.clone()])
+            .run(|app_data| async move {
+                let params = &app_data.settings.security.password_hashing;
+                let client = OAuthClient::new(
+                    "app".into(),
+                    None,
+                    None,
+                    oauth::Scope::legacy(),
+                    vec!["http://localhost:8000".into()],
+                    params,
+                )
+                .unwrap();
+                let client_id = client.id.clone();
+                app_data
+                    .oauth_clients
+                    .insert_one(client, None)
+                    .await
+                    .unwrap();
+
+                let actions = app_data.as_oauth_actions();
+                let eu = auth::EditUser::test(user.username.clone());
+
+                // PKCE is required for public clients
+                let result = actions
+                    .authorize(&eu, &authorize_params(client_id.clone()))
+                    .await;
+                assert!(matches!(
+                    result,
+                    Err(UserError::OAuthFlowError(
+                        OAuthFlowError::CodeChallengeRequiredError
+                    ))
+                ));
+
+                let verifier = "someVerifierWhichIsLongEnoughToBeValidForPKCE";
+                let challenge = base64::encode_config(
+                    Sha256::digest(verifier.as_bytes()),
+                    base64::URL_SAFE_NO_PAD,
+                );
+                let params = AuthorizeClientParams {
+                    code_challenge: Some(challenge),
+                    code_challenge_method: Some("S256".into()),
+                    ..authorize_params(client_id)
+                };
+                let url = actions.authorize(&eu, &params).await.unwrap();
+
+                let params = api::oauth::CreateTokenParams {
+                    code_verifier: Some("incorrectVerifier".into()),
+                    client_secret: None,
+                    ..code_params(&url)
+                };
+                let result = actions.create_token(params).await;
+                assert!(matches!(
+                    result,
+                    Err(UserError::OAuthFlowError(
+                        OAuthFlowError::InvalidCodeVerifierError
+                    ))
+                ));
+
+                // failed exchanges don't use the code
+                let params = api::oauth::CreateTokenParams {
+                    code_verifier: Some(verifier.into()),
+                    client_secret: None,
+                    ..code_params(&url)
+                };
+                actions.create_token(params.clone()).await.unwrap();
+
+                // codes can only be used once
+                let result = actions.create_token(params).await;
+                assert!(matches!(
+                    result,
+                    Err(UserError::OAuthFlowError(
+                        OAuthFlowError::InvalidAuthorizationCodeError
+                    ))
+                ));
+            })
+            .await;
+    }
+
+    #[actix_web::test]
+    async fn test_refresh_token_rotation() {
+        let user = test_user();
+
+        test_utils::setup()
+            .with_users(&[user.clone()])
+            .run(|app_data| async move {
+                let params = &app_data.settings.security.password_hashing;
+                let allowed_scopes = vec![oauth::Scope::ReadUser, oauth::Scope::ReadProjects];
+                let client = OAuthClient::new(
+                    "client".into(),
+                    None,
+                    Some("secret".into()),
+                    allowed_scopes,
+                    vec!["http://localhost:8000".into()],
+                    params,
+                )
+                .unwrap();
+                let client_id = client.id.clone();
+                app_data
+                    .oauth_clients
+                    .insert_one(client, None)
+                    .await
+                    .unwrap();
+
+                let actions = app_data.as_oauth_actions();
+                let eu = auth::EditUser::test(user.username.clone());
+                let params = AuthorizeClientParams {
+                    client_secret: Some("secret".into()),
+                    ..authorize_params(client_id.clone())
+                };
+                let url = actions.authorize(&eu, &params).await.unwrap();
+                let token = actions.create_token(code_params(&url)).await.unwrap();
+
+                let params = api::oauth::CreateTokenParams {
+                    grant_type: Some("refresh_token".into()),
+                    refresh_token: Some(token.refresh_token.clone()),
+                    scope: Some("projects:read".into()),
+                    client_id: Some(client_id.clone()),
+                    client_secret: Some("secret".into()),
+                    ..Default::default()
+                };
+                let refreshed = actions.create_token(params.clone()).await.unwrap();
+                assert_eq!(refreshed.scope, "projects:read");
+                assert_ne!(refreshed.refresh_token, token.refresh_token);
+
+                // the previous access token is revoked
+                let query = doc! {"id": &token.access_token};
+                let old_token = app_data.oauth_tokens.find_one(query, None).await.unwrap();
+                assert!(old_token.is_none());
+
+                // refresh tokens can only be used once
+                let result = actions.create_token(params).await;
+                assert!(matches!(
+                    result,
+                    Err(UserError::OAuthFlowError(
+                        OAuthFlowError::InvalidRefreshTokenError
+                    ))
+                ));
+            })
+            .await;
+    }
+
+    #[actix_web::test]
+    async fn test_exchange_code_requires_client_secret() {
+        let user = test_user();
+
+        test_utils::setup()
+            .with_users(&[user.clone()])
+            .run(|app_data| async move {
+                let params = &app_data.settings.security.password_hashing;
+                let client = OAuthClient::new(
+                    "client".into(),
+                    None,
+                    Some("secret".into()),
+                    vec![oauth::Scope::ReadUser],
+                    vec!["http://localhost:8000".into()],
+                    params,
+                )
+                .unwrap();
+                let client_id = client.id.clone();
+                app_data
+                    .oauth_clients
+                    .insert_one(client, None)
+                    .await
+                    .unwrap();
+
+                let actions = app_data.as_oauth_actions();
+                let eu = auth::EditUser::test(user.username.clone());
+                let params = AuthorizeClientParams {
+                    client_secret: Some("secret".into()),
+                    ..authorize_params(client_id)
+                };
+                let url = actions.authorize(&eu, &params).await.unwrap();
+
+                let params = api::oauth::CreateTokenParams {
+                    client_secret: None,
+                    ..code_params(&url)
+                };
+                let result = actions.create_token(params).await;
+                assert!(matches!(
+                    result,
+                    Err(UserError::OAuthFlowError(
+                        OAuthFlowError::InvalidClientError
+                    ))
+                ));
+
+                // the code can still be exchanged by the client
+                actions.create_token(code_params(&url)).await.unwrap();
+            })
+            .await;
+    }
+
+    #[actix_web::test]
+    async fn test_refresh_token_invalid_client_secret() {
+        let user = test_user();
+
+        test_utils::setup()
+            .with_users(&[user.clone()])
+            .run(|app_data| async move {
+                let params = &app_data.settings.security.password_hashing;
+                let client = OAuthClient::new(
+                    "client".into(),
+                    None,
+                    Some("secret".into()),
+                    vec![oauth::Scope::ReadUser],
+                    vec!["http://localhost:8000".into()],
+                    params,
+                )
+                .unwrap();
+                let client_id = client.id.clone();
+                app_data
+                    .oauth_clients
+                    .insert_one(client, None)
+                    .await
+                    .unwrap();
+
+                let actions = app_data.as_oauth_actions();
+                let eu = auth::EditUser::test(user.username.clone());
+                let params = AuthorizeClientParams {
+                    client_secret: Some("secret".into()),
+                    ..authorize_params(client_id.clone())
+                };
+                let url = actions.authorize(&eu, &params).await.unwrap();
+                let token = actions.create_token(code_params(&url)).await.unwrap();
+
+                let params = api::oauth::CreateTokenParams {
+                    grant_type: Some("refresh_token".into()),
+                    refresh_token: Some(token.refresh_token.clone()),
+                    client_id: Some(client_id.clone()),
+                    client_secret: Some("incorrectSecret".into()),
+                    ..Default::default()
+                };
+                let result = actions.create_token(params.clone()).await;
+                assert!(matches!(
+                    result,
+                    Err(UserError::OAuthFlowError(
+                        OAuthFlowError::InvalidClientError
+                    ))
+                ));
+
+                // the refresh token can still be used by the client
+                let params = api::oauth::CreateTokenParams {
+                    client_secret: Some("secret".into()),
+                    ..params
+                };
+                actions.create_token(params).await.unwrap();
+            })
+            .await;
+    }
+
+    #[actix_web::test]
+    async fn test_refresh_token_banned_user() {
+        let user = test_user();
+
+        test_utils::setup()
+            .with_users(&[user.clone()])
+            .run(|app_data| async move {
+                let params = &app_data.settings.security.password_hashing;
+                let client = OAuthClient::new(
+                    "client".into(),
+                    None,
+                    Some("secret".into()),
+                    vec![oauth::Scope::ReadUser],
+                    vec!["http://localhost:8000".into()],
+                    params,
+                )
+                .unwrap();
+                let client_id = client.id.clone();
+                app_data
+                    .oauth_clients
+                    .insert_one(client, None)
+                    .await
+                    .unwrap();
+
+                let actions = app_data.as_oauth_actions();
+                let eu = auth::EditUser::test(user.username.clone());
+                let params = AuthorizeClientParams {
+                    client_secret: Some("secret".into()),
+                    ..authorize_params(client_id.clone())
+                };
+                let url = actions.authorize(&eu, &params).await.unwrap();
+                let token = actions.create_token(code_params(&url)).await.unwrap();
+
+                let account =
+                    BannedAccount::new(user.username.clone(), user.email.clone(), None, None, None);
+                app_data
+                    .banned_accounts
+                    .insert_one(account, None)
+                    .await
+                    .unwrap();
+
+                let params = api::oauth::CreateTokenParams {
+                    grant_type: Some("refresh_token".into()),
+                    refresh_token: Some(token.refresh_token),
+                    client_id: Some(client_id),
+                    client_secret: Some("secret".into()),
+                    ..Default::default()
+                };
+                let result = actions.create_token(params).await;
+                assert!(matches!(result, Err(UserError::BannedUserError)));
+            })
+            .await;
+    }
+
+    #[actix_web::test]
+    async fn test_list_and_revoke_authorized_clients() {
+        let user = test_user();
+
+        test_utils::setup()
+            .with_users(&[user.clone()])
+            .run(|app_data| async move {
+                let params = &app_data.settings.security.password_hashing;
+                let allowed_scopes = vec![oauth::Scope::ReadUser, oauth::Scope::ReadProjects];
+                let client = OAuthClient::new(
+                    "client".into(),
+                    None,
+                    None,
+                    allowed_scopes,
+                    vec!["http://localhost:8000".into()],
+                    params,
+                )
+                .unwrap();
+                let client_id = client.id.clone();
+                app_data
+                    .oauth_clients
+                    .insert_one(client, None)
+                    .await
+                    .unwrap();
+
+                let tokens = vec![
+                    OAuthToken::new(
+                        client_id.clone(),
+                        user.username.clone(),
+                        vec![oauth::Scope::ReadUser],
+                        Duration::from_secs(3600),
+                    ),
+                    OAuthToken::new(
+                        client_id.clone(),
+                        user.username.clone(),
+                        vec![oauth::Scope::ReadProjects],
+                        Duration::from_secs(3600),
+                    ),
+                ];
+                app_data
+                    .oauth_tokens
+                    .insert_many(tokens, None)
+                    .await
+                    .unwrap();
+
+                let actions = app_data.as_oauth_actions();
+                let auth_mc = auth::ManageAuthorizedClients::test(user.username.clone());
+                let clients = actions.list_authorized_clients(&auth_mc).await.unwrap();
+                assert_eq!(clients.len(), 1);
+                assert_eq!(clients[0].name, "client");
+                assert_eq!(clients[0].token_count, 2);
+                assert_eq!(clients[0].scopes.len(), 2);
+
+                actions
+                    .revoke_authorized_client(&auth_mc, &client_id)
+                    .await
+                    .unwrap();
+
+                let clients = actions.list_authorized_clients(&auth_mc).await.unwrap();
+                assert!(clients.is_empty());
+            })
+            .await;
+    }
+
+    #[actix_web::test]
+    async fn test_revoke_refresh_token() {
+        let user = test_user();
+
+        test_utils::setup()
+            .with_users(&[user.clone()])
+            .run(|app_data| async move {
+                let params = &app_data.settings.security.password_hashing;
+                let allowed_scopes = vec![oauth::Scope::ReadUser];
+                let client = OAuthClient::new(
+                    "client".into(),
+                    None,
+                    Some("secret".into()),
+                    allowed_scopes,
+                    vec!["http://localhost:8000".into()],
+                    params,
+                )
+                .unwrap();
+                let client_id = client.id.clone();
+                app_data
+                    .oauth_clients
+                    .insert_one(client, None)
+                    .await
+                    .unwrap();
+
+                let actions = app_data.as_oauth_actions();
+                let eu = auth::EditUser::test(user.username.clone());
+                let params = AuthorizeClientParams {
+                    client_secret: Some("secret".into()),
+                    ..authorize_params(client_id.clone())
+                };
+                let url = actions.authorize(&eu, &params).await.unwrap();
+                let token = actions.create_token(code_params(&url)).await.unwrap();
+
+                let params = api::oauth::RevokeTokenParams {
+                    token: token.refresh_token,
+                    client_id: Some(client_id),
+                    client_secret: Some("secret".into()),
+                    ..Default::default()
+                };
+                actions.revoke_token(params.clone()).await.unwrap();
+
+                // the access token is revoked along with the refresh token
+                let query = doc! {"id": &token.access_token};
+                let access_token = app_data.oauth_tokens.find_one(query, None).await.unwrap();
+                assert!(access_token.is_none());
+
+                // revoking an unknown token is not an error
+                actions.revoke_token(params).await.unwrap();
+            })
+            .await;
+    }
+
+    #[actix_web::test]
+    async fn test_openid_id_token() {
+        let user = test_user();
+
+        test_utils::setup()
+            .with_users(&[user.clone()])
+            .run(|mut app_data| async move {
+                app_data.oauth_signing_keys = std::sync::Arc::new(SigningKeys::test());
+                let params = &app_data.settings.security.password_hashing;
+                let allowed_scopes = vec![oauth::Scope::OpenId, oauth::Scope::Email];
+                let client = OAuthClient::new(
+                    "client".into(),
+                    None,
+                    Some("secret".into()),
+                    allowed_scopes,
+                    vec!["http://localhost:8000".into()],
+                    params,
+                )
+                .unwrap();
+                let client_id = client.id.clone();
+                app_data
+                    .oauth_clients
+                    .insert_one(client, None)
+                    .await
+                    .unwrap();
+
+                let actions = app_data.as_oauth_actions();
+                let eu = auth::EditUser::test(user.username.clone());
+                let params = AuthorizeClientParams {
+                    client_secret: Some("secret".into()),
+                    nonce: Some("someNonce".into()),
+                    scope: Some("openid email".into()),
+                    ..authorize_params(client_id.clone())
+                };
+                let url = actions.authorize(&eu, &params).await.unwrap();
+                let token = actions.create_token(code_params(&url)).await.unwrap();
+
+                let id_token = token.id_token.unwrap();
+                let header = jsonwebtoken::decode_header(&id_token).unwrap();
+                let jwks = actions.jwks();
+                let jwk = jwks.find(&header.kid.unwrap()).unwrap();
+                let key = jsonwebtoken::DecodingKey::from_jwk(jwk).unwrap();
+                let mut validation = jsonwebtoken::Validation::new(header.alg);
+                validation.set_audience(&[client_id.as_str()]);
+                validation.set_issuer(&[app_data.settings.public_url.trim_end_matches('/')]);
+                let claims =
+                    jsonwebtoken::decode::<serde_json::Value>(&id_token, &key, &validation)
+                        .unwrap()
+                        .claims;
+
+                let query = doc! {"username": &user.username};
+                let user_id = app_data
+                    .users
+                    .find_one(query, None)
+                    .await
+                    .unwrap()
+                    .unwrap()
+                    .id
+                    .unwrap()
+                    .to_hex();
+                assert_eq!(claims["sub"], user_id);
+                assert_eq!(claims["preferred_username"], user.username);
+                assert_eq!(claims["nonce"], "someNonce");
+                assert_eq!(claims["email"], user.email);
+
+                let user_info = actions.get_user_info(&token.access_token).await.unwrap();
+                assert_eq!(user_info.sub, user_id);
+                assert_eq!(user_info.email, Some(user.email));
+            })
+            .await;
+    }
+
+    #[actix_web::test]
+    async fn test_openid_requires_signing_key() {
+        let user = test_user();
+
+        test_utils::setup()
+            .with_users(&[user.clone()])
+            .run(|app_data| async move {
+                let params = &app_data.settings.security.password_hashing;
+                let allowed_scopes = vec![oauth::Scope::OpenId];
+                let client = OAuthClient::new(
+                    "client".into(),
+                    None,
+                    Some("secret".into()),
+                    allowed_scopes,
+                    vec!["http://localhost:8000".into()],
+                    params,
+                )
+                .unwrap();
+                let client_id = client.id.clone();
+                app_data
+                    .oauth_clients
+                    .insert_one(client, None)
+                    .await
+                    .unwrap();
+
+                let actions = app_data.as_oauth_actions();
+                let eu = auth::EditUser::test(user.username.clone());
+                let params = AuthorizeClientParams {
+                    client_secret: Some("secret".into()),
+                    scope: Some("openid".into()),
+                    ..authorize_params(client_id)
+                };
+                let result = actions.authorize(&eu, &params).await;
+                assert!(matches!(
+                    result,
+                    Err(UserError::OAuthFlowError(OAuthFlowError::InvalidScopeError))
+                ));
+            })
+            .await;
+    }
+
+    #[actix_web::test]
+    async fn test_authorize_unregistered_redirect_uri() {
+        let user = test_user();
+
+        test_utils::setup()
+            .with_users(&[user.clone()])
+            .run(|app_data| async move {
+                let params = &app_data.settings.security.password_hashing;
+                let client = OAuthClient::new(
+                    "client".into(),
+                    None,
+                    Some("secret".into()),
+                    oauth::Scope::legacy(),
+                    vec!["https://example.com/callback".into()],
+                    params,
+                )
+                .unwrap();
+                let client_id = client.id.clone();
+                app_data
+                    .oauth_clients
+                    .insert_one(client, None)
+                    .await
+                    .unwrap();
+
+                let actions = app_data.as_oauth_actions();
+                let eu = auth::EditUser::test(user.username.clone());
+                let params = AuthorizeClientParams {
+                    client_secret: Some("secret".into()),
+                    redirect_uri: Some("https://example.com/callback/other".into()),
+                    ..authorize_params(client_id)
+                };
+                let result = actions.authorize(&eu, &params).await;
+                assert!(matches!(
+                    result,
+                    Err(UserError::OAuthFlowError(
+                        OAuthFlowError::InvalidRedirectUrlError
+                    ))
+                ));
+            })
+            .await;
+    }
+
+    #[actix_web::test]
+    async fn test_owned_clients() {
+        let user = test_user();
+
+        test_utils::setup()
+            .with_users(&[user.clone()])
+            .run(|app_data| async move {
+                let actions = app_data.as_oauth_actions();
+                let data = oauth::CreateClientData {
+                    name: "My App".into(),
+                    allowed_scopes: vec![oauth::Scope::ReadUser],
+                    public: false,
+                    redirect_uris: vec!["https://example.com/callback".into()],
+                };
+                let cc = auth::CreateClient::test(Some(user.username.clone()));
+                let created = actions.create_client(&cc, &data).await.unwrap();
+
+                let data = oauth::CreateClientData {
+                    name: "Admin App".into(),
+                    ..data
+                };
+                let cc = auth::CreateClient::test(None);
+                actions.create_client(&cc, &data).await.unwrap();
+
+                // only the user's own clients are listed
+                let lc = auth::ListOAuthClients::test(Some(user.username.clone()));
+                let clients = actions.list_clients(&lc).await.unwrap();
+                assert_eq!(clients.len(), 1);
+                assert_eq!(clients[0].owner, Some(user.username.clone()));
+
+                // the previous secret is invalid after rotation
+                let mc = auth::ManageClient::test(created.id.clone());
+                let rotated = actions.rotate_client_secret(&mc).await.unwrap();
+                let client = actions.get_client(&created.id).await.unwrap();
+                let old_secret = created.secret.unwrap();
+                let new_secret = rotated.secret.unwrap();
+                assert!(!actions
+                    .verify_client_secret(&client, &old_secret)
+                    .await
+                    .unwrap());
+                assert!(actions
+                    .verify_client_secret(&client, &new_secret)
+                    .await
+                    .unwrap());
+            })
+            .await;
+    }
+
+    #[actix_web::test]
+    async fn test_create_client_invalid_name() {
+        let user = test_user();
+
+        test_utils::setup()
+            .with_users(&[user.clone()])
+            .run(|app_data| async move {
+                let actions = app_data.as_oauth_actions();
+                let data = oauth::CreateClientData {
+                    name: "<script>alert(1)</script>".into(),
+                    allowed_scopes: vec![oauth::Scope::ReadUser],
+                    public: false,
+                    redirect_uris: vec!["https://example.com/callback".into()],
+                };
+                let cc = auth::CreateClient::test(Some(user.username.clone()));
+                let result = actions.create_client(&cc, &data).await;
+                assert!(matches!(
+                    result,
+                    Err(UserError::InvalidOAuthClientNameError)
+                ));
+
+                let data = oauth::CreateClientData {
+                    name: "My\nApp".into(),
+                    ..data
+                };
+                let result = actions.create_client(&cc, &data).await;
+                assert!(matches!(
+                    result,
+                    Err(UserError::InvalidOAuthClientNameError)
+                ));
+            })
+            .await;
+    }
+
+    #[actix_web::test]
+    async fn test_delete_client_revokes_tokens() {
+        let user = test_user();
+
+        test_utils::setup()
+            .with_users(&[user.clone()])
+            .run(|app_data| async move {
+                let params = &app_data.settings.security.password_hashing;
+                let client = OAuthClient::new(
+                    "client".into(),
+                    None,
+                    Some("secret".into()),
+                    vec![oauth::Scope::ReadUser],
+                    vec!["http://localhost:8000".into()],
+                    params,
+                )
+                .unwrap();
+                let client_id = client.id.clone();
+                app_data
+                    .oauth_clients
+                    .insert_one(client, None)
+                    .await
+                    .unwrap();
+
+                let actions = app_data.as_oauth_actions();
+                let eu = auth::EditUser::test(user.username.clone());
+                let params = AuthorizeClientParams {
+                    client_secret: Some("secret".into()),
+                    ..authorize_params(client_id.clone())
+                };
+                let url = actions.authorize(&eu, &params).await.unwrap();
+                actions.create_token(code_params(&url)).await.unwrap();
+                let url = actions.authorize(&eu, &params).await.unwrap();
+
+                let mc = auth::ManageClient::test(client_id.clone());
+                actions.delete_client(&mc).await.unwrap();
+
+                let query = doc! {"clientId": &client_id};
+                let tokens = app_data
+                    .oauth_tokens
+                    .count_documents(query.clone(), None)
+                    .await
+                    .unwrap();
+                let refresh_tokens = app_data
+                    .oauth_refresh_tokens
+                    .count_documents(query.clone(), None)
+                    .await
+                    .unwrap();
+                let codes = app_data
+                    .oauth_codes
+                    .count_documents(query, None)
+                    .await
+                    .unwrap();
+                assert_eq!(tokens + refresh_tokens + codes, 0);
+
+                let result = actions.create_token(code_params(&url)).await;
+                assert!(result.is_err());
+            })
+            .await;
+    }
+
+    #[test]
+    fn test_valid_redirect_uris() {
+        let uris = vec![
+            "https://example.com/callback".to_string(),
+            "http://localhost:8080/callback".to_string(),
+            "http://127.0.0.1/callback".to_string(),
+            "org.example.app:/callback".to_string(),
+        ];
+        assert!(ensure_valid_redirect_uris(&uris).is_ok());
+    }
+
+    #[test]
+    fn test_invalid_redirect_uris() {
+        let uris = [
+            "http://example.com/callback",
+            "https://example.com/callback#fragment",
+            "javascript:alert(1)",
+            "/callback",
+        ];
+        for uri in uris {
+            assert!(ensure_valid_redirect_uris(&[uri.to_string()]).is_err());
+        }
     }
 }
@@ -1,9 +1,14 @@
-use super::routes::Scope;
+use netsblox_cloud_common::api::oauth::Scope;
 
-pub(crate) fn authorize_page(username: &str, client: &str, scopes: &[Scope]) -> String {
+pub(crate) fn authorize_page(
+    username: &str,
+    client: &str,
+    scopes: &[Scope],
+    csrf_token: &str,
+) -> String {
     let scope_html: String = scopes
         .iter()
-        .map(|name| format!("<tr><td class=\"scope\">{}</td></tr>", name))
+        .map(|scope| format!("<tr><td class=\"scope\">{}</td></tr>", scope.description()))
         .collect();
 
     // TODO: fix the image
@@ -31,7 +36,9 @@ pub(crate) fn authorize_page(username: &str, client: &str, scopes: &[Scope]) ->
         </div>
         <div class=\"row\">
             <div class=\"center-align\">
-                <form id=\"allow\" action=\"/oauth/{username}/code\" method=\"post\"></form>
+                <form id=\"allow\" action=\"/oauth/{username}/code\" method=\"post\">
+                    <input type=\"hidden\" name=\"{csrf_field}\" value=\"{csrf_token}\"/>
+                </form>
                 <a id=\"denyButton\" class=\"waves-effect waves-light btn grey\">Deny</a>
                 <a id=\"allowButton\" class=\"waves-effect waves-light btn\">Allow</a>
             </div>
@@ -80,5 +87,34 @@ pub(crate) fn authorize_page(username: &str, client: &str, scopes: &[Scope]) ->
             }}
         </style>
 <html/>
-    ", username=username, client=client, scopes=scope_html)
+    ", username=escape_html(username), client=escape_html(client), scopes=scope_html, csrf_field=crate::csrf::CSRF_FIELD, csrf_token=escape_html(csrf_token))
+}
+
+/// Escape text so it can be safely inserted into HTML content or attribute values.
+fn escape_html(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '"' => escaped.push_str("&quot;"),
+            '\'' => escaped.push_str("&#x27;"),
+            c => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_authorize_page_escapes_client() {
+        let html = authorize_page("user", "<script>alert(1)</script>", &[], "token");
+
+        assert!(!html.contains("<script>alert(1)</script>"));
+        assert!(html.contains("&lt;script&gt;alert(1)&lt;/script&gt;"));
+    }
 }
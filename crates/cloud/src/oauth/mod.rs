@@ -1,3 +1,4 @@
 pub(crate) mod actions;
 mod html_template;
+pub(crate) mod oidc;
 pub(crate) mod routes;
@@ -0,0 +1,290 @@
+use std::fs;
+
+use derive_more::{Display, Error};
+use jsonwebtoken::{
+    jwk::{
+        AlgorithmParameters, CommonParameters, EllipticCurve, Jwk, JwkSet, KeyAlgorithm,
+        OctetKeyPairParameters, OctetKeyPairType, PublicKeyUse, RSAKeyParameters, RSAKeyType,
+    },
+    Algorithm, EncodingKey, Header,
+};
+use netsblox_cloud_common::api::oauth;
+use ring::signature::{Ed25519KeyPair, KeyPair, RsaKeyPair, RsaPublicKeyComponents};
+use serde::Serialize;
+
+use crate::{
+    config::{SigningAlgorithm, SigningKeyConfig},
+    errors::InternalError,
+};
+
+#[derive(Debug, Display, Error)]
+pub(crate) enum SigningKeyError {
+    Read(std::io::Error),
+    Pem(pem::PemError),
+    InvalidKey,
+}
+
+/// Keys used to sign ID tokens. The public keys are published as a JWKS so
+/// clients can verify the tokens.
+pub(crate) struct SigningKeys {
+    keys: Vec<SigningKey>,
+}
+
+impl SigningKeys {
+    pub(crate) fn new(configs: &[SigningKeyConfig]) -> Result<Self, SigningKeyError> {
+        let keys = configs
+            .iter()
+            .map(|config| {
+                let pem =
+                    fs::read_to_string(&config.private_key_file).map_err(SigningKeyError::Read)?;
+                SigningKey::from_pem(&config.id, config.algorithm, &pem)
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+
+        Ok(Self { keys })
+    }
+
+    /// ID tokens can only be issued if a signing key has been configured
+    pub(crate) fn is_enabled(&self) -> bool {
+        !self.keys.is_empty()
+    }
+
+    pub(crate) fn algorithms(&self) -> Vec<Algorithm> {
+        let mut algorithms: Vec<_> = self.keys.iter().map(|key| key.algorithm).collect();
+        algorithms.dedup();
+        algorithms
+    }
+
+    pub(crate) fn jwks(&self) -> JwkSet {
+        JwkSet {
+            keys: self.keys.iter().map(|key| key.jwk.clone()).collect(),
+        }
+    }
+
+    /// Sign the claims using the current (ie, first) key
+    pub(crate) fn sign<T: Serialize>(&self, claims: &T) -> Result<String, InternalError> {
+        let key = self.keys.first().ok_or(InternalError::NoSigningKeyError)?;
+        let mut header = Header::new(key.algorithm);
+        header.kid = Some(key.id.clone());
+
+        jsonwebtoken::encode(&header, claims, &key.encoding_key)
+            .map_err(InternalError::IdTokenSigningError)
+    }
+}
+
+struct SigningKey {
+    id: String,
+    algorithm: Algorithm,
+    encoding_key: EncodingKey,
+    jwk: Jwk,
+}
+
+impl SigningKey {
+    fn from_pem(
+        id: &str,
+        algorithm: SigningAlgorithm,
+        pem_str: &str,
+    ) -> Result<Self, SigningKeyError> {
+        let pem = pem::parse(pem_str).map_err(SigningKeyError::Pem)?;
+        let (algorithm, encoding_key, params) = match algorithm {
+            SigningAlgorithm::RS256 => {
+                let key_pair = if pem.tag() == "RSA PRIVATE KEY" {
+                    RsaKeyPair::from_der(pem.contents())
+                } else {
+                    RsaKeyPair::from_pkcs8(pem.contents())
+                }
+                .map_err(|_err| SigningKeyError::InvalidKey)?;
+
+                let public = RsaPublicKeyComponents::<Vec<u8>>::from(key_pair.public());
+                let params = AlgorithmParameters::RSA(RSAKeyParameters {
+                    key_type: RSAKeyType::RSA,
+                    n: base64::encode_config(&public.n, base64::URL_SAFE_NO_PAD),
+                    e: base64::encode_config(&public.e, base64::URL_SAFE_NO_PAD),
+                });
+                let encoding_key = EncodingKey::from_rsa_pem(pem_str.as_bytes())
+                    .map_err(|_err| SigningKeyError::InvalidKey)?;
+
+                (Algorithm::RS256, encoding_key, params)
+            }
+            SigningAlgorithm::EdDSA => {
+                let key_pair = Ed25519KeyPair::from_pkcs8_maybe_unchecked(pem.contents())
+                    .map_err(|_err| SigningKeyError::InvalidKey)?;
+
+                let params = AlgorithmParameters::OctetKeyPair(OctetKeyPairParameters {
+                    key_type: OctetKeyPairType::OctetKeyPair,
+                    curve: EllipticCurve::Ed25519,
+                    x: base64::encode_config(key_pair.public_key(), base64::URL_SAFE_NO_PAD),
+                });
+                let encoding_key = EncodingKey::from_ed_der(pem.contents());
+
+                (Algorithm::EdDSA, encoding_key, params)
+            }
+        };
+
+        let key_algorithm = match algorithm {
+            Algorithm::EdDSA => KeyAlgorithm::EdDSA,
+            _ => KeyAlgorithm::RS256,
+        };
+        let jwk = Jwk {
+            common: CommonParameters {
+                public_key_use: Some(PublicKeyUse::Signature),
+                key_algorithm: Some(key_algorithm),
+                key_id: Some(id.to_owned()),
+                ..Default::default()
+            },
+            algorithm: params,
+        };
+
+        Ok(Self {
+            id: id.to_owned(),
+            algorithm,
+            encoding_key,
+            jwk,
+        })
+    }
+}
+
+/// Claims included in the ID token. The subject is the ID of the user (since
+/// usernames can be reused after an account is deleted).
+#[derive(Serialize, Debug)]
+pub(crate) struct IdTokenClaims {
+    pub(crate) iss: String,
+    pub(crate) aud: String,
+    pub(crate) exp: u64,
+    pub(crate) iat: u64,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub(crate) nonce: Option<String>,
+    #[serde(flatten)]
+    pub(crate) user_info: UserInfo,
+}
+
+/// Claims about the user returned by the userinfo endpoint (and included in
+/// the ID token)
+#[derive(Serialize, Debug)]
+pub(crate) struct UserInfo {
+    pub(crate) sub: String,
+    pub(crate) preferred_username: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub(crate) email: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub(crate) email_verified: Option<bool>,
+}
+
+/// OpenID Connect discovery document
+#[derive(Serialize, Debug)]
+pub(crate) struct ProviderMetadata {
+    issuer: String,
+    authorization_endpoint: String,
+    token_endpoint: String,
+    userinfo_endpoint: String,
+    jwks_uri: String,
+    revocation_endpoint: String,
+    scopes_supported: Vec<oauth::Scope>,
+    response_types_supported: Vec<&'static str>,
+    grant_types_supported: Vec<&'static str>,
+    subject_types_supported: Vec<&'static str>,
+    id_token_signing_alg_values_supported: Vec<Algorithm>,
+    token_endpoint_auth_methods_supported: Vec<&'static str>,
+    code_challenge_methods_supported: Vec<&'static str>,
+    claims_supported: Vec<&'static str>,
+}
+
+impl ProviderMetadata {
+    pub(crate) fn new(issuer: &str, keys: &SigningKeys) -> Self {
+        let issuer = issuer.trim_end_matches('/');
+        Self {
+            issuer: issuer.to_owned(),
+            authorization_endpoint: format!("{}/oauth/authorize", issuer),
+            token_endpoint: format!("{}/oauth/token/", issuer),
+            userinfo_endpoint: format!("{}/oauth/userinfo", issuer),
+            jwks_uri: format!("{}/oauth/jwks", issuer),
+            revocation_endpoint: format!("{}/oauth/revoke", issuer),
+            scopes_supported: oauth::Scope::all(),
+            response_types_supported: vec!["code"],
+            grant_types_supported: vec!["authorization_code", "refresh_token"],
+            subject_types_supported: vec!["public"],
+            id_token_signing_alg_values_supported: keys.algorithms(),
+            token_endpoint_auth_methods_supported: vec![
+                "client_secret_basic",
+                "client_secret_post",
+                "none",
+            ],
+            code_challenge_methods_supported: vec!["S256"],
+            claims_supported: vec![
+                "iss",
+                "sub",
+                "aud",
+                "exp",
+                "iat",
+                "nonce",
+                "preferred_username",
+                "email",
+                "email_verified",
+            ],
+        }
+    }
+}
+
+#[cfg(test)]
+impl SigningKeys {
+    /// Create signing keys with a new Ed25519 key
+    pub(crate) fn test() -> Self {
+        let rng = ring::rand::SystemRandom::new();
+        let pkcs8 = Ed25519KeyPair::generate_pkcs8(&rng).unwrap();
+        let pem = pem::encode(&pem::Pem::new("PRIVATE KEY", pkcs8.as_ref().to_vec()));
+        let key = SigningKey::from_pem("test-key", SigningAlgorithm::EdDSA, &pem).unwrap();
+
+        Self { keys: vec![key] }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use jsonwebtoken::{DecodingKey, Validation};
+    use serde::Deserialize;
+
+    use super::*;
+
+    #[derive(Deserialize)]
+    struct Claims {
+        sub: String,
+        aud: String,
+    }
+
+    #[test]
+    fn test_sign_and_verify_with_jwks() {
+        let keys = SigningKeys::test();
+        let claims = IdTokenClaims {
+            iss: "http://localhost:7777".into(),
+            aud: "client".into(),
+            exp: jsonwebtoken::get_current_timestamp() + 60,
+            iat: jsonwebtoken::get_current_timestamp(),
+            nonce: None,
+            user_info: UserInfo {
+                sub: "user".into(),
+                preferred_username: "user".into(),
+                email: None,
+                email_verified: None,
+            },
+        };
+        let token = keys.sign(&claims).unwrap();
+
+        let header = jsonwebtoken::decode_header(&token).unwrap();
+        let jwks = keys.jwks();
+        let jwk = jwks.find(&header.kid.unwrap()).unwrap();
+        let key = DecodingKey::from_jwk(jwk).unwrap();
+        let mut validation = Validation::new(Algorithm::EdDSA);
+        validation.set_audience(&["client"]);
+
+        let data = jsonwebtoken::decode::<Claims>(&token, &key, &validation).unwrap();
+        assert_eq!(data.claims.sub, "user");
+        assert_eq!(data.claims.aud, "client");
+    }
+
+    #[test]
+    fn test_no_keys() {
+        let keys = SigningKeys::new(&[]).unwrap();
+        assert!(!keys.is_enabled());
+        assert!(keys.sign(&"claims").is_err());
+    }
+}
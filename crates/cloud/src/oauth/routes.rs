@@ -1,7 +1,6 @@
+use actix_session::SessionExt;
 use actix_web::http::header;
-use actix_web::{delete, get, post, web, HttpRequest, HttpResponse};
-use derive_more::Display;
-use mongodb::bson::doc;
+use actix_web::{delete, get, patch, post, route, web, Either, HttpRequest, HttpResponse};
 use netsblox_cloud_common::api;
 use serde::Deserialize;
 
@@ -9,24 +8,12 @@ use crate::app_data::AppData;
 use crate::common::api::oauth;
 use crate::errors::{InternalError, UserError};
 use crate::oauth::actions::OAuthActions;
-use crate::{auth, utils};
+use crate::{auth, csrf, utils};
 
 #[derive(Deserialize)]
 struct AuthorizeParams {
     client_id: oauth::ClientId,
-}
-
-// TODO: should we define scopes?
-// TODO: view username/email
-// TODO: view projects and libraries
-// TODO: edit projects and libraries
-
-#[derive(Debug, Display)]
-pub(crate) enum Scope {
-    #[display(fmt = "View created Alexa skills")]
-    ViewAlexaSkills,
-    #[display(fmt = "Execute blocks on your behalf")]
-    ExecuteBlocks,
+    scope: Option<String>,
 }
 
 #[get("/authorize")]
@@ -42,9 +29,17 @@ async fn authorization_page(
     };
 
     let response = if let Ok(auth_eu) = auth_eu {
+        // The consent form is submitted without javascript so the CSRF token
+        // needs to be included in the form
+        let csrf_token = csrf::get_or_create_token(&req.get_session());
         let actions: OAuthActions = app.as_oauth_actions();
         let html = actions
-            .render_auth_page(&auth_eu, &params.client_id)
+            .render_auth_page(
+                &auth_eu,
+                &params.client_id,
+                params.scope.as_deref(),
+                &csrf_token,
+            )
             .await?;
 
         HttpResponse::Ok()
@@ -68,10 +63,16 @@ async fn authorization_page(
 #[derive(Deserialize)]
 pub(crate) struct AuthorizeClientParams {
     pub(super) client_id: oauth::ClientId,
-    pub(super) client_secret: String,
+    /// Required for confidential clients (public clients must use PKCE instead)
+    pub(super) client_secret: Option<String>,
     pub(super) redirect_uri: Option<String>,
+    pub(super) code_challenge: Option<String>,
+    pub(super) code_challenge_method: Option<String>,
+    /// OpenID Connect nonce to include in the ID token
+    pub(super) nonce: Option<String>,
     pub(super) error: Option<String>,
     pub(super) error_description: Option<String>,
+    pub(super) scope: Option<String>,
     pub(super) state: String,
 }
 
@@ -94,22 +95,120 @@ async fn authorize_client(
     Ok(response)
 }
 
+/// Token endpoint (RFC 6749). Parameters can be sent as a form (as specified
+/// by the RFC) or as JSON.
 #[post("/token/")]
 async fn create_token(
     app: web::Data<AppData>,
-    params: web::Json<api::oauth::CreateTokenParams>,
+    params: Either<
+        web::Json<api::oauth::CreateTokenParams>,
+        web::Form<api::oauth::CreateTokenParams>,
+    >,
+    req: HttpRequest,
 ) -> Result<HttpResponse, UserError> {
+    let mut params = params.into_inner();
+    if params.client_secret.is_none() {
+        if let Some((client_id, secret)) = basic_auth(&req) {
+            params.client_id = Some(client_id);
+            params.client_secret = Some(secret);
+        }
+    }
+
     let actions: OAuthActions = app.as_oauth_actions();
-    let token = actions.create_token(params.into_inner()).await?;
+    let token = actions.create_token(params).await?;
 
     let response = HttpResponse::Ok()
         .insert_header(header::CacheControl(vec![header::CacheDirective::NoStore]))
         .insert_header(("Pragma", "no-cache"))
-        .json(token.id);
+        .json(token);
 
     Ok(response)
 }
 
+/// Get the client credentials from the Authorization header (HTTP basic auth)
+fn basic_auth(req: &HttpRequest) -> Option<(oauth::ClientId, String)> {
+    let value = req.headers().get(header::AUTHORIZATION)?.to_str().ok()?;
+    let (scheme, credentials) = value.split_once(' ')?;
+    if !scheme.eq_ignore_ascii_case("basic") {
+        return None;
+    }
+
+    let credentials = base64::decode(credentials.trim()).ok()?;
+    let credentials = String::from_utf8(credentials).ok()?;
+    let (client_id, secret) = credentials.split_once(':')?;
+
+    Some((
+        oauth::ClientId::new(client_id.to_owned()),
+        secret.to_owned(),
+    ))
+}
+
+#[post("/revoke")]
+async fn revoke_token(
+    app: web::Data<AppData>,
+    params: Either<
+        web::Json<api::oauth::RevokeTokenParams>,
+        web::Form<api::oauth::RevokeTokenParams>,
+    >,
+    req: HttpRequest,
+) -> Result<HttpResponse, UserError> {
+    let mut params = params.into_inner();
+    if params.client_secret.is_none() {
+        if let Some((client_id, secret)) = basic_auth(&req) {
+            params.client_id = Some(client_id);
+            params.client_secret = Some(secret);
+        }
+    }
+
+    let actions: OAuthActions = app.as_oauth_actions();
+    actions.revoke_token(params).await?;
+
+    let response = HttpResponse::Ok()
+        .insert_header(header::CacheControl(vec![header::CacheDirective::NoStore]))
+        .finish();
+
+    Ok(response)
+}
+
+/// OpenID Connect userinfo endpoint. The access token is passed in the
+/// Authorization header (`Bearer <token>`).
+#[route("/userinfo", method = "GET", method = "POST")]
+async fn get_user_info(
+    app: web::Data<AppData>,
+    req: HttpRequest,
+) -> Result<HttpResponse, UserError> {
+    let token_id = bearer_token(&req).ok_or(UserError::InvalidAccessTokenError)?;
+
+    let actions: OAuthActions = app.as_oauth_actions();
+    let user_info = actions.get_user_info(&token_id).await?;
+
+    Ok(HttpResponse::Ok().json(user_info))
+}
+
+fn bearer_token(req: &HttpRequest) -> Option<oauth::TokenId> {
+    let value = req.headers().get(header::AUTHORIZATION)?.to_str().ok()?;
+    let (scheme, token) = value.split_once(' ')?;
+    scheme
+        .eq_ignore_ascii_case("bearer")
+        .then(|| oauth::TokenId::new(token.trim().to_owned()))
+}
+
+#[get("/jwks")]
+async fn get_jwks(app: web::Data<AppData>) -> Result<HttpResponse, UserError> {
+    let actions: OAuthActions = app.as_oauth_actions();
+    Ok(HttpResponse::Ok().json(actions.jwks()))
+}
+
+/// OpenID Connect discovery document. This is served from the root (not the
+/// `/oauth` scope).
+#[get("/.well-known/openid-configuration")]
+pub(crate) async fn openid_configuration(
+    app: web::Data<AppData>,
+) -> Result<HttpResponse, UserError> {
+    let actions: OAuthActions = app.as_oauth_actions();
+    Ok(HttpResponse::Ok().json(actions.provider_metadata()))
+}
+
 #[get("/token/{tokenId}")]
 async fn get_token(
     app: web::Data<AppData>,
@@ -118,7 +217,8 @@ async fn get_token(
     // TODO: limit the number of requests from a single source?
     // TODO: ensure they are an authorized service host?
     let (token_id,) = path.into_inner();
-    let query = doc! {"id": &token_id};
+    let mut query = utils::not_expired();
+    query.insert("id", &token_id);
     let token: oauth::Token = app
         .oauth_tokens
         .find_one(query, None)
@@ -136,10 +236,10 @@ async fn create_client(
     params: web::Json<oauth::CreateClientData>,
     req: HttpRequest,
 ) -> Result<HttpResponse, UserError> {
-    let auth_cc = auth::try_manage_client(&app, &req).await?;
+    let auth_cc = auth::try_create_client(&app, &req).await?;
 
     let actions: OAuthActions = app.as_oauth_actions();
-    let client = actions.create_client(&auth_cc, &params.name).await?;
+    let client = actions.create_client(&auth_cc, &params).await?;
     Ok(HttpResponse::Ok().json(client))
 }
 
@@ -148,34 +248,194 @@ async fn list_clients(
     app: web::Data<AppData>,
     req: HttpRequest,
 ) -> Result<HttpResponse, UserError> {
-    let auth_cc = auth::try_manage_client(&app, &req).await?;
+    let auth_lc = auth::try_list_oauth_clients(&app, &req).await?;
 
     let actions: OAuthActions = app.as_oauth_actions();
-    let clients = actions.list_clients(&auth_cc).await?;
+    let clients = actions.list_clients(&auth_lc).await?;
 
     Ok(HttpResponse::Ok().json(clients))
 }
 
+#[patch("/clients/{client_id}")]
+async fn update_client(
+    app: web::Data<AppData>,
+    path: web::Path<(oauth::ClientId,)>,
+    data: web::Json<oauth::UpdateClientData>,
+    req: HttpRequest,
+) -> Result<HttpResponse, UserError> {
+    let (client_id,) = path.into_inner();
+    let auth_mc = auth::try_manage_client(&app, &req, &client_id).await?;
+
+    let actions: OAuthActions = app.as_oauth_actions();
+    let client = actions.update_client(&auth_mc, data.into_inner()).await?;
+
+    Ok(HttpResponse::Ok().json(client))
+}
+
+#[post("/clients/{client_id}/secret")]
+async fn rotate_client_secret(
+    app: web::Data<AppData>,
+    path: web::Path<(oauth::ClientId,)>,
+    req: HttpRequest,
+) -> Result<HttpResponse, UserError> {
+    let (client_id,) = path.into_inner();
+    let auth_mc = auth::try_manage_client(&app, &req, &client_id).await?;
+
+    let actions: OAuthActions = app.as_oauth_actions();
+    let client = actions.rotate_client_secret(&auth_mc).await?;
+
+    Ok(HttpResponse::Ok()
+        .insert_header(header::CacheControl(vec![header::CacheDirective::NoStore]))
+        .json(client))
+}
+
 #[delete("/clients/{client_id}")]
 async fn remove_client(
     app: web::Data<AppData>,
     path: web::Path<(oauth::ClientId,)>,
     req: HttpRequest,
 ) -> Result<HttpResponse, UserError> {
-    let auth_cc = auth::try_manage_client(&app, &req).await?;
     let (client_id,) = path.into_inner();
+    let auth_mc = auth::try_manage_client(&app, &req, &client_id).await?;
 
     let actions: OAuthActions = app.as_oauth_actions();
-    let client = actions.delete_client(&auth_cc, &client_id).await?;
+    let client = actions.delete_client(&auth_mc).await?;
 
     Ok(HttpResponse::Ok().json(client))
 }
 
+#[get("/user/{username}/tokens")]
+async fn list_authorized_clients(
+    app: web::Data<AppData>,
+    path: web::Path<(String,)>,
+    req: HttpRequest,
+) -> Result<HttpResponse, UserError> {
+    let (username,) = path.into_inner();
+    let auth_mc = auth::try_manage_authorized_clients(&app, &req, &username).await?;
+
+    let actions: OAuthActions = app.as_oauth_actions();
+    let clients = actions.list_authorized_clients(&auth_mc).await?;
+
+    Ok(HttpResponse::Ok().json(clients))
+}
+
+#[delete("/user/{username}/tokens/{client_id}")]
+async fn revoke_authorized_client(
+    app: web::Data<AppData>,
+    path: web::Path<(String, oauth::ClientId)>,
+    req: HttpRequest,
+) -> Result<HttpResponse, UserError> {
+    let (username, client_id) = path.into_inner();
+    let auth_mc = auth::try_manage_authorized_clients(&app, &req, &username).await?;
+
+    let actions: OAuthActions = app.as_oauth_actions();
+    actions
+        .revoke_authorized_client(&auth_mc, &client_id)
+        .await?;
+
+    Ok(HttpResponse::Ok().finish())
+}
+
 pub fn config(cfg: &mut web::ServiceConfig) {
     cfg.service(authorization_page)
         .service(authorize_client)
         .service(create_token)
+        .service(revoke_token)
+        .service(get_user_info)
+        .service(get_jwks)
         .service(create_client)
         .service(list_clients)
-        .service(remove_client);
+        .service(update_client)
+        .service(rotate_client_secret)
+        .service(remove_client)
+        .service(list_authorized_clients)
+        .service(revoke_authorized_client);
+}
+
+#[cfg(test)]
+mod tests {
+    use actix_web::{http, test, App};
+    use netsblox_cloud_common::{OAuthClient, User};
+
+    use super::*;
+    use crate::{csrf::middleware::CsrfProtection, test_utils};
+
+    #[actix_web::test]
+    async fn test_authorize_client_csrf_form() {
+        let user: User = api::NewUser {
+            username: "user".into(),
+            email: "user@netsblox.org".into(),
+            password: None,
+            group_id: None,
+            role: None,
+        }
+        .into();
+
+        test_utils::setup()
+            .with_users(&[user.clone()])
+            .run(|app_data| async move {
+                let client = OAuthClient::new(
+                    "client".into(),
+                    None,
+                    Some("secret".into()),
+                    vec![oauth::Scope::ReadUser],
+                    vec!["http://localhost:8000".into()],
+                    &app_data.settings.security.password_hashing,
+                )
+                .unwrap();
+                let client_id = client.id.clone();
+                app_data
+                    .oauth_clients
+                    .insert_one(client, None)
+                    .await
+                    .unwrap();
+
+                let app = test::init_service(
+                    App::new()
+                        .app_data(web::Data::new(app_data))
+                        .wrap(CsrfProtection)
+                        .wrap(test_utils::cookie::middleware())
+                        .service(web::scope("/oauth").configure(config)),
+                )
+                .await;
+
+                let req = test::TestRequest::get()
+                    .uri(&format!("/oauth/authorize?client_id={}", client_id))
+                    .cookie(test_utils::cookie::new(&user.username))
+                    .to_request();
+                let response = test::call_service(&app, req).await;
+                assert_eq!(response.status(), http::StatusCode::OK);
+                let cookie = response.response().cookies().next().unwrap().into_owned();
+                let html = test::read_body(response).await;
+                let html = std::str::from_utf8(&html).unwrap();
+                let field = format!("name=\"{}\" value=\"", csrf::CSRF_FIELD);
+                let token = html.split(&field).nth(1).unwrap().split('"').next().unwrap();
+
+                let uri = format!(
+                    "/oauth/{}/code?client_id={}&client_secret=secret&redirect_uri=http%3A%2F%2Flocalhost%3A8000&state=someState",
+                    &user.username, client_id
+                );
+                let req = test::TestRequest::post()
+                    .uri(&uri)
+                    .cookie(cookie.clone())
+                    .set_form([("other", "value")])
+                    .to_request();
+                let response = test::call_service(&app, req).await;
+                assert_eq!(response.status(), http::StatusCode::FORBIDDEN);
+
+                let req = test::TestRequest::post()
+                    .uri(&uri)
+                    .cookie(cookie)
+                    .set_form([(csrf::CSRF_FIELD, token)])
+                    .to_request();
+                let response = test::call_service(&app, req).await;
+                assert_eq!(response.status(), http::StatusCode::FOUND);
+                let location = response.headers().get(header::LOCATION).unwrap();
+                assert!(location
+                    .to_str()
+                    .unwrap()
+                    .starts_with("http://localhost:8000?code="));
+            })
+            .await;
+    }
 }
@@ -2,6 +2,7 @@ use std::collections::HashMap;
 use std::io::BufWriter;
 use std::sync::{Arc, RwLock};
 
+use crate::audit;
 use crate::auth;
 use crate::errors::{InternalError, UserError};
 use crate::network::topology::{self, TopologyActor};
@@ -24,7 +25,7 @@ use mongodb::{Collection, Cursor};
 use netsblox_cloud_common::api::{BrowserClientState, RoleData, RoleId, SaveState};
 use netsblox_cloud_common::{
     api::{self, PublishState},
-    ProjectMetadata,
+    AuditLogEntry, ProjectMetadata,
 };
 use netsblox_cloud_common::{Project, RoleMetadata};
 use s3::operation::put_object::PutObjectOutput;
@@ -37,6 +38,8 @@ pub(crate) struct ProjectActions<'a> {
 
     bucket: &'a String,
     s3: &'a s3::Client,
+
+    audit_log: &'a Collection<AuditLogEntry>,
 }
 
 impl<'a> ProjectActions<'a> {
@@ -47,6 +50,8 @@ impl<'a> ProjectActions<'a> {
 
         bucket: &'a String,
         s3: &'a s3::Client,
+
+        audit_log: &'a Collection<AuditLogEntry>,
     ) -> Self {
         Self {
             project_metadata,
@@ -54,6 +59,7 @@ impl<'a> ProjectActions<'a> {
             network,
             bucket,
             s3,
+            audit_log,
         }
     }
     pub async fn create_project(
@@ -313,6 +319,53 @@ impl<'a> ProjectActions<'a> {
         Ok(metadata.into())
     }
 
+    /// Remove a deleted user from the collaborators of all projects. Returns
+    /// the number of projects updated.
+    pub(crate) async fn remove_deleted_collaborator(
+        &self,
+        du: &auth::DeleteUser,
+    ) -> Result<u32, UserError> {
+        let query = doc! {"collaborators": &du.username};
+        let ids: Vec<_> = self
+            .project_metadata
+            .find(query, None)
+            .await
+            .map_err(InternalError::DatabaseConnectionError)?
+            .try_collect::<Vec<_>>()
+            .await
+            .map_err(InternalError::DatabaseConnectionError)?
+            .into_iter()
+            .map(|metadata| metadata.id)
+            .collect();
+
+        let mut count = 0;
+        for id in ids {
+            let query = doc! {"id": &id};
+            let update = doc! {
+                "$pull": {"collaborators": &du.username},
+                "$set": {
+                    "updated": DateTime::now()
+                }
+            };
+            let options = FindOneAndUpdateOptions::builder()
+                .return_document(ReturnDocument::After)
+                .build();
+
+            let metadata = self
+                .project_metadata
+                .find_one_and_update(query, update, options)
+                .await
+                .map_err(InternalError::DatabaseConnectionError)?;
+
+            if let Some(metadata) = metadata {
+                utils::on_room_changed(self.network, self.project_cache, metadata);
+                count += 1;
+            }
+        }
+
+        Ok(count)
+    }
+
     pub(crate) async fn set_latest_role(
         &self,
         md: &auth::projects::EditProject,
@@ -678,11 +731,27 @@ impl<'a> ProjectActions<'a> {
 
     pub(crate) async fn set_project_state(
         &self,
-        _mp: &auth::ModerateProjects,
+        mp: &auth::ModerateProjects,
         id: &api::ProjectId,
         state: PublishState,
     ) -> Result<api::ProjectMetadata, UserError> {
         let query = doc! {"id": id};
+        let metadata = self
+            .project_metadata
+            .find_one(query.clone(), None)
+            .await
+            .map_err(InternalError::DatabaseConnectionError)?
+            .ok_or(UserError::ProjectNotFoundError)?;
+
+        let entry = AuditLogEntry::new(
+            mp.moderator.clone(),
+            api::AuditAction::SetProjectState,
+            id.to_string(),
+            &metadata.state,
+            &state,
+        );
+        audit::record(self.audit_log, entry).await?;
+
         let update = doc! {
             "$set": {
                 "state": state,
@@ -197,7 +197,7 @@ async fn publish_project(
     req: HttpRequest,
 ) -> Result<HttpResponse, UserError> {
     let (project_id,) = path.into_inner();
-    let auth_ep = auth::try_edit_project(&app, &req, None, &project_id).await?;
+    let auth_ep = auth::try_publish_project(&app, &req, &project_id).await?;
     let actions: ProjectActions = app.as_project_actions();
     let state = actions.publish_project(&auth_ep).await?;
     Ok(HttpResponse::Ok().json(state))
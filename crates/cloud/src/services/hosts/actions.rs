@@ -1,22 +1,43 @@
+use std::time::{Duration, SystemTime};
+
 use futures::TryStreamExt;
 use lazy_static::lazy_static;
-use mongodb::{bson::doc, options::UpdateOptions, Collection};
-use netsblox_cloud_common::{api, AuthorizedServiceHost};
+use mongodb::{
+    bson::{doc, DateTime},
+    options::UpdateOptions,
+    Collection,
+};
+use netsblox_cloud_common::{api, AuditLogEntry, AuthorizedServiceHost};
 use regex::Regex;
+use uuid::Uuid;
 
 use crate::{
-    auth,
+    audit, auth,
+    config::ServiceHostSettings,
     errors::{InternalError, UserError},
 };
 
+use super::signing::{self, KeyCipher};
+
 pub(crate) struct HostActions<'a> {
     authorized_services: &'a Collection<AuthorizedServiceHost>,
+    audit_log: &'a Collection<AuditLogEntry>,
+    settings: &'a ServiceHostSettings,
+    cipher: KeyCipher,
 }
 
 impl<'a> HostActions<'a> {
-    pub(crate) fn new(authorized_services: &'a Collection<AuthorizedServiceHost>) -> Self {
+    pub(crate) fn new(
+        authorized_services: &'a Collection<AuthorizedServiceHost>,
+        audit_log: &'a Collection<AuditLogEntry>,
+        settings: &'a ServiceHostSettings,
+        cipher: KeyCipher,
+    ) -> Self {
         Self {
             authorized_services,
+            audit_log,
+            settings,
+            cipher,
         }
     }
 
@@ -43,13 +64,40 @@ impl<'a> HostActions<'a> {
     }
     pub(crate) async fn authorize(
         &self,
-        _ah: &auth::AuthorizeHost,
+        ah: &auth::AuthorizeHost,
         host: api::AuthorizedServiceHost,
     ) -> Result<String, UserError> {
         ensure_valid_service_id(&host.id)?;
 
+        let secret = new_secret();
         let query = doc! {"id": &host.id};
-        let host: AuthorizedServiceHost = host.into();
+        let host = AuthorizedServiceHost::new(
+            host.url,
+            host.id,
+            host.visibility,
+            host.capabilities,
+            signing::host_secret(&self.cipher, &secret)?,
+        );
+        let exists = self
+            .authorized_services
+            .count_documents(query.clone(), None)
+            .await
+            .map_err(InternalError::DatabaseConnectionError)?
+            > 0;
+
+        if exists {
+            return Err(UserError::ServiceHostAlreadyAuthorizedError);
+        }
+
+        let entry = AuditLogEntry::new(
+            ah.admin.clone(),
+            api::AuditAction::AuthorizeHost,
+            host.id.clone(),
+            &(),
+            &host_summary(&host),
+        );
+        audit::record(self.audit_log, entry).await?;
+
         let update = doc! {"$setOnInsert": &host};
         let options = UpdateOptions::builder().upsert(true).build();
         let result = self
@@ -59,7 +107,7 @@ impl<'a> HostActions<'a> {
             .map_err(InternalError::DatabaseConnectionError)?;
 
         if result.matched_count == 0 {
-            Ok(host.secret)
+            Ok(secret)
         } else {
             Err(UserError::ServiceHostAlreadyAuthorizedError)
         }
@@ -67,19 +115,99 @@ impl<'a> HostActions<'a> {
 
     pub(crate) async fn unauthorize(
         &self,
-        _ah: &auth::AuthorizeHost,
+        ah: &auth::AuthorizeHost,
         host_id: &str,
     ) -> Result<api::AuthorizedServiceHost, UserError> {
         let query = doc! {"id": &host_id};
         let host = self
             .authorized_services
-            .find_one_and_delete(query, None)
+            .find_one(query.clone(), None)
             .await
             .map_err(InternalError::DatabaseConnectionError)?
             .ok_or(UserError::ServiceHostNotFoundError)?;
 
+        let entry = AuditLogEntry::new(
+            ah.admin.clone(),
+            api::AuditAction::UnauthorizeHost,
+            host.id.clone(),
+            &host_summary(&host),
+            &(),
+        );
+        audit::record(self.audit_log, entry).await?;
+
+        self.authorized_services
+            .delete_one(query, None)
+            .await
+            .map_err(InternalError::DatabaseConnectionError)?;
+
         Ok(host.into())
     }
+
+    /// Create a new secret for the host. The previous secrets remain valid
+    /// for a while so the host can be updated without downtime.
+    pub(crate) async fn rotate_secret(
+        &self,
+        ah: &auth::AuthorizeHost,
+        host_id: &str,
+    ) -> Result<String, UserError> {
+        let query = doc! {"id": &host_id};
+        let host = self
+            .authorized_services
+            .find_one(query.clone(), None)
+            .await
+            .map_err(InternalError::DatabaseConnectionError)?
+            .ok_or(UserError::ServiceHostNotFoundError)?;
+
+        let overlap_end =
+            SystemTime::now() + Duration::from_secs(self.settings.secret_overlap_secs);
+        let overlap_end = DateTime::from_system_time(overlap_end);
+        let active_count = host.active_secrets().count();
+        let mut secrets: Vec<_> = host
+            .active_secrets()
+            .cloned()
+            .map(|mut secret| {
+                let expires_at = secret
+                    .expires_at
+                    .map(|time| time.min(overlap_end))
+                    .unwrap_or(overlap_end);
+                secret.expires_at = Some(expires_at);
+                secret
+            })
+            .collect();
+
+        let secret = new_secret();
+        secrets.push(signing::host_secret(&self.cipher, &secret)?);
+
+        let entry = AuditLogEntry::new(
+            ah.admin.clone(),
+            api::AuditAction::RotateHostSecret,
+            host.id.clone(),
+            &serde_json::json!({"activeSecrets": active_count}),
+            &serde_json::json!({"activeSecrets": secrets.len()}),
+        );
+        audit::record(self.audit_log, entry).await?;
+
+        let update = doc! {"$set": {"secrets": secrets}};
+        self.authorized_services
+            .update_one(query, update, None)
+            .await
+            .map_err(InternalError::DatabaseConnectionError)?;
+
+        Ok(secret)
+    }
+}
+
+fn new_secret() -> String {
+    Uuid::new_v4().to_string()
+}
+
+/// Details of the host to include in the audit log (ie, without the secret)
+fn host_summary(host: &AuthorizedServiceHost) -> serde_json::Value {
+    serde_json::json!({
+        "url": &host.url,
+        "visibility": &host.visibility,
+        "capabilities": &host.capabilities,
+    })
 }
 
 pub fn ensure_valid_service_id(id: &str) -> Result<(), UserError> {
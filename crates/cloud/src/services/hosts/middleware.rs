@@ -0,0 +1,129 @@
+use std::rc::Rc;
+
+use actix_web::{
+    body::MessageBody,
+    dev::{forward_ready, Payload, Service, ServiceRequest, ServiceResponse, Transform},
+    error::PayloadError,
+    web, Error,
+};
+use futures::{
+    future::{ready, LocalBoxFuture, Ready},
+    stream,
+};
+
+use super::signing;
+use crate::errors::UserError;
+
+/// Reject signed requests from service hosts whose body doesn't match the
+/// (signed) content hash. The body is restored afterwards so it can still be
+/// used by the handler.
+pub(crate) struct SignedBodyCheck;
+
+impl<S, B> Transform<S, ServiceRequest> for SignedBodyCheck
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = Error> + 'static,
+    B: MessageBody + 'static,
+{
+    type Response = ServiceResponse<B>;
+    type Error = Error;
+    type Transform = SignedBodyCheckMiddleware<S>;
+    type InitError = ();
+    type Future = Ready<Result<Self::Transform, Self::InitError>>;
+
+    fn new_transform(&self, service: S) -> Self::Future {
+        ready(Ok(SignedBodyCheckMiddleware {
+            service: Rc::new(service),
+        }))
+    }
+}
+
+pub(crate) struct SignedBodyCheckMiddleware<S> {
+    service: Rc<S>,
+}
+
+impl<S, B> Service<ServiceRequest> for SignedBodyCheckMiddleware<S>
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = Error> + 'static,
+    B: MessageBody + 'static,
+{
+    type Response = ServiceResponse<B>;
+    type Error = Error;
+    type Future = LocalBoxFuture<'static, Result<Self::Response, Self::Error>>;
+
+    forward_ready!(service);
+
+    fn call(&self, mut req: ServiceRequest) -> Self::Future {
+        let service = Rc::clone(&self.service);
+
+        Box::pin(async move {
+            if req.headers().contains_key(signing::SIGNATURE_HEADER) {
+                let body = req
+                    .extract::<web::Bytes>()
+                    .await
+                    .map_err(|_err| UserError::InvalidSignatureError)?;
+
+                if !signing::is_signed_body(req.request(), &body) {
+                    return Err(UserError::InvalidSignatureError.into());
+                }
+
+                let stream = stream::once(async move { Ok::<_, PayloadError>(body) });
+                req.set_payload(Payload::Stream {
+                    payload: Box::pin(stream),
+                });
+            }
+
+            service.call(req).await
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use actix_web::{http, post, test, App, HttpResponse};
+
+    use super::*;
+
+    #[post("/send")]
+    async fn send(body: web::Bytes) -> HttpResponse {
+        HttpResponse::Ok().body(body)
+    }
+
+    #[actix_web::test]
+    async fn test_signed_body() {
+        let app = test::init_service(App::new().wrap(SignedBodyCheck).service(send)).await;
+
+        let headers = signing::signed_headers("host", "secret", "POST", "/send", b"hello");
+        let req = headers
+            .into_iter()
+            .fold(test::TestRequest::post(), |req, header| {
+                req.insert_header(header)
+            })
+            .uri("/send")
+            .set_payload("hello")
+            .to_request();
+        let response = test::call_service(&app, req).await;
+        assert_eq!(response.status(), http::StatusCode::OK);
+        assert_eq!(test::read_body(response).await, "hello");
+    }
+
+    #[actix_web::test]
+    async fn test_reject_tampered_body() {
+        let app = test::init_service(App::new().wrap(SignedBodyCheck).service(send)).await;
+
+        let headers = signing::signed_headers("host", "secret", "POST", "/send", b"hello");
+        let req = headers
+            .into_iter()
+            .fold(test::TestRequest::post(), |req, header| {
+                req.insert_header(header)
+            })
+            .uri("/send")
+            .set_payload("tampered")
+            .to_request();
+        let response = test::try_call_service(&app, req).await;
+        let err = response.err().unwrap();
+        assert_eq!(
+            err.as_response_error().status_code(),
+            http::StatusCode::UNAUTHORIZED
+        );
+    }
+}
@@ -1,4 +1,6 @@
 pub(crate) mod actions;
+pub(crate) mod middleware;
 mod routes;
+pub(crate) mod signing;
 
 pub(crate) use routes::config;
@@ -6,6 +6,7 @@ use crate::errors::{InternalError, UserError};
 use crate::groups::actions::GroupActions;
 use crate::services::hosts::actions::HostActions;
 use crate::users::actions::UserActions;
+use actix_web::http::header;
 use actix_web::{delete, get, post, HttpRequest};
 use actix_web::{web, HttpResponse};
 use futures::TryStreamExt;
@@ -166,6 +167,23 @@ async fn unauthorize_host(
     Ok(HttpResponse::Ok().json(host))
 }
 
+#[post("/authorized/{id}/rotate")]
+async fn rotate_host_secret(
+    app: web::Data<AppData>,
+    path: web::Path<(String,)>,
+    req: HttpRequest,
+) -> Result<HttpResponse, UserError> {
+    let (host_id,) = path.into_inner();
+    let auth_ah = auth::try_auth_host(&app, &req).await?;
+
+    let actions: HostActions = app.as_host_actions();
+    let secret = actions.rotate_secret(&auth_ah, &host_id).await?;
+
+    Ok(HttpResponse::Ok()
+        .insert_header(header::CacheControl(vec![header::CacheDirective::NoStore]))
+        .json(secret))
+}
+
 pub fn config(cfg: &mut web::ServiceConfig) {
     cfg.service(list_group_hosts)
         .service(set_group_hosts)
@@ -174,16 +192,17 @@ pub fn config(cfg: &mut web::ServiceConfig) {
         .service(list_all_hosts)
         .service(authorize_host)
         .service(get_authorized_hosts)
-        .service(unauthorize_host);
+        .service(unauthorize_host)
+        .service(rotate_host_secret);
 }
 
 #[cfg(test)]
 mod test {
     use actix_web::{body::MessageBody, http, test, App};
-    use netsblox_cloud_common::{Group, User};
+    use netsblox_cloud_common::{AuthorizedServiceHost, Group, User};
 
     use super::*;
-    use crate::test_utils;
+    use crate::{services::hosts::signing, test_utils, utils};
 
     #[actix_web::test]
     async fn test_set_user_hosts() {
@@ -317,4 +336,104 @@ mod test {
             })
             .await;
     }
+
+    #[actix_web::test]
+    async fn test_rotate_host_secret() {
+        let admin: User = api::NewUser {
+            username: "admin".into(),
+            email: "admin@netsblox.org".into(),
+            password: None,
+            group_id: None,
+            role: Some(api::UserRole::Admin),
+        }
+        .into();
+        let host = AuthorizedServiceHost::new(
+            "http://localhost:5656".into(),
+            "TestServices".into(),
+            api::ServiceHostScope::Private,
+            Vec::new(),
+            signing::test_host_secret("oldSecret"),
+        );
+
+        test_utils::setup()
+            .with_users(&[admin.clone()])
+            .with_authorized_services(&[host.clone()])
+            .run(|app_data| async move {
+                let app = test::init_service(
+                    App::new()
+                        .app_data(web::Data::new(app_data.clone()))
+                        .wrap(test_utils::cookie::middleware())
+                        .configure(config),
+                )
+                .await;
+
+                let req = test::TestRequest::post()
+                    .uri(&format!("/authorized/{}/rotate", &host.id))
+                    .cookie(test_utils::cookie::new(&admin.username))
+                    .to_request();
+
+                let response = test::call_service(&app, req).await;
+                assert_eq!(response.status(), http::StatusCode::OK);
+                let bytes = response.into_body().try_into_bytes().unwrap();
+                let secret: String = serde_json::from_slice(&bytes).unwrap();
+
+                // Both secrets should be active but the old one should expire
+                let host = app_data
+                    .authorized_services
+                    .find_one(doc! {"id": &host.id}, None)
+                    .await
+                    .unwrap()
+                    .unwrap();
+
+                let secrets: Vec<_> = host.active_secrets().collect();
+                assert_eq!(secrets.len(), 2);
+                assert_eq!(secrets[0].hash, utils::sha512("oldSecret"));
+                assert!(secrets[0].expires_at.is_some());
+                assert_eq!(secrets[1].hash, utils::sha512(&secret));
+                assert!(secrets[1].expires_at.is_none());
+                assert!(secrets[1].signing_key.is_some());
+            })
+            .await;
+    }
+
+    #[actix_web::test]
+    async fn test_rotate_host_secret_admin_only() {
+        let user: User = api::NewUser {
+            username: "user".into(),
+            email: "user@netsblox.org".into(),
+            password: None,
+            group_id: None,
+            role: None,
+        }
+        .into();
+        let host = AuthorizedServiceHost::new(
+            "http://localhost:5656".into(),
+            "TestServices".into(),
+            api::ServiceHostScope::Private,
+            Vec::new(),
+            signing::test_host_secret("secret"),
+        );
+
+        test_utils::setup()
+            .with_users(&[user.clone()])
+            .with_authorized_services(&[host.clone()])
+            .run(|app_data| async move {
+                let app = test::init_service(
+                    App::new()
+                        .app_data(web::Data::new(app_data.clone()))
+                        .wrap(test_utils::cookie::middleware())
+                        .configure(config),
+                )
+                .await;
+
+                let req = test::TestRequest::post()
+                    .uri(&format!("/authorized/{}/rotate", &host.id))
+                    .cookie(test_utils::cookie::new(&user.username))
+                    .to_request();
+
+                let response = test::call_service(&app, req).await;
+                assert_eq!(response.status(), http::StatusCode::FORBIDDEN);
+            })
+            .await;
+    }
 }
@@ -0,0 +1,378 @@
+//! Authentication of requests from authorized service hosts.
+//!
+//! Hosts can either send their secret directly (`X-Authorization: <id>:<secret>`)
+//! or sign the request using the following headers:
+//!
+//! ```text
+//! X-Authorization: <id>
+//! X-Timestamp: <seconds since the unix epoch>
+//! X-Nonce: <unique value for the request>
+//! X-Content-SHA256: hex(SHA-256(body))
+//! X-Signature: hex(HMAC-SHA256(key, "<METHOD>\n<path and query>\n<timestamp>\n<nonce>\n<content hash>"))
+//! ```
+//!
+//! The content hash is checked against the body by the `SignedBodyCheck`
+//! middleware so the body can't be replaced without invalidating the signature.
+//!
+//! The key is derived from the secret as `HMAC-SHA256(secret, "signing")`. Only
+//! a hash of the secret is stored (to check requests sending the secret) along
+//! with the signing key encrypted using a server key so neither can be used to
+//! authenticate if the database is leaked.
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use actix_web::HttpRequest;
+use netsblox_cloud_common::HostSecret;
+use ring::{
+    aead, digest, hmac,
+    rand::{SecureRandom, SystemRandom},
+};
+
+use crate::{
+    config::Settings,
+    errors::{InternalError, UserError},
+    utils,
+};
+
+pub(crate) const AUTH_HEADER: &str = "X-Authorization";
+pub(crate) const TIMESTAMP_HEADER: &str = "X-Timestamp";
+pub(crate) const NONCE_HEADER: &str = "X-Nonce";
+pub(crate) const SIGNATURE_HEADER: &str = "X-Signature";
+pub(crate) const CONTENT_HASH_HEADER: &str = "X-Content-SHA256";
+
+const MIN_NONCE_LEN: usize = 8;
+const MAX_NONCE_LEN: usize = 128;
+
+pub(crate) enum HostCredentials {
+    /// The secret itself (ie, `X-Authorization: <id>:<secret>`)
+    Secret {
+        id: String,
+        secret: String,
+    },
+    Signature(SignedRequest),
+}
+
+impl HostCredentials {
+    /// Get the credentials of the request, if the request was sent by a host.
+    /// Signed requests are rejected if they are malformed or the timestamp is
+    /// more than `max_age` seconds off.
+    pub(crate) fn from_request(req: &HttpRequest, max_age: u64) -> Result<Option<Self>, UserError> {
+        let auth = match header(req, AUTH_HEADER) {
+            Some(auth) => auth,
+            None => return Ok(None),
+        };
+
+        let signature = match header(req, SIGNATURE_HEADER) {
+            Some(signature) => signature,
+            None => {
+                let creds = auth.split_once(':').map(|(id, secret)| Self::Secret {
+                    id: id.to_owned(),
+                    secret: secret.to_owned(),
+                });
+                return Ok(creds);
+            }
+        };
+
+        let signature = hex::decode(signature).map_err(|_err| UserError::InvalidSignatureError)?;
+        let nonce = header(req, NONCE_HEADER)
+            .filter(|nonce| (MIN_NONCE_LEN..=MAX_NONCE_LEN).contains(&nonce.len()))
+            .ok_or(UserError::InvalidSignatureError)?;
+        let timestamp = header(req, TIMESTAMP_HEADER)
+            .and_then(|timestamp| timestamp.parse::<u64>().ok())
+            .ok_or(UserError::RequestTimestampError)?;
+        let content_hash = header(req, CONTENT_HASH_HEADER)
+            .filter(|hash| hash.len() == 64 && hash.chars().all(|c| c.is_ascii_hexdigit()))
+            .ok_or(UserError::InvalidSignatureError)?;
+
+        if timestamp.abs_diff(now_secs()) > max_age {
+            return Err(UserError::RequestTimestampError);
+        }
+
+        let path = req
+            .uri()
+            .path_and_query()
+            .map(|path| path.as_str())
+            .unwrap_or_else(|| req.path());
+        let message = message(
+            req.method().as_str(),
+            path,
+            timestamp,
+            nonce,
+            &content_hash.to_ascii_lowercase(),
+        );
+
+        Ok(Some(Self::Signature(SignedRequest {
+            id: auth.to_owned(),
+            nonce: nonce.to_owned(),
+            message,
+            signature,
+        })))
+    }
+}
+
+pub(crate) struct SignedRequest {
+    pub(crate) id: String,
+    pub(crate) nonce: String,
+    message: String,
+    signature: Vec<u8>,
+}
+
+impl SignedRequest {
+    /// Check if the request was signed using the given (signing) key
+    pub(crate) fn is_signed_with(&self, key: &[u8]) -> bool {
+        let key = hmac::Key::new(hmac::HMAC_SHA256, key);
+        hmac::verify(&key, self.message.as_bytes(), &self.signature).is_ok()
+    }
+}
+
+/// Hex-encoded SHA-256 hash of a request body (as sent in the `X-Content-SHA256` header)
+pub(crate) fn content_hash(body: &[u8]) -> String {
+    hex::encode(digest::digest(&digest::SHA256, body))
+}
+
+/// Check the body of a signed request against the content hash covered by the
+/// signature. Requests which are not signed are always accepted.
+pub(crate) fn is_signed_body(req: &HttpRequest, body: &[u8]) -> bool {
+    if header(req, SIGNATURE_HEADER).is_none() {
+        return true;
+    }
+
+    header(req, CONTENT_HASH_HEADER)
+        .map(|hash| hash.eq_ignore_ascii_case(&content_hash(body)))
+        .unwrap_or(false)
+}
+
+/// Derive the key used to sign requests from the secret of a host
+pub(crate) fn signing_key(secret: &str) -> Vec<u8> {
+    let key = hmac::Key::new(hmac::HMAC_SHA256, secret.as_bytes());
+    hmac::sign(&key, b"signing").as_ref().to_vec()
+}
+
+/// Hash the secret of a host and encrypt the signing key derived from it
+pub(crate) fn host_secret(cipher: &KeyCipher, secret: &str) -> Result<HostSecret, InternalError> {
+    let signing_key = cipher.encrypt(&signing_key(secret))?;
+    Ok(HostSecret::new(utils::sha512(secret), Some(signing_key)))
+}
+
+/// Encryption of the signing keys stored for hosts. The encryption key is derived
+/// from `service_hosts.key` (or the cookie key if unset).
+pub(crate) struct KeyCipher {
+    key: aead::LessSafeKey,
+}
+
+impl KeyCipher {
+    pub(crate) fn new(settings: &Settings) -> Self {
+        let server_key = settings
+            .service_hosts
+            .key
+            .as_ref()
+            .unwrap_or(&settings.cookie.key);
+        let server_key = hmac::Key::new(hmac::HMAC_SHA256, server_key.as_bytes());
+        let key_bytes = hmac::sign(&server_key, b"hostSigningKeys");
+        // HMAC-SHA256 tags are always the length of an AES-256 key
+        let key = aead::UnboundKey::new(&aead::AES_256_GCM, key_bytes.as_ref()).unwrap();
+
+        Self {
+            key: aead::LessSafeKey::new(key),
+        }
+    }
+
+    /// Encrypt the signing key. The (hex-encoded) nonce is prepended to the ciphertext.
+    pub(crate) fn encrypt(&self, signing_key: &[u8]) -> Result<String, InternalError> {
+        let mut nonce = [0u8; aead::NONCE_LEN];
+        SystemRandom::new()
+            .fill(&mut nonce)
+            .map_err(|_err| InternalError::HostKeyEncryptionError)?;
+
+        let mut data = signing_key.to_vec();
+        self.key
+            .seal_in_place_append_tag(
+                aead::Nonce::assume_unique_for_key(nonce),
+                aead::Aad::empty(),
+                &mut data,
+            )
+            .map_err(|_err| InternalError::HostKeyEncryptionError)?;
+
+        Ok(format!("{}{}", hex::encode(nonce), hex::encode(data)))
+    }
+
+    /// Decrypt a signing key (returning None if it can't be decrypted with the
+    /// current server key)
+    pub(crate) fn decrypt(&self, encrypted: &str) -> Option<Vec<u8>> {
+        let data = hex::decode(encrypted).ok()?;
+        if data.len() < aead::NONCE_LEN {
+            return None;
+        }
+        let (nonce, data) = data.split_at(aead::NONCE_LEN);
+        let nonce = aead::Nonce::try_assume_unique_for_key(nonce).ok()?;
+
+        let mut data = data.to_vec();
+        let key = self
+            .key
+            .open_in_place(nonce, aead::Aad::empty(), &mut data)
+            .ok()?;
+        Some(key.to_vec())
+    }
+}
+
+/// Create a secret for a host in tests (encrypted using the default test settings)
+#[cfg(test)]
+pub(crate) fn test_host_secret(secret: &str) -> HostSecret {
+    let settings = Settings::new().unwrap();
+    host_secret(&KeyCipher::new(&settings), secret).unwrap()
+}
+
+/// Sign a request with the given (signing) key
+#[cfg(test)]
+fn sign(
+    key: &[u8],
+    method: &str,
+    path: &str,
+    timestamp: u64,
+    nonce: &str,
+    content_hash: &str,
+) -> String {
+    let key = hmac::Key::new(hmac::HMAC_SHA256, key);
+    let message = message(method, path, timestamp, nonce, content_hash);
+    hex::encode(hmac::sign(&key, message.as_bytes()))
+}
+
+fn message(method: &str, path: &str, timestamp: u64, nonce: &str, content_hash: &str) -> String {
+    format!(
+        "{}\n{}\n{}\n{}\n{}",
+        method, path, timestamp, nonce, content_hash
+    )
+}
+
+fn header<'a>(req: &'a HttpRequest, name: &str) -> Option<&'a str> {
+    req.headers()
+        .get(name)
+        .and_then(|value| value.to_str().ok())
+}
+
+pub(crate) fn now_secs() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|duration| duration.as_secs())
+        .unwrap_or(0)
+}
+
+/// Headers for a request (with the given body) signed using the given secret
+#[cfg(test)]
+pub(crate) fn signed_headers(
+    id: &str,
+    secret: &str,
+    method: &str,
+    path: &str,
+    body: &[u8],
+) -> Vec<(&'static str, String)> {
+    let key = signing_key(secret);
+    let timestamp = now_secs();
+    let nonce = uuid::Uuid::new_v4().to_string();
+    let content_hash = content_hash(body);
+    let signature = sign(&key, method, path, timestamp, &nonce, &content_hash);
+
+    vec![
+        (AUTH_HEADER, id.to_owned()),
+        (TIMESTAMP_HEADER, timestamp.to_string()),
+        (NONCE_HEADER, nonce),
+        (CONTENT_HASH_HEADER, content_hash),
+        (SIGNATURE_HEADER, signature),
+    ]
+}
+
+#[cfg(test)]
+mod tests {
+    use actix_web::test::TestRequest;
+
+    use super::*;
+
+    fn signed_request(headers: Vec<(&'static str, String)>) -> SignedRequest {
+        let req = headers
+            .into_iter()
+            .fold(TestRequest::post().uri("/send?a=1"), |req, header| {
+                req.insert_header(header)
+            })
+            .to_http_request();
+
+        match HostCredentials::from_request(&req, 60) {
+            Ok(Some(HostCredentials::Signature(req))) => req,
+            _ => panic!("Expected signed request"),
+        }
+    }
+
+    #[actix_web::test]
+    async fn test_verify_signature() {
+        let headers = signed_headers("host", "secret", "POST", "/send?a=1", b"");
+        let req = signed_request(headers);
+
+        assert_eq!(req.id, "host");
+        assert!(req.is_signed_with(&signing_key("secret")));
+        assert!(!req.is_signed_with(&signing_key("otherSecret")));
+        // the stored hash of the secret can't be used to sign requests
+        assert!(!req.is_signed_with(utils::sha512("secret").as_bytes()));
+    }
+
+    #[test]
+    fn test_encrypt_signing_key() {
+        let mut settings = Settings::new().unwrap();
+        let cipher = KeyCipher::new(&settings);
+        let encrypted = cipher.encrypt(&signing_key("secret")).unwrap();
+        assert_eq!(cipher.decrypt(&encrypted), Some(signing_key("secret")));
+
+        settings.service_hosts.key = Some("otherKey".into());
+        let cipher = KeyCipher::new(&settings);
+        assert!(cipher.decrypt(&encrypted).is_none());
+    }
+
+    #[actix_web::test]
+    async fn test_signature_covers_path() {
+        let headers = signed_headers("host", "secret", "POST", "/send?a=2", b"");
+        let req = signed_request(headers);
+
+        assert!(!req.is_signed_with(&signing_key("secret")));
+    }
+
+    #[actix_web::test]
+    async fn test_signature_covers_content_hash() {
+        let mut headers = signed_headers("host", "secret", "POST", "/send?a=1", b"body");
+        for (name, value) in headers.iter_mut() {
+            if *name == CONTENT_HASH_HEADER {
+                *value = content_hash(b"tampered");
+            }
+        }
+        let req = signed_request(headers);
+
+        assert!(!req.is_signed_with(&signing_key("secret")));
+    }
+
+    #[actix_web::test]
+    async fn test_reject_old_timestamp() {
+        let key = signing_key("secret");
+        let timestamp = now_secs() - 120;
+        let hash = content_hash(b"");
+        let signature = sign(&key, "GET", "/", timestamp, "someNonce", &hash);
+        let req = TestRequest::get()
+            .insert_header((AUTH_HEADER, "host"))
+            .insert_header((TIMESTAMP_HEADER, timestamp.to_string()))
+            .insert_header((NONCE_HEADER, "someNonce"))
+            .insert_header((CONTENT_HASH_HEADER, hash))
+            .insert_header((SIGNATURE_HEADER, signature))
+            .to_http_request();
+
+        let result = HostCredentials::from_request(&req, 60);
+        assert!(matches!(result, Err(UserError::RequestTimestampError)));
+    }
+
+    #[actix_web::test]
+    async fn test_secret_credentials() {
+        let req = TestRequest::get()
+            .insert_header((AUTH_HEADER, "host:secret"))
+            .to_http_request();
+
+        let creds = HostCredentials::from_request(&req, 60).unwrap();
+        assert!(matches!(
+            creds,
+            Some(HostCredentials::Secret { id, secret }) if id == "host" && secret == "secret"
+        ));
+    }
+}
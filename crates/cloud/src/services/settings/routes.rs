@@ -14,7 +14,7 @@ async fn list_user_hosts_with_settings(
     req: HttpRequest,
 ) -> Result<HttpResponse, UserError> {
     let (username,) = path.into_inner();
-    let auth_vu = auth::try_view_user(&app, &req, None, &username).await?;
+    let auth_vu = auth::try_view_user_settings(&app, &req, &username).await?;
 
     let actions: UserActions = app.as_user_actions();
     let settings = actions.get_service_settings(&auth_vu).await?;
@@ -30,7 +30,7 @@ async fn get_user_settings(
     req: HttpRequest,
 ) -> Result<HttpResponse, UserError> {
     let (username, host) = path.into_inner();
-    let auth_vu = auth::try_view_user(&app, &req, None, &username).await?;
+    let auth_vu = auth::try_view_user_settings(&app, &req, &username).await?;
 
     let actions: UserActions = app.as_user_actions();
     let settings = actions.get_service_settings(&auth_vu).await?;
@@ -48,7 +48,7 @@ async fn get_all_settings(
 ) -> Result<HttpResponse, UserError> {
     let (username, host) = path.into_inner();
 
-    let auth_vu = auth::try_view_user(&app, &req, None, &username).await?;
+    let auth_vu = auth::try_view_user_settings(&app, &req, &username).await?;
     let actions: SettingsActions = app.as_settings_actions();
     let all_settings = actions.get_settings(&auth_vu, &host).await?;
 
@@ -96,7 +96,7 @@ async fn list_group_hosts_with_settings(
 ) -> Result<HttpResponse, UserError> {
     let (group_id,) = path.into_inner();
 
-    let auth_vg = auth::try_view_group(&app, &req, &group_id).await?;
+    let auth_vg = auth::try_view_group_settings(&app, &req, &group_id).await?;
 
     let actions: GroupActions = app.as_group_actions();
     let settings = actions.get_service_settings(&auth_vg).await?;
@@ -112,7 +112,7 @@ async fn get_group_settings(
     req: HttpRequest,
 ) -> Result<HttpResponse, UserError> {
     let (group_id, host) = path.into_inner();
-    let auth_vg = auth::try_view_group(&app, &req, &group_id).await?;
+    let auth_vg = auth::try_view_group_settings(&app, &req, &group_id).await?;
 
     let actions: GroupActions = app.as_group_actions();
     let settings = actions.get_service_settings(&auth_vg).await?;
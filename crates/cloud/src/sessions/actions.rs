@@ -0,0 +1,127 @@
+use futures::TryStreamExt;
+use mongodb::{bson::doc, options::FindOptions, Collection};
+use netsblox_cloud_common::{api, UserSession};
+
+use crate::{
+    auth,
+    errors::{InternalError, UserError},
+};
+
+pub(crate) struct SessionActions<'a> {
+    sessions: &'a Collection<UserSession>,
+}
+
+impl<'a> SessionActions<'a> {
+    pub(crate) fn new(sessions: &'a Collection<UserSession>) -> Self {
+        Self { sessions }
+    }
+
+    pub(crate) async fn list_sessions(
+        &self,
+        ms: &auth::ManageSessions,
+    ) -> Result<Vec<api::UserSession>, UserError> {
+        let query = doc! {"username": &ms.username};
+        let options = FindOptions::builder().sort(doc! {"lastSeen": -1}).build();
+        let sessions = self
+            .sessions
+            .find(query, options)
+            .await
+            .map_err(InternalError::DatabaseConnectionError)?
+            .try_collect::<Vec<_>>()
+            .await
+            .map_err(InternalError::DatabaseConnectionError)?
+            .into_iter()
+            .map(|session| session.into())
+            .collect();
+
+        Ok(sessions)
+    }
+
+    pub(crate) async fn revoke_session(
+        &self,
+        ms: &auth::ManageSessions,
+        id: &str,
+    ) -> Result<api::UserSession, UserError> {
+        let query = doc! {"id": id, "username": &ms.username};
+        let session = self
+            .sessions
+            .find_one_and_delete(query, None)
+            .await
+            .map_err(InternalError::DatabaseConnectionError)?
+            .ok_or(UserError::SessionNotFoundError)?;
+
+        Ok(session.into())
+    }
+
+    /// Log the user out everywhere
+    pub(crate) async fn revoke_all_sessions(
+        &self,
+        ms: &auth::ManageSessions,
+    ) -> Result<(), UserError> {
+        let query = doc! {"username": &ms.username};
+        self.sessions
+            .delete_many(query, None)
+            .await
+            .map_err(InternalError::DatabaseConnectionError)?;
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{collections::HashMap, time::Duration};
+
+    use super::*;
+    use crate::test_utils;
+
+    fn session_for(username: &str) -> UserSession {
+        let state = HashMap::from([
+            ("username".to_owned(), format!("\"{}\"", username)),
+            ("device".to_owned(), "\"Firefox\"".to_owned()),
+        ]);
+        UserSession::new(
+            uuid::Uuid::new_v4().to_string(),
+            state,
+            Duration::from_secs(60),
+        )
+    }
+
+    #[actix_web::test]
+    async fn test_list_sessions() {
+        let session = session_for("user");
+        let other = session_for("other");
+
+        test_utils::setup()
+            .with_sessions(&[session.clone(), other])
+            .run(|app_data| async move {
+                let actions = app_data.as_session_actions();
+
+                let auth_ms = auth::ManageSessions::test("user".into());
+                let sessions = actions.list_sessions(&auth_ms).await.unwrap();
+                assert_eq!(sessions.len(), 1);
+                assert_eq!(sessions[0].id, session.id);
+                assert_eq!(sessions[0].device, Some("Firefox".into()));
+            })
+            .await;
+    }
+
+    #[actix_web::test]
+    async fn test_revoke_session_other_user() {
+        let session = session_for("other");
+
+        test_utils::setup()
+            .with_sessions(&[session.clone()])
+            .run(|app_data| async move {
+                let actions = app_data.as_session_actions();
+
+                let auth_ms = auth::ManageSessions::test("user".into());
+                let result = actions.revoke_session(&auth_ms, &session.id).await;
+                assert!(matches!(result, Err(UserError::SessionNotFoundError)));
+
+                let count = app_data.sessions.count_documents(None, None).await.unwrap();
+                assert_eq!(count, 1);
+            })
+            .await;
+    }
+}
@@ -0,0 +1,2 @@
+pub(crate) mod actions;
+pub(crate) mod store;
@@ -0,0 +1,212 @@
+use std::collections::HashMap;
+use std::time::SystemTime;
+
+use actix_session::storage::{LoadError, SaveError, SessionKey, SessionStore, UpdateError};
+use actix_web::cookie::time::Duration;
+use anyhow::anyhow;
+use mongodb::{bson::doc, bson::DateTime, Collection};
+use netsblox_cloud_common::UserSession;
+use uuid::Uuid;
+
+use crate::utils;
+
+/// Minimum time between updates to the "last seen" time of a session
+const LAST_SEEN_RESOLUTION: std::time::Duration = std::time::Duration::from_secs(60);
+
+/// Session storage backed by MongoDB so sessions can be listed and revoked.
+#[derive(Clone)]
+pub(crate) struct MongoSessionStore {
+    sessions: Collection<UserSession>,
+}
+
+impl MongoSessionStore {
+    pub(crate) fn new(sessions: Collection<UserSession>) -> Self {
+        Self { sessions }
+    }
+}
+
+#[async_trait::async_trait(?Send)]
+impl SessionStore for MongoSessionStore {
+    async fn load(
+        &self,
+        session_key: &SessionKey,
+    ) -> Result<Option<HashMap<String, String>>, LoadError> {
+        let query = doc! {
+            "keyHash": hash_key(session_key),
+            "expiresAt": {"$gt": DateTime::now()},
+        };
+        let session = self
+            .sessions
+            .find_one(query, None)
+            .await
+            .map_err(|err| LoadError::Other(err.into()))?;
+
+        Ok(session.map(|session| session.state))
+    }
+
+    async fn save(
+        &self,
+        session_state: HashMap<String, String>,
+        ttl: &Duration,
+    ) -> Result<SessionKey, SaveError> {
+        let key = SessionKey::try_from(new_key()).map_err(|err| SaveError::Other(err.into()))?;
+        let session = UserSession::new(hash_key(&key), session_state, to_std(ttl));
+
+        self.sessions
+            .insert_one(session, None)
+            .await
+            .map_err(|err| SaveError::Other(err.into()))?;
+
+        Ok(key)
+    }
+
+    async fn update(
+        &self,
+        session_key: SessionKey,
+        session_state: HashMap<String, String>,
+        ttl: &Duration,
+    ) -> Result<SessionKey, UpdateError> {
+        let query = doc! {"keyHash": hash_key(&session_key)};
+        let mut session = self
+            .sessions
+            .find_one(query.clone(), None)
+            .await
+            .map_err(|err| UpdateError::Other(err.into()))?
+            // Sessions which have been revoked should not be recreated
+            .ok_or_else(|| UpdateError::Other(anyhow!("Session not found.")))?;
+
+        session.set_state(session_state);
+        let now = SystemTime::now();
+        let update = doc! {
+            "$set": {
+                "state": mongodb::bson::to_bson(&session.state)
+                    .map_err(|err| UpdateError::Serialization(err.into()))?,
+                "username": session.username,
+                "device": session.device,
+                "ip": session.ip,
+                "lastSeen": DateTime::from_system_time(now),
+                "expiresAt": DateTime::from_system_time(now + to_std(ttl)),
+            }
+        };
+        self.sessions
+            .update_one(query, update, None)
+            .await
+            .map_err(|err| UpdateError::Other(err.into()))?;
+
+        Ok(session_key)
+    }
+
+    async fn update_ttl(&self, session_key: &SessionKey, ttl: &Duration) -> anyhow::Result<()> {
+        let now = SystemTime::now();
+        let query = doc! {
+            "keyHash": hash_key(session_key),
+            "lastSeen": {"$lt": DateTime::from_system_time(now - LAST_SEEN_RESOLUTION)},
+        };
+        let update = doc! {
+            "$set": {
+                "lastSeen": DateTime::from_system_time(now),
+                "expiresAt": DateTime::from_system_time(now + to_std(ttl)),
+            }
+        };
+        self.sessions.update_one(query, update, None).await?;
+
+        Ok(())
+    }
+
+    async fn delete(&self, session_key: &SessionKey) -> anyhow::Result<()> {
+        let query = doc! {"keyHash": hash_key(session_key)};
+        self.sessions.delete_one(query, None).await?;
+
+        Ok(())
+    }
+}
+
+fn new_key() -> String {
+    format!(
+        "{}{}",
+        Uuid::new_v4().as_simple(),
+        Uuid::new_v4().as_simple()
+    )
+}
+
+fn hash_key(key: &SessionKey) -> String {
+    utils::sha512(key.as_ref())
+}
+
+fn to_std(ttl: &Duration) -> std::time::Duration {
+    std::time::Duration::from_secs(ttl.whole_seconds().max(0) as u64)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_utils;
+
+    #[actix_web::test]
+    async fn test_save_load_session() {
+        test_utils::setup()
+            .run(|app_data| async move {
+                let store = app_data.as_session_store();
+                let state = HashMap::from([
+                    ("username".to_owned(), "\"user\"".to_owned()),
+                    ("ip".to_owned(), "\"127.0.0.1\"".to_owned()),
+                ]);
+                let key = store
+                    .save(state.clone(), &Duration::minutes(5))
+                    .await
+                    .unwrap();
+
+                let loaded = store.load(&key).await.unwrap();
+                assert_eq!(loaded, Some(state));
+
+                let query = doc! {"username": "user"};
+                let session = app_data.sessions.find_one(query, None).await.unwrap();
+                let session = session.unwrap();
+                assert_ne!(
+                    session.key_hash,
+                    key.as_ref(),
+                    "Session key stored in plaintext"
+                );
+                assert_eq!(session.ip, Some("127.0.0.1".into()));
+            })
+            .await;
+    }
+
+    #[actix_web::test]
+    async fn test_load_expired_session() {
+        test_utils::setup()
+            .run(|app_data| async move {
+                let store = app_data.as_session_store();
+                let key = store
+                    .save(HashMap::new(), &Duration::seconds(0))
+                    .await
+                    .unwrap();
+
+                let loaded = store.load(&key).await.unwrap();
+                assert!(loaded.is_none());
+            })
+            .await;
+    }
+
+    #[actix_web::test]
+    async fn test_update_revoked_session() {
+        test_utils::setup()
+            .run(|app_data| async move {
+                let store = app_data.as_session_store();
+                let key = store
+                    .save(HashMap::new(), &Duration::minutes(5))
+                    .await
+                    .unwrap();
+
+                app_data.sessions.delete_many(doc! {}, None).await.unwrap();
+
+                let result = store
+                    .update(key, HashMap::new(), &Duration::minutes(5))
+                    .await;
+                assert!(result.is_err());
+                let count = app_data.sessions.count_documents(None, None).await.unwrap();
+                assert_eq!(count, 0, "Revoked session was recreated");
+            })
+            .await;
+    }
+}
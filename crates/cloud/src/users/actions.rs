@@ -1,6 +1,8 @@
 use std::{
     collections::HashMap,
+    net::IpAddr,
     sync::{Arc, RwLock},
+    time::{Duration, SystemTime},
 };
 
 use crate::auth;
@@ -11,31 +13,66 @@ use lettre::{
     message::{Mailbox, MultiPart},
     Address, Message, SmtpTransport,
 };
+use log::warn;
 use lru::LruCache;
 use mongodb::{
-    bson::doc,
-    options::{FindOneAndUpdateOptions, ReturnDocument},
+    bson::{doc, DateTime},
+    options::{FindOneAndUpdateOptions, FindOptions, ReplaceOptions, ReturnDocument},
     Collection,
 };
-use netsblox_cloud_common::{api, BannedAccount, SetPasswordToken, User};
+use netsblox_cloud_common::{
+    api,
+    password::{self, HashParams},
+    AccessToken, AccountDeletion, AuditLogEntry, BannedAccount, BannedAddress,
+    EmailVerificationToken, OAuthRefreshToken, OAuthToken, SetPasswordToken, SignupChallenge,
+    TwoFactorAuth, User, UserSession,
+};
 use nonempty::NonEmpty;
 use regex::Regex;
 use rustrict::CensorStr;
 
 use crate::{
     app_data::metrics,
+    audit,
+    config::SignupSettings,
     errors::{InternalError, UserError},
+    ip_reputation::IpLists,
     network::topology::{self, TopologyActor},
     utils,
 };
 
-use super::{email_template, strategies};
+use super::strategies::{self, LoginFlow, Strategies};
+use super::{email_template, signup, two_factor};
+
+/// Info about the client creating an account (used to limit signups)
+#[derive(Default)]
+pub(crate) struct SignupClient {
+    pub(crate) addr: Option<IpAddr>,
+    pub(crate) user_agent: Option<String>,
+    pub(crate) solution: Option<api::SignupChallengeSolution>,
+}
+
+/// Minimum time between verification emails sent to a user
+const RESEND_VERIFICATION: Duration = Duration::from_secs(60 * 60);
 
 pub(crate) struct UserActions<'a> {
     users: &'a Collection<User>,
     banned_accounts: &'a Collection<BannedAccount>,
+    banned_addresses: &'a Collection<BannedAddress>,
+    ip_lists: &'a IpLists,
+    sessions: &'a Collection<UserSession>,
+    access_tokens: &'a Collection<AccessToken>,
+    oauth_tokens: &'a Collection<OAuthToken>,
+    oauth_refresh_tokens: &'a Collection<OAuthRefreshToken>,
     password_tokens: &'a Collection<SetPasswordToken>,
+    verification_tokens: &'a Collection<EmailVerificationToken>,
     metrics: &'a metrics::Metrics,
+    hash_params: &'a HashParams,
+    strategies: &'a Strategies,
+    audit_log: &'a Collection<AuditLogEntry>,
+    account_deletions: &'a Collection<AccountDeletion>,
+    signup_challenges: &'a Collection<SignupChallenge>,
+    signup_settings: &'a SignupSettings,
 
     network: &'a Addr<TopologyActor>,
 
@@ -52,8 +89,21 @@ pub(crate) struct UserActions<'a> {
 pub(crate) struct UserActionData<'a> {
     pub(crate) users: &'a Collection<User>,
     pub(crate) banned_accounts: &'a Collection<BannedAccount>,
+    pub(crate) banned_addresses: &'a Collection<BannedAddress>,
+    pub(crate) ip_lists: &'a IpLists,
+    pub(crate) sessions: &'a Collection<UserSession>,
+    pub(crate) access_tokens: &'a Collection<AccessToken>,
+    pub(crate) oauth_tokens: &'a Collection<OAuthToken>,
+    pub(crate) oauth_refresh_tokens: &'a Collection<OAuthRefreshToken>,
     pub(crate) password_tokens: &'a Collection<SetPasswordToken>,
+    pub(crate) verification_tokens: &'a Collection<EmailVerificationToken>,
     pub(crate) metrics: &'a metrics::Metrics,
+    pub(crate) hash_params: &'a HashParams,
+    pub(crate) strategies: &'a Strategies,
+    pub(crate) audit_log: &'a Collection<AuditLogEntry>,
+    pub(crate) account_deletions: &'a Collection<AccountDeletion>,
+    pub(crate) signup_challenges: &'a Collection<SignupChallenge>,
+    pub(crate) signup_settings: &'a SignupSettings,
 
     pub(crate) network: &'a Addr<TopologyActor>,
     pub(crate) friend_cache: &'a Arc<RwLock<LruCache<String, Vec<String>>>>,
@@ -69,8 +119,21 @@ impl<'a> UserActions<'a> {
         UserActions {
             users: data.users,
             banned_accounts: data.banned_accounts,
+            banned_addresses: data.banned_addresses,
+            ip_lists: data.ip_lists,
+            sessions: data.sessions,
+            access_tokens: data.access_tokens,
+            oauth_tokens: data.oauth_tokens,
+            oauth_refresh_tokens: data.oauth_refresh_tokens,
             password_tokens: data.password_tokens,
+            verification_tokens: data.verification_tokens,
             metrics: data.metrics,
+            hash_params: data.hash_params,
+            strategies: data.strategies,
+            audit_log: data.audit_log,
+            account_deletions: data.account_deletions,
+            signup_challenges: data.signup_challenges,
+            signup_settings: data.signup_settings,
 
             network: data.network,
 
@@ -82,10 +145,26 @@ impl<'a> UserActions<'a> {
         }
     }
 
-    pub(crate) async fn create_user(&self, cu: auth::CreateUser) -> Result<api::User, UserError> {
+    pub(crate) async fn create_user(
+        &self,
+        cu: auth::CreateUser,
+        client: SignupClient,
+    ) -> Result<api::User, UserError> {
         ensure_valid_email(&cu.data.email)?;
-        let user: User = cu.data.into();
+        let mut user = User::from_new_user(cu.data, self.hash_params)
+            .map_err(InternalError::PasswordHashError)?;
         ensure_valid_username(&user.username)?;
+        // Members are managed by the group owner so only other users need to verify their email
+        user.verified = user.is_member();
+
+        if cu.public_signup {
+            self.ensure_signup_allowed(&client).await?;
+            user.signup_ip = client.addr.map(|addr| addr.to_string());
+            user.signup_subnet = client
+                .addr
+                .map(|addr| signup::subnet(&addr, self.signup_settings));
+            user.signup_user_agent = client.user_agent;
+        }
 
         let query = doc! {"email": &user.email};
         if let Some(_account) = self
@@ -97,6 +176,21 @@ impl<'a> UserActions<'a> {
             return Err(UserError::InvalidEmailAddress);
         }
 
+        // The username is still in use until the data owned by a deleted account
+        // with the same name has been removed
+        let query = doc! {
+            "username": &user.username,
+            "state": {"$ne": api::AccountDeletionState::Complete},
+        };
+        if let Some(_deletion) = self
+            .account_deletions
+            .find_one(query, None)
+            .await
+            .map_err(InternalError::DatabaseConnectionError)?
+        {
+            return Err(UserError::UserExistsError);
+        }
+
         let query = doc! {"username": &user.username};
         let update = doc! {"$setOnInsert": &user};
         let options = mongodb::options::FindOneAndUpdateOptions::builder()
@@ -117,11 +211,97 @@ impl<'a> UserActions<'a> {
                     .await;
             }
             self.metrics.record_signup();
+            if !user.verified {
+                // The user can request another email so this doesn't need to fail the signup
+                if let Err(err) = self.request_verification(&user).await {
+                    warn!(
+                        "Unable to send verification email to {}: {}",
+                        &user.username, err
+                    );
+                }
+            }
             let user: api::User = user.into();
             Ok(user)
         }
     }
 
+    /// Issue a proof-of-work challenge for creating an account
+    pub(crate) async fn create_signup_challenge(&self) -> Result<api::SignupChallenge, UserError> {
+        let challenge = SignupChallenge::new(
+            signup::new_challenge_id(),
+            self.signup_settings.challenge_difficulty,
+        );
+        self.signup_challenges
+            .insert_one(&challenge, None)
+            .await
+            .map_err(InternalError::DatabaseConnectionError)?;
+
+        let ttl = Duration::from_secs(self.signup_settings.challenge_ttl_secs);
+        Ok(api::SignupChallenge {
+            expires_at: challenge.created_at.to_system_time() + ttl,
+            id: challenge.id,
+            difficulty: challenge.difficulty,
+        })
+    }
+
+    /// Ensure the client has not created too many accounts recently and has
+    /// solved a signup challenge (if required)
+    async fn ensure_signup_allowed(&self, client: &SignupClient) -> Result<(), UserError> {
+        let settings = self.signup_settings;
+        if let Some(addr) = client.addr {
+            let window = Duration::from_secs(settings.window_secs);
+            let since = DateTime::from_system_time(SystemTime::now() - window);
+            let limits = [
+                ("signupIp", addr.to_string(), settings.max_per_ip),
+                (
+                    "signupSubnet",
+                    signup::subnet(&addr, settings),
+                    settings.max_per_subnet,
+                ),
+            ];
+
+            for (field, value, max_count) in limits {
+                let query = doc! {field: value, "createdAt": {"$gt": since}};
+                let count = self
+                    .users
+                    .count_documents(query, None)
+                    .await
+                    .map_err(InternalError::DatabaseConnectionError)?;
+
+                if count >= max_count {
+                    return Err(UserError::SignupRateLimitError);
+                }
+            }
+        }
+
+        if settings.challenge_difficulty > 0 {
+            let solution = client
+                .solution
+                .as_ref()
+                .ok_or(UserError::SignupChallengeRequiredError)?;
+
+            // Challenges can only be used once
+            let ttl = Duration::from_secs(settings.challenge_ttl_secs);
+            let issued_after = DateTime::from_system_time(SystemTime::now() - ttl);
+            let query = doc! {
+                "id": &solution.challenge_id,
+                "createdAt": {"$gt": issued_after},
+            };
+            let challenge = self
+                .signup_challenges
+                .find_one_and_delete(query, None)
+                .await
+                .map_err(InternalError::DatabaseConnectionError)?
+                .ok_or(UserError::InvalidSignupChallengeError)?;
+
+            if !signup::is_solution(&challenge.id, &solution.nonce, challenge.difficulty) {
+                return Err(UserError::InvalidSignupChallengeError);
+            }
+        }
+
+        Ok(())
+    }
+
     pub(crate) async fn get_user(&self, vu: &auth::ViewUser) -> Result<api::User, UserError> {
         let query = doc! {"username": &vu.username};
         let user = self
@@ -134,15 +314,37 @@ impl<'a> UserActions<'a> {
         Ok(user.into())
     }
 
-    pub(crate) async fn delete_user(&self, eu: &auth::EditUser) -> Result<api::User, UserError> {
-        let query = doc! {"username": &eu.username};
+    /// Delete the user account (and log the user out everywhere). The rest of the
+    /// data owned by the user is removed by an `AccountDeletionJob`.
+    pub(crate) async fn delete_user(&self, du: &auth::DeleteUser) -> Result<api::User, UserError> {
+        let query = doc! {"username": &du.username};
+        self.users
+            .find_one(query.clone(), None)
+            .await
+            .map_err(InternalError::DatabaseConnectionError)?
+            .ok_or(UserError::UserNotFoundError)?;
+
+        // Record the deletion before removing the account so the username can't be
+        // reused until the rest of the data owned by the user has been removed
+        let deletion = AccountDeletion::new(du.username.clone(), du.requestor.clone());
+        let options = ReplaceOptions::builder().upsert(true).build();
+        self.account_deletions
+            .replace_one(query.clone(), &deletion, options)
+            .await
+            .map_err(InternalError::DatabaseConnectionError)?;
+
         let user = self
             .users
-            .find_one_and_delete(query, None)
+            .find_one_and_delete(query.clone(), None)
             .await
             .map_err(InternalError::DatabaseConnectionError)?
             .ok_or(UserError::UserNotFoundError)?;
 
+        self.sessions
+            .delete_many(query, None)
+            .await
+            .map_err(InternalError::DatabaseConnectionError)?;
+
         if let Some(group_id) = user.group_id.as_ref() {
             utils::group_members_updated(self.users, self.friend_cache.clone(), group_id).await;
         }
@@ -150,9 +352,52 @@ impl<'a> UserActions<'a> {
         Ok(user.into())
     }
 
+    pub(crate) async fn view_account_deletion(
+        &self,
+        vd: &auth::ViewAccountDeletion,
+    ) -> Result<api::AccountDeletion, UserError> {
+        let query = doc! {"username": &vd.username};
+        let deletion = self
+            .account_deletions
+            .find_one(query, None)
+            .await
+            .map_err(InternalError::DatabaseConnectionError)?
+            .ok_or(UserError::AccountDeletionNotFoundError)?;
+
+        Ok(deletion.into())
+    }
+
     pub(crate) async fn login(&self, request: api::LoginRequest) -> Result<api::User, UserError> {
         //let client_id = request.client_id.clone();
-        let user = strategies::login(self.users, request.credentials).await?;
+        let user = strategies::login(
+            self.users,
+            self.hash_params,
+            self.strategies,
+            request.credentials,
+        )
+        .await?;
+        self.check_two_factor(&user, request.two_factor_code.as_deref())
+            .await?;
+
+        Ok(user.into())
+    }
+
+    /// Complete a login using an external identity provider (eg, OpenID
+    /// Connect). A new user is created if the account has not been linked.
+    pub(crate) async fn login_external(
+        &self,
+        flow: &LoginFlow,
+        code: &str,
+    ) -> Result<api::User, UserError> {
+        let strategy = self.strategies.get(&flow.strategy)?;
+        let identity = strategy.exchange_code(code, flow).await?;
+        let user = strategies::login_external(self.users, identity).await?;
+
+        // There is no way to provide a code when redirected from the provider
+        // so two-factor authentication cannot be bypassed by linking an account
+        if user.has_two_factor() {
+            return Err(UserError::TwoFactorRequiredError);
+        }
 
         Ok(user.into())
     }
@@ -203,14 +448,120 @@ impl<'a> UserActions<'a> {
         Ok(())
     }
 
+    /// Send an email with a link for verifying the email address of the given user
+    pub(crate) async fn resend_verification(&self, eu: &auth::EditUser) -> Result<(), UserError> {
+        let query = doc! {"username": &eu.username};
+        let user = self
+            .users
+            .find_one(query, None)
+            .await
+            .map_err(InternalError::DatabaseConnectionError)?
+            .ok_or(UserError::UserNotFoundError)?;
+
+        if user.verified {
+            return Err(UserError::EmailAlreadyVerifiedError);
+        }
+
+        self.request_verification(&user).await
+    }
+
+    async fn request_verification(&self, user: &User) -> Result<(), UserError> {
+        // Only 1 email can be sent per hour but links are valid for longer so
+        // replace any token that is older than that.
+        let resend_cutoff = DateTime::from_system_time(SystemTime::now() - RESEND_VERIFICATION);
+        let query = doc! {"username": &user.username, "createdAt": {"$lt": resend_cutoff}};
+        self.verification_tokens
+            .delete_one(query, None)
+            .await
+            .map_err(InternalError::DatabaseConnectionError)?;
+
+        let token = EmailVerificationToken::new(user.username.clone(), user.email.clone());
+        let query = doc! {"username": &user.username};
+        let update = doc! {"$setOnInsert": &token};
+        let options = mongodb::options::UpdateOptions::builder()
+            .upsert(true)
+            .build();
+
+        let result = self
+            .verification_tokens
+            .update_one(query, update, options)
+            .await
+            .map_err(InternalError::DatabaseConnectionError)?;
+
+        if result.upserted_id.is_none() {
+            return Err(UserError::VerificationEmailSentError);
+        }
+
+        let email = VerifyEmailEmail {
+            sender: self.sender.clone(),
+            public_url: self.public_url.clone(),
+            user: user.clone(),
+            token,
+        };
+
+        utils::send_email(self.mailer, email)
+    }
+
+    pub(crate) async fn verify_email(
+        &self,
+        ve: &auth::VerifyEmail,
+    ) -> Result<api::User, UserError> {
+        // The email may have been changed since the link was sent
+        let query = doc! {"username": &ve.username, "email": &ve.email};
+        let update = doc! {"$set": {"verified": true}};
+        let options = FindOneAndUpdateOptions::builder()
+            .return_document(ReturnDocument::After)
+            .build();
+
+        let user = self
+            .users
+            .find_one_and_update(query, update, options)
+            .await
+            .map_err(InternalError::DatabaseConnectionError)?
+            .ok_or(UserError::VerificationLinkNotFoundError)?;
+
+        Ok(user.into())
+    }
+
     pub(crate) async fn update_user(&self, eu: &auth::UpdateUser) -> Result<api::User, UserError> {
         let query = doc! {"username": &eu.username};
 
         // Get a doc with just the fields to set
-        let update_fields = utils::fields_with_values(&eu.update)
+        let mut update_fields = utils::fields_with_values(&eu.update)
             .and_then(|obj| if obj.is_empty() { None } else { Some(obj) })
             .ok_or(UserError::UserUpdateFieldRequiredError)?;
 
+        let existing = self
+            .users
+            .find_one(query.clone(), None)
+            .await
+            .map_err(InternalError::DatabaseConnectionError)?
+            .ok_or(UserError::UserNotFoundError)?;
+
+        // A new email address needs to be verified (unless the user is a member)
+        let needs_verification = if let Some(email) = eu.update.email.as_ref() {
+            let is_member = eu.update.group_id.is_some() || existing.is_member();
+            &existing.email != email && !is_member
+        } else {
+            false
+        };
+
+        if needs_verification {
+            update_fields.insert("verified".into(), false.into());
+        }
+
+        let new_role = eu.update.role.as_ref();
+        if let Some(role) = new_role.filter(|role| **role != existing.role) {
+            let entry = AuditLogEntry::new(
+                eu.requestor.clone(),
+                api::AuditAction::SetUserRole,
+                existing.username.clone(),
+                &existing.role,
+                role,
+            );
+            audit::record(self.audit_log, entry).await?;
+        }
+
         let update = doc! {
           "$set": mongodb::bson::to_document(&update_fields).unwrap()
         };
@@ -226,6 +577,15 @@ impl<'a> UserActions<'a> {
             .map_err(InternalError::DatabaseConnectionError)?
             .ok_or(UserError::UserNotFoundError)?;
 
+        if needs_verification {
+            if let Err(err) = self.request_verification(&user).await {
+                warn!(
+                    "Unable to send verification email to {}: {}",
+                    &user.username, err
+                );
+            }
+        }
+
         Ok(user.into())
     }
 
@@ -235,16 +595,13 @@ impl<'a> UserActions<'a> {
         password: String,
     ) -> Result<api::User, UserError> {
         let query = doc! {"username": &sp.username};
-        let user = self
-            .users
-            .find_one(query.clone(), None)
-            .await
-            .map_err(InternalError::DatabaseConnectionError)?
-            .ok_or(UserError::UserNotFoundError)?;
+        let hash = password::hash(&password, self.hash_params)
+            .map_err(InternalError::PasswordHashError)?;
 
         let update = doc! {
             "$set": {
-                "hash": utils::sha512(&(password + &user.salt.unwrap_or_default()))
+                "hash": hash,
+                "salt": null,
             }
         };
         let user = self
@@ -257,31 +614,58 @@ impl<'a> UserActions<'a> {
         Ok(user.into())
     }
 
-    pub(crate) async fn set_hosts(
+    /// Start two-factor enrollment for the given user. Two-factor authentication
+    /// is not enabled until the enrollment is confirmed with a valid code.
+    pub(crate) async fn enroll_two_factor(
         &self,
-        eu: &auth::EditUser,
-        hosts: &[api::ServiceHost],
-    ) -> Result<api::User, UserError> {
-        let query = doc! {"username": &eu.username};
-        let update = doc! {"$set": {"servicesHosts": &hosts}};
-        let options = mongodb::options::FindOneAndUpdateOptions::builder()
-            .return_document(ReturnDocument::After)
-            .build();
-        let user = self
+        mt: &auth::ManageTwoFactor,
+    ) -> Result<api::TwoFactorEnrollment, UserError> {
+        let new_secret = two_factor::new_secret(&mt.username)?;
+        let (recovery_codes, code_hashes) = two_factor::new_recovery_codes()?;
+
+        let two_factor = TwoFactorAuth {
+            secret: new_secret.secret.clone(),
+            enabled: false,
+            recovery_codes: code_hashes,
+            last_used_step: None,
+            created_at: DateTime::now(),
+        };
+        let query = doc! {
+            "username": &mt.username,
+            "twoFactor.enabled": {"$ne": true},
+        };
+        let update = doc! {"$set": {"twoFactor": mongodb::bson::to_bson(&two_factor).unwrap()}};
+        let result = self
             .users
-            .find_one_and_update(query, update, options)
+            .update_one(query, update, None)
             .await
-            .map_err(InternalError::DatabaseConnectionError)?
-            .ok_or(UserError::UserNotFoundError)?;
+            .map_err(InternalError::DatabaseConnectionError)?;
 
-        Ok(user.into())
+        if result.matched_count == 0 {
+            // Either the user doesn't exist or they are already enrolled
+            let query = doc! {"username": &mt.username};
+            self.users
+                .find_one(query, None)
+                .await
+                .map_err(InternalError::DatabaseConnectionError)?
+                .ok_or(UserError::UserNotFoundError)?;
+
+            return Err(UserError::TwoFactorAlreadyEnabledError);
+        }
+
+        Ok(api::TwoFactorEnrollment {
+            secret: new_secret.secret,
+            provisioning_uri: new_secret.provisioning_uri,
+            recovery_codes,
+        })
     }
 
-    pub(crate) async fn ban_user(
+    pub(crate) async fn confirm_two_factor(
         &self,
-        bu: &auth::BanUser,
-    ) -> Result<api::BannedAccount, UserError> {
-        let query = doc! {"username": &bu.username};
+        mt: &auth::ManageTwoFactor,
+        code: &str,
+    ) -> Result<(), UserError> {
+        let query = doc! {"username": &mt.username};
         let user = self
             .users
             .find_one(query, None)
@@ -289,65 +673,93 @@ impl<'a> UserActions<'a> {
             .map_err(InternalError::DatabaseConnectionError)?
             .ok_or(UserError::UserNotFoundError)?;
 
-        let query = doc! {"username": &user.username};
-        let account = BannedAccount::new(user.username, user.email);
-        let update = doc! {"$setOnInsert": &account};
-        let options = FindOneAndUpdateOptions::builder()
-            .return_document(ReturnDocument::After)
-            .upsert(true)
-            .build();
+        let two_factor = user.two_factor.ok_or(UserError::TwoFactorNotEnabledError)?;
+        if two_factor.enabled {
+            return Err(UserError::TwoFactorAlreadyEnabledError);
+        }
 
-        self.banned_accounts
-            .find_one_and_update(query, update, options)
+        let step = two_factor::verify_code(&two_factor.secret, code)?
+            .ok_or(UserError::InvalidTwoFactorCodeError)?;
+
+        let query = doc! {
+            "username": &mt.username,
+            "twoFactor.secret": &two_factor.secret,
+        };
+        let update = doc! {
+            "$set": {
+                "twoFactor.enabled": true,
+                "twoFactor.lastUsedStep": step,
+            }
+        };
+        self.users
+            .update_one(query, update, None)
             .await
             .map_err(InternalError::DatabaseConnectionError)?;
 
-        Ok(account.into())
+        Ok(())
     }
 
-    pub(crate) async fn unban_user(
+    pub(crate) async fn disable_two_factor(
         &self,
-        bu: &auth::BanUser,
-    ) -> Result<api::BannedAccount, UserError> {
-        let query = doc! {"username": &bu.username};
-        let account = self
-            .banned_accounts
-            .find_one_and_delete(query, None)
+        mt: &auth::ManageTwoFactor,
+        code: &str,
+    ) -> Result<(), UserError> {
+        let query = doc! {"username": &mt.username};
+        let user = self
+            .users
+            .find_one(query.clone(), None)
             .await
             .map_err(InternalError::DatabaseConnectionError)?
             .ok_or(UserError::UserNotFoundError)?;
 
-        Ok(account.into())
+        if !user.has_two_factor() {
+            return Err(UserError::TwoFactorNotEnabledError);
+        }
+
+        self.check_two_factor(&user, Some(code)).await?;
+
+        let update = doc! {"$unset": {"twoFactor": true}};
+        self.users
+            .update_one(query, update, None)
+            .await
+            .map_err(InternalError::DatabaseConnectionError)?;
+
+        Ok(())
     }
 
-    pub(crate) async fn link_account(
+    pub(crate) async fn reset_two_factor(
         &self,
-        eu: &auth::EditUser,
-        creds: strategies::Credentials,
+        rt: &auth::ResetTwoFactor,
     ) -> Result<api::User, UserError> {
-        if let strategies::Credentials::NetsBlox { .. } = creds {
-            return Err(UserError::InvalidAccountTypeError);
-        };
-
-        strategies::authenticate(&creds).await?;
-
-        let account: api::LinkedAccount = creds.into();
-        let query = doc! {"linkedAccounts": {"$elemMatch": &account}};
-        let existing = self
+        let query = doc! {"username": &rt.username};
+        let exists = self
             .users
-            .find_one(query, None)
+            .count_documents(query.clone(), None)
             .await
-            .map_err(InternalError::DatabaseConnectionError)?;
+            .map_err(InternalError::DatabaseConnectionError)?
+            > 0;
 
-        if existing.is_some() {
-            return Err(UserError::AccountAlreadyLinkedError);
+        if !exists {
+            return Err(UserError::UserNotFoundError);
         }
 
-        let query = doc! {"username": &eu.username};
-        let update = doc! {"$push": {"linkedAccounts": &account}};
+        let entry = AuditLogEntry::new(
+            rt.admin.clone(),
+            api::AuditAction::ResetTwoFactor,
+            rt.username.clone(),
+            &(),
+            &(),
+        );
+        audit::record(self.audit_log, entry).await?;
+
+        let update = doc! {"$unset": {"twoFactor": true}};
+        let options = FindOneAndUpdateOptions::builder()
+            .return_document(ReturnDocument::After)
+            .build();
+
         let user = self
             .users
-            .find_one_and_update(query, update, None)
+            .find_one_and_update(query, update, options)
             .await
             .map_err(InternalError::DatabaseConnectionError)?
             .ok_or(UserError::UserNotFoundError)?;
@@ -355,17 +767,59 @@ impl<'a> UserActions<'a> {
         Ok(user.into())
     }
 
-    pub(crate) async fn unlink_account(
+    /// Ensure a valid TOTP (or recovery) code was provided if the user has
+    /// two-factor authentication enabled.
+    async fn check_two_factor(&self, user: &User, code: Option<&str>) -> Result<(), UserError> {
+        let two_factor = match user.two_factor.as_ref() {
+            Some(two_factor) if two_factor.enabled => two_factor,
+            _ => return Ok(()),
+        };
+        let code = code.ok_or(UserError::TwoFactorRequiredError)?;
+
+        // Each TOTP code can only be used once (and recovery codes are removed when used)
+        let (query, update) = match two_factor::verify_code(&two_factor.secret, code)? {
+            Some(step) => (
+                doc! {
+                    "username": &user.username,
+                    "$or": [
+                        {"twoFactor.lastUsedStep": null},
+                        {"twoFactor.lastUsedStep": {"$lt": step}},
+                    ]
+                },
+                doc! {"$set": {"twoFactor.lastUsedStep": step}},
+            ),
+            None => {
+                let hash = two_factor::hash_recovery_code(code);
+                (
+                    doc! {"username": &user.username, "twoFactor.recoveryCodes": &hash},
+                    doc! {"$pull": {"twoFactor.recoveryCodes": &hash}},
+                )
+            }
+        };
+
+        let result = self
+            .users
+            .update_one(query, update, None)
+            .await
+            .map_err(InternalError::DatabaseConnectionError)?;
+
+        if result.modified_count == 1 {
+            Ok(())
+        } else {
+            Err(UserError::InvalidTwoFactorCodeError)
+        }
+    }
+
+    pub(crate) async fn set_hosts(
         &self,
         eu: &auth::EditUser,
-        account: api::LinkedAccount,
+        hosts: &[api::ServiceHost],
     ) -> Result<api::User, UserError> {
         let query = doc! {"username": &eu.username};
-        let update = doc! {"$pull": {"linkedAccounts": &account}};
+        let update = doc! {"$set": {"servicesHosts": &hosts}};
         let options = mongodb::options::FindOneAndUpdateOptions::builder()
             .return_document(ReturnDocument::After)
             .build();
-
         let user = self
             .users
             .find_one_and_update(query, update, options)
@@ -376,21 +830,286 @@ impl<'a> UserActions<'a> {
         Ok(user.into())
     }
 
-    pub(crate) async fn list_users(
+    pub(crate) async fn ban_user(
         &self,
-        _lu: &auth::ListUsers,
-    ) -> Result<Vec<api::User>, UserError> {
-        let query = doc! {};
-        let cursor = self
+        bu: &auth::BanUser,
+        data: api::BanData,
+    ) -> Result<api::BannedAccount, UserError> {
+        let query = doc! {"username": &bu.username};
+        let user = self
             .users
-            .find(query, None)
-            .await
-            .map_err(InternalError::DatabaseConnectionError)?;
-        let users: Vec<_> = cursor
-            .try_collect::<Vec<_>>()
+            .find_one(query, None)
             .await
             .map_err(InternalError::DatabaseConnectionError)?
-            .into_iter()
+            .ok_or(UserError::UserNotFoundError)?;
+
+        let query = doc! {"username": &user.username};
+        let ttl = data
+            .expires_in_hours
+            .map(|hours| Duration::from_secs(u64::from(hours) * 60 * 60));
+        let account = BannedAccount::new(
+            user.username,
+            user.email,
+            Some(bu.moderator.clone()),
+            data.reason,
+            ttl,
+        );
+
+        // Banning again replaces the existing ban so it is recorded in the audit log
+        let existing = self
+            .banned_accounts
+            .find_one(query.clone(), None)
+            .await
+            .map_err(InternalError::DatabaseConnectionError)?
+            .map(api::BannedAccount::from);
+
+        let entry = AuditLogEntry::new(
+            bu.moderator.clone(),
+            api::AuditAction::BanUser,
+            account.username.clone(),
+            &existing,
+            &api::BannedAccount::from(account.clone()),
+        );
+        audit::record(self.audit_log, entry).await?;
+
+        // Banning again replaces the existing ban (eg, to change the reason or expiration)
+        let update = doc! {"$set": &account};
+        let options = FindOneAndUpdateOptions::builder()
+            .return_document(ReturnDocument::After)
+            .upsert(true)
+            .build();
+
+        self.banned_accounts
+            .find_one_and_update(query, update, options)
+            .await
+            .map_err(InternalError::DatabaseConnectionError)?;
+
+        // Log the user out everywhere (including scripts using access tokens and
+        // OAuth clients)
+        let query = doc! {"username": &account.username};
+        futures::try_join!(
+            self.sessions.delete_many(query.clone(), None),
+            self.access_tokens.delete_many(query.clone(), None),
+            self.oauth_tokens.delete_many(query.clone(), None),
+            self.oauth_refresh_tokens.delete_many(query, None),
+        )
+        .map_err(InternalError::DatabaseConnectionError)?;
+
+        Ok(account.into())
+    }
+
+    pub(crate) async fn unban_user(
+        &self,
+        bu: &auth::BanUser,
+    ) -> Result<api::BannedAccount, UserError> {
+        let query = doc! {"username": &bu.username};
+        let account: api::BannedAccount = self
+            .banned_accounts
+            .find_one(query.clone(), None)
+            .await
+            .map_err(InternalError::DatabaseConnectionError)?
+            .ok_or(UserError::UserNotFoundError)?
+            .into();
+
+        let entry = AuditLogEntry::new(
+            bu.moderator.clone(),
+            api::AuditAction::UnbanUser,
+            account.username.clone(),
+            &account,
+            &(),
+        );
+        audit::record(self.audit_log, entry).await?;
+
+        self.banned_accounts
+            .delete_one(query, None)
+            .await
+            .map_err(InternalError::DatabaseConnectionError)?;
+
+        Ok(account)
+    }
+
+    /// List the active account bans, most recent first
+    pub(crate) async fn list_banned_accounts(
+        &self,
+        _mb: &auth::ManageBans,
+    ) -> Result<Vec<api::BannedAccount>, UserError> {
+        let options = FindOptions::builder().sort(doc! {"bannedAt": -1}).build();
+        let accounts: Vec<api::BannedAccount> = self
+            .banned_accounts
+            .find(utils::not_expired(), options)
+            .await
+            .map_err(InternalError::DatabaseConnectionError)?
+            .try_collect::<Vec<_>>()
+            .await
+            .map_err(InternalError::DatabaseConnectionError)?
+            .into_iter()
+            .map(|account| account.into())
+            .collect();
+
+        Ok(accounts)
+    }
+
+    /// Ban an IP address or range of addresses (in CIDR notation) from
+    /// logging in or creating accounts.
+    pub(crate) async fn ban_address(
+        &self,
+        mb: &auth::ManageBans,
+        data: api::BanAddressData,
+    ) -> Result<api::BannedAddress, UserError> {
+        let addr = utils::parse_address_range(&data.addr)?;
+        let ttl = data
+            .expires_in_hours
+            .map(|hours| Duration::from_secs(u64::from(hours) * 60 * 60));
+        let ban = BannedAddress::new(addr.to_string(), mb.moderator.clone(), data.reason, ttl);
+
+        let entry = AuditLogEntry::new(
+            mb.moderator.clone(),
+            api::AuditAction::BanAddress,
+            ban.addr.clone(),
+            &(),
+            &api::BannedAddress::from(ban.clone()),
+        );
+        audit::record(self.audit_log, entry).await?;
+
+        self.banned_addresses
+            .insert_one(&ban, None)
+            .await
+            .map_err(InternalError::DatabaseConnectionError)?;
+        self.ip_lists.add_ban(&ban);
+
+        Ok(ban.into())
+    }
+
+    /// List the active IP address bans, most recent first
+    pub(crate) async fn list_banned_addresses(
+        &self,
+        _mb: &auth::ManageBans,
+    ) -> Result<Vec<api::BannedAddress>, UserError> {
+        let options = FindOptions::builder().sort(doc! {"bannedAt": -1}).build();
+        let bans: Vec<api::BannedAddress> = self
+            .banned_addresses
+            .find(utils::not_expired(), options)
+            .await
+            .map_err(InternalError::DatabaseConnectionError)?
+            .try_collect::<Vec<_>>()
+            .await
+            .map_err(InternalError::DatabaseConnectionError)?
+            .into_iter()
+            .map(|ban| ban.into())
+            .collect();
+
+        Ok(bans)
+    }
+
+    pub(crate) async fn unban_address(
+        &self,
+        mb: &auth::ManageBans,
+        id: &str,
+    ) -> Result<api::BannedAddress, UserError> {
+        let query = doc! {"id": id};
+        let ban: api::BannedAddress = self
+            .banned_addresses
+            .find_one(query.clone(), None)
+            .await
+            .map_err(InternalError::DatabaseConnectionError)?
+            .ok_or(UserError::BanNotFoundError)?
+            .into();
+
+        let entry = AuditLogEntry::new(
+            mb.moderator.clone(),
+            api::AuditAction::UnbanAddress,
+            ban.addr.clone(),
+            &ban,
+            &(),
+        );
+        audit::record(self.audit_log, entry).await?;
+
+        self.banned_addresses
+            .delete_one(query, None)
+            .await
+            .map_err(InternalError::DatabaseConnectionError)?;
+        self.ip_lists.remove_ban(&ban.id);
+
+        Ok(ban)
+    }
+
+    pub(crate) async fn link_account(
+        &self,
+        eu: &auth::EditUser,
+        creds: strategies::Credentials,
+    ) -> Result<api::User, UserError> {
+        let account = match creds {
+            strategies::Credentials::NetsBlox { .. } => {
+                return Err(UserError::InvalidAccountTypeError);
+            }
+            strategies::Credentials::Snap { username, password } => {
+                self.strategies
+                    .get("snap")?
+                    .authenticate(&username, &password)
+                    .await?
+                    .account
+            }
+        };
+
+        let query = doc! {"linkedAccounts": {"$elemMatch": &account}};
+        let existing = self
+            .users
+            .find_one(query, None)
+            .await
+            .map_err(InternalError::DatabaseConnectionError)?;
+
+        if existing.is_some() {
+            return Err(UserError::AccountAlreadyLinkedError);
+        }
+
+        let query = doc! {"username": &eu.username};
+        let update = doc! {"$push": {"linkedAccounts": &account}};
+        let user = self
+            .users
+            .find_one_and_update(query, update, None)
+            .await
+            .map_err(InternalError::DatabaseConnectionError)?
+            .ok_or(UserError::UserNotFoundError)?;
+
+        Ok(user.into())
+    }
+
+    pub(crate) async fn unlink_account(
+        &self,
+        eu: &auth::EditUser,
+        account: api::LinkedAccount,
+    ) -> Result<api::User, UserError> {
+        let query = doc! {"username": &eu.username};
+        let update = doc! {"$pull": {"linkedAccounts": &account}};
+        let options = mongodb::options::FindOneAndUpdateOptions::builder()
+            .return_document(ReturnDocument::After)
+            .build();
+
+        let user = self
+            .users
+            .find_one_and_update(query, update, options)
+            .await
+            .map_err(InternalError::DatabaseConnectionError)?
+            .ok_or(UserError::UserNotFoundError)?;
+
+        Ok(user.into())
+    }
+
+    pub(crate) async fn list_users(
+        &self,
+        _lu: &auth::ListUsers,
+    ) -> Result<Vec<api::User>, UserError> {
+        let query = doc! {};
+        let cursor = self
+            .users
+            .find(query, None)
+            .await
+            .map_err(InternalError::DatabaseConnectionError)?;
+        let users: Vec<_> = cursor
+            .try_collect::<Vec<_>>()
+            .await
+            .map_err(InternalError::DatabaseConnectionError)?
+            .into_iter()
             .map(|user| user.into())
             .collect();
 
@@ -488,6 +1207,8 @@ fn is_valid_username(name: &str) -> bool {
         && !name.is_inappropriate()
 }
 
+/// Parse an IP address or CIDR range. Single addresses are treated as a range
+/// containing only the given address.
 struct SetPasswordEmail {
     sender: Mailbox,
     user: User,
@@ -529,6 +1250,47 @@ impl TryFrom<SetPasswordEmail> for lettre::Message {
     }
 }
 
+struct VerifyEmailEmail {
+    sender: Mailbox,
+    user: User,
+    token: EmailVerificationToken,
+    public_url: String,
+}
+
+impl VerifyEmailEmail {
+    fn render(&self) -> MultiPart {
+        let url = format!(
+            "{}/users/{}/verification?token={}",
+            self.public_url, &self.user.username, &self.token.secret
+        );
+        email_template::verify_email(&self.user.username, &url)
+    }
+}
+
+impl TryFrom<VerifyEmailEmail> for lettre::Message {
+    type Error = UserError;
+
+    fn try_from(email: VerifyEmailEmail) -> Result<Self, UserError> {
+        let subject = "Verify your NetsBlox Email Address";
+        let body = email.render();
+        let to_email = email.token.email;
+        let message = Message::builder()
+            .from(email.sender)
+            .to(Mailbox::new(
+                None,
+                to_email
+                    .parse::<Address>()
+                    .map_err(|_err| UserError::InvalidEmailAddress)?,
+            ))
+            .subject(subject.to_string())
+            .date_now()
+            .multipart(body)
+            .map_err(|_err| InternalError::EmailBuildError)?;
+
+        Ok(message)
+    }
+}
+
 struct ForgotUsernameEmail {
     sender: Mailbox,
     usernames: NonEmpty<String>,
@@ -599,13 +1361,296 @@ mod tests {
                     role: None,
                 };
                 let auth_cu = auth::CreateUser::test(new_user);
-                let user = actions.create_user(auth_cu).await.unwrap();
+                let user = actions
+                    .create_user(auth_cu, SignupClient::default())
+                    .await
+                    .unwrap();
                 assert!(user.group_id.is_some(), "User is not assigned to a group.");
                 assert_eq!(
                     user.group_id.unwrap(),
                     group.id,
                     "User assigned to incorrect group"
                 );
+                assert!(user.verified, "Members should not need verification.");
+            })
+            .await;
+    }
+
+    fn new_user(username: &str) -> api::NewUser {
+        api::NewUser {
+            username: username.into(),
+            email: format!("{}@netsblox.org", username),
+            password: None,
+            group_id: None,
+            role: None,
+        }
+    }
+
+    fn signup_client(addr: &str) -> SignupClient {
+        SignupClient {
+            addr: Some(addr.parse().unwrap()),
+            user_agent: Some("TestAgent".into()),
+            solution: None,
+        }
+    }
+
+    #[actix_web::test]
+    async fn test_create_user_deletion_in_progress() {
+        test_utils::setup()
+            .run(|app_data| async move {
+                let deletion = AccountDeletion::new("user".into(), "admin".into());
+                app_data
+                    .account_deletions
+                    .insert_one(deletion, None)
+                    .await
+                    .unwrap();
+
+                let actions = app_data.as_user_actions();
+                let auth_cu = auth::CreateUser::test(new_user("user"));
+                let result = actions.create_user(auth_cu, SignupClient::default()).await;
+                assert!(matches!(result, Err(UserError::UserExistsError)));
+
+                // the username can be reused once the deletion is complete
+                let query = doc! {"username": "user"};
+                let update = doc! {"$set": {"state": api::AccountDeletionState::Complete}};
+                app_data
+                    .account_deletions
+                    .update_one(query, update, None)
+                    .await
+                    .unwrap();
+
+                let auth_cu = auth::CreateUser::test(new_user("user"));
+                actions
+                    .create_user(auth_cu, SignupClient::default())
+                    .await
+                    .unwrap();
+            })
+            .await;
+    }
+
+    #[actix_web::test]
+    async fn test_create_user_signup_limit_ip() {
+        test_utils::setup()
+            .run(|mut app_data| async move {
+                app_data.settings.signup.max_per_ip = 2;
+                let actions = app_data.as_user_actions();
+
+                for name in ["user1", "user2"] {
+                    let auth_cu = auth::CreateUser::test_signup(new_user(name));
+                    actions
+                        .create_user(auth_cu, signup_client("10.0.0.1"))
+                        .await
+                        .unwrap();
+                }
+
+                let auth_cu = auth::CreateUser::test_signup(new_user("user3"));
+                let result = actions
+                    .create_user(auth_cu, signup_client("10.0.0.1"))
+                    .await;
+                assert!(matches!(result, Err(UserError::SignupRateLimitError)));
+
+                // other addresses are not affected
+                let auth_cu = auth::CreateUser::test_signup(new_user("user4"));
+                actions
+                    .create_user(auth_cu, signup_client("10.0.1.1"))
+                    .await
+                    .unwrap();
+
+                let user = app_data
+                    .users
+                    .find_one(doc! {"username": "user1"}, None)
+                    .await
+                    .unwrap()
+                    .unwrap();
+                assert_eq!(user.signup_ip.as_deref(), Some("10.0.0.1"));
+                assert_eq!(user.signup_subnet.as_deref(), Some("10.0.0.0/24"));
+                assert_eq!(user.signup_user_agent.as_deref(), Some("TestAgent"));
+            })
+            .await;
+    }
+
+    #[actix_web::test]
+    async fn test_create_user_signup_limit_subnet() {
+        test_utils::setup()
+            .run(|mut app_data| async move {
+                app_data.settings.signup.max_per_subnet = 1;
+                let actions = app_data.as_user_actions();
+
+                let auth_cu = auth::CreateUser::test_signup(new_user("user1"));
+                actions
+                    .create_user(auth_cu, signup_client("10.0.0.1"))
+                    .await
+                    .unwrap();
+
+                let auth_cu = auth::CreateUser::test_signup(new_user("user2"));
+                let result = actions
+                    .create_user(auth_cu, signup_client("10.0.0.2"))
+                    .await;
+                assert!(matches!(result, Err(UserError::SignupRateLimitError)));
+
+                // accounts created by group owners/moderators are not limited
+                let auth_cu = auth::CreateUser::test(new_user("user3"));
+                actions
+                    .create_user(auth_cu, signup_client("10.0.0.3"))
+                    .await
+                    .unwrap();
+            })
+            .await;
+    }
+
+    #[actix_web::test]
+    async fn test_create_user_signup_challenge() {
+        test_utils::setup()
+            .run(|mut app_data| async move {
+                app_data.settings.signup.challenge_difficulty = 4;
+                let actions = app_data.as_user_actions();
+
+                let auth_cu = auth::CreateUser::test_signup(new_user("user1"));
+                let result = actions
+                    .create_user(auth_cu, signup_client("10.0.0.1"))
+                    .await;
+                assert!(matches!(
+                    result,
+                    Err(UserError::SignupChallengeRequiredError)
+                ));
+
+                let challenge = actions.create_signup_challenge().await.unwrap();
+                assert_eq!(challenge.difficulty, 4);
+                let solution = api::SignupChallengeSolution {
+                    nonce: signup::solve(&challenge.id, challenge.difficulty),
+                    challenge_id: challenge.id,
+                };
+                let client = SignupClient {
+                    solution: Some(solution.clone()),
+                    ..signup_client("10.0.0.1")
+                };
+                let auth_cu = auth::CreateUser::test_signup(new_user("user1"));
+                actions.create_user(auth_cu, client).await.unwrap();
+
+                // challenges cannot be reused
+                let client = SignupClient {
+                    solution: Some(solution),
+                    ..signup_client("10.0.0.1")
+                };
+                let auth_cu = auth::CreateUser::test_signup(new_user("user2"));
+                let result = actions.create_user(auth_cu, client).await;
+                assert!(matches!(
+                    result,
+                    Err(UserError::InvalidSignupChallengeError)
+                ));
+            })
+            .await;
+    }
+
+    #[actix_web::test]
+    async fn test_create_user_invalid_challenge_solution() {
+        test_utils::setup()
+            .run(|mut app_data| async move {
+                app_data.settings.signup.challenge_difficulty = 16;
+                let actions = app_data.as_user_actions();
+
+                let challenge = actions.create_signup_challenge().await.unwrap();
+                let nonce = (0u64..)
+                    .map(|nonce| nonce.to_string())
+                    .find(|nonce| !signup::is_solution(&challenge.id, nonce, 16))
+                    .unwrap();
+                let client = SignupClient {
+                    solution: Some(api::SignupChallengeSolution {
+                        challenge_id: challenge.id,
+                        nonce,
+                    }),
+                    ..signup_client("10.0.0.1")
+                };
+                let auth_cu = auth::CreateUser::test_signup(new_user("user1"));
+                let result = actions.create_user(auth_cu, client).await;
+                assert!(matches!(
+                    result,
+                    Err(UserError::InvalidSignupChallengeError)
+                ));
+            })
+            .await;
+    }
+
+    #[actix_web::test]
+    async fn test_create_user_unverified() {
+        test_utils::setup()
+            .run(|app_data| async move {
+                let actions = app_data.as_user_actions();
+
+                let new_user = api::NewUser {
+                    username: "someUser".into(),
+                    email: "someUser@netsblox.org".into(),
+                    password: None,
+                    group_id: None,
+                    role: None,
+                };
+                let auth_cu = auth::CreateUser::test(new_user);
+                let user = actions
+                    .create_user(auth_cu, SignupClient::default())
+                    .await
+                    .unwrap();
+                assert!(!user.verified);
+
+                let query = doc! {"username": &user.username};
+                let token = app_data
+                    .verification_tokens
+                    .find_one(query, None)
+                    .await
+                    .unwrap()
+                    .expect("Verification token not created.");
+                assert_eq!(token.email, user.email);
+            })
+            .await;
+    }
+
+    #[actix_web::test]
+    async fn test_verify_email() {
+        let mut user: User = api::NewUser {
+            username: "user".into(),
+            email: "user@netsblox.org".into(),
+            password: None,
+            group_id: None,
+            role: None,
+        }
+        .into();
+        user.verified = false;
+
+        test_utils::setup()
+            .with_users(&[user.clone()])
+            .run(|app_data| async move {
+                let actions = app_data.as_user_actions();
+
+                let auth_ve = auth::VerifyEmail::test(user.username.clone(), user.email.clone());
+                let user = actions.verify_email(&auth_ve).await.unwrap();
+                assert!(user.verified);
+            })
+            .await;
+    }
+
+    #[actix_web::test]
+    async fn test_verify_email_changed() {
+        let mut user: User = api::NewUser {
+            username: "user".into(),
+            email: "user@netsblox.org".into(),
+            password: None,
+            group_id: None,
+            role: None,
+        }
+        .into();
+        user.verified = false;
+
+        test_utils::setup()
+            .with_users(&[user.clone()])
+            .run(|app_data| async move {
+                let actions = app_data.as_user_actions();
+
+                let auth_ve =
+                    auth::VerifyEmail::test(user.username.clone(), "old@netsblox.org".into());
+                let result = actions.verify_email(&auth_ve).await;
+                assert!(matches!(
+                    result,
+                    Err(UserError::VerificationLinkNotFoundError)
+                ));
             })
             .await;
     }
@@ -631,7 +1676,8 @@ mod tests {
                     group_id: None,
                     role: None,
                 };
-                let auth_uu = auth::UpdateUser::test(user.username.clone(), data.clone());
+                let auth_uu =
+                    auth::UpdateUser::test(user.username.clone(), data.clone(), "admin".into());
                 let res_user = actions.update_user(&auth_uu).await.unwrap();
 
                 let query = doc! {"username": user.username};
@@ -645,6 +1691,7 @@ mod tests {
                 assert!(matches!(user.role, UserRole::User));
                 assert_eq!(user.email, data.email.unwrap(), "Email not updated.");
                 assert_eq!(res_user.email, user.email, "Returned original user");
+                assert!(!user.verified, "New email not marked as unverified.");
             })
             .await;
     }
@@ -670,7 +1717,7 @@ mod tests {
                     group_id: None,
                     role: Some(UserRole::Teacher),
                 };
-                let auth_uu = auth::UpdateUser::test(user.username.clone(), data);
+                let auth_uu = auth::UpdateUser::test(user.username.clone(), data, "admin".into());
                 actions.update_user(&auth_uu).await.unwrap();
 
                 let query = doc! {"username": user.username};
@@ -709,7 +1756,8 @@ mod tests {
                     group_id: Some(api::GroupId::new("someGroup".into())),
                     role: None,
                 };
-                let auth_uu = auth::UpdateUser::test(user.username.clone(), data.clone());
+                let auth_uu =
+                    auth::UpdateUser::test(user.username.clone(), data.clone(), "admin".into());
                 actions.update_user(&auth_uu).await.unwrap();
 
                 let query = doc! {"username": user.username};
@@ -755,9 +1803,15 @@ mod tests {
             .run(|app_data| async move {
                 let actions = app_data.as_user_actions();
 
-                let auth_bu = auth::BanUser::test(user.username.clone());
-                actions.ban_user(&auth_bu).await.unwrap();
-                actions.ban_user(&auth_bu).await.unwrap();
+                let auth_bu = auth::BanUser::test(user.username.clone(), "moderator".into());
+                actions
+                    .ban_user(&auth_bu, Default::default())
+                    .await
+                    .unwrap();
+                actions
+                    .ban_user(&auth_bu, Default::default())
+                    .await
+                    .unwrap();
 
                 actions.unban_user(&auth_bu).await.unwrap();
                 // Check that the user is not banned
@@ -771,6 +1825,289 @@ mod tests {
             .await;
     }
 
+    #[actix_web::test]
+    async fn test_ban_user_revokes_tokens() {
+        let user: User = api::NewUser {
+            username: "user".into(),
+            email: "user@netsblox.org".into(),
+            password: None,
+            group_id: None,
+            role: None,
+        }
+        .into();
+
+        test_utils::setup()
+            .with_users(&[user.clone()])
+            .run(|app_data| async move {
+                let auth_mt = auth::ManageAccessTokens::test(user.username.clone());
+                let data = api::CreateAccessTokenData {
+                    name: "script".into(),
+                    scopes: vec![api::TokenScope::Write],
+                    expires_in_days: None,
+                };
+                app_data
+                    .as_access_token_actions()
+                    .create_token(&auth_mt, data)
+                    .await
+                    .unwrap();
+                let token = OAuthToken::new(
+                    api::oauth::ClientId::new("client".into()),
+                    user.username.clone(),
+                    vec![api::oauth::Scope::ReadUser],
+                    Duration::from_secs(3600),
+                );
+                app_data.oauth_tokens.insert_one(token, None).await.unwrap();
+
+                let actions = app_data.as_user_actions();
+                let auth_bu = auth::BanUser::test(user.username.clone(), "moderator".into());
+                actions
+                    .ban_user(&auth_bu, Default::default())
+                    .await
+                    .unwrap();
+
+                let query = doc! {"username": &user.username};
+                let count = app_data
+                    .access_tokens
+                    .count_documents(query.clone(), None)
+                    .await
+                    .unwrap();
+                assert_eq!(count, 0);
+                let count = app_data
+                    .oauth_tokens
+                    .count_documents(query, None)
+                    .await
+                    .unwrap();
+                assert_eq!(count, 0);
+            })
+            .await;
+    }
+
+    #[actix_web::test]
+    async fn test_ban_audit_log() {
+        let user: User = api::NewUser {
+            username: "user".into(),
+            email: "user@netsblox.org".into(),
+            password: None,
+            group_id: None,
+            role: None,
+        }
+        .into();
+
+        test_utils::setup()
+            .with_users(&[user.clone()])
+            .run(|app_data| async move {
+                let actions = app_data.as_user_actions();
+
+                let auth_bu = auth::BanUser::test(user.username.clone(), "moderator".into());
+                actions
+                    .ban_user(&auth_bu, Default::default())
+                    .await
+                    .unwrap();
+
+                let query = doc! {"target": &user.username};
+                let entry = actions
+                    .audit_log
+                    .find_one(query, None)
+                    .await
+                    .unwrap()
+                    .unwrap();
+
+                assert_eq!(entry.actor, "moderator");
+                assert_eq!(entry.action, api::AuditAction::BanUser);
+                assert!(entry.before.is_none());
+                assert!(entry.after.is_some());
+            })
+            .await;
+    }
+
+    #[actix_web::test]
+    async fn test_reban_audit_log() {
+        let user: User = api::NewUser {
+            username: "user".into(),
+            email: "user@netsblox.org".into(),
+            password: None,
+            group_id: None,
+            role: None,
+        }
+        .into();
+
+        test_utils::setup()
+            .with_users(&[user.clone()])
+            .run(|app_data| async move {
+                let actions = app_data.as_user_actions();
+
+                let auth_bu = auth::BanUser::test(user.username.clone(), "moderator".into());
+                let data = api::BanData {
+                    reason: Some("spam".into()),
+                    expires_in_hours: None,
+                };
+                actions.ban_user(&auth_bu, data).await.unwrap();
+                let data = api::BanData {
+                    reason: Some("harassment".into()),
+                    expires_in_hours: Some(24),
+                };
+                actions.ban_user(&auth_bu, data).await.unwrap();
+
+                let query = doc! {"target": &user.username, "before": {"$ne": null}};
+                let entry = actions
+                    .audit_log
+                    .find_one(query, None)
+                    .await
+                    .unwrap()
+                    .unwrap();
+
+                let before = entry.before.unwrap();
+                assert_eq!(before["reason"], "spam");
+                let after = entry.after.unwrap();
+                assert_eq!(after["reason"], "harassment");
+            })
+            .await;
+    }
+
+    #[actix_web::test]
+    async fn test_update_role_audit_log() {
+        let user: User = api::NewUser {
+            username: "user".into(),
+            email: "user@netsblox.org".into(),
+            password: None,
+            group_id: None,
+            role: None,
+        }
+        .into();
+
+        test_utils::setup()
+            .with_users(&[user.clone()])
+            .run(|app_data| async move {
+                let actions = app_data.as_user_actions();
+
+                let data = api::UpdateUserData {
+                    email: None,
+                    group_id: None,
+                    role: Some(api::UserRole::Moderator),
+                };
+                let auth_uu = auth::UpdateUser::test(user.username.clone(), data, "admin".into());
+                actions.update_user(&auth_uu).await.unwrap();
+
+                let query =
+                    doc! {"target": &user.username, "action": api::AuditAction::SetUserRole};
+                let entry = actions
+                    .audit_log
+                    .find_one(query, None)
+                    .await
+                    .unwrap()
+                    .unwrap();
+
+                assert_eq!(entry.actor, "admin");
+            })
+            .await;
+    }
+
+    #[actix_web::test]
+    async fn test_enroll_two_factor() {
+        let user: User = api::NewUser {
+            username: "user".into(),
+            email: "user@netsblox.org".into(),
+            password: None,
+            group_id: None,
+            role: None,
+        }
+        .into();
+
+        test_utils::setup()
+            .with_users(&[user.clone()])
+            .run(|app_data| async move {
+                let actions = app_data.as_user_actions();
+
+                let auth_mt = auth::ManageTwoFactor::test(user.username.clone());
+                let enrollment = actions.enroll_two_factor(&auth_mt).await.unwrap();
+
+                let query = doc! {"username": &user.username};
+                let user = app_data.users.find_one(query, None).await.unwrap().unwrap();
+                let two_factor = user.two_factor.unwrap();
+                assert!(!two_factor.enabled, "Enabled before confirmation");
+                assert_eq!(two_factor.secret, enrollment.secret);
+                assert_eq!(
+                    two_factor.recovery_codes.len(),
+                    enrollment.recovery_codes.len()
+                );
+                assert!(
+                    !two_factor
+                        .recovery_codes
+                        .contains(&enrollment.recovery_codes[0]),
+                    "Recovery codes stored in plaintext"
+                );
+            })
+            .await;
+    }
+
+    #[actix_web::test]
+    async fn test_enroll_two_factor_already_enabled() {
+        let mut user: User = api::NewUser {
+            username: "user".into(),
+            email: "user@netsblox.org".into(),
+            password: None,
+            group_id: None,
+            role: None,
+        }
+        .into();
+        user.two_factor = Some(TwoFactorAuth {
+            secret: "JBSWY3DPEHPK3PXPJBSWY3DPEHPK3PXP".into(),
+            enabled: true,
+            recovery_codes: Vec::new(),
+            last_used_step: None,
+            created_at: DateTime::now(),
+        });
+
+        test_utils::setup()
+            .with_users(&[user.clone()])
+            .run(|app_data| async move {
+                let actions = app_data.as_user_actions();
+
+                let auth_mt = auth::ManageTwoFactor::test(user.username.clone());
+                let result = actions.enroll_two_factor(&auth_mt).await;
+                assert!(matches!(
+                    result,
+                    Err(UserError::TwoFactorAlreadyEnabledError)
+                ));
+
+                let query = doc! {"username": &user.username};
+                let user = app_data.users.find_one(query, None).await.unwrap().unwrap();
+                assert_eq!(
+                    user.two_factor.unwrap().secret,
+                    "JBSWY3DPEHPK3PXPJBSWY3DPEHPK3PXP"
+                );
+            })
+            .await;
+    }
+
+    #[actix_web::test]
+    async fn test_confirm_two_factor_invalid_code() {
+        let user: User = api::NewUser {
+            username: "user".into(),
+            email: "user@netsblox.org".into(),
+            password: None,
+            group_id: None,
+            role: None,
+        }
+        .into();
+
+        test_utils::setup()
+            .with_users(&[user.clone()])
+            .run(|app_data| async move {
+                let actions = app_data.as_user_actions();
+
+                let auth_mt = auth::ManageTwoFactor::test(user.username.clone());
+                actions.enroll_two_factor(&auth_mt).await.unwrap();
+                let result = actions.confirm_two_factor(&auth_mt, "notACode").await;
+                assert!(matches!(result, Err(UserError::InvalidTwoFactorCodeError)));
+
+                let query = doc! {"username": &user.username};
+                let user = app_data.users.find_one(query, None).await.unwrap().unwrap();
+                assert!(!user.has_two_factor());
+            })
+            .await;
+    }
+
     #[actix_web::test]
     async fn test_forgot_username_none() {
         test_utils::setup()
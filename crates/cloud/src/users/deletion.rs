@@ -0,0 +1,361 @@
+use futures::TryStreamExt;
+use log::{error, info};
+use mongodb::bson::{doc, DateTime, Document};
+use netsblox_cloud_common::api;
+
+use crate::{
+    app_data::AppData,
+    auth,
+    errors::{InternalError, UserError},
+};
+
+/// Cascading deletion of the data owned by a deleted account (projects, libraries,
+/// friends, etc). Progress is recorded in the database as each step completes so
+/// it can be checked while the job is running.
+pub(crate) struct AccountDeletionJob {
+    app: AppData,
+    du: auth::DeleteUser,
+}
+
+impl AccountDeletionJob {
+    /// Run the job in the background. The deletion should already be recorded in
+    /// the database (see `UserActions::delete_user`).
+    pub(crate) fn start(app: &AppData, du: auth::DeleteUser) {
+        let job = AccountDeletionJob {
+            app: app.clone(),
+            du,
+        };
+        actix_web::rt::spawn(job.run());
+    }
+
+    /// Restart any deletions which didn't finish (eg, if the server was stopped
+    /// while the job was running)
+    pub(crate) async fn resume_all(app: &AppData) -> Result<(), InternalError> {
+        let query = doc! {"state": {"$ne": api::AccountDeletionState::Complete}};
+        let deletions: Vec<_> = app
+            .account_deletions
+            .find(query, None)
+            .await
+            .map_err(InternalError::DatabaseConnectionError)?
+            .try_collect()
+            .await
+            .map_err(InternalError::DatabaseConnectionError)?;
+
+        for deletion in deletions {
+            info!("Resuming deletion of data owned by {}", &deletion.username);
+            let query = doc! {"username": &deletion.username};
+            let update = doc! {
+                "$set": {
+                    "state": api::AccountDeletionState::InProgress,
+                    "error": null,
+                    "updatedAt": DateTime::now(),
+                }
+            };
+            app.account_deletions
+                .update_one(query, update, None)
+                .await
+                .map_err(InternalError::DatabaseConnectionError)?;
+
+            let du = auth::DeleteUser::from_account_deletion(&deletion);
+            AccountDeletionJob::start(app, du);
+        }
+
+        Ok(())
+    }
+
+    pub(crate) async fn run(self) {
+        let (state, error) = match self.delete_all().await {
+            Ok(_) => {
+                info!("Deleted all data owned by {}", &self.du.username);
+                (api::AccountDeletionState::Complete, None)
+            }
+            Err(err) => {
+                error!(
+                    "Unable to delete data owned by {}: {}",
+                    &self.du.username, err
+                );
+                (api::AccountDeletionState::Failed, Some(err.to_string()))
+            }
+        };
+
+        let update = doc! {"$set": {"state": state, "error": error}};
+        if let Err(err) = self.update(update).await {
+            error!("Unable to update account deletion status: {}", err);
+        }
+    }
+
+    async fn delete_all(&self) -> Result<(), UserError> {
+        self.delete_projects().await?;
+
+        let actions = self.app.as_project_actions();
+        let count = actions.remove_deleted_collaborator(&self.du).await?;
+        self.update(doc! {"$set": {"progress.collaborationsRemoved": count}})
+            .await?;
+
+        let query = doc! {"owner": &self.du.username};
+        let result = self
+            .app
+            .libraries
+            .delete_many(query, None)
+            .await
+            .map_err(InternalError::DatabaseConnectionError)?;
+        let count = result.deleted_count as u32;
+        self.update(doc! {"$set": {"progress.librariesDeleted": count}})
+            .await?;
+
+        let actions = self.app.as_friend_actions();
+        let count = actions.remove_all_links(&self.du).await?;
+        self.update(doc! {"$set": {"progress.friendsRemoved": count}})
+            .await?;
+
+        self.delete_invites().await?;
+        self.revoke_tokens().await?;
+
+        // Clients are owned by username so they would otherwise be managed by
+        // anyone who signs up with the same name
+        let actions = self.app.as_oauth_actions();
+        let count = actions.delete_owned_clients(&self.du).await?;
+        self.update(doc! {"$set": {"progress.clientsDeleted": count}})
+            .await?;
+
+        Ok(())
+    }
+
+    async fn delete_projects(&self) -> Result<(), UserError> {
+        let query = doc! {"owner": &self.du.username};
+        let projects: Vec<_> = self
+            .app
+            .project_metadata
+            .find(query, None)
+            .await
+            .map_err(InternalError::DatabaseConnectionError)?
+            .try_collect()
+            .await
+            .map_err(InternalError::DatabaseConnectionError)?;
+
+        let total = projects.len() as u32;
+        self.update(doc! {"$set": {"progress.projectsTotal": total}})
+            .await?;
+
+        // Projects are deleted one at a time (rather than concurrently) to avoid
+        // flooding S3 with requests when deleting a user with many projects
+        let actions = self.app.as_project_actions();
+        for metadata in projects {
+            if let Some(dp) = auth::DeleteProject::from_delete_user(&self.du, &metadata) {
+                match actions.delete_project(&dp).await {
+                    Ok(_) | Err(UserError::ProjectNotFoundError) => {}
+                    Err(err) => return Err(err),
+                };
+                self.update(doc! {"$inc": {"progress.projectsDeleted": 1}})
+                    .await?;
+            }
+        }
+
+        Ok(())
+    }
+
+    async fn delete_invites(&self) -> Result<(), UserError> {
+        let query = doc! {
+            "$or": [
+                {"sender": &self.du.username},
+                {"receiver": &self.du.username},
+            ]
+        };
+        let collab_invites = self
+            .app
+            .collab_invites
+            .delete_many(query, None)
+            .await
+            .map_err(InternalError::DatabaseConnectionError)?;
+
+        let query = doc! {"username": &self.du.username};
+        let occupant_invites = self
+            .app
+            .occupant_invites
+            .delete_many(query, None)
+            .await
+            .map_err(InternalError::DatabaseConnectionError)?;
+
+        let count = (collab_invites.deleted_count + occupant_invites.deleted_count) as u32;
+        self.update(doc! {"$set": {"progress.invitesDeleted": count}})
+            .await
+    }
+
+    async fn revoke_tokens(&self) -> Result<(), UserError> {
+        let query = doc! {"username": &self.du.username};
+        let results = futures::try_join!(
+            self.app.oauth_tokens.delete_many(query.clone(), None),
+            self.app
+                .oauth_refresh_tokens
+                .delete_many(query.clone(), None),
+            self.app.access_tokens.delete_many(query.clone(), None),
+            self.app.password_tokens.delete_many(query.clone(), None),
+            self.app.verification_tokens.delete_many(query, None),
+        )
+        .map_err(InternalError::DatabaseConnectionError)?;
+
+        let count = results.0.deleted_count
+            + results.1.deleted_count
+            + results.2.deleted_count
+            + results.3.deleted_count
+            + results.4.deleted_count;
+        self.update(doc! {"$set": {"progress.tokensRevoked": count as u32}})
+            .await
+    }
+
+    async fn update(&self, mut update: Document) -> Result<(), UserError> {
+        let query = doc! {"username": &self.du.username};
+        let now = DateTime::now();
+        match update.get_document_mut("$set") {
+            Ok(set) => {
+                set.insert("updatedAt", now);
+            }
+            Err(_) => {
+                update.insert("$set", doc! {"updatedAt": now});
+            }
+        };
+
+        self.app
+            .account_deletions
+            .update_one(query, update, None)
+            .await
+            .map_err(InternalError::DatabaseConnectionError)?;
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use netsblox_cloud_common::{
+        api::FriendLinkState, AccountDeletion, FriendLink, Library, OAuthClient, User,
+    };
+
+    use super::*;
+    use crate::test_utils;
+
+    #[actix_web::test]
+    async fn test_delete_all() {
+        let user: User = api::NewUser {
+            username: "user".into(),
+            email: "user@netsblox.org".into(),
+            password: None,
+            group_id: None,
+            role: None,
+        }
+        .into();
+        let other: User = api::NewUser {
+            username: "other".into(),
+            email: "other@netsblox.org".into(),
+            password: None,
+            group_id: None,
+            role: None,
+        }
+        .into();
+        let project = test_utils::project::builder()
+            .with_owner(user.username.clone())
+            .build();
+        let shared = test_utils::project::builder()
+            .with_owner(other.username.clone())
+            .with_collaborators(&[&user.username])
+            .build();
+        let library = Library {
+            owner: user.username.clone(),
+            name: "library".into(),
+            notes: "".into(),
+            blocks: "<blocks/>".into(),
+            state: api::PublishState::Private,
+        };
+        let client = OAuthClient::new(
+            "client".into(),
+            Some(user.username.clone()),
+            None,
+            vec![api::oauth::Scope::ReadUser],
+            vec!["http://localhost:8000".into()],
+            &Default::default(),
+        )
+        .unwrap();
+        let link = FriendLink::new(
+            user.username.clone(),
+            other.username.clone(),
+            Some(FriendLinkState::Approved),
+        );
+
+        test_utils::setup()
+            .with_users(&[user.clone(), other.clone()])
+            .with_projects(&[project.clone(), shared.clone()])
+            .with_libraries(&[library])
+            .with_friend_links(&[link])
+            .run(|app_data| async move {
+                let du = auth::DeleteUser::test(user.username.clone(), user.username.clone());
+                let deletion = AccountDeletion::new(user.username.clone(), user.username.clone());
+                app_data
+                    .account_deletions
+                    .insert_one(deletion, None)
+                    .await
+                    .unwrap();
+
+                let job = AccountDeletionJob {
+                    app: app_data.clone(),
+                    du,
+                };
+                app_data
+                    .oauth_clients
+                    .insert_one(&client, None)
+                    .await
+                    .unwrap();
+
+                job.run().await;
+
+                let query = doc! {"username": &user.username};
+                let deletion = app_data
+                    .account_deletions
+                    .find_one(query, None)
+                    .await
+                    .unwrap()
+                    .unwrap();
+                assert_eq!(deletion.state, api::AccountDeletionState::Complete);
+                let expected = api::AccountDeletionProgress {
+                    projects_total: 1,
+                    projects_deleted: 1,
+                    collaborations_removed: 1,
+                    libraries_deleted: 1,
+                    friends_removed: 1,
+                    invites_deleted: 0,
+                    tokens_revoked: 0,
+                    clients_deleted: 1,
+                };
+                assert_eq!(deletion.progress, expected);
+
+                let query = doc! {"id": &project.id};
+                let metadata = app_data
+                    .project_metadata
+                    .find_one(query, None)
+                    .await
+                    .unwrap();
+                assert!(metadata.is_none());
+
+                let query = doc! {"id": &shared.id};
+                let metadata = app_data
+                    .project_metadata
+                    .find_one(query, None)
+                    .await
+                    .unwrap()
+                    .unwrap();
+                assert!(metadata.collaborators.is_empty());
+
+                let query = doc! {"id": &client.id};
+                let client = app_data.oauth_clients.find_one(query, None).await.unwrap();
+                assert!(client.is_none());
+
+                let vu = auth::ViewUser::test(other.username.clone());
+                let friends = app_data
+                    .as_friend_actions()
+                    .list_friends(&vu)
+                    .await
+                    .unwrap();
+                assert!(friends.is_empty());
+            })
+            .await;
+    }
+}
@@ -0,0 +1,203 @@
+use std::io::{Cursor, Write};
+
+use futures::TryStreamExt;
+use mongodb::bson::doc;
+use netsblox_cloud_common::api;
+use serde::Serialize;
+use zip::{write::FileOptions, ZipWriter};
+
+use crate::{
+    app_data::AppData,
+    auth,
+    errors::{InternalError, UserError},
+};
+
+/// Details recorded when the account was created (used to limit signups)
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+struct SignupInfo {
+    ip: Option<String>,
+    subnet: Option<String>,
+    user_agent: Option<String>,
+}
+
+/// Archive (zip) containing all the data stored about a user, including their
+/// account details, projects, libraries, friends, and message logs.
+pub(crate) struct UserDataExport<'a> {
+    app: &'a AppData,
+    ed: &'a auth::ExportUserData,
+    writer: ZipWriter<Cursor<Vec<u8>>>,
+}
+
+impl<'a> UserDataExport<'a> {
+    pub(crate) fn new(app: &'a AppData, ed: &'a auth::ExportUserData) -> Self {
+        Self {
+            app,
+            ed,
+            writer: ZipWriter::new(Cursor::new(Vec::new())),
+        }
+    }
+
+    /// Build the archive and return its contents
+    pub(crate) async fn build(mut self) -> Result<Vec<u8>, UserError> {
+        let query = doc! {"username": &self.ed.username};
+        let user = self
+            .app
+            .users
+            .find_one(query, None)
+            .await
+            .map_err(InternalError::DatabaseConnectionError)?
+            .ok_or(UserError::UserNotFoundError)?;
+
+        // The password hash and salt are omitted (along with the two-factor secret)
+        let service_settings = user.service_settings.clone();
+        let linked_accounts = user.linked_accounts.clone();
+        let group_id = user.group_id.clone();
+        let signup = SignupInfo {
+            ip: user.signup_ip.clone(),
+            subnet: user.signup_subnet.clone(),
+            user_agent: user.signup_user_agent.clone(),
+        };
+        let user: api::User = user.into();
+        self.add_json("user.json", &user)?;
+        self.add_json("signup.json", &signup)?;
+        self.add_json("service-settings.json", &service_settings)?;
+        self.add_json("linked-accounts.json", &linked_accounts)?;
+
+        if let Some(group_id) = group_id {
+            let query = doc! {"id": group_id};
+            let group: Option<api::Group> = self
+                .app
+                .groups
+                .find_one(query, None)
+                .await
+                .map_err(InternalError::DatabaseConnectionError)?
+                .map(|group| group.into());
+            self.add_json("group.json", &group)?;
+        }
+
+        self.add_projects().await?;
+        self.add_libraries().await?;
+        self.add_friends().await?;
+        self.add_messages().await?;
+
+        let archive = self
+            .writer
+            .finish()
+            .map_err(InternalError::ArchiveError)?
+            .into_inner();
+
+        Ok(archive)
+    }
+
+    async fn add_projects(&mut self) -> Result<(), UserError> {
+        let query = doc! {"owner": &self.ed.username};
+        let projects: Vec<_> = self
+            .app
+            .project_metadata
+            .find(query, None)
+            .await
+            .map_err(InternalError::DatabaseConnectionError)?
+            .try_collect()
+            .await
+            .map_err(InternalError::DatabaseConnectionError)?;
+
+        let metadata: Vec<api::ProjectMetadata> =
+            projects.iter().cloned().map(|md| md.into()).collect();
+        self.add_json("projects.json", &metadata)?;
+
+        let actions = self.app.as_project_actions();
+        for metadata in projects {
+            if let Some(vp) = auth::ViewProject::from_export_user_data(self.ed, metadata) {
+                let project = actions.get_project(&vp).await?;
+                let path = format!("projects/{}-{}.xml", &project.name, &project.id);
+                self.add_file(&path, project.to_xml().as_bytes())?;
+            }
+        }
+
+        Ok(())
+    }
+
+    async fn add_libraries(&mut self) -> Result<(), UserError> {
+        let query = doc! {"owner": &self.ed.username};
+        let libraries: Vec<_> = self
+            .app
+            .libraries
+            .find(query, None)
+            .await
+            .map_err(InternalError::DatabaseConnectionError)?
+            .try_collect()
+            .await
+            .map_err(InternalError::DatabaseConnectionError)?;
+
+        let metadata: Vec<api::LibraryMetadata> =
+            libraries.iter().cloned().map(|lib| lib.into()).collect();
+        self.add_json("libraries.json", &metadata)?;
+
+        for library in libraries {
+            let path = format!("libraries/{}.xml", &library.name);
+            self.add_file(&path, library.blocks.as_bytes())?;
+        }
+
+        Ok(())
+    }
+
+    async fn add_friends(&mut self) -> Result<(), UserError> {
+        let query = doc! {
+            "$or": [
+                {"sender": &self.ed.username},
+                {"recipient": &self.ed.username}
+            ]
+        };
+        let links: Vec<api::FriendLink> = self
+            .app
+            .friends
+            .find(query, None)
+            .await
+            .map_err(InternalError::DatabaseConnectionError)?
+            .try_collect::<Vec<_>>()
+            .await
+            .map_err(InternalError::DatabaseConnectionError)?
+            .into_iter()
+            .map(|link| link.into())
+            .collect();
+
+        self.add_json("friends.json", &links)
+    }
+
+    async fn add_messages(&mut self) -> Result<(), UserError> {
+        let query = doc! {
+            "$or": [
+                {"sender": &self.ed.username},
+                {"recipients": &self.ed.username}
+            ]
+        };
+        let messages: Vec<_> = self
+            .app
+            .logged_messages
+            .find(query, None)
+            .await
+            .map_err(InternalError::DatabaseConnectionError)?
+            .try_collect()
+            .await
+            .map_err(InternalError::DatabaseConnectionError)?;
+
+        self.add_json("messages.json", &messages)
+    }
+
+    fn add_json<T: Serialize>(&mut self, path: &str, data: &T) -> Result<(), UserError> {
+        let content = serde_json::to_vec_pretty(data).unwrap();
+        self.add_file(path, &content)
+    }
+
+    fn add_file(&mut self, path: &str, content: &[u8]) -> Result<(), UserError> {
+        self.writer
+            .start_file(path, FileOptions::default())
+            .map_err(InternalError::ArchiveError)?;
+        self.writer
+            .write_all(content)
+            .map_err(|err| InternalError::ArchiveError(err.into()))?;
+
+        Ok(())
+    }
+}
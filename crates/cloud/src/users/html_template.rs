@@ -96,3 +96,31 @@ pub(crate) fn set_password_page(username: &str) -> String {
 <html/>
     ", username=username)
 }
+
+pub(crate) fn email_verified_page(username: &str) -> String {
+    format!("
+<html>
+    <head>
+        <link rel=\"stylesheet\" href=\"https://cdnjs.cloudflare.com/ajax/libs/materialize/1.0.0/css/materialize.min.css\"/>
+        <meta name=\"viewport\" content=\"width=device-width, initial-scale=1.0\"/>
+        <title>NetsBlox Email Verified</title>
+    </head>
+    <body>
+        <div class=\"blue-grey lighten-5 valign-wrapper\" style=\"height:100%\">
+            <div class=\"container\">
+                <div class=\"row\">
+                    <div class=\"col s12 m8 offset-m2 l6 offset-l3\">
+                        <div class=\"card\">
+                            <div class=\"card-content\">
+                                <span class=\"card-title\">Email verified!</span>
+                                <p>The email address for {username} has been verified. You can now close this page.</p>
+                            </div>
+                        </div>
+                    </div>
+                </div>
+            </div>
+        </div>
+    </body>
+<html/>
+    ", username=username)
+}
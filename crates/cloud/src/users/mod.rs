@@ -1,6 +1,10 @@
 pub(crate) mod actions;
+pub(crate) mod deletion;
 pub(crate) mod routes;
 
 mod email_template;
+mod export;
 mod html_template;
-mod strategies;
+mod signup;
+pub(crate) mod strategies;
+mod two_factor;
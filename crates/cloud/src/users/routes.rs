@@ -4,11 +4,13 @@ use crate::app_data::AppData;
 use crate::auth;
 use crate::common::api;
 use crate::errors::UserError;
-use crate::users::actions::UserActions;
+use crate::users::actions::{SignupClient, UserActions};
+use crate::users::deletion::AccountDeletionJob;
+use crate::users::export::UserDataExport;
 use crate::utils;
 use actix_session::Session;
 use actix_web::http::header;
-use actix_web::{get, patch, post, HttpRequest};
+use actix_web::{delete, get, patch, post, HttpRequest};
 use actix_web::{web, HttpResponse};
 use mongodb::bson::doc;
 use serde::Deserialize;
@@ -28,22 +30,41 @@ async fn create_user(
     app: web::Data<AppData>,
     req: HttpRequest,
     user_data: web::Json<api::NewUser>,
+    solution: Option<web::Query<api::SignupChallengeSolution>>,
 ) -> Result<HttpResponse, UserError> {
-    let req_addr = req.peer_addr().map(|addr| addr.ip());
+    let req_addr = utils::get_client_addr(&req, &app.settings.security.trusted_proxies);
     if let Some(addr) = req_addr {
-        app.ensure_not_tor_ip(&addr).await?;
+        app.ensure_ip_allowed(&addr, api::IpReputationAction::BlockSignup)
+            .await?;
     }
 
-    // TODO: record IP? Definitely
-    // TODO: add more security features. Maybe activate accounts?
+    let client = SignupClient {
+        addr: req_addr,
+        user_agent: req
+            .headers()
+            .get(header::USER_AGENT)
+            .and_then(|agent| agent.to_str().ok())
+            .map(|agent| agent.to_owned()),
+        solution: solution.map(|solution| solution.into_inner()),
+    };
 
     let auth_cu = auth::try_create_user(&app, &req, user_data.into_inner()).await?;
     let actions: UserActions = app.as_user_actions();
-    let user = actions.create_user(auth_cu).await?;
+    let user = actions.create_user(auth_cu, client).await?;
 
     Ok(HttpResponse::Ok().json(user))
 }
 
+/// Get a proof-of-work challenge to solve when creating an account. The
+/// solution is passed to `/create` as query parameters (`challengeId` and `nonce`).
+#[post("/create/challenge")]
+async fn create_signup_challenge(app: web::Data<AppData>) -> Result<HttpResponse, UserError> {
+    let actions: UserActions = app.as_user_actions();
+    let challenge = actions.create_signup_challenge().await?;
+
+    Ok(HttpResponse::Ok().json(challenge))
+}
+
 #[post("/login")]
 async fn login(
     req: HttpRequest,
@@ -51,46 +72,180 @@ async fn login(
     request: web::Json<api::LoginRequest>,
     session: Session,
 ) -> Result<HttpResponse, UserError> {
-    let req_addr = req.peer_addr().map(|addr| addr.ip());
+    let req_addr = utils::get_client_addr(&req, &app.settings.security.trusted_proxies);
     if let Some(addr) = req_addr {
-        app.ensure_not_tor_ip(&addr).await?;
+        app.ensure_ip_allowed(&addr, api::IpReputationAction::BlockLogin)
+            .await?;
     }
 
     let request = request.into_inner();
 
+    let username = match &request.credentials {
+        api::Credentials::Snap { username, .. } | api::Credentials::NetsBlox { username, .. } => {
+            username.clone()
+        }
+    };
+    let attempts = app.as_login_attempt_actions();
+    attempts
+        .ensure_not_locked(&username, req_addr.as_ref())
+        .await?;
+
     let actions: UserActions = app.as_user_actions();
     let client_id = request.client_id.clone();
-    let user = actions.login(request).await?;
+    let client_secret = request.client_secret.clone();
+    let result = actions.login(request).await;
+    attempts
+        .record(&username, req_addr.as_ref(), &result)
+        .await?;
+    let user = result?;
 
     let helper = app.as_login_helper();
-    helper.login(session, &user, client_id).await?;
+    helper
+        .login(&req, session, &user, client_id, client_secret.as_deref())
+        .await?;
 
     Ok(HttpResponse::Ok().json(user))
 }
 
+#[derive(Deserialize, Debug)]
+#[serde(rename_all = "camelCase")]
+struct ExternalLoginQueryParams {
+    pub client_id: Option<api::ClientId>,
+    /// Secret issued with the client ID (required if the client ID is set)
+    pub client_secret: Option<String>,
+    /// URL to redirect to after login
+    pub redirect: Option<String>,
+}
+
+/// Login using an external identity provider (eg, OpenID Connect) by
+/// redirecting to the provider's login page
+#[get("/strategies/{strategy}/login")]
+async fn start_external_login(
+    app: web::Data<AppData>,
+    path: web::Path<(String,)>,
+    params: web::Query<ExternalLoginQueryParams>,
+    session: Session,
+) -> Result<HttpResponse, UserError> {
+    let (name,) = path.into_inner();
+    let strategy = app.strategies.get(&name)?;
+
+    let params = params.into_inner();
+    if let Some(client_id) = &params.client_id {
+        app.client_secrets
+            .verify(client_id, params.client_secret.as_deref())?;
+    }
+    if let Some(url) = &params.redirect {
+        utils::ensure_allowed_redirect(
+            url,
+            &app.settings.public_url,
+            &app.settings.allowed_redirect_origins,
+        )?;
+    }
+
+    let redirect_uri = format!(
+        "{}/users/strategies/{}/callback",
+        app.settings.public_url, &name
+    );
+    let flow = strategies::LoginFlow::new(name, redirect_uri, params.client_id, params.redirect);
+    let url = strategy.authorization_url(&flow).await?;
+
+    session.insert("loginFlow", &flow).unwrap();
+
+    Ok(HttpResponse::Found()
+        .insert_header((header::LOCATION, url))
+        .finish())
+}
+
+#[derive(Deserialize, Debug)]
+struct ExternalLoginCallbackParams {
+    pub code: String,
+    pub state: String,
+}
+
+#[get("/strategies/{strategy}/callback")]
+async fn finish_external_login(
+    req: HttpRequest,
+    app: web::Data<AppData>,
+    path: web::Path<(String,)>,
+    params: web::Query<ExternalLoginCallbackParams>,
+    session: Session,
+) -> Result<HttpResponse, UserError> {
+    let (name,) = path.into_inner();
+    let flow = session
+        .remove_as::<strategies::LoginFlow>("loginFlow")
+        .and_then(|flow| flow.ok())
+        .filter(|flow| flow.strategy == name && flow.state == params.state)
+        .ok_or(UserError::LoginStateMismatchError)?;
+
+    if let Some(url) = &flow.redirect {
+        utils::ensure_allowed_redirect(
+            url,
+            &app.settings.public_url,
+            &app.settings.allowed_redirect_origins,
+        )?;
+    }
+
+    let req_addr = utils::get_client_addr(&req, &app.settings.security.trusted_proxies);
+    if let Some(addr) = req_addr {
+        app.ensure_ip_allowed(&addr, api::IpReputationAction::BlockLogin)
+            .await?;
+    }
+
+    let actions: UserActions = app.as_user_actions();
+    let user = actions.login_external(&flow, &params.code).await?;
+
+    let helper = app.as_login_helper();
+    // The client secret was checked when starting the login flow
+    let client_secret = flow
+        .client_id
+        .as_ref()
+        .map(|client_id| app.client_secrets.issue(client_id));
+    helper
+        .login(
+            &req,
+            session,
+            &user,
+            flow.client_id,
+            client_secret.as_deref(),
+        )
+        .await?;
+
+    if let Some(url) = flow.redirect {
+        Ok(HttpResponse::Found()
+            .insert_header((header::LOCATION, url))
+            .finish())
+    } else {
+        Ok(HttpResponse::Ok().json(user))
+    }
+}
+
 #[derive(Deserialize, Debug)]
 #[serde(rename_all = "camelCase")]
 struct LogoutQueryParams {
     pub client_id: Option<api::ClientId>,
+    /// Secret issued with the client ID (required if the client ID is set)
+    pub client_secret: Option<String>,
 }
 
-// TODO: make sure the username and client ID are already associated
-// TODO: ideally this would make sure there is a client token/secret
 #[post("/logout")]
 async fn logout(
     app: web::Data<AppData>,
     params: web::Query<LogoutQueryParams>,
     session: Session,
-) -> HttpResponse {
+) -> Result<HttpResponse, UserError> {
+    if let Some(client_id) = &params.client_id {
+        app.client_secrets
+            .verify(client_id, params.client_secret.as_deref())?;
+    }
+
     session.purge();
 
     if let Some(client_id) = &params.client_id {
-        // FIXME: this method should be updated as it currently could be used to half logout other users...
         let actions: UserActions = app.as_user_actions();
         actions.logout(client_id);
     }
 
-    HttpResponse::Ok().finish()
+    Ok(HttpResponse::Ok().finish())
 }
 
 #[get("/whoami")]
@@ -117,13 +272,16 @@ async fn forgot_username(
 async fn ban_user(
     app: web::Data<AppData>,
     path: web::Path<(String,)>,
+    data: Option<web::Json<api::BanData>>,
     req: HttpRequest,
 ) -> Result<HttpResponse, UserError> {
     let (username,) = path.into_inner();
     let auth_bu = auth::try_ban_user(&app, &req, &username).await?;
 
+    // The ban details are optional (permanent ban w/o a reason by default)
+    let data = data.map(|data| data.into_inner()).unwrap_or_default();
     let actions: UserActions = app.as_user_actions();
-    let account = actions.ban_user(&auth_bu).await?;
+    let account = actions.ban_user(&auth_bu, data).await?;
 
     Ok(HttpResponse::Ok().json(account))
 }
@@ -143,6 +301,61 @@ async fn unban_user(
     Ok(HttpResponse::Ok().json(account))
 }
 
+#[get("/bans")]
+async fn list_banned_accounts(
+    app: web::Data<AppData>,
+    req: HttpRequest,
+) -> Result<HttpResponse, UserError> {
+    let auth_mb = auth::try_manage_bans(&app, &req).await?;
+
+    let actions: UserActions = app.as_user_actions();
+    let accounts = actions.list_banned_accounts(&auth_mb).await?;
+
+    Ok(HttpResponse::Ok().json(accounts))
+}
+
+#[get("/bans/addresses")]
+async fn list_banned_addresses(
+    app: web::Data<AppData>,
+    req: HttpRequest,
+) -> Result<HttpResponse, UserError> {
+    let auth_mb = auth::try_manage_bans(&app, &req).await?;
+
+    let actions: UserActions = app.as_user_actions();
+    let bans = actions.list_banned_addresses(&auth_mb).await?;
+
+    Ok(HttpResponse::Ok().json(bans))
+}
+
+#[post("/bans/addresses")]
+async fn ban_address(
+    app: web::Data<AppData>,
+    data: web::Json<api::BanAddressData>,
+    req: HttpRequest,
+) -> Result<HttpResponse, UserError> {
+    let auth_mb = auth::try_manage_bans(&app, &req).await?;
+
+    let actions: UserActions = app.as_user_actions();
+    let ban = actions.ban_address(&auth_mb, data.into_inner()).await?;
+
+    Ok(HttpResponse::Ok().json(ban))
+}
+
+#[delete("/bans/addresses/{id}")]
+async fn unban_address(
+    app: web::Data<AppData>,
+    path: web::Path<(String,)>,
+    req: HttpRequest,
+) -> Result<HttpResponse, UserError> {
+    let (id,) = path.into_inner();
+    let auth_mb = auth::try_manage_bans(&app, &req).await?;
+
+    let actions: UserActions = app.as_user_actions();
+    let ban = actions.unban_address(&auth_mb, &id).await?;
+
+    Ok(HttpResponse::Ok().json(ban))
+}
+
 #[post("/{username}/delete")]
 async fn delete_user(
     app: web::Data<AppData>,
@@ -151,23 +364,61 @@ async fn delete_user(
 ) -> Result<HttpResponse, UserError> {
     let (username,) = path.into_inner();
 
-    let auth_eu = auth::try_edit_user(&app, &req, None, &username).await?;
+    let auth_du = auth::try_delete_user(&app, &req, &username).await?;
 
     let actions: UserActions = app.as_user_actions();
-    let user = actions.delete_user(&auth_eu).await?;
+    let user = actions.delete_user(&auth_du).await?;
+
+    // Remove everything else owned by the user in the background
+    AccountDeletionJob::start(&app, auth_du);
 
     Ok(HttpResponse::Ok().json(user))
 }
 
+#[get("/{username}/export")]
+async fn export_user_data(
+    app: web::Data<AppData>,
+    path: web::Path<(String,)>,
+    req: HttpRequest,
+) -> Result<HttpResponse, UserError> {
+    let (username,) = path.into_inner();
+
+    let auth_ed = auth::try_export_user_data(&app, &req, &username).await?;
+    let archive = UserDataExport::new(&app, &auth_ed).build().await?;
+
+    let disposition = format!("attachment; filename=\"{}.zip\"", &username);
+    Ok(HttpResponse::Ok()
+        .content_type("application/zip")
+        .insert_header((header::CONTENT_DISPOSITION, disposition))
+        .body(archive))
+}
+
+#[get("/{username}/deletion")]
+async fn view_account_deletion(
+    app: web::Data<AppData>,
+    path: web::Path<(String,)>,
+    req: HttpRequest,
+) -> Result<HttpResponse, UserError> {
+    let (username,) = path.into_inner();
+
+    let auth_vd = auth::try_view_account_deletion(&app, &req, &username).await?;
+
+    let actions: UserActions = app.as_user_actions();
+    let deletion = actions.view_account_deletion(&auth_vd).await?;
+
+    Ok(HttpResponse::Ok().json(deletion))
+}
+
 #[post("/{username}/password")]
 async fn reset_password(
     app: web::Data<AppData>,
     req: HttpRequest,
     path: web::Path<(String,)>,
 ) -> Result<HttpResponse, UserError> {
-    let req_addr = req.peer_addr().map(|addr| addr.ip());
+    let req_addr = utils::get_client_addr(&req, &app.settings.security.trusted_proxies);
     if let Some(addr) = req_addr {
-        app.ensure_not_tor_ip(&addr).await?;
+        app.ensure_ip_allowed(&addr, api::IpReputationAction::BlockLogin)
+            .await?;
     }
 
     let (username,) = path.into_inner();
@@ -211,6 +462,199 @@ async fn change_password(
     Ok(HttpResponse::Ok().json(user))
 }
 
+/// Resend the email for verifying the user's email address
+#[post("/{username}/verification")]
+async fn resend_verification(
+    app: web::Data<AppData>,
+    path: web::Path<(String,)>,
+    req: HttpRequest,
+) -> Result<HttpResponse, UserError> {
+    let (username,) = path.into_inner();
+    let auth_eu = auth::try_edit_user(&app, &req, None, &username).await?;
+
+    let actions: UserActions = app.as_user_actions();
+    actions.resend_verification(&auth_eu).await?;
+
+    Ok(HttpResponse::Ok().finish())
+}
+
+#[derive(Deserialize)]
+struct VerifyEmailQueryParams {
+    pub token: String,
+}
+
+/// Verify the user's email address using the link sent to them
+#[get("/{username}/verification")]
+async fn verify_email(
+    app: web::Data<AppData>,
+    path: web::Path<(String,)>,
+    params: web::Query<VerifyEmailQueryParams>,
+) -> Result<HttpResponse, UserError> {
+    let (username,) = path.into_inner();
+    let auth_ve = auth::try_verify_email(&app, &username, &params.token).await?;
+
+    let actions: UserActions = app.as_user_actions();
+    let user = actions.verify_email(&auth_ve).await?;
+
+    let html = html_template::email_verified_page(&user.username);
+    Ok(HttpResponse::Ok()
+        .content_type(header::ContentType::html())
+        .body(html))
+}
+
+#[get("/{username}/sessions")]
+async fn list_sessions(
+    app: web::Data<AppData>,
+    path: web::Path<(String,)>,
+    req: HttpRequest,
+) -> Result<HttpResponse, UserError> {
+    let (username,) = path.into_inner();
+    let auth_ms = auth::try_manage_sessions(&app, &req, &username).await?;
+
+    let actions = app.as_session_actions();
+    let sessions = actions.list_sessions(&auth_ms).await?;
+
+    Ok(HttpResponse::Ok().json(sessions))
+}
+
+#[delete("/{username}/sessions")]
+async fn revoke_all_sessions(
+    app: web::Data<AppData>,
+    path: web::Path<(String,)>,
+    req: HttpRequest,
+) -> Result<HttpResponse, UserError> {
+    let (username,) = path.into_inner();
+    let auth_ms = auth::try_manage_sessions(&app, &req, &username).await?;
+
+    let actions = app.as_session_actions();
+    actions.revoke_all_sessions(&auth_ms).await?;
+
+    Ok(HttpResponse::Ok().finish())
+}
+
+#[delete("/{username}/sessions/{id}")]
+async fn revoke_session(
+    app: web::Data<AppData>,
+    path: web::Path<(String, String)>,
+    req: HttpRequest,
+) -> Result<HttpResponse, UserError> {
+    let (username, id) = path.into_inner();
+    let auth_ms = auth::try_manage_sessions(&app, &req, &username).await?;
+
+    let actions = app.as_session_actions();
+    let session = actions.revoke_session(&auth_ms, &id).await?;
+
+    Ok(HttpResponse::Ok().json(session))
+}
+
+#[get("/{username}/tokens")]
+async fn list_access_tokens(
+    app: web::Data<AppData>,
+    path: web::Path<(String,)>,
+    req: HttpRequest,
+) -> Result<HttpResponse, UserError> {
+    let (username,) = path.into_inner();
+    let auth_mt = auth::try_manage_access_tokens(&req, &username).await?;
+
+    let actions = app.as_access_token_actions();
+    let tokens = actions.list_tokens(&auth_mt).await?;
+
+    Ok(HttpResponse::Ok().json(tokens))
+}
+
+#[post("/{username}/tokens")]
+async fn create_access_token(
+    app: web::Data<AppData>,
+    path: web::Path<(String,)>,
+    data: web::Json<api::CreateAccessTokenData>,
+    req: HttpRequest,
+) -> Result<HttpResponse, UserError> {
+    let (username,) = path.into_inner();
+    let auth_mt = auth::try_manage_access_tokens(&req, &username).await?;
+
+    let actions = app.as_access_token_actions();
+    let token = actions.create_token(&auth_mt, data.into_inner()).await?;
+
+    Ok(HttpResponse::Ok().json(token))
+}
+
+#[delete("/{username}/tokens/{id}")]
+async fn revoke_access_token(
+    app: web::Data<AppData>,
+    path: web::Path<(String, String)>,
+    req: HttpRequest,
+) -> Result<HttpResponse, UserError> {
+    let (username, id) = path.into_inner();
+    let auth_mt = auth::try_manage_access_tokens(&req, &username).await?;
+
+    let actions = app.as_access_token_actions();
+    let token = actions.revoke_token(&auth_mt, &id).await?;
+
+    Ok(HttpResponse::Ok().json(token))
+}
+
+#[post("/{username}/two-factor")]
+async fn enroll_two_factor(
+    path: web::Path<(String,)>,
+    app: web::Data<AppData>,
+    req: HttpRequest,
+) -> Result<HttpResponse, UserError> {
+    let (username,) = path.into_inner();
+    let auth_mt = auth::try_manage_two_factor(&req, &username).await?;
+
+    let actions: UserActions = app.as_user_actions();
+    let enrollment = actions.enroll_two_factor(&auth_mt).await?;
+
+    Ok(HttpResponse::Ok().json(enrollment))
+}
+
+#[post("/{username}/two-factor/confirm")]
+async fn confirm_two_factor(
+    path: web::Path<(String,)>,
+    app: web::Data<AppData>,
+    code: web::Json<String>,
+    req: HttpRequest,
+) -> Result<HttpResponse, UserError> {
+    let (username,) = path.into_inner();
+    let auth_mt = auth::try_manage_two_factor(&req, &username).await?;
+
+    let actions: UserActions = app.as_user_actions();
+    actions.confirm_two_factor(&auth_mt, &code).await?;
+
+    Ok(HttpResponse::Ok().finish())
+}
+
+#[post("/{username}/two-factor/disable")]
+async fn disable_two_factor(
+    path: web::Path<(String,)>,
+    app: web::Data<AppData>,
+    code: web::Json<String>,
+    req: HttpRequest,
+) -> Result<HttpResponse, UserError> {
+    let (username,) = path.into_inner();
+    let auth_mt = auth::try_manage_two_factor(&req, &username).await?;
+
+    let actions: UserActions = app.as_user_actions();
+    actions.disable_two_factor(&auth_mt, &code).await?;
+
+    Ok(HttpResponse::Ok().finish())
+}
+
+#[post("/{username}/two-factor/reset")]
+async fn reset_two_factor(
+    path: web::Path<(String,)>,
+    app: web::Data<AppData>,
+    req: HttpRequest,
+) -> Result<HttpResponse, UserError> {
+    let (username,) = path.into_inner();
+    let auth_rt = auth::try_reset_two_factor(&app, &req, &username).await?;
+
+    let actions: UserActions = app.as_user_actions();
+    let user = actions.reset_two_factor(&auth_rt).await?;
+
+    Ok(HttpResponse::Ok().json(user))
+}
+
 #[get("/{username}")]
 async fn view_user(
     app: web::Data<AppData>,
@@ -280,34 +724,61 @@ async fn unlink_account(
 
 pub fn config(cfg: &mut web::ServiceConfig) {
     cfg.service(create_user)
+        .service(create_signup_challenge)
         .service(update_user)
         .service(list_users)
         .service(login)
+        .service(start_external_login)
+        .service(finish_external_login)
         .service(logout)
         .service(delete_user)
+        .service(view_account_deletion)
+        .service(export_user_data)
         .service(forgot_username)
+        .service(list_banned_accounts)
+        .service(list_banned_addresses)
+        .service(ban_address)
+        .service(unban_address)
         .service(ban_user)
         .service(unban_user)
         .service(reset_password)
         .service(change_password_page)
         .service(change_password)
+        .service(resend_verification)
+        .service(verify_email)
         .service(whoami)
         .service(view_user)
         .service(link_account)
-        .service(unlink_account);
+        .service(unlink_account)
+        .service(list_sessions)
+        .service(revoke_all_sessions)
+        .service(revoke_session)
+        .service(list_access_tokens)
+        .service(create_access_token)
+        .service(revoke_access_token)
+        .service(enroll_two_factor)
+        .service(confirm_two_factor)
+        .service(disable_two_factor)
+        .service(reset_two_factor);
 }
 
 #[cfg(test)]
 mod tests {
-    use std::time::Duration;
+    use std::{
+        collections::HashMap,
+        time::{Duration, SystemTime},
+    };
 
     use crate::{errors::InternalError, network::topology, test_utils};
 
     use super::*;
+    use crate::access_tokens::middleware::AccessTokenAuth;
+    use crate::users::two_factor;
     use actix_web::{http, test, App};
+    use mongodb::bson::DateTime;
     use netsblox_cloud_common::{
         api::{BannedAccount, Credentials, UserRole},
-        Group, User,
+        BannedAddress, Group, TwoFactorAuth, User, UserSession,
     };
 
     #[actix_web::test]
@@ -723,6 +1194,8 @@ mod tests {
                 let credentials = api::LoginRequest {
                     credentials: Credentials::NetsBlox { username, password },
                     client_id: None,
+                    client_secret: None,
+                    two_factor_code: None,
                 };
                 let req = test::TestRequest::post()
                     .uri("/login")
@@ -770,6 +1243,8 @@ mod tests {
                         password,
                     },
                     client_id: None,
+                    client_secret: None,
+                    two_factor_code: None,
                 };
                 let req = test::TestRequest::post()
                     .uri("/login")
@@ -811,6 +1286,8 @@ mod tests {
                         password: "badpwd".into(),
                     },
                     client_id: None,
+                    client_secret: None,
+                    two_factor_code: None,
                 };
                 let req = test::TestRequest::post()
                     .uri("/login")
@@ -826,12 +1303,22 @@ mod tests {
     }
 
     #[actix_web::test]
-    async fn test_login_invalid_user() {
+    async fn test_login_locked() {
         let username: String = "user".into();
         let password: String = "password".into();
+        let user: User = api::NewUser {
+            username: username.clone(),
+            email: "user@netsblox.org".into(),
+            password: Some(password.clone()),
+            group_id: None,
+            role: None,
+        }
+        .into();
 
         test_utils::setup()
-            .run(|app_data| async {
+            .with_users(&[user])
+            .run(|mut app_data| async {
+                app_data.settings.login_protection.max_failures_per_user = 2;
                 let app = test::init_service(
                     App::new()
                         .wrap(test_utils::cookie::middleware())
@@ -839,19 +1326,64 @@ mod tests {
                         .configure(config),
                 )
                 .await;
-                let credentials = api::LoginRequest {
-                    credentials: Credentials::NetsBlox { username, password },
-                    client_id: None,
+                let login_req = |password: &str| {
+                    let credentials = api::LoginRequest {
+                        credentials: Credentials::NetsBlox {
+                            username: username.clone(),
+                            password: password.into(),
+                        },
+                        client_id: None,
+                        client_secret: None,
+                        two_factor_code: None,
+                    };
+                    test::TestRequest::post()
+                        .uri("/login")
+                        .set_json(&credentials)
+                        .to_request()
                 };
-                let req = test::TestRequest::post()
-                    .uri("/login")
-                    .set_json(&credentials)
-                    .to_request();
 
-                let response = test::call_service(&app, req).await;
-                assert_eq!(response.status(), http::StatusCode::NOT_FOUND);
-                let cookie = response.headers().get(http::header::SET_COOKIE);
-                assert!(cookie.is_none());
+                for _ in 0..2 {
+                    let response = test::call_service(&app, login_req("badpwd")).await;
+                    assert_eq!(response.status(), http::StatusCode::FORBIDDEN);
+                }
+
+                // the correct password is rejected while the account is locked
+                let response = test::call_service(&app, login_req(&password)).await;
+                assert_eq!(response.status(), http::StatusCode::TOO_MANY_REQUESTS);
+                assert!(response.headers().get(http::header::RETRY_AFTER).is_some());
+            })
+            .await;
+    }
+
+    #[actix_web::test]
+    async fn test_login_invalid_user() {
+        let username: String = "user".into();
+        let password: String = "password".into();
+
+        test_utils::setup()
+            .run(|app_data| async {
+                let app = test::init_service(
+                    App::new()
+                        .wrap(test_utils::cookie::middleware())
+                        .app_data(web::Data::new(app_data))
+                        .configure(config),
+                )
+                .await;
+                let credentials = api::LoginRequest {
+                    credentials: Credentials::NetsBlox { username, password },
+                    client_id: None,
+                    client_secret: None,
+                    two_factor_code: None,
+                };
+                let req = test::TestRequest::post()
+                    .uri("/login")
+                    .set_json(&credentials)
+                    .to_request();
+
+                let response = test::call_service(&app, req).await;
+                assert_eq!(response.status(), http::StatusCode::NOT_FOUND);
+                let cookie = response.headers().get(http::header::SET_COOKIE);
+                assert!(cookie.is_none());
             })
             .await;
     }
@@ -886,7 +1418,9 @@ mod tests {
                         username: username.clone(),
                         password,
                     },
+                    client_secret: Some(app_data.client_secrets.issue(&client.id)),
                     client_id: Some(client.id),
+                    two_factor_code: None,
                 };
                 let req = test::TestRequest::post()
                     .uri("/login")
@@ -940,6 +1474,8 @@ mod tests {
                 let credentials = api::LoginRequest {
                     credentials: Credentials::NetsBlox { username, password },
                     client_id: None,
+                    client_secret: None,
+                    two_factor_code: None,
                 };
                 let req = test::TestRequest::post()
                     .uri("/login")
@@ -954,18 +1490,8 @@ mod tests {
             .await;
     }
 
-    //     #[actix_web::test]
-    //     async fn test_login_with_strategy() {
-    //         todo!();
-    //     }
-
-    //     #[actix_web::test]
-    //     async fn test_login_with_strategy_403() {
-    //         todo!();
-    //     }
-
     #[actix_web::test]
-    async fn test_logout() {
+    async fn test_login_ban_expired() {
         let username: String = "user".into();
         let password: String = "password".into();
         let user: User = api::NewUser {
@@ -979,7 +1505,23 @@ mod tests {
 
         test_utils::setup()
             .with_users(&[user.clone()])
-            .run(|app_data| async {
+            .run(|app_data| async move {
+                let mut ban = netsblox_cloud_common::BannedAccount::new(
+                    user.username,
+                    user.email,
+                    Some("moderator".into()),
+                    None,
+                    Some(Duration::from_secs(60)),
+                );
+                ban.expires_at = Some(DateTime::from_system_time(
+                    SystemTime::now() - Duration::from_secs(60),
+                ));
+                app_data
+                    .banned_accounts
+                    .insert_one(ban, None)
+                    .await
+                    .unwrap();
+
                 let app = test::init_service(
                     App::new()
                         .wrap(test_utils::cookie::middleware())
@@ -987,23 +1529,25 @@ mod tests {
                         .configure(config),
                 )
                 .await;
+                let credentials = api::LoginRequest {
+                    credentials: Credentials::NetsBlox { username, password },
+                    client_id: None,
+                    client_secret: None,
+                    two_factor_code: None,
+                };
                 let req = test::TestRequest::post()
-                    .uri("/logout")
-                    .cookie(test_utils::cookie::new(&username))
+                    .uri("/login")
+                    .set_json(&credentials)
                     .to_request();
 
                 let response = test::call_service(&app, req).await;
                 assert_eq!(response.status(), http::StatusCode::OK);
-                let cookie = response.headers().get(http::header::SET_COOKIE);
-                assert!(cookie.is_some());
-                let cookie_data = cookie.unwrap().to_str().unwrap();
-                assert!(cookie_data.starts_with("test_netsblox=;"));
             })
             .await;
     }
 
     #[actix_web::test]
-    async fn test_logout_set_client_username() {
+    async fn test_login_banned_address() {
         let username: String = "user".into();
         let password: String = "password".into();
         let user: User = api::NewUser {
@@ -1014,162 +1558,141 @@ mod tests {
             role: None,
         }
         .into();
-        let client = test_utils::network::Client::new(Some(username.clone()), None);
 
         test_utils::setup()
-            .with_users(&[user.clone()])
-            .with_clients(&[client.clone()])
+            .with_users(&[user])
             .run(|app_data| async move {
+                let ban = BannedAddress::new("10.0.0.0/8".into(), "moderator".into(), None, None);
+                app_data
+                    .banned_addresses
+                    .insert_one(&ban, None)
+                    .await
+                    .unwrap();
+                app_data.ip_lists.add_ban(&ban);
+
                 let app = test::init_service(
                     App::new()
                         .wrap(test_utils::cookie::middleware())
-                        .app_data(web::Data::new(app_data.clone()))
+                        .app_data(web::Data::new(app_data))
                         .configure(config),
                 )
                 .await;
+                let credentials = api::LoginRequest {
+                    credentials: Credentials::NetsBlox { username, password },
+                    client_id: None,
+                    client_secret: None,
+                    two_factor_code: None,
+                };
                 let req = test::TestRequest::post()
-                    .uri(&format!("/logout?clientId={}", client.id.as_str()))
-                    .cookie(test_utils::cookie::new(&username))
+                    .uri("/login")
+                    .peer_addr("10.1.2.3:8080".parse().unwrap())
+                    .set_json(&credentials)
                     .to_request();
 
                 let response = test::call_service(&app, req).await;
-                assert_eq!(response.status(), http::StatusCode::OK);
-
-                // Give it a little time for the actix message to be received
-                // (message passing is async)
-                tokio::time::sleep(Duration::from_millis(10)).await;
-
-                let task = app_data
-                    .network
-                    .send(topology::GetOnlineUsers(None))
-                    .await
-                    .map_err(InternalError::ActixMessageError)
-                    .unwrap();
-                let online_friends = task.run().await;
-
-                assert_eq!(online_friends.len(), 0);
+                assert_eq!(response.status(), http::StatusCode::FORBIDDEN);
             })
             .await;
     }
 
-    #[actix_web::test]
-    async fn test_delete_user_admin() {
-        let admin: User = api::NewUser {
-            username: "admin".into(),
-            email: "admin@netsblox.org".into(),
-            password: None,
-            group_id: None,
-            role: Some(UserRole::Admin),
-        }
-        .into();
-        let other_username = "other_user";
-        let other_user: User = api::NewUser {
-            username: other_username.to_string(),
+    fn two_factor_user(username: &str, password: &str) -> User {
+        let mut user: User = api::NewUser {
+            username: username.to_owned(),
             email: "user@netsblox.org".into(),
-            password: None,
+            password: Some(password.to_owned()),
             group_id: None,
             role: None,
         }
         .into();
+        user.two_factor = Some(TwoFactorAuth {
+            secret: "JBSWY3DPEHPK3PXPJBSWY3DPEHPK3PXP".into(),
+            enabled: true,
+            recovery_codes: vec![two_factor::hash_recovery_code("recoverycode")],
+            last_used_step: None,
+            created_at: mongodb::bson::DateTime::now(),
+        });
+        user
+    }
+
+    #[actix_web::test]
+    async fn test_login_two_factor_required() {
+        let username: String = "user".into();
+        let password: String = "password".into();
+        let user = two_factor_user(&username, &password);
 
         test_utils::setup()
-            .with_users(&[admin, other_user])
-            .run(|app_data| async move {
+            .with_users(&[user])
+            .run(|app_data| async {
                 let app = test::init_service(
                     App::new()
                         .wrap(test_utils::cookie::middleware())
-                        .app_data(web::Data::new(app_data.clone()))
+                        .app_data(web::Data::new(app_data))
                         .configure(config),
                 )
                 .await;
-
-                let cookie = test_utils::cookie::new("admin");
+                let credentials = api::LoginRequest {
+                    credentials: Credentials::NetsBlox { username, password },
+                    client_id: None,
+                    client_secret: None,
+                    two_factor_code: None,
+                };
                 let req = test::TestRequest::post()
-                    .uri("/other_user/delete")
-                    .cookie(cookie)
+                    .uri("/login")
+                    .set_json(&credentials)
                     .to_request();
 
                 let response = test::call_service(&app, req).await;
-                assert_eq!(response.status(), http::StatusCode::OK);
-
-                let query = doc! {"username": other_username};
-                let result = app_data
-                    .users
-                    .find_one(query, None)
-                    .await
-                    .expect("Could not query for user");
-
-                assert!(result.is_none(), "User not deleted");
+                assert_eq!(response.status(), http::StatusCode::UNAUTHORIZED);
+                assert!(response
+                    .headers()
+                    .contains_key(http::header::WWW_AUTHENTICATE));
+                let cookie = response.headers().get(http::header::SET_COOKIE);
+                assert!(cookie.is_none());
             })
             .await;
     }
 
     #[actix_web::test]
-    async fn test_delete_user_unauth() {
-        let other_username = "other_user";
-        let other_user: User = api::NewUser {
-            username: other_username.to_string(),
-            email: "user@netsblox.org".into(),
-            password: None,
-            group_id: None,
-            role: None,
-        }
-        .into();
+    async fn test_login_two_factor_invalid_code() {
+        let username: String = "user".into();
+        let password: String = "password".into();
+        let user = two_factor_user(&username, &password);
 
         test_utils::setup()
-            .with_users(&[other_user])
-            .run(|app_data| async move {
+            .with_users(&[user])
+            .run(|app_data| async {
                 let app = test::init_service(
                     App::new()
-                        .app_data(web::Data::new(app_data.clone()))
+                        .wrap(test_utils::cookie::middleware())
+                        .app_data(web::Data::new(app_data))
                         .configure(config),
                 )
                 .await;
-
+                let credentials = api::LoginRequest {
+                    credentials: Credentials::NetsBlox { username, password },
+                    client_id: None,
+                    client_secret: None,
+                    two_factor_code: Some("notACode".into()),
+                };
                 let req = test::TestRequest::post()
-                    .uri("/other_user/delete")
+                    .uri("/login")
+                    .set_json(&credentials)
                     .to_request();
 
                 let response = test::call_service(&app, req).await;
-                assert_eq!(response.status(), http::StatusCode::UNAUTHORIZED);
-
-                let query = doc! {"username": other_username};
-                let result = app_data
-                    .users
-                    .find_one(query, None)
-                    .await
-                    .expect("Could not query for user");
-
-                assert!(result.is_some(), "User deleted");
+                assert_eq!(response.status(), http::StatusCode::FORBIDDEN);
             })
             .await;
     }
 
     #[actix_web::test]
-    async fn test_delete_user_group_owner() {
-        let owner_name = "owner".to_string();
-        let owner: User = api::NewUser {
-            username: owner_name.clone(),
-            email: "owner@netsblox.org".into(),
-            password: None,
-            group_id: None,
-            role: None,
-        }
-        .into();
-        let group = Group::new(owner_name.clone(), "some_group".into());
-        let other_username = "other_user";
-        let other_user: User = api::NewUser {
-            username: other_username.to_string(),
-            email: "user@netsblox.org".into(),
-            password: None,
-            group_id: Some(group.id.clone()),
-            role: None,
-        }
-        .into();
+    async fn test_login_two_factor_recovery_code() {
+        let username: String = "user".into();
+        let password: String = "password".into();
+        let user = two_factor_user(&username, &password);
 
         test_utils::setup()
-            .with_users(&[owner, other_user])
-            .with_groups(&[group])
+            .with_users(&[user])
             .run(|app_data| async move {
                 let app = test::init_service(
                     App::new()
@@ -1178,49 +1701,49 @@ mod tests {
                         .configure(config),
                 )
                 .await;
-
+                let credentials = api::LoginRequest {
+                    credentials: Credentials::NetsBlox {
+                        username: username.clone(),
+                        password,
+                    },
+                    client_id: None,
+                    client_secret: None,
+                    two_factor_code: Some("recoverycode".into()),
+                };
                 let req = test::TestRequest::post()
-                    .uri("/other_user/delete")
-                    .cookie(test_utils::cookie::new(&owner_name))
+                    .uri("/login")
+                    .set_json(&credentials)
                     .to_request();
 
                 let response = test::call_service(&app, req).await;
                 assert_eq!(response.status(), http::StatusCode::OK);
 
-                let query = doc! {"username": other_username};
-                let result = app_data
-                    .users
-                    .find_one(query, None)
-                    .await
-                    .expect("Could not query for user");
+                // recovery codes can only be used once
+                let req = test::TestRequest::post()
+                    .uri("/login")
+                    .set_json(&credentials)
+                    .to_request();
 
-                assert!(result.is_none(), "User not deleted");
+                let response = test::call_service(&app, req).await;
+                assert_eq!(response.status(), http::StatusCode::FORBIDDEN);
             })
             .await;
     }
 
     #[actix_web::test]
-    async fn test_delete_user_other_user() {
-        let user1: User = api::NewUser {
-            username: "user1".to_string(),
-            email: "user1@netsblox.org".into(),
-            password: None,
-            group_id: None,
-            role: None,
-        }
-        .into();
-        let user1_name = user1.username.clone();
-        let user2: User = api::NewUser {
-            username: "user2".to_string(),
-            email: "user@netsblox.org".into(),
+    async fn test_reset_two_factor() {
+        let user = two_factor_user("user", "password");
+        let admin: User = api::NewUser {
+            username: "admin".into(),
+            email: "admin@netsblox.org".into(),
             password: None,
             group_id: None,
-            role: None,
+            role: Some(UserRole::Admin),
         }
         .into();
 
         test_utils::setup()
-            .with_users(&[user1, user2])
+            .with_users(&[user.clone(), admin.clone()])
             .run(|app_data| async move {
                 let app = test::init_service(
                     App::new()
@@ -1229,14 +1752,412 @@ mod tests {
                         .configure(config),
                 )
                 .await;
-
                 let req = test::TestRequest::post()
-                    .uri("/user2/delete")
-                    .cookie(test_utils::cookie::new(&user1_name))
+                    .cookie(test_utils::cookie::new(&admin.username))
+                    .uri(&format!("/{}/two-factor/reset", &user.username))
                     .to_request();
 
                 let response = test::call_service(&app, req).await;
-                assert_eq!(response.status(), http::StatusCode::FORBIDDEN);
+                assert_eq!(response.status(), http::StatusCode::OK);
+
+                let query = doc! {"username": &user.username};
+                let user = app_data.users.find_one(query, None).await.unwrap().unwrap();
+                assert!(user.two_factor.is_none());
+            })
+            .await;
+    }
+
+    #[actix_web::test]
+    async fn test_reset_two_factor_self() {
+        let user = two_factor_user("user", "password");
+
+        test_utils::setup()
+            .with_users(&[user.clone()])
+            .run(|app_data| async move {
+                let app = test::init_service(
+                    App::new()
+                        .wrap(test_utils::cookie::middleware())
+                        .app_data(web::Data::new(app_data.clone()))
+                        .configure(config),
+                )
+                .await;
+                let req = test::TestRequest::post()
+                    .cookie(test_utils::cookie::new(&user.username))
+                    .uri(&format!("/{}/two-factor/reset", &user.username))
+                    .to_request();
+
+                let response = test::call_service(&app, req).await;
+                assert_eq!(response.status(), http::StatusCode::FORBIDDEN);
+
+                let query = doc! {"username": &user.username};
+                let user = app_data.users.find_one(query, None).await.unwrap().unwrap();
+                assert!(user.has_two_factor());
+            })
+            .await;
+    }
+
+    //     #[actix_web::test]
+    //     async fn test_login_with_strategy() {
+    //         todo!();
+    //     }
+
+    //     #[actix_web::test]
+    //     async fn test_login_with_strategy_403() {
+    //         todo!();
+    //     }
+
+    #[actix_web::test]
+    async fn test_login_with_strategy_redirect_not_allowed() {
+        test_utils::setup()
+            .run(|app_data| async {
+                let app = test::init_service(
+                    App::new()
+                        .wrap(test_utils::cookie::middleware())
+                        .app_data(web::Data::new(app_data))
+                        .configure(config),
+                )
+                .await;
+                let req = test::TestRequest::get()
+                    .uri("/strategies/snap/login?redirect=https%3A%2F%2Fevil.example.com%2F")
+                    .to_request();
+
+                let response = test::call_service(&app, req).await;
+                assert_eq!(response.status(), http::StatusCode::BAD_REQUEST);
+            })
+            .await;
+    }
+
+    #[actix_web::test]
+    async fn test_logout() {
+        let username: String = "user".into();
+        let password: String = "password".into();
+        let user: User = api::NewUser {
+            username: username.clone(),
+            email: "user@netsblox.org".into(),
+            password: Some(password.clone()),
+            group_id: None,
+            role: None,
+        }
+        .into();
+
+        test_utils::setup()
+            .with_users(&[user.clone()])
+            .run(|app_data| async {
+                let app = test::init_service(
+                    App::new()
+                        .wrap(test_utils::cookie::middleware())
+                        .app_data(web::Data::new(app_data))
+                        .configure(config),
+                )
+                .await;
+                let req = test::TestRequest::post()
+                    .uri("/logout")
+                    .cookie(test_utils::cookie::new(&username))
+                    .to_request();
+
+                let response = test::call_service(&app, req).await;
+                assert_eq!(response.status(), http::StatusCode::OK);
+                let cookie = response.headers().get(http::header::SET_COOKIE);
+                assert!(cookie.is_some());
+                let cookie_data = cookie.unwrap().to_str().unwrap();
+                assert!(cookie_data.starts_with("test_netsblox=;"));
+            })
+            .await;
+    }
+
+    #[actix_web::test]
+    async fn test_logout_set_client_username() {
+        let username: String = "user".into();
+        let password: String = "password".into();
+        let user: User = api::NewUser {
+            username: username.clone(),
+            email: "user@netsblox.org".into(),
+            password: Some(password.clone()),
+            group_id: None,
+            role: None,
+        }
+        .into();
+        let client = test_utils::network::Client::new(Some(username.clone()), None);
+
+        test_utils::setup()
+            .with_users(&[user.clone()])
+            .with_clients(&[client.clone()])
+            .run(|app_data| async move {
+                let app = test::init_service(
+                    App::new()
+                        .wrap(test_utils::cookie::middleware())
+                        .app_data(web::Data::new(app_data.clone()))
+                        .configure(config),
+                )
+                .await;
+                let secret = app_data.client_secrets.issue(&client.id);
+                let req = test::TestRequest::post()
+                    .uri(&format!(
+                        "/logout?clientId={}&clientSecret={}",
+                        client.id.as_str(),
+                        secret
+                    ))
+                    .cookie(test_utils::cookie::new(&username))
+                    .to_request();
+
+                let response = test::call_service(&app, req).await;
+                assert_eq!(response.status(), http::StatusCode::OK);
+
+                // Give it a little time for the actix message to be received
+                // (message passing is async)
+                tokio::time::sleep(Duration::from_millis(10)).await;
+
+                let task = app_data
+                    .network
+                    .send(topology::GetOnlineUsers(None))
+                    .await
+                    .map_err(InternalError::ActixMessageError)
+                    .unwrap();
+                let online_friends = task.run().await;
+
+                assert_eq!(online_friends.len(), 0);
+            })
+            .await;
+    }
+
+    #[actix_web::test]
+    async fn test_logout_invalid_client_secret() {
+        let username: String = "user".into();
+        let user: User = api::NewUser {
+            username: username.clone(),
+            email: "user@netsblox.org".into(),
+            password: None,
+            group_id: None,
+            role: None,
+        }
+        .into();
+        let client = test_utils::network::Client::new(Some(username.clone()), None);
+        let other_client = test_utils::network::Client::new(None, None);
+
+        test_utils::setup()
+            .with_users(&[user.clone()])
+            .with_clients(&[client.clone(), other_client.clone()])
+            .run(|app_data| async move {
+                let app = test::init_service(
+                    App::new()
+                        .wrap(test_utils::cookie::middleware())
+                        .app_data(web::Data::new(app_data.clone()))
+                        .configure(config),
+                )
+                .await;
+                let secret = app_data.client_secrets.issue(&other_client.id);
+                let req = test::TestRequest::post()
+                    .uri(&format!(
+                        "/logout?clientId={}&clientSecret={}",
+                        client.id.as_str(),
+                        secret
+                    ))
+                    .cookie(test_utils::cookie::new(&username))
+                    .to_request();
+
+                let response = test::call_service(&app, req).await;
+                assert_eq!(response.status(), http::StatusCode::UNAUTHORIZED);
+
+                tokio::time::sleep(Duration::from_millis(10)).await;
+
+                let task = app_data
+                    .network
+                    .send(topology::GetOnlineUsers(None))
+                    .await
+                    .map_err(InternalError::ActixMessageError)
+                    .unwrap();
+                let online_friends = task.run().await;
+
+                assert_eq!(online_friends.len(), 1);
+            })
+            .await;
+    }
+
+    #[actix_web::test]
+    async fn test_delete_user_admin() {
+        let admin: User = api::NewUser {
+            username: "admin".into(),
+            email: "admin@netsblox.org".into(),
+            password: None,
+            group_id: None,
+            role: Some(UserRole::Admin),
+        }
+        .into();
+        let other_username = "other_user";
+        let other_user: User = api::NewUser {
+            username: other_username.to_string(),
+            email: "user@netsblox.org".into(),
+            password: None,
+            group_id: None,
+            role: None,
+        }
+        .into();
+
+        test_utils::setup()
+            .with_users(&[admin, other_user])
+            .run(|app_data| async move {
+                let app = test::init_service(
+                    App::new()
+                        .wrap(test_utils::cookie::middleware())
+                        .app_data(web::Data::new(app_data.clone()))
+                        .configure(config),
+                )
+                .await;
+
+                let cookie = test_utils::cookie::new("admin");
+                let req = test::TestRequest::post()
+                    .uri("/other_user/delete")
+                    .cookie(cookie)
+                    .to_request();
+
+                let response = test::call_service(&app, req).await;
+                assert_eq!(response.status(), http::StatusCode::OK);
+
+                let query = doc! {"username": other_username};
+                let result = app_data
+                    .users
+                    .find_one(query, None)
+                    .await
+                    .expect("Could not query for user");
+
+                assert!(result.is_none(), "User not deleted");
+            })
+            .await;
+    }
+
+    #[actix_web::test]
+    async fn test_delete_user_unauth() {
+        let other_username = "other_user";
+        let other_user: User = api::NewUser {
+            username: other_username.to_string(),
+            email: "user@netsblox.org".into(),
+            password: None,
+            group_id: None,
+            role: None,
+        }
+        .into();
+
+        test_utils::setup()
+            .with_users(&[other_user])
+            .run(|app_data| async move {
+                let app = test::init_service(
+                    App::new()
+                        .app_data(web::Data::new(app_data.clone()))
+                        .configure(config),
+                )
+                .await;
+
+                let req = test::TestRequest::post()
+                    .uri("/other_user/delete")
+                    .to_request();
+
+                let response = test::call_service(&app, req).await;
+                assert_eq!(response.status(), http::StatusCode::UNAUTHORIZED);
+
+                let query = doc! {"username": other_username};
+                let result = app_data
+                    .users
+                    .find_one(query, None)
+                    .await
+                    .expect("Could not query for user");
+
+                assert!(result.is_some(), "User deleted");
+            })
+            .await;
+    }
+
+    #[actix_web::test]
+    async fn test_delete_user_group_owner() {
+        let owner_name = "owner".to_string();
+        let owner: User = api::NewUser {
+            username: owner_name.clone(),
+            email: "owner@netsblox.org".into(),
+            password: None,
+            group_id: None,
+            role: None,
+        }
+        .into();
+        let group = Group::new(owner_name.clone(), "some_group".into());
+        let other_username = "other_user";
+        let other_user: User = api::NewUser {
+            username: other_username.to_string(),
+            email: "user@netsblox.org".into(),
+            password: None,
+            group_id: Some(group.id.clone()),
+            role: None,
+        }
+        .into();
+
+        test_utils::setup()
+            .with_users(&[owner, other_user])
+            .with_groups(&[group])
+            .run(|app_data| async move {
+                let app = test::init_service(
+                    App::new()
+                        .wrap(test_utils::cookie::middleware())
+                        .app_data(web::Data::new(app_data.clone()))
+                        .configure(config),
+                )
+                .await;
+
+                let req = test::TestRequest::post()
+                    .uri("/other_user/delete")
+                    .cookie(test_utils::cookie::new(&owner_name))
+                    .to_request();
+
+                let response = test::call_service(&app, req).await;
+                assert_eq!(response.status(), http::StatusCode::OK);
+
+                let query = doc! {"username": other_username};
+                let result = app_data
+                    .users
+                    .find_one(query, None)
+                    .await
+                    .expect("Could not query for user");
+
+                assert!(result.is_none(), "User not deleted");
+            })
+            .await;
+    }
+
+    #[actix_web::test]
+    async fn test_delete_user_other_user() {
+        let user1: User = api::NewUser {
+            username: "user1".to_string(),
+            email: "user1@netsblox.org".into(),
+            password: None,
+            group_id: None,
+            role: None,
+        }
+        .into();
+        let user1_name = user1.username.clone();
+        let user2: User = api::NewUser {
+            username: "user2".to_string(),
+            email: "user@netsblox.org".into(),
+            password: None,
+            group_id: None,
+            role: None,
+        }
+        .into();
+
+        test_utils::setup()
+            .with_users(&[user1, user2])
+            .run(|app_data| async move {
+                let app = test::init_service(
+                    App::new()
+                        .wrap(test_utils::cookie::middleware())
+                        .app_data(web::Data::new(app_data.clone()))
+                        .configure(config),
+                )
+                .await;
+
+                let req = test::TestRequest::post()
+                    .uri("/user2/delete")
+                    .cookie(test_utils::cookie::new(&user1_name))
+                    .to_request();
+
+                let response = test::call_service(&app, req).await;
+                assert_eq!(response.status(), http::StatusCode::FORBIDDEN);
 
                 let query = doc! {"username": "user2"};
                 let result = app_data
@@ -1251,16 +2172,176 @@ mod tests {
     }
 
     #[actix_web::test]
-    async fn test_ban_user() {
-        let admin: User = api::NewUser {
-            username: "admin".to_string(),
-            email: "admin@netsblox.org".into(),
+    async fn test_ban_user() {
+        let admin: User = api::NewUser {
+            username: "admin".to_string(),
+            email: "admin@netsblox.org".into(),
+            password: None,
+            group_id: None,
+            role: Some(UserRole::Admin),
+        }
+        .into();
+        let admin_name = admin.username.clone();
+        let some_user: User = api::NewUser {
+            username: "some_user".to_string(),
+            email: "user@netsblox.org".into(),
+            password: None,
+            group_id: None,
+            role: None,
+        }
+        .into();
+
+        test_utils::setup()
+            .with_users(&[admin, some_user])
+            .run(|app_data| async move {
+                let app = test::init_service(
+                    App::new()
+                        .wrap(test_utils::cookie::middleware())
+                        .app_data(web::Data::new(app_data.clone()))
+                        .configure(config),
+                )
+                .await;
+
+                let req = test::TestRequest::post()
+                    .uri("/some_user/ban")
+                    .cookie(test_utils::cookie::new(&admin_name))
+                    .to_request();
+
+                // This will panic if the response isn't a banned account; no assert needed
+                let _account: BannedAccount = test::call_and_read_body_json(&app, req).await;
+
+                let query = doc! {"username": "some_user"};
+                let result = app_data
+                    .banned_accounts
+                    .find_one(query, None)
+                    .await
+                    .expect("Could not query for user");
+
+                assert!(result.is_some(), "User not banned");
+            })
+            .await;
+    }
+
+    #[actix_web::test]
+    async fn test_export_user_data() {
+        let mut user: User = api::NewUser {
+            username: "user".into(),
+            email: "user@netsblox.org".into(),
+            password: None,
+            group_id: None,
+            role: None,
+        }
+        .into();
+        user.signup_ip = Some("10.1.2.3".into());
+        user.signup_user_agent = Some("Firefox".into());
+        let project = test_utils::project::builder()
+            .with_name("project")
+            .with_owner(user.username.clone())
+            .build();
+        let library = netsblox_cloud_common::Library {
+            owner: user.username.clone(),
+            name: "library".into(),
+            notes: "".into(),
+            blocks: "<blocks/>".into(),
+            state: api::PublishState::Private,
+        };
+
+        test_utils::setup()
+            .with_users(&[user.clone()])
+            .with_projects(&[project.clone()])
+            .with_libraries(&[library])
+            .run(|app_data| async move {
+                let app = test::init_service(
+                    App::new()
+                        .wrap(test_utils::cookie::middleware())
+                        .app_data(web::Data::new(app_data))
+                        .configure(config),
+                )
+                .await;
+
+                let req = test::TestRequest::get()
+                    .uri("/user/export")
+                    .cookie(test_utils::cookie::new(&user.username))
+                    .to_request();
+
+                let response = test::call_service(&app, req).await;
+                assert_eq!(response.status(), http::StatusCode::OK);
+                let content_type = response.headers().get(http::header::CONTENT_TYPE).unwrap();
+                assert_eq!(content_type, "application/zip");
+
+                let body = test::read_body(response).await;
+                let mut archive = zip::ZipArchive::new(std::io::Cursor::new(body)).unwrap();
+                let names: Vec<_> = archive.file_names().map(|name| name.to_owned()).collect();
+                assert!(names.contains(&"user.json".to_string()));
+                assert!(names.contains(&format!("projects/project-{}.xml", &project.id)));
+                assert!(names.contains(&"libraries/library.xml".to_string()));
+                assert!(names.contains(&"friends.json".to_string()));
+                assert!(names.contains(&"messages.json".to_string()));
+
+                let user_data: serde_json::Value =
+                    serde_json::from_reader(archive.by_name("user.json").unwrap()).unwrap();
+                assert!(user_data.get("hash").is_none());
+                assert!(user_data.get("salt").is_none());
+
+                let signup: serde_json::Value =
+                    serde_json::from_reader(archive.by_name("signup.json").unwrap()).unwrap();
+                assert_eq!(signup["ip"], "10.1.2.3");
+                assert_eq!(signup["userAgent"], "Firefox");
+            })
+            .await;
+    }
+
+    #[actix_web::test]
+    async fn test_export_user_data_403() {
+        let user: User = api::NewUser {
+            username: "user".into(),
+            email: "user@netsblox.org".into(),
+            password: None,
+            group_id: None,
+            role: None,
+        }
+        .into();
+        let other: User = api::NewUser {
+            username: "other".into(),
+            email: "other@netsblox.org".into(),
+            password: None,
+            group_id: None,
+            role: None,
+        }
+        .into();
+
+        test_utils::setup()
+            .with_users(&[user.clone(), other.clone()])
+            .run(|app_data| async move {
+                let app = test::init_service(
+                    App::new()
+                        .wrap(test_utils::cookie::middleware())
+                        .app_data(web::Data::new(app_data))
+                        .configure(config),
+                )
+                .await;
+
+                let req = test::TestRequest::get()
+                    .uri("/user/export")
+                    .cookie(test_utils::cookie::new(&other.username))
+                    .to_request();
+
+                let response = test::call_service(&app, req).await;
+                assert_eq!(response.status(), http::StatusCode::FORBIDDEN);
+            })
+            .await;
+    }
+
+    #[actix_web::test]
+    async fn test_ban_user_temporary() {
+        let moderator: User = api::NewUser {
+            username: "moderator".to_string(),
+            email: "moderator@netsblox.org".into(),
             password: None,
             group_id: None,
-            role: Some(UserRole::Admin),
+            role: Some(UserRole::Moderator),
         }
         .into();
-        let admin_name = admin.username.clone();
         let some_user: User = api::NewUser {
             username: "some_user".to_string(),
             email: "user@netsblox.org".into(),
@@ -1271,7 +2352,7 @@ mod tests {
         .into();
 
         test_utils::setup()
-            .with_users(&[admin, some_user])
+            .with_users(&[moderator.clone(), some_user])
             .run(|app_data| async move {
                 let app = test::init_service(
                     App::new()
@@ -1281,22 +2362,130 @@ mod tests {
                 )
                 .await;
 
+                let data = api::BanData {
+                    reason: Some("spam".into()),
+                    expires_in_hours: Some(24),
+                };
                 let req = test::TestRequest::post()
                     .uri("/some_user/ban")
-                    .cookie(test_utils::cookie::new(&admin_name))
+                    .cookie(test_utils::cookie::new(&moderator.username))
+                    .set_json(&data)
                     .to_request();
 
-                // This will panic if the response isn't a banned account; no assert needed
-                let _account: BannedAccount = test::call_and_read_body_json(&app, req).await;
+                let account: BannedAccount = test::call_and_read_body_json(&app, req).await;
+                assert!(account.expires_at.is_some());
 
-                let query = doc! {"username": "some_user"};
-                let result = app_data
-                    .banned_accounts
-                    .find_one(query, None)
+                let req = test::TestRequest::get()
+                    .uri("/bans")
+                    .cookie(test_utils::cookie::new(&moderator.username))
+                    .to_request();
+
+                let bans: Vec<BannedAccount> = test::call_and_read_body_json(&app, req).await;
+                assert_eq!(bans.len(), 1);
+                assert_eq!(bans[0].moderator, Some(moderator.username.clone()));
+                assert_eq!(bans[0].reason, Some("spam".into()));
+            })
+            .await;
+    }
+
+    #[actix_web::test]
+    async fn test_ban_address() {
+        let moderator: User = api::NewUser {
+            username: "moderator".to_string(),
+            email: "moderator@netsblox.org".into(),
+            password: None,
+            group_id: None,
+            role: Some(UserRole::Moderator),
+        }
+        .into();
+
+        test_utils::setup()
+            .with_users(&[moderator.clone()])
+            .run(|app_data| async move {
+                let app = test::init_service(
+                    App::new()
+                        .wrap(test_utils::cookie::middleware())
+                        .app_data(web::Data::new(app_data.clone()))
+                        .configure(config),
+                )
+                .await;
+
+                let data = api::BanAddressData {
+                    addr: "192.168.1.7/16".into(),
+                    reason: None,
+                    expires_in_hours: None,
+                };
+                let req = test::TestRequest::post()
+                    .uri("/bans/addresses")
+                    .cookie(test_utils::cookie::new(&moderator.username))
+                    .set_json(&data)
+                    .to_request();
+
+                let ban: api::BannedAddress = test::call_and_read_body_json(&app, req).await;
+                assert_eq!(&ban.addr, "192.168.0.0/16");
+
+                let addr = "192.168.4.2".parse().unwrap();
+                assert!(app_data
+                    .ensure_ip_allowed(&addr, api::IpReputationAction::BlockLogin)
                     .await
-                    .expect("Could not query for user");
+                    .is_err());
+                let addr = "192.169.0.1".parse().unwrap();
+                assert!(app_data
+                    .ensure_ip_allowed(&addr, api::IpReputationAction::BlockLogin)
+                    .await
+                    .is_ok());
 
-                assert!(result.is_some(), "User not banned");
+                let req = test::TestRequest::delete()
+                    .uri(&format!("/bans/addresses/{}", ban.id))
+                    .cookie(test_utils::cookie::new(&moderator.username))
+                    .to_request();
+
+                let response = test::call_service(&app, req).await;
+                assert_eq!(response.status(), http::StatusCode::OK);
+                let addr = "192.168.4.2".parse().unwrap();
+                assert!(app_data
+                    .ensure_ip_allowed(&addr, api::IpReputationAction::BlockLogin)
+                    .await
+                    .is_ok());
+            })
+            .await;
+    }
+
+    #[actix_web::test]
+    async fn test_ban_address_403() {
+        let user: User = api::NewUser {
+            username: "user".to_string(),
+            email: "user@netsblox.org".into(),
+            password: None,
+            group_id: None,
+            role: None,
+        }
+        .into();
+
+        test_utils::setup()
+            .with_users(&[user.clone()])
+            .run(|app_data| async move {
+                let app = test::init_service(
+                    App::new()
+                        .wrap(test_utils::cookie::middleware())
+                        .app_data(web::Data::new(app_data.clone()))
+                        .configure(config),
+                )
+                .await;
+
+                let data = api::BanAddressData {
+                    addr: "10.0.0.1".into(),
+                    reason: None,
+                    expires_in_hours: None,
+                };
+                let req = test::TestRequest::post()
+                    .uri("/bans/addresses")
+                    .cookie(test_utils::cookie::new(&user.username))
+                    .set_json(&data)
+                    .to_request();
+
+                let response = test::call_service(&app, req).await;
+                assert_eq!(response.status(), http::StatusCode::FORBIDDEN);
             })
             .await;
     }
@@ -1352,6 +2541,229 @@ mod tests {
             .await;
     }
 
+    #[actix_web::test]
+    async fn test_ban_user_revokes_sessions() {
+        let admin: User = api::NewUser {
+            username: "admin".to_string(),
+            email: "admin@netsblox.org".into(),
+            password: None,
+            group_id: None,
+            role: Some(UserRole::Admin),
+        }
+        .into();
+        let admin_name = admin.username.clone();
+        let some_user: User = api::NewUser {
+            username: "some_user".to_string(),
+            email: "user@netsblox.org".into(),
+            password: None,
+            group_id: None,
+            role: None,
+        }
+        .into();
+        let state = HashMap::from([("username".to_owned(), "\"some_user\"".to_owned())]);
+        let session = UserSession::new("someKeyHash".into(), state, Duration::from_secs(60));
+
+        test_utils::setup()
+            .with_users(&[admin, some_user])
+            .with_sessions(&[session])
+            .run(|app_data| async move {
+                let app = test::init_service(
+                    App::new()
+                        .wrap(test_utils::cookie::middleware())
+                        .app_data(web::Data::new(app_data.clone()))
+                        .configure(config),
+                )
+                .await;
+
+                let req = test::TestRequest::post()
+                    .uri("/some_user/ban")
+                    .cookie(test_utils::cookie::new(&admin_name))
+                    .to_request();
+
+                let response = test::call_service(&app, req).await;
+                assert_eq!(response.status(), http::StatusCode::OK);
+
+                let query = doc! {"username": "some_user"};
+                let count = app_data
+                    .sessions
+                    .count_documents(query, None)
+                    .await
+                    .unwrap();
+
+                assert_eq!(count, 0, "Sessions not revoked");
+            })
+            .await;
+    }
+
+    #[actix_web::test]
+    async fn test_list_sessions_403() {
+        let user: User = api::NewUser {
+            username: "user".to_string(),
+            email: "user@netsblox.org".into(),
+            password: None,
+            group_id: None,
+            role: None,
+        }
+        .into();
+        let user_name = user.username.clone();
+
+        test_utils::setup()
+            .with_users(&[user])
+            .run(|app_data| async move {
+                let app = test::init_service(
+                    App::new()
+                        .wrap(test_utils::cookie::middleware())
+                        .app_data(web::Data::new(app_data.clone()))
+                        .configure(config),
+                )
+                .await;
+
+                let req = test::TestRequest::get()
+                    .uri("/some_user/sessions")
+                    .cookie(test_utils::cookie::new(&user_name))
+                    .to_request();
+
+                let response = test::call_service(&app, req).await;
+                assert_eq!(response.status(), http::StatusCode::FORBIDDEN);
+            })
+            .await;
+    }
+
+    async fn create_token(app_data: &AppData, username: &str, scope: api::TokenScope) -> String {
+        let data = api::CreateAccessTokenData {
+            name: "script".into(),
+            scopes: vec![scope],
+            expires_in_days: None,
+        };
+        app_data
+            .as_access_token_actions()
+            .create_token(&auth::ManageAccessTokens::test(username.into()), data)
+            .await
+            .unwrap()
+            .secret
+    }
+
+    #[actix_web::test]
+    async fn test_whoami_access_token() {
+        test_utils::setup()
+            .run(|app_data| async move {
+                let app = test::init_service(
+                    App::new()
+                        .wrap(AccessTokenAuth)
+                        .wrap(test_utils::cookie::middleware())
+                        .app_data(web::Data::new(app_data.clone()))
+                        .configure(config),
+                )
+                .await;
+
+                let secret = create_token(&app_data, "user", api::TokenScope::Read).await;
+                let req = test::TestRequest::get()
+                    .uri("/whoami")
+                    .insert_header(("Authorization", format!("Bearer {}", secret)))
+                    .to_request();
+
+                let username = test::call_and_read_body(&app, req).await;
+                assert_eq!(username, "user");
+            })
+            .await;
+    }
+
+    #[actix_web::test]
+    async fn test_invalid_access_token_401() {
+        test_utils::setup()
+            .run(|app_data| async move {
+                let app = test::init_service(
+                    App::new()
+                        .wrap(AccessTokenAuth)
+                        .wrap(test_utils::cookie::middleware())
+                        .app_data(web::Data::new(app_data.clone()))
+                        .configure(config),
+                )
+                .await;
+
+                let req = test::TestRequest::get()
+                    .uri("/whoami")
+                    .insert_header(("Authorization", "Bearer nb_notAToken"))
+                    .to_request();
+
+                let response = test::call_service(&app, req).await;
+                assert_eq!(response.status(), http::StatusCode::UNAUTHORIZED);
+            })
+            .await;
+    }
+
+    #[actix_web::test]
+    async fn test_read_access_token_cannot_edit() {
+        let user: User = api::NewUser {
+            username: "user".to_string(),
+            email: "user@netsblox.org".into(),
+            password: None,
+            group_id: None,
+            role: None,
+        }
+        .into();
+
+        test_utils::setup()
+            .with_users(&[user])
+            .run(|app_data| async move {
+                let app = test::init_service(
+                    App::new()
+                        .wrap(AccessTokenAuth)
+                        .wrap(test_utils::cookie::middleware())
+                        .app_data(web::Data::new(app_data.clone()))
+                        .configure(config),
+                )
+                .await;
+
+                let secret = create_token(&app_data, "user", api::TokenScope::Read).await;
+                let update = api::UpdateUserData {
+                    email: Some("new@netsblox.org".into()),
+                    group_id: None,
+                    role: None,
+                };
+                let req = test::TestRequest::patch()
+                    .uri("/user")
+                    .insert_header(("Authorization", format!("Bearer {}", secret)))
+                    .set_json(&update)
+                    .to_request();
+
+                let response = test::call_service(&app, req).await;
+                assert_eq!(response.status(), http::StatusCode::FORBIDDEN);
+            })
+            .await;
+    }
+
+    #[actix_web::test]
+    async fn test_access_token_cannot_create_tokens() {
+        test_utils::setup()
+            .run(|app_data| async move {
+                let app = test::init_service(
+                    App::new()
+                        .wrap(AccessTokenAuth)
+                        .wrap(test_utils::cookie::middleware())
+                        .app_data(web::Data::new(app_data.clone()))
+                        .configure(config),
+                )
+                .await;
+
+                let secret = create_token(&app_data, "user", api::TokenScope::Write).await;
+                let data = api::CreateAccessTokenData {
+                    name: "another".into(),
+                    scopes: vec![api::TokenScope::Write],
+                    expires_in_days: None,
+                };
+                let req = test::TestRequest::post()
+                    .uri("/user/tokens")
+                    .insert_header(("Authorization", format!("Bearer {}", secret)))
+                    .set_json(&data)
+                    .to_request();
+
+                let response = test::call_service(&app, req).await;
+                assert_eq!(response.status(), http::StatusCode::FORBIDDEN);
+            })
+            .await;
+    }
+
     #[actix_web::test]
     #[ignore] // ignore until we can test fns using the mailer
     async fn test_reset_password() {
@@ -0,0 +1,85 @@
+use std::net::IpAddr;
+
+use ipnet::{IpNet, Ipv4Net, Ipv6Net};
+use sha2::{Digest, Sha256};
+use uuid::Uuid;
+
+use crate::config::SignupSettings;
+
+pub(super) fn new_challenge_id() -> String {
+    Uuid::new_v4().as_simple().to_string()
+}
+
+/// Check if SHA-256(challenge ID + nonce) starts with (at least) `difficulty` zero bits
+pub(super) fn is_solution(challenge_id: &str, nonce: &str, difficulty: u8) -> bool {
+    let hash = Sha256::new()
+        .chain_update(challenge_id.as_bytes())
+        .chain_update(nonce.as_bytes())
+        .finalize();
+
+    leading_zero_bits(&hash) >= u32::from(difficulty)
+}
+
+fn leading_zero_bits(bytes: &[u8]) -> u32 {
+    let mut count = 0;
+    for byte in bytes {
+        count += byte.leading_zeros();
+        if *byte != 0 {
+            break;
+        }
+    }
+    count
+}
+
+/// Get the subnet containing the address (used to limit signups from a network)
+pub(super) fn subnet(addr: &IpAddr, settings: &SignupSettings) -> String {
+    let subnet = match addr {
+        IpAddr::V4(addr) => Ipv4Net::new(*addr, settings.ipv4_subnet_prefix).map(IpNet::from),
+        IpAddr::V6(addr) => Ipv6Net::new(*addr, settings.ipv6_subnet_prefix).map(IpNet::from),
+    };
+
+    subnet
+        .map(|net| net.trunc())
+        .unwrap_or_else(|_err| IpNet::from(*addr))
+        .to_string()
+}
+
+#[cfg(test)]
+pub(super) fn solve(challenge_id: &str, difficulty: u8) -> String {
+    (0u64..)
+        .map(|nonce| nonce.to_string())
+        .find(|nonce| is_solution(challenge_id, nonce, difficulty))
+        .unwrap()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_leading_zero_bits() {
+        assert_eq!(leading_zero_bits(&[0, 0, 0b0001_0000]), 19);
+        assert_eq!(leading_zero_bits(&[0b1000_0000, 0]), 0);
+    }
+
+    #[test]
+    fn test_solution() {
+        let nonce = solve("someChallenge", 8);
+        assert!(is_solution("someChallenge", &nonce, 8));
+    }
+
+    #[test]
+    fn test_any_nonce_solves_easiest_challenge() {
+        assert!(is_solution("someChallenge", "", 0));
+    }
+
+    #[test]
+    fn test_subnet() {
+        let settings = SignupSettings::default();
+        let addr: IpAddr = "192.168.1.42".parse().unwrap();
+        assert_eq!(subnet(&addr, &settings), "192.168.1.0/24");
+
+        let addr: IpAddr = "2001:db8::1".parse().unwrap();
+        assert_eq!(subnet(&addr, &settings), "2001:db8::/64");
+    }
+}
@@ -0,0 +1,567 @@
+mod oidc;
+mod snap;
+
+use std::{collections::HashMap, time::SystemTime};
+
+use async_trait::async_trait;
+use futures::TryStreamExt;
+use mongodb::{
+    bson::{doc, DateTime},
+    options::{FindOneAndUpdateOptions, ReturnDocument, UpdateOptions},
+    Collection,
+};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use uuid::Uuid;
+
+pub(crate) use crate::common::api::Credentials;
+use crate::common::password::{self, HashParams};
+use crate::config::{AuthSettings, StrategyConfig};
+use crate::{
+    common::api::{self, UserRole},
+    utils,
+};
+use crate::{
+    common::User,
+    errors::{InternalError, UserError},
+};
+
+/// An account with an external identity provider
+#[derive(Clone, Debug)]
+pub(crate) struct ExternalIdentity {
+    pub(crate) account: api::LinkedAccount,
+    /// Preferred NetsBlox username (if a new user needs to be created)
+    pub(crate) username: String,
+    pub(crate) email: String,
+    /// Whether the provider has verified the email address
+    pub(crate) email_verified: bool,
+}
+
+/// State of a redirect-based login. This is stored in the session until the
+/// user returns from the identity provider.
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub(crate) struct LoginFlow {
+    pub(crate) strategy: String,
+    pub(crate) state: String,
+    pub(crate) nonce: String,
+    pub(crate) code_verifier: String,
+    /// Callback URL for the identity provider
+    pub(crate) redirect_uri: String,
+    pub(crate) client_id: Option<api::ClientId>,
+    /// URL to send the user to after login
+    pub(crate) redirect: Option<String>,
+}
+
+impl LoginFlow {
+    pub(crate) fn new(
+        strategy: String,
+        redirect_uri: String,
+        client_id: Option<api::ClientId>,
+        redirect: Option<String>,
+    ) -> Self {
+        let random = || Uuid::new_v4().as_simple().to_string();
+        Self {
+            strategy,
+            state: random(),
+            nonce: random(),
+            code_verifier: random() + &random(),
+            redirect_uri,
+            client_id,
+            redirect,
+        }
+    }
+
+    /// PKCE code challenge (S256) for the verifier
+    pub(crate) fn code_challenge(&self) -> String {
+        let hash = Sha256::digest(self.code_verifier.as_bytes());
+        base64::encode_config(hash, base64::URL_SAFE_NO_PAD)
+    }
+}
+
+/// A way of logging in using an external identity provider. Strategies
+/// support logging in with a username and password or by redirecting to the
+/// provider (or both).
+#[async_trait]
+pub(crate) trait AuthStrategy: Send + Sync {
+    /// Name of the strategy. This is used as the strategy of linked accounts.
+    fn name(&self) -> &str;
+
+    async fn authenticate(
+        &self,
+        _username: &str,
+        _password: &str,
+    ) -> Result<ExternalIdentity, UserError> {
+        Err(UserError::InvalidAccountTypeError)
+    }
+
+    /// Get the URL of the provider's login page
+    async fn authorization_url(&self, _flow: &LoginFlow) -> Result<String, UserError> {
+        Err(UserError::InvalidAccountTypeError)
+    }
+
+    /// Complete a redirect-based login using the code from the provider
+    async fn exchange_code(
+        &self,
+        _code: &str,
+        _flow: &LoginFlow,
+    ) -> Result<ExternalIdentity, UserError> {
+        Err(UserError::InvalidAccountTypeError)
+    }
+}
+
+/// Login strategies registered from the settings
+pub(crate) struct Strategies {
+    strategies: HashMap<String, Box<dyn AuthStrategy>>,
+}
+
+impl Strategies {
+    pub(crate) fn new(settings: &AuthSettings) -> Self {
+        let mut strategies = Self {
+            strategies: HashMap::new(),
+        };
+        strategies.register(snap::SnapStrategy);
+
+        for config in &settings.strategies {
+            match config {
+                StrategyConfig::Oidc(config) => {
+                    strategies.register(oidc::OidcStrategy::new(config.clone()))
+                }
+            }
+        }
+
+        strategies
+    }
+
+    pub(crate) fn register(&mut self, strategy: impl AuthStrategy + 'static) {
+        self.strategies
+            .insert(strategy.name().to_owned(), Box::new(strategy));
+    }
+
+    pub(crate) fn get(&self, name: &str) -> Result<&dyn AuthStrategy, UserError> {
+        self.strategies
+            .get(name)
+            .map(|strategy| strategy.as_ref())
+            .ok_or(UserError::InvalidAccountTypeError)
+    }
+}
+
+pub async fn login(
+    users: &Collection<User>,
+    hash_params: &HashParams,
+    strategies: &Strategies,
+    credentials: Credentials,
+) -> Result<User, UserError> {
+    match credentials {
+        Credentials::Snap { username, password } => {
+            let identity = strategies
+                .get("snap")?
+                .authenticate(&username, &password)
+                .await?;
+
+            login_external(users, identity).await
+        }
+        Credentials::NetsBlox { username, password } => {
+            let query = doc! {"username": &username};
+            let user = users
+                .find_one(query.clone(), None)
+                .await
+                .map_err(InternalError::DatabaseConnectionError)?
+                .ok_or(UserError::UserNotFoundError)?;
+
+            let verification =
+                password::verify(&password, &user.hash, user.salt.as_deref(), hash_params);
+
+            let user = match verification {
+                password::Verification::Invalid => {
+                    return Err(UserError::IncorrectPasswordError);
+                }
+                // Upgrade legacy (or outdated) hashes now that we know the password
+                password::Verification::Valid { needs_rehash: true } => {
+                    update_hash(users, &username, &password, hash_params).await?
+                }
+                password::Verification::Valid {
+                    needs_rehash: false,
+                } => user,
+            };
+
+            Ok(user)
+        }
+    }
+}
+
+/// Get the user with the given linked account. A new user is created if the
+/// account has not been linked to any user.
+pub(crate) async fn login_external(
+    users: &Collection<User>,
+    identity: ExternalIdentity,
+) -> Result<User, UserError> {
+    let query = doc! {"linkedAccounts": {"$elemMatch": &identity.account}};
+    let user_opt = users
+        .find_one(query, None)
+        .await
+        .map_err(InternalError::DatabaseConnectionError)?;
+
+    if let Some(user) = user_opt {
+        Ok(user)
+    } else {
+        create_account(users, identity).await
+    }
+}
+
+async fn update_hash(
+    users: &Collection<User>,
+    username: &str,
+    password: &str,
+    hash_params: &HashParams,
+) -> Result<User, UserError> {
+    let query = doc! {"username": &username};
+    let hash = password::hash(password, hash_params).map_err(InternalError::PasswordHashError)?;
+
+    let options = FindOneAndUpdateOptions::builder()
+        .return_document(ReturnDocument::After)
+        .build();
+
+    let update = doc! {
+        "$set": {
+            "hash": hash,
+            "salt": null,
+        }
+    };
+
+    users
+        .find_one_and_update(query, update, options)
+        .await
+        .map_err(InternalError::DatabaseConnectionError)?
+        .ok_or(UserError::UserNotFoundError)
+}
+
+async fn create_account(
+    users: &Collection<User>,
+    identity: ExternalIdentity,
+) -> Result<User, UserError> {
+    let username = username_from(users, &identity).await?;
+    let query = doc! {"username": &username};
+
+    let hash: String = "None".to_owned();
+    let user = User {
+        // TODO: impl From instead?
+        id: None,
+        username,
+        hash,
+        salt: None,
+        email: identity.email,
+        group_id: None,
+        created_at: DateTime::from_system_time(SystemTime::now()),
+        linked_accounts: vec![identity.account],
+        role: UserRole::User,
+        services_hosts: None,
+        service_settings: HashMap::new(),
+        two_factor: None,
+        verified: identity.email_verified,
+        signup_ip: None,
+        signup_subnet: None,
+        signup_user_agent: None,
+    };
+
+    let update = doc!("$setOnInsert": &user);
+    let options = UpdateOptions::builder().upsert(true).build();
+    users
+        .update_one(query, update, options)
+        .await
+        .map_err(InternalError::DatabaseConnectionError)?;
+
+    Ok(user)
+}
+
+async fn username_from(
+    users: &Collection<User>,
+    identity: &ExternalIdentity,
+) -> Result<String, UserError> {
+    let basename = identity.username.to_owned();
+    let starts_with_name = mongodb::bson::Regex {
+        pattern: format!("^{}", regex::escape(&basename)),
+        options: String::new(),
+    };
+    let query = doc! {"username": {"$regex": starts_with_name}};
+    // TODO: this could be optimized to map on the stream...
+    let existing_names = users
+        .find(query, None)
+        .await
+        .map_err(InternalError::DatabaseConnectionError)?
+        .try_collect::<Vec<_>>()
+        .await
+        .map_err(InternalError::DatabaseConnectionError)?
+        .into_iter()
+        .map(|user| user.username)
+        .collect::<Vec<_>>();
+
+    get_unique_username(
+        existing_names.iter().map(|n| n.as_str()),
+        &basename,
+        &identity.account.strategy,
+    )
+}
+
+fn get_unique_username<'a>(
+    names: impl Iterator<Item = &'a str>,
+    basename: &str,
+    strategy: &str,
+) -> Result<String, UserError> {
+    let strategy: String = strategy
+        .to_ascii_lowercase()
+        .chars()
+        .filter(|l| l.is_alphabetic())
+        .collect();
+
+    let candidates = std::iter::once(basename.to_owned())
+        .chain(std::iter::once_with(|| {
+            format!("{}_{}", basename, &strategy)
+        }))
+        .chain((2..1000).map(|n| format!("{}_{}{}", basename, &strategy, n)));
+
+    utils::find_first_unique(names, candidates).ok_or(UserError::UsernameExists)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{test_utils, utils::sha512};
+
+    use super::*;
+
+    #[actix_web::test]
+    async fn test_login_rehash_legacy_hash() {
+        let password: String = "somePassword...".into();
+        let mut user: User = api::NewUser {
+            username: "user".to_string(),
+            email: "user@netsblox.org".into(),
+            password: Some(password.clone()),
+            group_id: None,
+            role: None,
+        }
+        .into();
+        user.salt = None;
+        user.hash = sha512(&password);
+
+        test_utils::setup()
+            .with_users(&[user.clone()])
+            .run(|app_data| async move {
+                // check initial login
+                let params = &app_data.settings.security.password_hashing;
+                let credentials = Credentials::NetsBlox {
+                    username: user.username.clone(),
+                    password,
+                };
+                login(
+                    &app_data.users,
+                    params,
+                    &app_data.strategies,
+                    credentials.clone(),
+                )
+                .await
+                .unwrap();
+
+                // check that the hash has been upgraded
+                let query = doc! {"username": &user.username};
+                let user = app_data.users.find_one(query, None).await.unwrap().unwrap();
+                assert!(user.hash.starts_with("$argon2id$"));
+                assert!(user.salt.is_none());
+
+                // check that we can login again
+                login(&app_data.users, params, &app_data.strategies, credentials)
+                    .await
+                    .unwrap();
+            })
+            .await;
+    }
+
+    #[actix_web::test]
+    async fn test_login_rehash_legacy_salted_hash() {
+        let password: String = "somePassword...".into();
+        let mut user: User = api::NewUser {
+            username: "user".to_string(),
+            email: "user@netsblox.org".into(),
+            password: Some(password.clone()),
+            group_id: None,
+            role: None,
+        }
+        .into();
+        user.salt = Some("someSalt".into());
+        user.hash = sha512(&(password.clone() + "someSalt"));
+
+        test_utils::setup()
+            .with_users(&[user.clone()])
+            .run(|app_data| async move {
+                let params = &app_data.settings.security.password_hashing;
+                let credentials = Credentials::NetsBlox {
+                    username: user.username.clone(),
+                    password,
+                };
+                login(
+                    &app_data.users,
+                    params,
+                    &app_data.strategies,
+                    credentials.clone(),
+                )
+                .await
+                .unwrap();
+
+                let query = doc! {"username": &user.username};
+                let user = app_data.users.find_one(query, None).await.unwrap().unwrap();
+                assert!(user.hash.starts_with("$argon2id$"));
+                assert!(user.salt.is_none());
+
+                login(&app_data.users, params, &app_data.strategies, credentials)
+                    .await
+                    .unwrap();
+            })
+            .await;
+    }
+
+    #[actix_web::test]
+    async fn test_login_dont_rehash_failed_login() {
+        let password: String = "somePassword...".into();
+        let mut user: User = api::NewUser {
+            username: "user".to_string(),
+            email: "user@netsblox.org".into(),
+            password: Some(password.clone()),
+            group_id: None,
+            role: None,
+        }
+        .into();
+        user.salt = None;
+        user.hash = sha512(&password);
+
+        test_utils::setup()
+            .with_users(&[user.clone()])
+            .run(|app_data| async move {
+                // check initial login
+                let params = &app_data.settings.security.password_hashing;
+                let credentials = Credentials::NetsBlox {
+                    username: user.username.clone(),
+                    password: "badPassword".into(),
+                };
+                let result = login(
+                    &app_data.users,
+                    params,
+                    &app_data.strategies,
+                    credentials.clone(),
+                )
+                .await;
+                assert!(result.is_err());
+
+                // hash should still be the legacy one
+                let query = doc! {"username": &user.username};
+                let updated_user = app_data.users.find_one(query, None).await.unwrap().unwrap();
+                assert_eq!(updated_user.hash, user.hash);
+            })
+            .await;
+    }
+
+    #[actix_web::test]
+    async fn test_login_dont_rehash_current_hash() {
+        let password: String = "somePassword...".into();
+
+        test_utils::setup()
+            .run(|app_data| async move {
+                let params = &app_data.settings.security.password_hashing;
+                let user = User::from_new_user(
+                    api::NewUser {
+                        username: "user".to_string(),
+                        email: "user@netsblox.org".into(),
+                        password: Some(password.clone()),
+                        group_id: None,
+                        role: None,
+                    },
+                    params,
+                )
+                .unwrap();
+                app_data.users.insert_one(&user, None).await.unwrap();
+
+                let credentials = Credentials::NetsBlox {
+                    username: user.username.clone(),
+                    password,
+                };
+                login(&app_data.users, params, &app_data.strategies, credentials)
+                    .await
+                    .unwrap();
+
+                let query = doc! {"username": &user.username};
+                let updated_user = app_data.users.find_one(query, None).await.unwrap().unwrap();
+                assert_eq!(user.hash, updated_user.hash);
+            })
+            .await;
+    }
+
+    #[actix_web::test]
+    async fn test_login_external_links_account() {
+        let identity = ExternalIdentity {
+            account: api::LinkedAccount {
+                username: "subject-123".into(),
+                strategy: "myschool".into(),
+            },
+            username: "student".into(),
+            email: "student@myschool.edu".into(),
+            email_verified: true,
+        };
+
+        test_utils::setup()
+            .run(|app_data| async move {
+                let user = login_external(&app_data.users, identity.clone())
+                    .await
+                    .unwrap();
+                assert_eq!(user.username, "student");
+                assert_eq!(user.linked_accounts.len(), 1);
+
+                // logging in again should use the same user
+                let user2 = login_external(&app_data.users, identity).await.unwrap();
+                assert_eq!(user2.username, user.username);
+
+                let count = app_data.users.count_documents(None, None).await.unwrap();
+                assert_eq!(count, 1);
+            })
+            .await;
+    }
+
+    #[actix_web::test]
+    async fn test_get_unique_username_strat_suffix() {
+        let creds = api::LinkedAccount {
+            username: "brian".into(),
+            strategy: "snap".into(),
+        };
+        let names = ["brian"];
+        let name =
+            get_unique_username(names.into_iter(), &creds.username, &creds.strategy).unwrap();
+        assert_eq!(name.as_str(), "brian_snap");
+    }
+
+    #[actix_web::test]
+    async fn test_get_unique_username_strat_suffix_inc() {
+        let creds = api::LinkedAccount {
+            username: "brian".into(),
+            strategy: "snap".into(),
+        };
+        let names = ["brian", "brian_snap", "brian_snap2"];
+        let name =
+            get_unique_username(names.into_iter(), &creds.username, &creds.strategy).unwrap();
+        assert_eq!(name.as_str(), "brian_snap3");
+    }
+
+    #[actix_web::test]
+    async fn test_get_unique_username_none() {
+        let creds = api::LinkedAccount {
+            username: "brian".into(),
+            strategy: "snap".into(),
+        };
+        let existing: Vec<_> = std::iter::once("brian".to_string())
+            .chain(std::iter::once("brian_snap".to_string()))
+            .chain((2..10000).map(|n| format!("brian_snap{}", n)))
+            .collect();
+        let name_res = get_unique_username(
+            existing.iter().map(|n| n.as_str()),
+            &creds.username,
+            &creds.strategy,
+        );
+
+        assert!(matches!(name_res, Err(UserError::UsernameExists)));
+    }
+}
@@ -0,0 +1,367 @@
+use async_trait::async_trait;
+use jsonwebtoken::{
+    jwk::{Jwk, JwkSet},
+    Algorithm, DecodingKey, Validation,
+};
+use reqwest::Url;
+use serde::Deserialize;
+use tokio::sync::RwLock;
+
+use super::{AuthStrategy, ExternalIdentity, LoginFlow};
+use crate::{common::api, config::OidcConfig, errors::UserError};
+
+/// Signing algorithms accepted for ID tokens. Symmetric algorithms are not
+/// supported since the keys are retrieved from the (public) JWKS.
+const ALLOWED_ALGORITHMS: [Algorithm; 8] = [
+    Algorithm::RS256,
+    Algorithm::RS384,
+    Algorithm::RS512,
+    Algorithm::PS256,
+    Algorithm::PS384,
+    Algorithm::PS512,
+    Algorithm::ES256,
+    Algorithm::EdDSA,
+];
+
+#[derive(Deserialize, Clone, Debug)]
+struct ProviderMetadata {
+    issuer: String,
+    authorization_endpoint: String,
+    token_endpoint: String,
+    jwks_uri: String,
+}
+
+#[derive(Deserialize, Debug)]
+struct TokenResponse {
+    id_token: String,
+}
+
+#[derive(Deserialize, Debug)]
+struct IdTokenClaims {
+    sub: String,
+    email: Option<String>,
+    #[serde(default)]
+    email_verified: bool,
+    preferred_username: Option<String>,
+    nonce: Option<String>,
+}
+
+/// Login using a generic OpenID Connect provider (authorization code flow).
+/// Provider endpoints and signing keys are discovered from the issuer.
+pub(crate) struct OidcStrategy {
+    config: OidcConfig,
+    client: reqwest::Client,
+    metadata: RwLock<Option<ProviderMetadata>>,
+    jwks: RwLock<Option<JwkSet>>,
+}
+
+impl OidcStrategy {
+    pub(crate) fn new(config: OidcConfig) -> Self {
+        Self {
+            config,
+            client: reqwest::Client::new(),
+            metadata: RwLock::new(None),
+            jwks: RwLock::new(None),
+        }
+    }
+
+    async fn metadata(&self) -> Result<ProviderMetadata, UserError> {
+        if let Some(metadata) = self.metadata.read().await.as_ref() {
+            return Ok(metadata.clone());
+        }
+
+        let url = format!(
+            "{}/.well-known/openid-configuration",
+            self.config.issuer.trim_end_matches('/')
+        );
+        let metadata = self
+            .client
+            .get(url)
+            .send()
+            .await
+            .and_then(|response| response.error_for_status())
+            .map_err(|_err| UserError::IdentityProviderConnectionError)?
+            .json::<ProviderMetadata>()
+            .await
+            .map_err(|_err| UserError::IdentityProviderConnectionError)?;
+
+        if metadata.issuer.trim_end_matches('/') != self.config.issuer.trim_end_matches('/') {
+            return Err(UserError::IdentityProviderConnectionError);
+        }
+
+        *self.metadata.write().await = Some(metadata.clone());
+        Ok(metadata)
+    }
+
+    /// Find the signing key with the given ID, refreshing the key set if the
+    /// key is unknown (eg, if the provider has rotated its keys).
+    async fn signing_key(&self, kid: Option<&str>) -> Result<Jwk, UserError> {
+        let cached = self
+            .jwks
+            .read()
+            .await
+            .as_ref()
+            .and_then(|jwks| find_key(jwks, kid));
+
+        if let Some(key) = cached {
+            return Ok(key);
+        }
+
+        let metadata = self.metadata().await?;
+        let jwks = self
+            .client
+            .get(metadata.jwks_uri)
+            .send()
+            .await
+            .and_then(|response| response.error_for_status())
+            .map_err(|_err| UserError::IdentityProviderConnectionError)?
+            .json::<JwkSet>()
+            .await
+            .map_err(|_err| UserError::IdentityProviderConnectionError)?;
+
+        let key = find_key(&jwks, kid);
+        *self.jwks.write().await = Some(jwks);
+        key.ok_or(UserError::InvalidIdentityTokenError)
+    }
+
+    async fn verify_id_token(&self, token: &str, nonce: &str) -> Result<IdTokenClaims, UserError> {
+        let header = jsonwebtoken::decode_header(token)
+            .map_err(|_err| UserError::InvalidIdentityTokenError)?;
+
+        if !ALLOWED_ALGORITHMS.contains(&header.alg) {
+            return Err(UserError::InvalidIdentityTokenError);
+        }
+
+        let jwk = self.signing_key(header.kid.as_deref()).await?;
+        let key =
+            DecodingKey::from_jwk(&jwk).map_err(|_err| UserError::InvalidIdentityTokenError)?;
+        let metadata = self.metadata().await?;
+
+        let mut validation = Validation::new(header.alg);
+        validation.set_audience(&[&self.config.client_id]);
+        validation.set_issuer(&[&metadata.issuer]);
+        validation.set_required_spec_claims(&["exp", "iss", "aud", "sub"]);
+
+        let claims = jsonwebtoken::decode::<IdTokenClaims>(token, &key, &validation)
+            .map_err(|_err| UserError::InvalidIdentityTokenError)?
+            .claims;
+
+        if claims.nonce.as_deref() != Some(nonce) {
+            return Err(UserError::InvalidIdentityTokenError);
+        }
+
+        Ok(claims)
+    }
+}
+
+#[async_trait]
+impl AuthStrategy for OidcStrategy {
+    fn name(&self) -> &str {
+        &self.config.name
+    }
+
+    async fn authorization_url(&self, flow: &LoginFlow) -> Result<String, UserError> {
+        let metadata = self.metadata().await?;
+        let scope = self.config.scopes.join(" ");
+        let challenge = flow.code_challenge();
+        let url = Url::parse_with_params(
+            &metadata.authorization_endpoint,
+            &[
+                ("response_type", "code"),
+                ("client_id", &self.config.client_id),
+                ("redirect_uri", &flow.redirect_uri),
+                ("scope", &scope),
+                ("state", &flow.state),
+                ("nonce", &flow.nonce),
+                ("code_challenge", &challenge),
+                ("code_challenge_method", "S256"),
+            ],
+        )
+        .map_err(|_err| UserError::IdentityProviderConnectionError)?;
+
+        Ok(url.to_string())
+    }
+
+    async fn exchange_code(
+        &self,
+        code: &str,
+        flow: &LoginFlow,
+    ) -> Result<ExternalIdentity, UserError> {
+        let metadata = self.metadata().await?;
+        let params = [
+            ("grant_type", "authorization_code"),
+            ("code", code),
+            ("redirect_uri", &flow.redirect_uri),
+            ("code_verifier", &flow.code_verifier),
+        ];
+
+        let response = self
+            .client
+            .post(metadata.token_endpoint)
+            .basic_auth(&self.config.client_id, Some(&self.config.client_secret))
+            .form(&params)
+            .send()
+            .await
+            .map_err(|_err| UserError::IdentityProviderConnectionError)?;
+
+        // An error here is most likely an invalid (or reused) code
+        let token = response
+            .error_for_status()
+            .map_err(|_err| UserError::InvalidIdentityTokenError)?
+            .json::<TokenResponse>()
+            .await
+            .map_err(|_err| UserError::IdentityProviderConnectionError)?;
+
+        let claims = self.verify_id_token(&token.id_token, &flow.nonce).await?;
+        let email = claims.email.ok_or(UserError::InvalidEmailAddress)?;
+        let username = claims
+            .preferred_username
+            .as_deref()
+            .or_else(|| email.split('@').next())
+            .map(username_hint)
+            .unwrap_or_default();
+
+        Ok(ExternalIdentity {
+            account: api::LinkedAccount {
+                username: claims.sub,
+                strategy: self.config.name.clone(),
+            },
+            username,
+            email,
+            email_verified: claims.email_verified,
+        })
+    }
+}
+
+fn find_key(jwks: &JwkSet, kid: Option<&str>) -> Option<Jwk> {
+    match kid {
+        Some(kid) => jwks.find(kid).cloned(),
+        // only allowed if there is no ambiguity
+        None if jwks.keys.len() == 1 => jwks.keys.first().cloned(),
+        None => None,
+    }
+}
+
+/// Convert the username from the provider into a (valid) NetsBlox username
+fn username_hint(name: &str) -> String {
+    let name: String = name
+        .chars()
+        .skip_while(|c| !c.is_ascii_alphabetic())
+        .filter(|c| c.is_ascii_alphanumeric() || *c == '_' || *c == '-')
+        .take(16)
+        .collect();
+
+    if name.len() < 4 {
+        format!("user_{}", name)
+    } else {
+        name
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_utils::idp::MockIdp;
+
+    async fn authorize(idp: &MockIdp, strategy: &OidcStrategy) -> (String, LoginFlow) {
+        let flow = LoginFlow::new(
+            strategy.name().to_owned(),
+            "http://localhost/callback".into(),
+            None,
+            None,
+        );
+        let url = strategy.authorization_url(&flow).await.unwrap();
+        let code = idp.authorize(&url);
+        (code, flow)
+    }
+
+    #[actix_web::test]
+    async fn test_authorization_url() {
+        let idp = MockIdp::start().await;
+        let strategy = OidcStrategy::new(idp.config());
+        let flow = LoginFlow::new("myschool".into(), "http://localhost/cb".into(), None, None);
+
+        let url = strategy.authorization_url(&flow).await.unwrap();
+        let url = Url::parse(&url).unwrap();
+        let params: Vec<_> = url.query_pairs().into_owned().collect();
+
+        assert!(url.as_str().starts_with(&idp.url));
+        assert!(params.contains(&("state".into(), flow.state.clone())));
+        assert!(params.contains(&("nonce".into(), flow.nonce.clone())));
+        assert!(params.contains(&("code_challenge_method".into(), "S256".into())));
+        assert!(params.contains(&("scope".into(), "openid email profile".into())));
+    }
+
+    #[actix_web::test]
+    async fn test_exchange_code() {
+        let idp = MockIdp::start().await;
+        let strategy = OidcStrategy::new(idp.config());
+        let (code, flow) = authorize(&idp, &strategy).await;
+
+        let identity = strategy.exchange_code(&code, &flow).await.unwrap();
+
+        assert_eq!(identity.account.username, "subject-123");
+        assert_eq!(identity.account.strategy, "myschool");
+        assert_eq!(identity.email, "student@myschool.edu");
+        assert!(identity.email_verified);
+        assert_eq!(identity.username, "student");
+    }
+
+    #[actix_web::test]
+    async fn test_exchange_code_reused() {
+        let idp = MockIdp::start().await;
+        let strategy = OidcStrategy::new(idp.config());
+        let (code, flow) = authorize(&idp, &strategy).await;
+
+        strategy.exchange_code(&code, &flow).await.unwrap();
+        let result = strategy.exchange_code(&code, &flow).await;
+
+        assert!(matches!(result, Err(UserError::InvalidIdentityTokenError)));
+    }
+
+    #[actix_web::test]
+    async fn test_reject_invalid_nonce() {
+        let idp = MockIdp::start().await;
+        idp.set_claim("nonce", "someOtherNonce".into());
+        let strategy = OidcStrategy::new(idp.config());
+        let (code, flow) = authorize(&idp, &strategy).await;
+
+        let result = strategy.exchange_code(&code, &flow).await;
+
+        assert!(matches!(result, Err(UserError::InvalidIdentityTokenError)));
+    }
+
+    #[actix_web::test]
+    async fn test_reject_invalid_audience() {
+        let idp = MockIdp::start().await;
+        idp.set_claim("aud", "someOtherClient".into());
+        let strategy = OidcStrategy::new(idp.config());
+        let (code, flow) = authorize(&idp, &strategy).await;
+
+        let result = strategy.exchange_code(&code, &flow).await;
+
+        assert!(matches!(result, Err(UserError::InvalidIdentityTokenError)));
+    }
+
+    #[actix_web::test]
+    async fn test_reject_unknown_key() {
+        let idp = MockIdp::start().await;
+        idp.set_key_id("someOtherKey");
+        let strategy = OidcStrategy::new(idp.config());
+        let (code, flow) = authorize(&idp, &strategy).await;
+
+        let result = strategy.exchange_code(&code, &flow).await;
+
+        assert!(matches!(result, Err(UserError::InvalidIdentityTokenError)));
+    }
+
+    #[test]
+    fn test_username_hint() {
+        assert_eq!(username_hint("jane.doe"), "janedoe");
+        assert_eq!(username_hint("42abc"), "user_abc");
+        assert_eq!(
+            username_hint("averyveryverylongusername"),
+            "averyveryverylon"
+        );
+    }
+}
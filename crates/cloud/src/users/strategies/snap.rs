@@ -0,0 +1,76 @@
+use async_trait::async_trait;
+use reqwest::Method;
+use serde::Deserialize;
+
+use super::{AuthStrategy, ExternalIdentity};
+use crate::{common::api, errors::UserError, utils::sha512};
+
+#[allow(dead_code)]
+#[derive(Deserialize)]
+struct SnapUser {
+    id: u32,
+    created: String,
+    email: String,
+    role: String,
+    username: String,
+    project_count: u32,
+    verified: bool,
+}
+
+/// Login using Snap! credentials
+pub(crate) struct SnapStrategy;
+
+#[async_trait]
+impl AuthStrategy for SnapStrategy {
+    fn name(&self) -> &str {
+        "snap"
+    }
+
+    async fn authenticate(
+        &self,
+        username: &str,
+        password: &str,
+    ) -> Result<ExternalIdentity, UserError> {
+        let url = &format!("https://snap.berkeley.edu/api/v1/users/{}/login", username,);
+        let client = reqwest::Client::new();
+        let pwd_hash = sha512(password);
+        let response = client
+            .request(Method::POST, url)
+            .body(pwd_hash)
+            .send()
+            .await
+            .map_err(|_err| UserError::SnapConnectionError)?;
+
+        if response.status().as_u16() > 399 {
+            return Err(UserError::IncorrectUsernameOrPasswordError);
+        }
+
+        let cookie = response
+            .cookies()
+            .next()
+            .ok_or(UserError::SnapConnectionError)?;
+        let url = &format!("https://snap.berkeley.edu/api/v1/users/{}", username);
+
+        let user_data = client
+            .request(Method::GET, url)
+            .header("Cookie", format!("{}={}", cookie.name(), cookie.value()))
+            .send()
+            .await
+            .map_err(|_err| UserError::SnapConnectionError)?
+            .json::<SnapUser>()
+            .await
+            .map_err(|_err| UserError::SnapConnectionError)?;
+
+        // TODO: ensure email isn't banned?
+
+        Ok(ExternalIdentity {
+            account: api::LinkedAccount {
+                username: username.to_lowercase(),
+                strategy: self.name().to_owned(),
+            },
+            username: username.to_lowercase(),
+            email: user_data.email,
+            email_verified: user_data.verified,
+        })
+    }
+}
@@ -0,0 +1,126 @@
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use passwords::PasswordGenerator;
+use totp_rs::{Algorithm, Secret, TOTP};
+
+use crate::{errors::InternalError, utils};
+
+const ISSUER: &str = "NetsBlox";
+const STEP: u64 = 30;
+const DIGITS: usize = 6;
+/// Number of adjacent time steps to accept (to allow for clock drift)
+const SKEW: u64 = 1;
+const RECOVERY_CODE_COUNT: usize = 10;
+
+pub(super) struct NewSecret {
+    pub(super) secret: String,
+    pub(super) provisioning_uri: String,
+}
+
+/// Generate a new shared secret for the given user
+pub(super) fn new_secret(username: &str) -> Result<NewSecret, InternalError> {
+    let secret = Secret::generate_secret().to_encoded().to_string();
+    let provisioning_uri = totp(&secret, username)?.get_url();
+
+    Ok(NewSecret {
+        secret,
+        provisioning_uri,
+    })
+}
+
+/// Generate one-time recovery codes. Returns the codes and their hashes (for storage).
+pub(super) fn new_recovery_codes() -> Result<(Vec<String>, Vec<String>), InternalError> {
+    let codes = PasswordGenerator::new()
+        .length(10)
+        .numbers(true)
+        .lowercase_letters(true)
+        .uppercase_letters(false)
+        .symbols(false)
+        .spaces(false)
+        .exclude_similar_characters(true)
+        .generate(RECOVERY_CODE_COUNT)
+        .map_err(|_err| InternalError::PasswordGenerationError)?;
+
+    let hashes = codes.iter().map(|code| hash_recovery_code(code)).collect();
+    Ok((codes, hashes))
+}
+
+pub(super) fn hash_recovery_code(code: &str) -> String {
+    utils::sha512(&code.trim().to_lowercase())
+}
+
+/// Get the time step for the given code, if it is currently valid
+pub(super) fn verify_code(secret: &str, code: &str) -> Result<Option<i64>, InternalError> {
+    let totp = totp(secret, "")?;
+    let now = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|time| time.as_secs())
+        .unwrap_or_default();
+
+    let current = now / STEP;
+    let step = (current.saturating_sub(SKEW)..=current + SKEW)
+        .find(|step| totp.check(code.trim(), step * STEP))
+        .map(|step| step as i64);
+
+    Ok(step)
+}
+
+fn totp(secret: &str, username: &str) -> Result<TOTP, InternalError> {
+    let secret = Secret::Encoded(secret.to_owned())
+        .to_bytes()
+        .map_err(|_err| InternalError::TwoFactorSecretError)?;
+
+    Ok(TOTP::new_unchecked(
+        Algorithm::SHA1,
+        DIGITS,
+        0,
+        STEP,
+        secret,
+        Some(ISSUER.to_owned()),
+        username.to_owned(),
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_verify_current_code() {
+        let NewSecret { secret, .. } = new_secret("someUser").unwrap();
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap()
+            .as_secs();
+        let code = totp(&secret, "someUser").unwrap().generate(now);
+
+        let step = verify_code(&secret, &code).unwrap();
+        assert_eq!(step, Some((now / STEP) as i64));
+    }
+
+    #[test]
+    fn test_verify_invalid_code() {
+        let NewSecret { secret, .. } = new_secret("someUser").unwrap();
+        let step = verify_code(&secret, "abcdef").unwrap();
+        assert!(step.is_none());
+    }
+
+    #[test]
+    fn test_provisioning_uri() {
+        let NewSecret {
+            secret,
+            provisioning_uri,
+        } = new_secret("someUser").unwrap();
+
+        assert!(provisioning_uri.starts_with("otpauth://totp/NetsBlox:someUser?"));
+        assert!(provisioning_uri.contains(&secret));
+    }
+
+    #[test]
+    fn test_recovery_codes_hashed() {
+        let (codes, hashes) = new_recovery_codes().unwrap();
+        assert_eq!(codes.len(), RECOVERY_CODE_COUNT);
+        assert_eq!(hashes[0], hash_recovery_code(&codes[0]));
+        assert_ne!(codes[0], hashes[0]);
+    }
+}
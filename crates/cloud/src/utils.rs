@@ -1,30 +1,40 @@
 use actix::Addr;
 use actix_session::SessionExt;
-use actix_web::HttpRequest;
+use actix_web::{http::header, HttpMessage, HttpRequest};
 use futures::TryStreamExt;
+use ipnet::IpNet;
 use lazy_static::lazy_static;
 use lettre::{Message, SmtpTransport, Transport};
 use log::error;
 use lru::LruCache;
-use mongodb::{bson::doc, Collection};
+use mongodb::{
+    bson::{doc, DateTime, Document},
+    options::UpdateOptions,
+    Collection,
+};
 use netsblox_cloud_common::{
     api::{self, GroupId, UserRole},
-    AuthorizedServiceHost, FriendLink, Group, ProjectMetadata, User,
+    AuthorizedServiceHost, FriendLink, Group, HostRequestNonce, ProjectMetadata, User,
 };
 use nonempty::NonEmpty;
 use regex::Regex;
+use reqwest::Url;
 use rustrict::CensorStr;
 use serde::Serialize;
 use sha2::{Digest, Sha512};
 use std::{
     borrow::Borrow,
     collections::HashSet,
+    net::{IpAddr, SocketAddr},
     sync::{Arc, RwLock},
 };
 
 use crate::{
+    access_tokens::middleware::AuthenticatedToken,
+    app_data::AppData,
     errors::{InternalError, UserError},
     network::topology::{self, TopologyActor},
+    services::hosts::signing::{self, HostCredentials, KeyCipher},
 };
 
 pub(crate) fn on_room_changed(
@@ -290,33 +300,235 @@ fn get_cached_friends(
     cache.get(username).map(|friends| friends.to_owned())
 }
 
+/// Ensure the URL (to redirect to after logging in) is on the public URL or
+/// one of the allowed origins (eg, the editor)
+pub(crate) fn ensure_allowed_redirect(
+    uri: &str,
+    public_url: &str,
+    allowed_origins: &[String],
+) -> Result<(), UserError> {
+    let origin = Url::parse(uri)
+        .ok()
+        .filter(|url| matches!(url.scheme(), "http" | "https"))
+        .map(|url| url.origin().ascii_serialization())
+        .ok_or(UserError::RedirectNotAllowedError)?;
+
+    let public_origin = Url::parse(public_url)
+        .ok()
+        .map(|url| url.origin().ascii_serialization());
+
+    let is_allowed = public_origin.as_deref() == Some(origin.as_str())
+        || allowed_origins
+            .iter()
+            .any(|allowed| allowed.trim_end_matches('/') == origin);
+
+    if is_allowed {
+        Ok(())
+    } else {
+        Err(UserError::RedirectNotAllowedError)
+    }
+}
+
+/// Query for documents (eg, bans) which haven't expired. Expired documents are
+/// removed by a TTL index but this isn't immediate so they should be filtered out, too.
+pub(crate) fn not_expired() -> Document {
+    doc! {"$or": [
+        {"expiresAt": null},
+        {"expiresAt": {"$gt": DateTime::now()}},
+    ]}
+}
+
+/// Parse an IP address or range of addresses (in CIDR notation)
+pub(crate) fn parse_address_range(addr: &str) -> Result<IpNet, UserError> {
+    addr.parse::<IpNet>()
+        .map(|range| range.trunc())
+        .or_else(|_| addr.parse::<IpAddr>().map(IpNet::from))
+        .map_err(|_err| UserError::InvalidAddressError)
+}
+
+/// Get the address of the client. If the request was sent by a trusted proxy,
+/// the forwarded addresses are checked from right to left (ie, starting with the
+/// closest proxy) and the first one which isn't a trusted proxy is used. Entries
+/// further to the left are set by the client and can't be trusted.
+pub(crate) fn get_client_addr(req: &HttpRequest, trusted_proxies: &[String]) -> Option<IpAddr> {
+    let trusted_proxies: Vec<_> = trusted_proxies
+        .iter()
+        .filter_map(|proxy| parse_address_range(proxy).ok())
+        .collect();
+    let is_trusted = |addr: &IpAddr| trusted_proxies.iter().any(|range| range.contains(addr));
+
+    let mut client_addr = req.peer_addr()?.ip();
+    for addr in forwarded_addrs(req).iter().rev() {
+        if !is_trusted(&client_addr) {
+            break;
+        }
+
+        match parse_forwarded_addr(addr) {
+            Some(addr) => client_addr = addr,
+            None => break,
+        }
+    }
+
+    Some(client_addr)
+}
+
+/// Get the addresses from the Forwarded header (or X-Forwarded-For if unset)
+/// in the order they were added.
+fn forwarded_addrs(req: &HttpRequest) -> Vec<String> {
+    let header_values = |name: header::HeaderName| {
+        req.headers()
+            .get_all(name)
+            .filter_map(|value| value.to_str().ok())
+            .flat_map(|value| value.split(','))
+            .map(|entry| entry.trim().to_owned())
+            .collect::<Vec<_>>()
+    };
+
+    let forwarded: Vec<_> = header_values(header::FORWARDED)
+        .into_iter()
+        .filter_map(|entry| {
+            entry.split(';').find_map(|pair| {
+                let (name, value) = pair.trim().split_once('=')?;
+                name.eq_ignore_ascii_case("for")
+                    .then(|| value.trim_matches('"').to_owned())
+            })
+        })
+        .collect();
+
+    if forwarded.is_empty() {
+        header_values(header::X_FORWARDED_FOR)
+    } else {
+        forwarded
+    }
+}
+
+fn parse_forwarded_addr(addr: &str) -> Option<IpAddr> {
+    addr.parse::<IpAddr>()
+        .or_else(|_| addr.parse::<SocketAddr>().map(|addr| addr.ip()))
+        .or_else(|_| {
+            addr.trim_start_matches('[')
+                .trim_end_matches(']')
+                .parse::<IpAddr>()
+        })
+        .ok()
+}
+
+/// Get the username of the requestor. Requests can be authenticated using
+/// either the session or a personal access token.
 pub(crate) fn get_username(req: &HttpRequest) -> Option<String> {
+    if let Some(token) = req.extensions().get::<AuthenticatedToken>() {
+        return Some(token.username.clone());
+    }
+
     let session = req.get_session();
     session.get::<String>("username").unwrap_or(None)
 }
 
+/// Check if the request was authenticated using a personal access token
+pub(crate) fn is_token_auth(req: &HttpRequest) -> bool {
+    req.extensions().get::<AuthenticatedToken>().is_some()
+}
+
+/// Get the authorized host which sent the request, if any. The host is stored
+/// in the request extensions since the nonce of a signed request is recorded
+/// (to prevent replays) and would be rejected if verified again.
 pub(crate) async fn get_authorized_host(
-    authorized_services: &Collection<AuthorizedServiceHost>,
+    app: &AppData,
     req: &HttpRequest,
 ) -> Result<Option<AuthorizedServiceHost>, UserError> {
-    let query = req
-        .headers()
-        .get("X-Authorization")
-        .and_then(|value| value.to_str().ok())
-        .and_then(|value_str| {
-            let mut chunks = value_str.split(':');
-            let id = chunks.next();
-            let secret = chunks.next();
-            id.and_then(|id| secret.map(|s| (id, s)))
-        })
-        .map(|(id, secret)| doc! {"id": id, "secret": secret});
+    if let Some(host) = req.extensions().get::<AuthorizedServiceHost>() {
+        return Ok(Some(host.clone()));
+    }
 
-    let host = authorized_services
-        .find_one(query, None)
+    let settings = &app.settings.service_hosts;
+    let host = match HostCredentials::from_request(req, settings.signature_max_age_secs)? {
+        Some(HostCredentials::Secret { id, secret }) => {
+            if settings.require_signed_requests {
+                return Err(UserError::SignedRequestRequiredError);
+            }
+
+            let hash = sha512(&secret);
+            let query = doc! {"id": &id, "secrets.hash": &hash};
+            let host = app
+                .authorized_services
+                .find_one(query, None)
+                .await
+                .map_err(InternalError::DatabaseConnectionError)?
+                .filter(|host| host.active_secrets().any(|secret| secret.hash == hash));
+
+            let needs_signing_key = host
+                .iter()
+                .flat_map(|host| host.active_secrets())
+                .any(|secret| secret.hash == hash && secret.signing_key.is_none());
+            if needs_signing_key {
+                let cipher = KeyCipher::new(&app.settings);
+                store_host_signing_key(&app.authorized_services, &cipher, &id, &secret).await?;
+            }
+
+            host
+        }
+        Some(HostCredentials::Signature(request)) => {
+            let cipher = KeyCipher::new(&app.settings);
+            let query = doc! {"id": &request.id};
+            let host = app
+                .authorized_services
+                .find_one(query, None)
+                .await
+                .map_err(InternalError::DatabaseConnectionError)?
+                .filter(|host| {
+                    host.active_secrets()
+                        .filter_map(|secret| secret.signing_key.as_ref())
+                        .filter_map(|key| cipher.decrypt(key))
+                        .any(|key| request.is_signed_with(&key))
+                })
+                .ok_or(UserError::InvalidSignatureError)?;
+
+            let nonce = HostRequestNonce::new(request.id, request.nonce);
+            let query = doc! {"hostId": &nonce.host_id, "nonce": &nonce.nonce};
+            let update = doc! {"$setOnInsert": nonce};
+            let options = UpdateOptions::builder().upsert(true).build();
+            let result = app
+                .host_nonces
+                .update_one(query, update, options)
+                .await
+                .map_err(InternalError::DatabaseConnectionError)?;
+
+            if result.matched_count > 0 {
+                return Err(UserError::ReplayedRequestError);
+            }
+
+            Some(host)
+        }
+        None => None,
+    };
+
+    if let Some(host) = host.as_ref() {
+        req.extensions_mut().insert(host.clone());
+    }
+
+    Ok(host)
+}
+
+/// Store the (encrypted) signing key for a secret of a host if it was hashed
+/// before signing keys were stored
+pub(crate) async fn store_host_signing_key(
+    hosts: &Collection<AuthorizedServiceHost>,
+    cipher: &KeyCipher,
+    id: &str,
+    secret: &str,
+) -> Result<(), InternalError> {
+    let query = doc! {
+        "id": id,
+        "secrets": {"$elemMatch": {"hash": sha512(secret), "signingKey": null}},
+    };
+    let signing_key = cipher.encrypt(&signing::signing_key(secret))?;
+    let update = doc! {"$set": {"secrets.$.signingKey": signing_key}};
+    hosts
+        .update_one(query, update, None)
         .await
         .map_err(InternalError::DatabaseConnectionError)?;
 
-    Ok(host)
+    Ok(())
 }
 
 pub(crate) fn send_email(
@@ -387,11 +599,146 @@ mod tests {
     use itertools::Itertools;
     use lru::LruCache;
     use mongodb::bson::DateTime;
+    use netsblox_cloud_common::HostSecret;
 
     use crate::test_utils;
 
     use super::*;
 
+    #[test]
+    fn test_client_addr_from_trusted_proxy() {
+        let trusted = vec!["10.0.0.0/8".to_owned()];
+        let req = actix_web::test::TestRequest::get()
+            .peer_addr("10.1.2.3:8080".parse().unwrap())
+            .insert_header(("X-Forwarded-For", "1.2.3.4"))
+            .to_http_request();
+
+        let addr = get_client_addr(&req, &trusted);
+        assert_eq!(addr, Some("1.2.3.4".parse().unwrap()));
+    }
+
+    #[test]
+    fn test_client_addr_ignore_untrusted_proxy() {
+        let trusted = vec!["10.0.0.0/8".to_owned()];
+        let req = actix_web::test::TestRequest::get()
+            .peer_addr("192.168.1.2:8080".parse().unwrap())
+            .insert_header(("X-Forwarded-For", "1.2.3.4"))
+            .to_http_request();
+
+        let addr = get_client_addr(&req, &trusted);
+        assert_eq!(addr, Some("192.168.1.2".parse().unwrap()));
+    }
+
+    #[test]
+    fn test_client_addr_forwarded_with_port() {
+        let trusted = vec!["10.1.2.3".to_owned()];
+        let req = actix_web::test::TestRequest::get()
+            .peer_addr("10.1.2.3:8080".parse().unwrap())
+            .insert_header(("Forwarded", "for=\"[2001:db8::1]:4711\""))
+            .to_http_request();
+
+        let addr = get_client_addr(&req, &trusted);
+        assert_eq!(addr, Some("2001:db8::1".parse().unwrap()));
+    }
+
+    #[test]
+    fn test_client_addr_multiple_hops() {
+        let trusted = vec!["10.0.0.0/8".to_owned()];
+        let req = actix_web::test::TestRequest::get()
+            .peer_addr("10.1.2.3:8080".parse().unwrap())
+            .insert_header(("X-Forwarded-For", "6.6.6.6, 1.2.3.4"))
+            .to_http_request();
+
+        let addr = get_client_addr(&req, &trusted);
+        assert_eq!(addr, Some("1.2.3.4".parse().unwrap()));
+
+        // addresses of trusted proxies are skipped
+        let req = actix_web::test::TestRequest::get()
+            .peer_addr("10.1.2.3:8080".parse().unwrap())
+            .insert_header(("X-Forwarded-For", "6.6.6.6, 1.2.3.4, 10.4.5.6"))
+            .to_http_request();
+
+        let addr = get_client_addr(&req, &trusted);
+        assert_eq!(addr, Some("1.2.3.4".parse().unwrap()));
+    }
+
+    #[test]
+    fn test_ensure_allowed_redirect() {
+        let public_url = "https://cloud.netsblox.org";
+        let allowed = vec!["https://editor.netsblox.org/".to_owned()];
+
+        assert!(
+            ensure_allowed_redirect("https://cloud.netsblox.org/x", public_url, &allowed).is_ok()
+        );
+        assert!(
+            ensure_allowed_redirect("https://editor.netsblox.org/?a=b", public_url, &allowed)
+                .is_ok()
+        );
+        assert!(
+            ensure_allowed_redirect("https://evil.example.com/", public_url, &allowed).is_err()
+        );
+        assert!(ensure_allowed_redirect(
+            "https://editor.netsblox.org.evil.com/",
+            public_url,
+            &allowed
+        )
+        .is_err());
+        assert!(ensure_allowed_redirect("javascript:alert(1)", public_url, &allowed).is_err());
+        assert!(ensure_allowed_redirect("/relative", public_url, &allowed).is_err());
+    }
+
+    #[test]
+    fn test_parse_address_range() {
+        let range = parse_address_range("10.1.2.3/8").unwrap();
+        assert_eq!(range.to_string(), "10.0.0.0/8");
+
+        let range = parse_address_range("10.1.2.3").unwrap();
+        assert_eq!(range.to_string(), "10.1.2.3/32");
+
+        let range = parse_address_range("::1").unwrap();
+        assert_eq!(range.to_string(), "::1/128");
+
+        assert!(parse_address_range("10.1.2").is_err());
+        assert!(parse_address_range("10.1.2.3/33").is_err());
+    }
+
+    #[actix_web::test]
+    async fn test_get_authorized_host_stores_signing_key() {
+        let host = AuthorizedServiceHost::new(
+            "http://localhost:5656".into(),
+            "TestServices".into(),
+            api::ServiceHostScope::Private,
+            Vec::new(),
+            HostSecret::new(sha512("secret"), None),
+        );
+
+        test_utils::setup()
+            .with_authorized_services(&[host.clone()])
+            .run(|app_data| async move {
+                let auth = format!("{}:secret", &host.id);
+                let req = actix_web::test::TestRequest::get()
+                    .insert_header((signing::AUTH_HEADER, auth))
+                    .to_http_request();
+                let authorized = get_authorized_host(&app_data, &req).await.unwrap();
+                assert!(authorized.is_some());
+
+                // signed requests can be used once the signing key is stored
+                let host = app_data
+                    .authorized_services
+                    .find_one(doc! {"id": &host.id}, None)
+                    .await
+                    .unwrap()
+                    .unwrap();
+                let cipher = KeyCipher::new(&app_data.settings);
+                let signing_key = host.secrets[0].signing_key.as_ref().unwrap();
+                assert_eq!(
+                    cipher.decrypt(signing_key),
+                    Some(signing::signing_key("secret"))
+                );
+            })
+            .await;
+    }
+
     #[actix_web::test]
     async fn test_update_project_cache_ignore_stale() {
         // This issue was discovered around old projects hanging around in the project cache
@@ -24,6 +24,7 @@ pub(crate) struct User {
 impl From<User> for cloud::User {
     fn from(user: User) -> cloud::User {
         cloud::User {
+            id: None,
             username: user.username,
             email: user.email,
             group_id: user
@@ -52,6 +53,11 @@ impl From<User> for cloud::User {
                     .collect::<Vec<_>>()
             }),
             service_settings: HashMap::new(),
+            two_factor: None,
+            verified: true,
+            signup_ip: None,
+            signup_subnet: None,
+            signup_user_agent: None,
         }
     }
 }
@@ -227,6 +233,9 @@ impl From<BannedAccount> for cloud::BannedAccount {
             username: acct.username,
             email: acct.email,
             banned_at: acct.banned_at,
+            moderator: None,
+            reason: None,
+            expires_at: None,
         }
     }
 }